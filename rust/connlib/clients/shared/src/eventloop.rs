@@ -7,7 +7,7 @@ use crate::{
 };
 use anyhow::Result;
 use connlib_shared::{
-    messages::{ConnectionAccepted, GatewayResponse, ResourceAccepted, ResourceId},
+    messages::{ConnectionAccepted, DnsServer, GatewayResponse, ResourceAccepted, ResourceId},
     Callbacks,
 };
 use firezone_tunnel::ClientTunnel;
@@ -16,6 +16,7 @@ use std::{
     collections::HashMap,
     net::IpAddr,
     task::{Context, Poll},
+    time::Duration,
 };
 
 pub struct Eventloop<C: Callbacks> {
@@ -32,6 +33,14 @@ pub enum Command {
     Stop,
     Reconnect,
     SetDns(Vec<IpAddr>),
+    SetUpstreamDns(Vec<DnsServer>),
+    SetDnsResourceTtl(Duration),
+    StartCapture {
+        duration: Duration,
+        max_bytes: usize,
+    },
+    StopCapture(tokio::sync::oneshot::Sender<Option<Vec<u8>>>),
+    RotatePresharedKeys,
 }
 
 impl<C: Callbacks> Eventloop<C> {
@@ -62,6 +71,31 @@ where
                         tracing::warn!("Failed to update DNS: {e}");
                     }
                 }
+                Poll::Ready(Some(Command::SetUpstreamDns(upstream_dns))) => {
+                    if let Err(e) = self.tunnel.set_upstream_dns(upstream_dns) {
+                        tracing::warn!("Failed to update upstream DNS: {e}");
+                    }
+                }
+                Poll::Ready(Some(Command::SetDnsResourceTtl(ttl))) => {
+                    self.tunnel.set_dns_resource_ttl(ttl);
+                }
+                Poll::Ready(Some(Command::StartCapture {
+                    duration,
+                    max_bytes,
+                })) => {
+                    self.tunnel.start_capture(None, duration, max_bytes);
+                }
+                Poll::Ready(Some(Command::StopCapture(tx))) => {
+                    let _ = tx.send(self.tunnel.stop_capture());
+                }
+                Poll::Ready(Some(Command::RotatePresharedKeys)) => {
+                    for rotation in self.tunnel.rotate_preshared_keys() {
+                        tracing::debug!(gateway = %rotation.gateway_id, "Sending new preshared key to gateway");
+
+                        self.portal
+                            .send(PHOENIX_TOPIC, EgressMessages::RotatePresharedKey(rotation));
+                    }
+                }
                 Poll::Ready(Some(Command::Reconnect)) => {
                     self.portal.reconnect();
                     if let Err(e) = self.tunnel.reconnect() {
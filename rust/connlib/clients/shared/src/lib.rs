@@ -1,5 +1,7 @@
 //! Main connlib library for clients.
-pub use connlib_shared::messages::ResourceDescription;
+pub use connlib_shared::messages::{
+    DnsServer, HttpsDnsServer, IpDnsServer, ResourceDescription, TlsDnsServer,
+};
 pub use connlib_shared::{
     keypair, Callbacks, Cidrv4, Cidrv6, Error, LoginUrl, LoginUrlError, StaticSecret,
 };
@@ -68,6 +70,7 @@ impl Session {
     /// - Close and re-open a connection to the portal.
     /// - Refresh all allocations
     /// - Rebind local UDP sockets
+    /// - Migrate all connections to the new network, keeping their wireguard sessions
     ///
     /// # Implementation note
     ///
@@ -95,6 +98,48 @@ impl Session {
         let _ = self.channel.send(Command::SetDns(new_dns));
     }
 
+    /// Sets upstream DNS servers configured on this device, e.g. DNS over HTTPS or DNS over TLS servers.
+    ///
+    /// These take precedence over the upstream DNS servers configured in the portal.
+    /// Pass an empty list to fall back to those.
+    pub fn set_upstream_dns(&self, upstream_dns: Vec<DnsServer>) {
+        let _ = self.channel.send(Command::SetUpstreamDns(upstream_dns));
+    }
+
+    /// Sets the TTL of our answers for DNS resources, the default is 1 second.
+    ///
+    /// A longer TTL saves queries at the cost of noticing later that a resource needs a connection.
+    pub fn set_dns_resource_ttl(&self, ttl: Duration) {
+        let _ = self.channel.send(Command::SetDnsResourceTtl(ttl));
+    }
+
+    /// Starts capturing the plaintext packets of all connections, replacing any previous capture.
+    ///
+    /// The capture stops recording after `duration` or once it reaches `max_bytes`, whatever comes first.
+    pub fn start_capture(&self, duration: Duration, max_bytes: usize) {
+        let _ = self.channel.send(Command::StartCapture {
+            duration,
+            max_bytes,
+        });
+    }
+
+    /// Stops the current capture.
+    ///
+    /// The returned channel resolves to the capture as a pcapng file or `None` if there wasn't one.
+    pub fn stop_capture(&self) -> tokio::sync::oneshot::Receiver<Option<Vec<u8>>> {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        let _ = self.channel.send(Command::StopCapture(tx));
+
+        rx
+    }
+
+    /// Rotates the preshared keys of the connections to all gateways.
+    ///
+    /// The gateways receive the new keys via the portal, see [`RotatePresharedKey`](connlib_shared::messages::RotatePresharedKey).
+    pub fn rotate_preshared_keys(&self) {
+        let _ = self.channel.send(Command::RotatePresharedKeys);
+    }
+
     /// Disconnect a [`Session`].
     ///
     /// This consumes [`Session`] which cleans up all state associated with it.
@@ -1,6 +1,6 @@
 use connlib_shared::messages::{
     GatewayId, GatewayResponse, Interface, Key, Relay, RequestConnection, ResourceDescription,
-    ResourceId, ReuseConnection,
+    ResourceId, ReuseConnection, RotatePresharedKey,
 };
 use serde::{Deserialize, Serialize};
 use std::{collections::HashSet, net::IpAddr};
@@ -88,6 +88,7 @@ pub enum EgressMessages {
     RequestConnection(RequestConnection),
     ReuseConnection(ReuseConnection),
     BroadcastIceCandidates(BroadcastGatewayIceCandidates),
+    RotatePresharedKey(RotatePresharedKey),
 }
 
 #[cfg(test)]
@@ -95,7 +96,8 @@ mod test {
     use super::*;
     use chrono::DateTime;
     use connlib_shared::messages::{
-        DnsServer, IpDnsServer, ResourceDescriptionCidr, ResourceDescriptionDns, Stun, Turn,
+        DnsServer, HttpsDnsServer, IpDnsServer, ResourceDescriptionCidr, ResourceDescriptionDns,
+        Stun, TlsDnsServer, Turn,
     };
     use phoenix_channel::PhoenixMessage;
 
@@ -174,6 +176,58 @@ mod test {
         assert_eq!(m, ingress_message);
     }
 
+    #[test]
+    fn config_updated_with_encrypted_dns() {
+        let m = PhoenixMessage::new_message(
+            "client",
+            IngressMessages::ConfigChanged(ConfigUpdate {
+                interface: Interface {
+                    ipv4: "100.67.138.25".parse().unwrap(),
+                    ipv6: "fd00:2021:1111::e:65ea".parse().unwrap(),
+                    upstream_dns: vec![
+                        DnsServer::Tls(TlsDnsServer {
+                            address: "1.1.1.1:853".parse().unwrap(),
+                            server_name: "one.one.one.one".to_owned(),
+                        }),
+                        DnsServer::Https(HttpsDnsServer {
+                            address: "1.0.0.1:443".parse().unwrap(),
+                            server_name: "cloudflare-dns.com".to_owned(),
+                        }),
+                    ],
+                },
+            }),
+            None,
+        );
+        let message = r#"
+        {
+            "event": "config_changed",
+            "ref": null,
+            "topic": "client",
+            "payload": {
+              "interface": {
+                "ipv6": "fd00:2021:1111::e:65ea",
+                "upstream_dns": [
+                  {
+                    "protocol": "tls",
+                    "address": "1.1.1.1:853",
+                    "server_name": "one.one.one.one"
+                  },
+                  {
+                    "protocol": "https",
+                    "address": "1.0.0.1:443",
+                    "server_name": "cloudflare-dns.com"
+                  }
+                ],
+                "ipv4": "100.67.138.25"
+              }
+            }
+          }
+        "#;
+        let ingress_message: PhoenixMessage<IngressMessages, ReplyMessages> =
+            serde_json::from_str(message).unwrap();
+        assert_eq!(m, ingress_message);
+    }
+
     #[test]
     fn init_phoenix_message() {
         let m = PhoenixMessage::new_message(
@@ -134,6 +134,30 @@ pub struct ReuseConnection {
     pub payload: Option<Dname>,
 }
 
+/// Represents a new preshared key for an established connection from a client to a gateway.
+///
+/// The client generates the key and installs it right away; the portal forwards it to the gateway, which installs it upon receipt.
+/// Until then, the client keeps retransmitting its handshake with the new key and traffic continues to use the current one.
+/// If the gateway doesn't install the same key within 90 seconds, both sides abandon the rotation and keep the current key.
+///
+/// While this is a client-only message it's hosted in common since the tunnel
+/// makes use of this message type.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct RotatePresharedKey {
+    /// Gateway id of the connection.
+    pub gateway_id: GatewayId,
+    /// The new preshared key the client generated for the connection.
+    pub client_preshared_key: SecretKey,
+}
+
+impl PartialEq for RotatePresharedKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.gateway_id == other.gateway_id
+    }
+}
+
+impl Eq for RotatePresharedKey {}
+
 // Custom implementation of partial eq to ignore client_rtc_sdp
 impl PartialEq for RequestConnection {
     fn eq(&self, other: &Self) -> bool {
@@ -300,22 +324,31 @@ pub struct ResourceDescriptionCidr {
     pub name: String,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Hash)]
 #[serde(tag = "protocol", rename_all = "snake_case")]
 pub enum DnsServer {
     IpPort(IpDnsServer),
+    /// DNS over TLS, see [RFC 7858](https://www.rfc-editor.org/rfc/rfc7858).
+    ///
+    /// Unlike [`DnsServer::IpPort`], queries can't be forwarded as packets through the tunnel but are always sent by connlib's own resolver.
+    /// Its connections bypass the tunnel, hence the server must not be within a CIDR resource.
+    Tls(TlsDnsServer),
+    /// DNS over HTTPS, see [RFC 8484](https://www.rfc-editor.org/rfc/rfc8484).
+    ///
+    /// The same limitation as for [`DnsServer::Tls`] applies.
+    Https(HttpsDnsServer),
 }
 
 impl DnsServer {
     pub fn ip(&self) -> IpAddr {
-        match self {
-            DnsServer::IpPort(s) => s.address.ip(),
-        }
+        self.address().ip()
     }
 
     pub fn address(&self) -> SocketAddr {
         match self {
             DnsServer::IpPort(s) => s.address,
+            DnsServer::Tls(s) => s.address,
+            DnsServer::Https(s) => s.address,
         }
     }
 }
@@ -336,6 +369,24 @@ pub struct IpDnsServer {
     pub address: SocketAddr,
 }
 
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Hash)]
+pub struct TlsDnsServer {
+    /// Address of the server, usually on port 853.
+    pub address: SocketAddr,
+    /// Name to send as SNI and to validate the server's certificate against.
+    pub server_name: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Hash)]
+pub struct HttpsDnsServer {
+    /// Address of the server, usually on port 443.
+    pub address: SocketAddr,
+    /// Name to send as SNI and to validate the server's certificate against.
+    ///
+    /// Queries are sent to `https://{server_name}/dns-query`.
+    pub server_name: String,
+}
+
 /// Represents a wireguard interface configuration.
 ///
 /// Note that the ips are /32 for ipv4 and /128 for ipv6.
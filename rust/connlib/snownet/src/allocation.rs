@@ -170,6 +170,22 @@ impl Allocation {
         self.authenticate_and_queue(make_refresh_request());
     }
 
+    /// Builds a REFRESH request with a lifetime of 0, asking the relay to delete our allocation.
+    ///
+    /// Returns `None` if we don't have an allocation.
+    pub fn deallocate(self) -> Option<Transmit<'static>> {
+        if !self.has_allocation() {
+            return None;
+        }
+
+        Some(Transmit {
+            src: None,
+            dst: self.server,
+            payload: encode(self.authenticate(make_delete_allocation_request())).into(),
+            segment_size: None,
+        })
+    }
+
     #[tracing::instrument(level = "debug", skip_all, fields(id, method, class, rtt))]
     pub fn handle_input(
         &mut self,
@@ -728,6 +744,7 @@ impl Allocation {
             src: None,
             dst: self.server,
             payload: encode(authenticated_message).into(),
+            segment_size: None,
         });
 
         true
@@ -795,6 +812,13 @@ fn make_refresh_request() -> Message<Attribute> {
     message
 }
 
+fn make_delete_allocation_request() -> Message<Attribute> {
+    let mut message = make_refresh_request();
+    message.add_attribute(Lifetime::new(Duration::ZERO).expect("zero is a valid lifetime"));
+
+    message
+}
+
 fn make_channel_bind_request(target: SocketAddr, channel: u16) -> Message<Attribute> {
     let mut message = Message::new(
         MessageClass::Request,
@@ -1531,6 +1555,34 @@ mod tests {
         assert!(lifetime.is_none() || lifetime.is_some_and(|l| l.lifetime() != Duration::ZERO));
     }
 
+    #[test]
+    fn deallocate_sends_refresh_with_zero_lifetime() {
+        let mut allocation = Allocation::for_test(Instant::now());
+
+        let allocate = allocation.next_message().unwrap();
+        allocation.handle_test_input(
+            &allocate_response(&allocate, &[RELAY_ADDR_IP4]),
+            Instant::now(),
+        );
+
+        let transmit = allocation.deallocate().unwrap();
+        let refresh = decode(&transmit.payload).unwrap().unwrap();
+
+        assert_eq!(transmit.dst, RELAY);
+        assert_eq!(refresh.method(), REFRESH);
+        assert_eq!(
+            refresh.get_attribute::<Lifetime>().map(|l| l.lifetime()),
+            Some(Duration::ZERO)
+        );
+    }
+
+    #[test]
+    fn deallocate_without_allocation_does_nothing() {
+        let allocation = Allocation::for_test(Instant::now());
+
+        assert!(allocation.deallocate().is_none());
+    }
+
     #[test]
     fn failed_refresh_will_invalidate_relay_candiates() {
         let mut allocation = Allocation::for_test(Instant::now());
@@ -0,0 +1,240 @@
+use pcapng_writer::PacketOption;
+use std::collections::HashSet;
+use std::fmt;
+use std::hash::Hash;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant, SystemTime};
+
+pub(crate) use pcapng_writer::Direction;
+
+/// Which connections to capture, see [`Node::start_capture`](crate::Node::start_capture).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CaptureFilter<TId> {
+    /// Capture all connections, including ones that are created while the capture is running.
+    AllConnections,
+    Connections(HashSet<TId>),
+}
+
+impl<TId> CaptureFilter<TId>
+where
+    TId: Eq + Hash,
+{
+    fn matches(&self, id: &TId) -> bool {
+        match self {
+            CaptureFilter::AllConnections => true,
+            CaptureFilter::Connections(ids) => ids.contains(id),
+        }
+    }
+}
+
+/// The UDP datagram carrying a (encrypted) packet on the wire.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Outer {
+    /// Our local socket, `None` if the packet is sent via a relay from any interface.
+    pub(crate) src: Option<SocketAddr>,
+    pub(crate) dst: SocketAddr,
+    /// The remote behind the relay, if the packet is relayed.
+    pub(crate) relayed: Option<SocketAddr>,
+}
+
+/// A capture of the plaintext IP packets of some connections, in the [pcapng](https://www.ietf.org/archive/id/draft-ietf-opsawg-pcapng-01.html) format.
+///
+/// Each packet carries a comment with its connection and [`Outer`] datagram.
+/// Captures are bounded in time and size and silently stop recording once either is exhausted.
+pub(crate) struct Capture<TId> {
+    filter: CaptureFilter<TId>,
+    file: pcapng_writer::Writer,
+
+    started_at: Instant,
+    /// `started_at` as a UNIX timestamp, used to derive the timestamps of all packets.
+    started_at_unix: Duration,
+    deadline: Instant,
+    max_bytes: usize,
+}
+
+impl<TId> Capture<TId>
+where
+    TId: Eq + Hash + fmt::Display,
+{
+    pub(crate) fn new(
+        filter: CaptureFilter<TId>,
+        duration: Duration,
+        max_bytes: usize,
+        now: Instant,
+        now_unix: SystemTime,
+    ) -> Self {
+        Self {
+            filter,
+            file: pcapng_writer::Writer::new(),
+            started_at: now,
+            started_at_unix: now_unix
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default(),
+            deadline: now + duration,
+            max_bytes,
+        }
+    }
+
+    /// Records a plaintext IP packet of the given connection, if the connection is being captured.
+    ///
+    /// Packets are dropped once the capture ran out of time or bytes.
+    pub(crate) fn record(
+        &mut self,
+        connection: &TId,
+        direction: Direction,
+        packet: &[u8],
+        outer: Outer,
+        now: Instant,
+    ) {
+        if now >= self.deadline || !self.filter.matches(connection) {
+            return;
+        }
+
+        let timestamp = self.started_at_unix + now.duration_since(self.started_at);
+        let comment = comment(connection, direction, outer);
+        let options = [
+            PacketOption::Comment(&comment),
+            PacketOption::Direction(direction),
+        ];
+
+        if !self
+            .file
+            .write_packet(timestamp, packet, &options, self.max_bytes)
+        {
+            self.deadline = now; // Stop the capture instead of skipping just this packet to avoid confusing gaps.
+        }
+    }
+
+    /// The pcapng file of everything recorded so far.
+    pub(crate) fn into_pcapng(self) -> Vec<u8> {
+        self.file.into_bytes()
+    }
+}
+
+/// Describes the connection and outer datagram of a packet, e.g. `connection=1 outer=udp 192.0.2.1:52625 -> 203.0.113.1:3478 relayed=198.51.100.1:61122`.
+fn comment(connection: &impl fmt::Display, direction: Direction, outer: Outer) -> String {
+    let src = outer
+        .src
+        .map(|src| src.to_string())
+        .unwrap_or_else(|| "*".to_owned());
+    let dst = outer.dst;
+
+    let mut comment = match direction {
+        Direction::Inbound => format!("connection={connection} outer=udp {dst} -> {src}"),
+        Direction::Outbound => format!("connection={connection} outer=udp {src} -> {dst}"),
+    };
+
+    if let Some(relayed) = outer.relayed {
+        comment.push_str(&format!(" relayed={relayed}"));
+    }
+
+    comment
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOCAL: &str = "192.0.2.1:52625";
+    const REMOTE: &str = "198.51.100.1:61122";
+    const RELAY: &str = "203.0.113.1:3478";
+
+    /// The size of the section header and interface description.
+    const HEADER_LEN: usize = 48;
+    /// The option code of a comment.
+    const OPT_COMMENT: u16 = 1;
+
+    #[test]
+    fn records_packet_with_connection_and_outer_tuple() {
+        let mut capture = make_capture(CaptureFilter::AllConnections, 1024);
+        let now = capture.started_at;
+
+        capture.record(&1, Direction::Outbound, b"hello", direct(), now);
+
+        let file = capture.into_pcapng();
+        let block = &file[HEADER_LEN..];
+
+        assert_eq!(&block[20..24], &5u32.to_le_bytes()); // Captured length.
+        assert_eq!(&block[28..33], b"hello");
+
+        let comment = format!("connection=1 outer=udp {LOCAL} -> {REMOTE}");
+        assert_eq!(&block[36..38], &OPT_COMMENT.to_le_bytes());
+        assert_eq!(&block[38..40], &(comment.len() as u16).to_le_bytes());
+        assert_eq!(&block[40..40 + comment.len()], comment.as_bytes());
+    }
+
+    #[test]
+    fn inbound_packets_are_described_from_the_remote() {
+        let comment = comment(&1, Direction::Inbound, direct());
+
+        assert_eq!(
+            comment,
+            format!("connection=1 outer=udp {REMOTE} -> {LOCAL}")
+        );
+    }
+
+    #[test]
+    fn relayed_packets_include_remote() {
+        let outer = Outer {
+            src: None,
+            dst: RELAY.parse().unwrap(),
+            relayed: Some(REMOTE.parse().unwrap()),
+        };
+
+        let comment = comment(&1, Direction::Outbound, outer);
+
+        assert_eq!(
+            comment,
+            format!("connection=1 outer=udp * -> {RELAY} relayed={REMOTE}")
+        );
+    }
+
+    #[test]
+    fn only_records_selected_connections() {
+        let mut capture = make_capture(CaptureFilter::Connections(HashSet::from([2])), 1024);
+        let now = capture.started_at;
+
+        capture.record(&1, Direction::Outbound, b"hello", direct(), now);
+
+        assert_eq!(capture.into_pcapng().len(), HEADER_LEN);
+    }
+
+    #[test]
+    fn stops_recording_after_deadline() {
+        let mut capture = make_capture(CaptureFilter::AllConnections, 1024);
+        let later = capture.started_at + Duration::from_secs(61);
+
+        capture.record(&1, Direction::Outbound, b"hello", direct(), later);
+
+        assert_eq!(capture.into_pcapng().len(), HEADER_LEN);
+    }
+
+    #[test]
+    fn stops_recording_once_out_of_bytes() {
+        let mut capture = make_capture(CaptureFilter::AllConnections, 100);
+        let now = capture.started_at;
+
+        capture.record(&1, Direction::Outbound, b"hello", direct(), now);
+        capture.record(&1, Direction::Outbound, b"hi", direct(), now);
+
+        assert_eq!(capture.into_pcapng().len(), HEADER_LEN);
+    }
+
+    fn direct() -> Outer {
+        Outer {
+            src: Some(LOCAL.parse().unwrap()),
+            dst: REMOTE.parse().unwrap(),
+            relayed: None,
+        }
+    }
+
+    fn make_capture(filter: CaptureFilter<u64>, max_bytes: usize) -> Capture<u64> {
+        Capture::new(
+            filter,
+            Duration::from_secs(60),
+            max_bytes,
+            Instant::now(),
+            SystemTime::UNIX_EPOCH,
+        )
+    }
+}
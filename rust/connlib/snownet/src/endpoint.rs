@@ -0,0 +1,20 @@
+use crate::Transmit;
+use std::{net::SocketAddr, time::Instant};
+
+/// Anything sans-IO that sends and receives UDP datagrams, e.g. a wrapper around a [`Node`](crate::Node) or a relay.
+///
+/// It decouples the state machine from its UDP transport: the same implementation can be driven by real sockets or an in-memory network.
+/// The transport also acts as the clock, passing `now` to every call.
+pub trait Endpoint {
+    /// Handles a datagram that arrived on our socket `local` from `from`.
+    fn handle_input(&mut self, local: SocketAddr, from: SocketAddr, packet: &[u8], now: Instant);
+
+    /// Returns the next datagram to send.
+    ///
+    /// Datagrams without a `src` are sent from the endpoint's default socket.
+    fn poll_transmit(&mut self) -> Option<Transmit<'static>>;
+
+    fn poll_timeout(&mut self) -> Option<Instant>;
+
+    fn handle_timeout(&mut self, now: Instant);
+}
@@ -2,18 +2,26 @@
 
 mod allocation;
 mod backoff;
+mod capture;
 mod channel_data;
+mod endpoint;
 mod index;
 mod ip_packet;
+mod nat_discovery;
 mod node;
+mod relay_selection;
 mod ringbuffer;
 mod stats;
 mod stun_binding;
 mod utils;
 
+pub use capture::CaptureFilter;
+pub use endpoint::Endpoint;
 pub use ip_packet::{IpPacket, MutableIpPacket};
 pub use node::{
-    Answer, Client, ClientNode, Credentials, Error, Event, Node, Offer, Server, ServerNode,
-    Transmit,
+    AddressFamilyPreference, Answer, Client, ClientNode, Credentials, Error, Event, Node, Offer,
+    Server, ServerNode, Transmit,
+};
+pub use stats::{
+    AddressFamily, ConnectionStats, NatBehaviour, NatType, NodeStats, PathType, RelayStats,
 };
-pub use stats::{ConnectionStats, NodeStats};
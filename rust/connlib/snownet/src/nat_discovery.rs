@@ -0,0 +1,680 @@
+use crate::node::{CandidateEvent, Transmit};
+use crate::stats::{AddressFamily, NatBehaviour, NatType};
+use bytecodec::{DecodeExt, EncodeExt};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    net::{IpAddr, SocketAddr},
+    time::{Duration, Instant},
+};
+use str0m::{net::Protocol, Candidate};
+use stun_codec::{rfc5389, Message, MessageClass, TransactionId};
+
+/// The `OTHER-ADDRESS` attribute, advertised by STUN servers that support RFC 5780.
+const OTHER_ADDRESS: u16 = 0x802C;
+/// The `CHANGE-REQUEST` attribute, see <https://www.rfc-editor.org/rfc/rfc5780#section-7.2>.
+const CHANGE_REQUEST: u16 = 0x0003;
+const CHANGE_IP: u32 = 0x04;
+const CHANGE_PORT: u32 = 0x02;
+
+/// How long we wait for a response to a filtering test before sending another one.
+const FILTERING_TEST_TIMEOUT: Duration = Duration::from_secs(1);
+/// How many unanswered requests we send before we consider a filtering test as failed.
+const FILTERING_TEST_ATTEMPTS: u32 = 3;
+
+/// The largest difference between consecutively allocated ports we still consider predictable.
+const MAX_PORT_DELTA: u16 = 10;
+/// How many server-reflexive candidates we predict for NATs with address-dependent mapping.
+const NUM_PREDICTED_CANDIDATES: u16 = 5;
+
+/// A SANS-IO state machine that classifies our NAT according to [RFC 5780](https://www.rfc-editor.org/rfc/rfc5780).
+///
+/// The mapping behaviour is derived from the server-reflexive addresses our STUN bindings observe.
+/// Observations are only compared if they were made for the same local socket and address family, e.g. our IPv6 traffic may not be NATed at all.
+/// If different sockets or address families behave differently, we report the most restrictive behaviour.
+/// If the NAT maps each destination to a new port but does so predictably, we generate candidates for the ports it will likely use next.
+///
+/// The filtering behaviour can only be tested against STUN servers that advertise an `OTHER-ADDRESS`, i.e. support RFC 5780.
+#[derive(Debug, Default)]
+pub struct NatDiscovery {
+    /// The server-reflexive address (and its base) observed by each STUN server.
+    observations: BTreeMap<SocketAddr, (SocketAddr, SocketAddr)>,
+    mapping: Option<NatBehaviour>,
+    filtering: FilteringTest,
+    predicted_candidates: Vec<Candidate>,
+
+    buffered_transmits: VecDeque<Transmit<'static>>,
+    events: VecDeque<CandidateEvent>,
+}
+
+impl NatDiscovery {
+    pub fn nat_type(&self) -> NatType {
+        NatType {
+            mapping: self.mapping,
+            filtering: match self.filtering {
+                FilteringTest::Done(filtering) => Some(filtering),
+                FilteringTest::Idle | FilteringTest::InProgress { .. } => None,
+            },
+        }
+    }
+
+    pub fn predicted_candidates(&self) -> impl Iterator<Item = &Candidate> + '_ {
+        self.predicted_candidates.iter()
+    }
+
+    /// Records the server-reflexive address observed by one of our STUN servers.
+    ///
+    /// `packet` is the binding response, which tells us whether the server supports RFC 5780.
+    pub fn handle_binding(
+        &mut self,
+        server: SocketAddr,
+        mapped: SocketAddr,
+        base: SocketAddr,
+        packet: &[u8],
+        now: Instant,
+    ) {
+        if self.observations.insert(server, (mapped, base)) != Some((mapped, base)) {
+            self.reclassify();
+        }
+
+        if matches!(self.filtering, FilteringTest::Idle) && has_attribute(packet, OTHER_ADDRESS) {
+            tracing::debug!(%server, "STUN server supports RFC 5780, testing NAT filtering");
+
+            self.send_filtering_test(server, Change::AddressAndPort, 1, now);
+        }
+    }
+
+    /// Handles a response to one of our filtering tests.
+    ///
+    /// These arrive from the other address of the STUN server, which is why we match them by transaction ID only.
+    pub fn handle_input(&mut self, packet: &[u8]) -> bool {
+        let FilteringTest::InProgress { id, change, .. } = self.filtering else {
+            return false;
+        };
+
+        let Ok(Ok(message)) =
+            stun_codec::MessageDecoder::<rfc5389::Attribute>::default().decode_from_bytes(packet)
+        else {
+            return false;
+        };
+
+        if message.transaction_id() != id || message.class() != MessageClass::SuccessResponse {
+            return false;
+        }
+
+        let filtering = match change {
+            Change::AddressAndPort => NatBehaviour::EndpointIndependent,
+            Change::Port => NatBehaviour::AddressDependent,
+        };
+
+        tracing::info!(?filtering, "Classified NAT filtering behaviour");
+
+        self.filtering = FilteringTest::Done(filtering);
+
+        true
+    }
+
+    pub fn handle_timeout(&mut self, now: Instant) {
+        let FilteringTest::InProgress {
+            server,
+            change,
+            at,
+            attempt,
+            ..
+        } = self.filtering
+        else {
+            return;
+        };
+
+        if at + FILTERING_TEST_TIMEOUT > now {
+            return;
+        }
+
+        if attempt < FILTERING_TEST_ATTEMPTS {
+            self.send_filtering_test(server, change, attempt + 1, now);
+            return;
+        }
+
+        match change {
+            Change::AddressAndPort => self.send_filtering_test(server, Change::Port, 1, now),
+            Change::Port => {
+                let filtering = NatBehaviour::AddressAndPortDependent;
+
+                tracing::info!(?filtering, "Classified NAT filtering behaviour");
+
+                self.filtering = FilteringTest::Done(filtering);
+            }
+        }
+    }
+
+    pub fn poll_timeout(&self) -> Option<Instant> {
+        match self.filtering {
+            FilteringTest::InProgress { at, .. } => Some(at + FILTERING_TEST_TIMEOUT),
+            FilteringTest::Idle | FilteringTest::Done(_) => None,
+        }
+    }
+
+    pub fn poll_transmit(&mut self) -> Option<Transmit<'static>> {
+        self.buffered_transmits.pop_front()
+    }
+
+    pub fn poll_event(&mut self) -> Option<CandidateEvent> {
+        self.events.pop_front()
+    }
+
+    /// Forgets everything we learned about our NAT, e.g. because our network changed.
+    ///
+    /// Predicted candidates are dropped without an event, the caller is expected to invalidate them.
+    pub fn reset(&mut self) {
+        self.observations.clear();
+        self.mapping = None;
+        self.filtering = FilteringTest::Idle;
+        self.predicted_candidates.clear();
+    }
+
+    fn reclassify(&mut self) {
+        let mut observations_by_family_and_base =
+            BTreeMap::<(AddressFamily, SocketAddr), Vec<(SocketAddr, SocketAddr)>>::new();
+
+        for (server, (mapped, base)) in &self.observations {
+            observations_by_family_and_base
+                .entry((AddressFamily::of(*server), *base))
+                .or_default()
+                .push((*server, *mapped));
+        }
+
+        let mut mapping = None;
+        let mut predicted = Vec::new();
+
+        for ((_, base), observations) in observations_by_family_and_base {
+            let group_mapping = classify_mapping(&observations);
+
+            match group_mapping {
+                Some(NatBehaviour::AddressDependent | NatBehaviour::AddressAndPortDependent) => {
+                    predicted.extend(predict_candidates(
+                        observations.iter().map(|(_, mapped)| (*mapped, base)),
+                    ));
+                }
+                Some(NatBehaviour::EndpointIndependent) | None => {}
+            }
+
+            mapping = mapping.max(group_mapping);
+        }
+
+        if mapping != self.mapping {
+            tracing::info!(?mapping, "Classified NAT mapping behaviour");
+            self.mapping = mapping;
+        }
+
+        for candidate in &self.predicted_candidates {
+            if !predicted.contains(candidate) {
+                self.events
+                    .push_back(CandidateEvent::Invalid(candidate.clone()));
+            }
+        }
+
+        for candidate in &predicted {
+            if !self.predicted_candidates.contains(candidate) {
+                tracing::debug!(%candidate, "Predicted server-reflexive candidate");
+
+                self.events
+                    .push_back(CandidateEvent::New(candidate.clone()));
+            }
+        }
+
+        self.predicted_candidates = predicted;
+    }
+
+    fn send_filtering_test(
+        &mut self,
+        server: SocketAddr,
+        change: Change,
+        attempt: u32,
+        now: Instant,
+    ) {
+        let request = Message::<rfc5389::Attribute>::new(
+            MessageClass::Request,
+            rfc5389::methods::BINDING,
+            TransactionId::new(rand::random()),
+        );
+        let id = request.transaction_id();
+
+        let flags = match change {
+            Change::AddressAndPort => CHANGE_IP | CHANGE_PORT,
+            Change::Port => CHANGE_PORT,
+        };
+
+        self.filtering = FilteringTest::InProgress {
+            server,
+            change,
+            id,
+            at: now,
+            attempt,
+        };
+        self.buffered_transmits.push_back(Transmit {
+            src: None,
+            dst: server,
+            payload: with_change_request(
+                stun_codec::MessageEncoder::<rfc5389::Attribute>::default()
+                    .encode_into_bytes(request)
+                    .expect("binding requests can always be encoded"),
+                flags,
+            )
+            .into(),
+            segment_size: None,
+        });
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+enum FilteringTest {
+    /// We don't know a STUN server that supports RFC 5780 (yet).
+    #[default]
+    Idle,
+    InProgress {
+        server: SocketAddr,
+        change: Change,
+        id: TransactionId,
+        at: Instant,
+        attempt: u32,
+    },
+    Done(NatBehaviour),
+}
+
+/// What we ask the STUN server to change about the address it responds from.
+///
+/// See tests II and III in <https://www.rfc-editor.org/rfc/rfc5780#section-4.4>.
+#[derive(Debug, Clone, Copy)]
+enum Change {
+    AddressAndPort,
+    Port,
+}
+
+/// Classifies the mapping behaviour, given the server-reflexive address each STUN server observed.
+///
+/// All observations are expected to originate from the same local socket and be of the same address family.
+/// Telling address-dependent from address and port-dependent mapping requires two STUN servers on the same IP.
+/// Without them, we conservatively assume the latter.
+fn classify_mapping(observations: &[(SocketAddr, SocketAddr)]) -> Option<NatBehaviour> {
+    let pairs = observations
+        .iter()
+        .enumerate()
+        .flat_map(|(i, a)| observations[i + 1..].iter().map(move |b| (a, b)));
+
+    let mut different_ip_pairs = pairs
+        .clone()
+        .filter(|((a, _), (b, _))| a.ip() != b.ip())
+        .peekable();
+
+    different_ip_pairs.peek()?;
+
+    if different_ip_pairs.all(|((_, a), (_, b))| a == b) {
+        return Some(NatBehaviour::EndpointIndependent);
+    }
+
+    let same_ip_pair = pairs
+        .filter(|((a, _), (b, _))| a.ip() == b.ip() && a.port() != b.port())
+        .map(|((_, a), (_, b))| a == b)
+        .next();
+
+    match same_ip_pair {
+        Some(true) => Some(NatBehaviour::AddressDependent),
+        Some(false) | None => Some(NatBehaviour::AddressAndPortDependent),
+    }
+}
+
+/// Predicts the next ports a NAT will map to, assuming it allocates them in constant steps.
+///
+/// Returns no candidates if the observed ports are not evenly spaced.
+fn predict_candidates(
+    observations: impl Iterator<Item = (SocketAddr, SocketAddr)>,
+) -> Vec<Candidate> {
+    let mut observations = observations.peekable();
+
+    let Some((first, base)) = observations.peek().copied() else {
+        return Vec::new();
+    };
+    let public_ip: IpAddr = first.ip();
+
+    let mut ports = observations
+        .filter(|(mapped, _)| mapped.ip() == public_ip)
+        .map(|(mapped, _)| mapped.port())
+        .collect::<Vec<_>>();
+    ports.sort_unstable();
+    ports.dedup();
+
+    let Some(delta) = ports.windows(2).map(|w| w[1] - w[0]).next() else {
+        return Vec::new();
+    };
+
+    if delta > MAX_PORT_DELTA || ports.windows(2).any(|w| w[1] - w[0] != delta) {
+        return Vec::new();
+    }
+
+    let last = *ports.last().expect("at least two ports");
+
+    (1..=NUM_PREDICTED_CANDIDATES)
+        .filter_map(|i| last.checked_add(delta * i))
+        .filter_map(|port| {
+            Candidate::server_reflexive(SocketAddr::new(public_ip, port), base, Protocol::Udp).ok()
+        })
+        .collect()
+}
+
+/// Appends a `CHANGE-REQUEST` attribute to an encoded STUN message.
+fn with_change_request(mut message: Vec<u8>, flags: u32) -> Vec<u8> {
+    message.extend_from_slice(&CHANGE_REQUEST.to_be_bytes());
+    message.extend_from_slice(&4u16.to_be_bytes());
+    message.extend_from_slice(&flags.to_be_bytes());
+
+    let length = (message.len() - 20) as u16;
+    message[2..4].copy_from_slice(&length.to_be_bytes());
+
+    message
+}
+
+/// Whether the encoded STUN message contains an attribute of the given type.
+fn has_attribute(packet: &[u8], attribute: u16) -> bool {
+    let mut rest = packet.get(20..).unwrap_or_default();
+
+    while let [t1, t2, l1, l2, tail @ ..] = rest {
+        if u16::from_be_bytes([*t1, *t2]) == attribute {
+            return true;
+        }
+
+        // Attributes are padded to a multiple of 4 bytes.
+        let length = (u16::from_be_bytes([*l1, *l2]) as usize + 3) & !3;
+
+        let Some(tail) = tail.get(length..) else {
+            return false;
+        };
+        rest = tail;
+    }
+
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr, SocketAddrV4, SocketAddrV6};
+    use stun_codec::rfc5389::attributes::XorMappedAddress;
+
+    const BASE: SocketAddr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(192, 168, 0, 2), 1000));
+    const OTHER_BASE: SocketAddr =
+        SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(192, 168, 0, 2), 2000));
+    const BASE_IP6: SocketAddr = SocketAddr::V6(SocketAddrV6::new(
+        Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 2),
+        1000,
+        0,
+        0,
+    ));
+    const SERVER1: SocketAddr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 3478));
+    const SERVER1_OTHER_PORT: SocketAddr =
+        SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 3479));
+    const SERVER2: SocketAddr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 2), 3478));
+    const SERVER3: SocketAddr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 3), 3478));
+    const SERVER1_IP6: SocketAddr = SocketAddr::V6(SocketAddrV6::new(
+        Ipv6Addr::new(0x2001, 0xdb8, 1, 0, 0, 0, 0, 1),
+        3478,
+        0,
+        0,
+    ));
+    const SERVER2_IP6: SocketAddr = SocketAddr::V6(SocketAddrV6::new(
+        Ipv6Addr::new(0x2001, 0xdb8, 1, 0, 0, 0, 0, 2),
+        3478,
+        0,
+        0,
+    ));
+
+    #[test]
+    fn same_mapping_for_all_servers_is_endpoint_independent() {
+        let mapping = classify_mapping(&[(SERVER1, public(5000)), (SERVER2, public(5000))]);
+
+        assert_eq!(mapping, Some(NatBehaviour::EndpointIndependent));
+    }
+
+    #[test]
+    fn single_server_cannot_classify_mapping() {
+        let mapping =
+            classify_mapping(&[(SERVER1, public(5000)), (SERVER1_OTHER_PORT, public(5001))]);
+
+        assert_eq!(mapping, None);
+    }
+
+    #[test]
+    fn same_mapping_for_same_ip_is_address_dependent() {
+        let mapping = classify_mapping(&[
+            (SERVER1, public(5000)),
+            (SERVER1_OTHER_PORT, public(5000)),
+            (SERVER2, public(5001)),
+        ]);
+
+        assert_eq!(mapping, Some(NatBehaviour::AddressDependent));
+    }
+
+    #[test]
+    fn different_mapping_per_server_is_address_and_port_dependent() {
+        let mapping = classify_mapping(&[(SERVER1, public(5000)), (SERVER2, public(5001))]);
+
+        assert_eq!(mapping, Some(NatBehaviour::AddressAndPortDependent));
+    }
+
+    #[test]
+    fn unnated_ipv6_does_not_make_endpoint_independent_ipv4_mapping_dependent() {
+        let mut discovery = NatDiscovery::default();
+        let now = Instant::now();
+
+        discovery.handle_binding(SERVER1, public(5000), BASE, &[], now);
+        discovery.handle_binding(SERVER2, public(5000), BASE, &[], now);
+        discovery.handle_binding(SERVER1_IP6, BASE_IP6, BASE_IP6, &[], now);
+        discovery.handle_binding(SERVER2_IP6, BASE_IP6, BASE_IP6, &[], now);
+
+        assert_eq!(
+            discovery.nat_type().mapping,
+            Some(NatBehaviour::EndpointIndependent)
+        );
+        assert_eq!(discovery.predicted_candidates().count(), 0);
+    }
+
+    #[test]
+    fn predicts_ports_of_sequential_ipv4_nat_on_dual_stack_host() {
+        let mut discovery = NatDiscovery::default();
+        let now = Instant::now();
+
+        discovery.handle_binding(SERVER1_IP6, BASE_IP6, BASE_IP6, &[], now);
+        discovery.handle_binding(SERVER1, public(5000), BASE, &[], now);
+        discovery.handle_binding(SERVER2_IP6, BASE_IP6, BASE_IP6, &[], now);
+        discovery.handle_binding(SERVER2, public(5002), BASE, &[], now);
+
+        let predicted = discovery.predicted_candidates().collect::<Vec<_>>();
+
+        assert_eq!(
+            discovery.nat_type().mapping,
+            Some(NatBehaviour::AddressAndPortDependent)
+        );
+        assert_eq!(predicted.len(), usize::from(NUM_PREDICTED_CANDIDATES));
+        assert!(predicted
+            .iter()
+            .all(|c| c.addr().is_ipv4() && c.base() == BASE));
+    }
+
+    #[test]
+    fn only_compares_observations_of_the_same_base() {
+        let mut discovery = NatDiscovery::default();
+        let now = Instant::now();
+
+        discovery.handle_binding(SERVER1, public(5000), BASE, &[], now);
+        discovery.handle_binding(SERVER2, public(5000), BASE, &[], now);
+        discovery.handle_binding(SERVER3, public(6000), OTHER_BASE, &[], now);
+        discovery.handle_binding(SERVER1_OTHER_PORT, public(6000), OTHER_BASE, &[], now);
+
+        assert_eq!(
+            discovery.nat_type().mapping,
+            Some(NatBehaviour::EndpointIndependent)
+        );
+    }
+
+    #[test]
+    fn predicts_ports_of_sequential_nat() {
+        let mut discovery = NatDiscovery::default();
+        let now = Instant::now();
+
+        discovery.handle_binding(SERVER1, public(5000), BASE, &[], now);
+        discovery.handle_binding(SERVER2, public(5002), BASE, &[], now);
+        discovery.handle_binding(SERVER3, public(5004), BASE, &[], now);
+
+        let predicted = discovery
+            .predicted_candidates()
+            .map(|c| c.addr().port())
+            .collect::<Vec<_>>();
+
+        assert_eq!(predicted, vec![5006, 5008, 5010, 5012, 5014]);
+        assert_eq!(
+            discovery.nat_type().mapping,
+            Some(NatBehaviour::AddressAndPortDependent)
+        );
+        assert!(matches!(
+            discovery.poll_event(),
+            Some(CandidateEvent::New(c)) if c.addr() == public(5006)
+        ));
+    }
+
+    #[test]
+    fn does_not_predict_random_ports() {
+        let mut discovery = NatDiscovery::default();
+        let now = Instant::now();
+
+        discovery.handle_binding(SERVER1, public(5000), BASE, &[], now);
+        discovery.handle_binding(SERVER2, public(5002), BASE, &[], now);
+        discovery.handle_binding(SERVER3, public(5042), BASE, &[], now);
+
+        assert_eq!(discovery.predicted_candidates().count(), 0);
+        assert!(discovery.poll_event().is_none());
+    }
+
+    #[test]
+    fn does_not_predict_for_endpoint_independent_mapping() {
+        let mut discovery = NatDiscovery::default();
+        let now = Instant::now();
+
+        discovery.handle_binding(SERVER1, public(5000), BASE, &[], now);
+        discovery.handle_binding(SERVER2, public(5000), BASE, &[], now);
+
+        assert_eq!(discovery.predicted_candidates().count(), 0);
+    }
+
+    #[test]
+    fn only_tests_filtering_with_rfc5780_servers() {
+        let mut discovery = NatDiscovery::default();
+        let now = Instant::now();
+
+        discovery.handle_binding(SERVER1, public(5000), BASE, &response(false), now);
+
+        assert!(discovery.poll_transmit().is_none());
+
+        discovery.handle_binding(SERVER2, public(5000), BASE, &response(true), now);
+
+        let request = discovery.poll_transmit().unwrap();
+        assert_eq!(request.dst, SERVER2);
+        assert!(has_attribute(&request.payload, CHANGE_REQUEST));
+    }
+
+    #[test]
+    fn response_from_changed_address_is_endpoint_independent_filtering() {
+        let mut discovery = NatDiscovery::default();
+        let now = Instant::now();
+
+        discovery.handle_binding(SERVER1, public(5000), BASE, &response(true), now);
+        let request = discovery.poll_transmit().unwrap();
+
+        assert!(discovery.handle_input(&response_to(&request.payload)));
+        assert_eq!(
+            discovery.nat_type().filtering,
+            Some(NatBehaviour::EndpointIndependent)
+        );
+    }
+
+    #[test]
+    fn response_from_changed_port_is_address_dependent_filtering() {
+        let mut now = Instant::now();
+        let mut discovery = NatDiscovery::default();
+
+        discovery.handle_binding(SERVER1, public(5000), BASE, &response(true), now);
+
+        for _ in 0..FILTERING_TEST_ATTEMPTS {
+            assert!(discovery.poll_transmit().is_some());
+            now += FILTERING_TEST_TIMEOUT;
+            discovery.handle_timeout(now);
+        }
+
+        let request = discovery.poll_transmit().unwrap();
+
+        assert!(discovery.handle_input(&response_to(&request.payload)));
+        assert_eq!(
+            discovery.nat_type().filtering,
+            Some(NatBehaviour::AddressDependent)
+        );
+    }
+
+    #[test]
+    fn no_response_is_address_and_port_dependent_filtering() {
+        let mut now = Instant::now();
+        let mut discovery = NatDiscovery::default();
+
+        discovery.handle_binding(SERVER1, public(5000), BASE, &response(true), now);
+
+        for _ in 0..FILTERING_TEST_ATTEMPTS * 2 {
+            now += FILTERING_TEST_TIMEOUT;
+            discovery.handle_timeout(now);
+        }
+
+        assert_eq!(
+            discovery.nat_type().filtering,
+            Some(NatBehaviour::AddressAndPortDependent)
+        );
+        assert_eq!(discovery.poll_timeout(), None);
+    }
+
+    fn public(port: u16) -> SocketAddr {
+        SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(203, 0, 113, 1), port))
+    }
+
+    /// A binding response, optionally with an `OTHER-ADDRESS` attribute.
+    fn response(other_address: bool) -> Vec<u8> {
+        let mut message = Message::<rfc5389::Attribute>::new(
+            MessageClass::SuccessResponse,
+            rfc5389::methods::BINDING,
+            TransactionId::new([0; 12]),
+        );
+        message.add_attribute(rfc5389::Attribute::XorMappedAddress(XorMappedAddress::new(
+            public(5000),
+        )));
+        let mut message = stun_codec::MessageEncoder::<rfc5389::Attribute>::default()
+            .encode_into_bytes(message)
+            .unwrap();
+
+        if other_address {
+            message.extend_from_slice(&OTHER_ADDRESS.to_be_bytes());
+            message.extend_from_slice(&8u16.to_be_bytes());
+            message.extend_from_slice(&[0, 1, 0x0d, 0x96, 10, 0, 0, 101]);
+
+            let length = (message.len() - 20) as u16;
+            message[2..4].copy_from_slice(&length.to_be_bytes());
+        }
+
+        message
+    }
+
+    /// Our requests carry a `CHANGE-REQUEST` which `stun_codec` doesn't understand, so we read the transaction ID directly.
+    fn response_to(request: &[u8]) -> Vec<u8> {
+        let transaction_id = TransactionId::new(request[8..20].try_into().unwrap());
+
+        stun_codec::MessageEncoder::<rfc5389::Attribute>::default()
+            .encode_into_bytes(Message::<rfc5389::Attribute>::new(
+                MessageClass::SuccessResponse,
+                rfc5389::methods::BINDING,
+                transaction_id,
+            ))
+            .unwrap()
+    }
+}
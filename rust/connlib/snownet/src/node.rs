@@ -9,7 +9,7 @@ use rand::random;
 use secrecy::{ExposeSecret, Secret};
 use std::hash::Hash;
 use std::marker::PhantomData;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime};
 use std::{
     collections::{HashMap, HashSet, VecDeque},
     net::SocketAddr,
@@ -20,20 +20,28 @@ use str0m::net::Protocol;
 use str0m::{Candidate, CandidateKind, IceConnectionState};
 
 use crate::allocation::{Allocation, Socket};
+use crate::capture::{Capture, CaptureFilter, Direction, Outer};
 use crate::index::IndexLfsr;
-use crate::stats::{ConnectionStats, NodeStats};
+use crate::nat_discovery::NatDiscovery;
+use crate::relay_selection::{self, RelayProbe};
+use crate::stats::{AddressFamily, ConnectionStats, NodeStats, PathType, RelayStats};
 use crate::stun_binding::StunBinding;
 use crate::utils::earliest;
 use crate::{IpPacket, MutableIpPacket};
 use boringtun::noise::errors::WireGuardError;
 use std::borrow::Cow;
-use std::ops::ControlFlow;
+use std::ops::{ControlFlow, Range};
 use stun_codec::rfc5389::attributes::{Realm, Username};
 use tracing::{field, info_span, Span};
 
 // Note: Taken from boringtun
 const HANDSHAKE_RATE_LIMIT: u64 = 100;
 
+/// We set a Wireguard keep-alive to ensure the WG session doesn't timeout on an idle connection.
+///
+/// Without such a timeout, using a tunnel after the REKEY_TIMEOUT requires handshaking a new session which delays the new application packet by 1 RTT.
+const WG_KEEP_ALIVE: Option<u16> = Some(10);
+
 /// How long we will at most wait for a candidate from the remote.
 const CANDIDATE_TIMEOUT: Duration = Duration::from_secs(10);
 
@@ -42,6 +50,40 @@ const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(20);
 
 const MAX_UDP_SIZE: usize = (1 << 16) - 1;
 
+/// The maximum number of datagrams the Linux kernel accepts in a single GSO send, see `UDP_MAX_SEGMENTS`.
+const MAX_GSO_SEGMENTS: usize = 64;
+
+/// How much a wireguard data message adds to an IP packet (message header and authentication tag).
+const WG_DATA_OVERHEAD: usize = 32;
+
+/// The size of a wireguard handshake initiation as per `HANDSHAKE_INIT_SZ` constant in [`boringtun`].
+///
+/// [`boringtun`] sends one instead of the packet if it needs a new session.
+/// This is also the largest packet it may construct when we aren't encapsulating data, e.g. when updating timers or sending keepalives.
+const WG_HANDSHAKE_INIT_SIZE: usize = 148;
+
+/// The size of a channel-data header, prepended to everything we send via a relay.
+const CHANNEL_DATA_HEADER_SIZE: usize = 4;
+
+/// How often we re-evaluate which relays to allocate on, see [`Node::set_max_relays_per_family`].
+const RELAY_SELECTION_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// How long a connection may take to move to a new path after our network changed.
+const MIGRATION_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long we wait for the response to an ICE binding request before we no longer consider it for RTT measurements.
+const ICE_RTT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long the remote has to complete a handshake with a new preshared key before we abandon the rotation, see [`Node::rotate_preshared_key`].
+///
+/// Matches wireguard's `REKEY_ATTEMPT_TIME`.
+const KEY_ROTATION_TIMEOUT: Duration = Duration::from_secs(90);
+
+/// How long we keep decrypting packets of the previous session after rotating the preshared key.
+///
+/// The remote may still send on the previous session until it completed the handshake itself and packets might be in-flight.
+const PREVIOUS_TUNNEL_GRACE_PERIOD: Duration = Duration::from_secs(10);
+
 /// Manages a set of wireguard connections for a server.
 pub type ServerNode<TId> = Node<Server, TId>;
 /// Manages a set of wireguard connections for a client.
@@ -82,18 +124,64 @@ pub struct Node<T, TId> {
     next_rate_limiter_reset: Option<Instant>,
 
     bindings: HashMap<SocketAddr, StunBinding>,
+    nat_discovery: NatDiscovery,
     allocations: HashMap<SocketAddr, Allocation>,
+    turn_servers: HashMap<SocketAddr, TurnServer>,
+
+    max_relays_per_family: Option<usize>,
+    next_relay_selection: Option<Instant>,
 
     connections: Connections<TId>,
     pending_events: VecDeque<Event<TId>>,
 
     buffer: Box<[u8; MAX_UDP_SIZE]>,
+    /// Holds the datagrams of [`Node::encapsulate_batch`].
+    batch_buffer: Vec<u8>,
 
     stats: NodeStats,
 
+    capture: Option<Capture<TId>>,
+
+    family_preference: AddressFamilyPreference,
+
     marker: PhantomData<T>,
 }
 
+/// Which address family new connections should try first.
+///
+/// Similar to [happy eyeballs](https://www.rfc-editor.org/rfc/rfc8305), local candidates of the other address family are held back for a head start.
+/// Once it is over, candidate pairs of both address families race each other and the first one to succeed wins.
+/// The head start is counted from when the signalling of the connection completed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AddressFamilyPreference {
+    /// Use candidates of both address families right away.
+    #[default]
+    Any,
+    /// Give IPv4 candidate pairs a head start over IPv6 ones.
+    Ipv4First { head_start: Duration },
+    /// Give IPv6 candidate pairs a head start over IPv4 ones.
+    Ipv6First { head_start: Duration },
+}
+
+impl AddressFamilyPreference {
+    /// Whether a local candidate on the given address is initially held back.
+    fn is_held_back(&self, addr: SocketAddr) -> bool {
+        match self {
+            AddressFamilyPreference::Any => false,
+            AddressFamilyPreference::Ipv4First { .. } => addr.is_ipv6(),
+            AddressFamilyPreference::Ipv6First { .. } => addr.is_ipv4(),
+        }
+    }
+
+    fn head_start(&self) -> Option<Duration> {
+        match self {
+            AddressFamilyPreference::Any => None,
+            AddressFamilyPreference::Ipv4First { head_start }
+            | AddressFamilyPreference::Ipv6First { head_start } => Some(*head_start),
+        }
+    }
+}
+
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
     #[error("Unknown interface")]
@@ -128,14 +216,69 @@ where
             next_rate_limiter_reset: None,
             pending_events: VecDeque::default(),
             buffer: Box::new([0u8; MAX_UDP_SIZE]),
+            batch_buffer: Vec::default(),
             bindings: HashMap::default(),
+            nat_discovery: NatDiscovery::default(),
             allocations: HashMap::default(),
+            turn_servers: HashMap::default(),
+            max_relays_per_family: None,
+            next_relay_selection: None,
             connections: Default::default(),
             stats: Default::default(),
+            capture: None,
+            family_preference: Default::default(),
+        }
+    }
+
+    /// Sets which address family new connections should try first.
+    ///
+    /// Existing connections are not affected.
+    pub fn set_address_family_preference(&mut self, preference: AddressFamilyPreference) {
+        self.family_preference = preference;
+    }
+
+    /// Only allocate on the `max` relays with the lowest RTT per address family.
+    ///
+    /// By default, we allocate on every TURN server passed to us.
+    /// With a limit, we first measure the RTT to each TURN server via STUN binding requests and allocate on the fastest ones as soon as they respond.
+    /// Every few minutes, we re-evaluate the ranking and move to faster relays, unless a connection is still using the current one.
+    /// Allocations on relays we move away from are deleted.
+    pub fn set_max_relays_per_family(&mut self, max: usize, now: Instant) {
+        self.max_relays_per_family = Some(max);
+
+        for (server, turn_server) in self.turn_servers.iter_mut() {
+            turn_server
+                .probe
+                .get_or_insert_with(|| RelayProbe::new(*server, now));
         }
     }
 
+    /// Migrates all connections to a new network, e.g. after switching from Wi-Fi to cellular.
+    ///
+    /// All local candidates are considered stale: we invalidate them, refresh our STUN bindings and TURN allocations and signal the resulting candidates to the remotes.
+    /// Established connections keep their wireguard session and keep sending on their current path until ICE nominates a new one.
+    /// Only if that doesn't happen within 30 seconds, the connection fails.
     pub fn reconnect(&mut self, now: Instant) {
+        let stale_candidates = self
+            .host_candidates
+            .drain()
+            .chain(self.bindings.values().filter_map(|b| b.candidate()))
+            .chain(self.nat_discovery.predicted_candidates().cloned())
+            .collect::<Vec<_>>();
+
+        for candidate in stale_candidates {
+            self.invalidate_local_candidate(&candidate);
+        }
+
+        // We might be behind a different NAT now.
+        self.nat_discovery.reset();
+
+        for (id, connection) in self.connections.iter_established_mut() {
+            let _span = info_span!("connection", %id).entered();
+
+            connection.start_migration(now);
+        }
+
         for binding in self.bindings.values_mut() {
             binding.refresh(now);
         }
@@ -143,6 +286,45 @@ where
         for allocation in self.allocations.values_mut() {
             allocation.refresh(now);
         }
+
+        for probe in self
+            .turn_servers
+            .values_mut()
+            .filter_map(|t| t.probe.as_mut())
+        {
+            probe.refresh(now);
+        }
+
+        // Our network changed and with it, which relays are the fastest.
+        if self.max_relays_per_family.is_some() {
+            self.next_relay_selection = Some(now);
+        }
+    }
+
+    /// Starts capturing the plaintext IP packets of the selected connections, replacing any previous capture.
+    ///
+    /// Each packet is annotated with its connection and the UDP datagram it travelled in on the wire.
+    /// This allows correlating captures of both ends of a connection, as well as with a capture of the encrypted traffic.
+    /// The capture stops recording after `duration` or once it reaches `max_bytes`, whatever comes first.
+    /// Packets are timestamped relative to `now_unix`, which needs to be wall-clock time to be comparable with captures of other nodes.
+    pub fn start_capture(
+        &mut self,
+        filter: CaptureFilter<TId>,
+        duration: Duration,
+        max_bytes: usize,
+        now: Instant,
+        now_unix: SystemTime,
+    ) {
+        tracing::info!(?duration, %max_bytes, "Starting capture");
+
+        self.capture = Some(Capture::new(filter, duration, max_bytes, now, now_unix));
+    }
+
+    /// Stops the current capture, returning everything recorded as a pcapng file.
+    pub fn stop_capture(&mut self) -> Option<Vec<u8>> {
+        let capture = self.capture.take()?;
+
+        Some(capture.into_pcapng())
     }
 
     pub fn public_key(&self) -> PublicKey {
@@ -156,7 +338,39 @@ where
     }
 
     pub fn stats(&self) -> (NodeStats, impl Iterator<Item = (TId, ConnectionStats)> + '_) {
-        (self.stats, self.connections.stats())
+        let mut stats = self.stats.clone();
+
+        let ranking = relay_selection::rank(self.probes());
+        let mut relays = self
+            .turn_servers
+            .iter()
+            .map(|(server, turn_server)| RelayStats {
+                server: *server,
+                rtt: turn_server.probe.as_ref().and_then(RelayProbe::rtt),
+                allocated: self.allocations.contains_key(server),
+            })
+            .collect::<Vec<_>>();
+        relays.sort_by_key(|r| {
+            let family = AddressFamily::of(r.server);
+            let rank = ranking
+                .get(&family)
+                .and_then(|ranked| ranked.iter().position(|s| s == &r.server))
+                .unwrap_or(usize::MAX);
+
+            (r.server.is_ipv6(), rank, r.server)
+        });
+        stats.relays = relays;
+        stats.nat_type = self.nat_discovery.nat_type();
+
+        for (_, connection) in self.connections.iter_established() {
+            match connection.won_by {
+                Some(AddressFamily::V4) => stats.connections_won_by_ipv4 += 1,
+                Some(AddressFamily::V6) => stats.connections_won_by_ipv6 += 1,
+                None => {}
+            }
+        }
+
+        (stats, self.connections.stats())
     }
 
     /// Add an address as a `host` candidate.
@@ -257,6 +471,18 @@ where
     ) -> Result<Option<(TId, MutableIpPacket<'s>)>, Error> {
         self.add_local_as_host_candidate(local)?;
 
+        let outer_from = from;
+
+        match self.probes_try_handle(from, packet, now) {
+            ControlFlow::Continue(()) => {}
+            ControlFlow::Break(()) => return Ok(None),
+        }
+
+        match self.nat_discovery_try_handle(packet) {
+            ControlFlow::Continue(()) => {}
+            ControlFlow::Break(()) => return Ok(None),
+        }
+
         match self.bindings_try_handle(from, local, packet, now) {
             ControlFlow::Continue(()) => {}
             ControlFlow::Break(()) => return Ok(None),
@@ -283,9 +509,52 @@ where
                 ControlFlow::Break(Err(e)) => return Err(e),
             };
 
+        if let Some(capture) = self.capture.as_mut() {
+            let outer = Outer {
+                src: Some(local),
+                dst: outer_from,
+                relayed: relayed.map(|_| from),
+            };
+
+            capture.record(&id, Direction::Inbound, packet.packet(), outer, now);
+        }
+
         Ok(Some((id, packet)))
     }
 
+    /// Decapsulate several incoming packets at once, e.g. all datagrams of a GRO batch.
+    ///
+    /// A decrypted packet is never larger than the encrypted one, hence they are written back-to-back into `buffer` which needs to be as large as all `packets` combined.
+    /// Packets that are handled internally (see [`Node::decapsulate`]) don't yield anything, packets that fail to decapsulate are skipped.
+    pub fn decapsulate_batch<'s, 'p>(
+        &mut self,
+        packets: impl IntoIterator<Item = (SocketAddr, SocketAddr, &'p [u8])>,
+        now: Instant,
+        mut buffer: &'s mut [u8],
+    ) -> Vec<(TId, MutableIpPacket<'s>)> {
+        let mut decapsulated = Vec::new();
+
+        for (local, from, packet) in packets {
+            if buffer.len() < packet.len() {
+                tracing::warn!("Buffer is full, dropping remaining packets");
+                break;
+            }
+
+            let (packet_buffer, rest) = std::mem::take(&mut buffer).split_at_mut(packet.len());
+            buffer = rest;
+
+            match self.decapsulate(local, from, packet, now, packet_buffer) {
+                Ok(Some(decapsulated_packet)) => decapsulated.push(decapsulated_packet),
+                Ok(None) => {}
+                Err(e) => {
+                    tracing::warn!(%local, %from, num_bytes = %packet.len(), "Failed to decapsulate incoming packet: {e}")
+                }
+            }
+        }
+
+        decapsulated
+    }
+
     /// Encapsulate an outgoing IP packet.
     ///
     /// Wireguard is an IP tunnel, so we "enforce" that only IP packets are sent through it.
@@ -306,6 +575,16 @@ where
         // Must bail early if we don't have a socket yet to avoid running into WG timeouts.
         let socket = conn.peer_socket.ok_or(Error::NotConnected)?;
 
+        if let Some(capture) = self.capture.as_mut() {
+            capture.record(
+                &connection,
+                Direction::Outbound,
+                packet.packet(),
+                socket.outer(),
+                now,
+            );
+        }
+
         let (header, payload) = self.buffer.as_mut().split_at_mut(4);
 
         let Some(packet) = conn.encapsulate(packet.packet(), payload)? else {
@@ -320,6 +599,7 @@ where
                 src: Some(source),
                 dst: remote,
                 payload: Cow::Borrowed(packet),
+                segment_size: None,
             })),
             PeerSocket::Relay { relay, dest: peer } => {
                 let Some(allocation) = self.allocations.get_mut(&relay) else {
@@ -341,11 +621,125 @@ where
                     src: None,
                     dst: relay,
                     payload: Cow::Borrowed(channel_data_packet),
+                    segment_size: None,
                 }))
             }
         }
     }
 
+    /// Encapsulate several outgoing IP packets of the same connection at once.
+    ///
+    /// Consecutive packets that encrypt to datagrams of the same size are coalesced into a single [`Transmit`] with [`Transmit::segment_size`] set, ready to be sent with UDP GSO.
+    /// For bulk transfers, that is typically all of them, i.e. we return a single [`Transmit`].
+    /// A smaller datagram ends a [`Transmit`], as does reaching the limits of GSO.
+    ///
+    /// Packets that fail to encapsulate are skipped.
+    #[tracing::instrument(level = "debug", skip_all, fields(id = %connection))]
+    pub fn encapsulate_batch<'s, 'p>(
+        &'s mut self,
+        connection: TId,
+        packets: impl IntoIterator<Item = IpPacket<'p>>,
+        now: Instant,
+    ) -> Result<Vec<Transmit<'s>>, Error> {
+        let conn = self
+            .connections
+            .get_established_mut(&connection)
+            .ok_or(Error::NotConnected)?;
+
+        // Must bail early if we don't have a socket yet to avoid running into WG timeouts.
+        let socket = conn.peer_socket.ok_or(Error::NotConnected)?;
+
+        self.batch_buffer.clear();
+        let mut segments = Vec::new();
+
+        for packet in packets {
+            if let Some(capture) = self.capture.as_mut() {
+                capture.record(
+                    &connection,
+                    Direction::Outbound,
+                    packet.packet(),
+                    socket.outer(),
+                    now,
+                );
+            }
+
+            let start = self.batch_buffer.len();
+            let max_len = CHANNEL_DATA_HEADER_SIZE
+                + (packet.packet().len() + WG_DATA_OVERHEAD).max(WG_HANDSHAKE_INIT_SIZE);
+            self.batch_buffer.resize(start + max_len, 0);
+
+            let len = match socket {
+                PeerSocket::Direct { .. } => {
+                    // Direct datagrams don't need a header, encapsulate right at the start of the segment.
+                    match conn.encapsulate(packet.packet(), &mut self.batch_buffer[start..]) {
+                        Ok(Some(encrypted)) => Some(encrypted.len()),
+                        Ok(None) => None,
+                        Err(e) => {
+                            tracing::warn!("Failed to encapsulate packet: {e}");
+                            None
+                        }
+                    }
+                }
+                PeerSocket::Relay { relay, dest: peer } => {
+                    let (header, payload) =
+                        self.batch_buffer[start..].split_at_mut(CHANNEL_DATA_HEADER_SIZE);
+
+                    let encrypted = match conn.encapsulate(packet.packet(), payload) {
+                        Ok(Some(encrypted)) => encrypted,
+                        Ok(None) => {
+                            self.batch_buffer.truncate(start);
+                            continue;
+                        }
+                        Err(e) => {
+                            tracing::warn!("Failed to encapsulate packet: {e}");
+                            self.batch_buffer.truncate(start);
+                            continue;
+                        }
+                    };
+
+                    let Some(allocation) = self.allocations.get_mut(&relay) else {
+                        tracing::warn!(%relay, "No allocation");
+                        self.batch_buffer.truncate(start);
+                        continue;
+                    };
+
+                    let total_length = allocation.encode_to_slice(peer, encrypted, header, now);
+
+                    if total_length.is_none() {
+                        tracing::warn!(%peer, "No channel");
+                    }
+
+                    total_length
+                }
+            };
+
+            match len {
+                Some(len) => {
+                    self.batch_buffer.truncate(start + len);
+                    segments.push(len);
+                }
+                None => self.batch_buffer.truncate(start),
+            }
+        }
+
+        let (src, dst) = match socket {
+            PeerSocket::Direct { source, dest } => (Some(source), dest),
+            PeerSocket::Relay { relay, .. } => (None, relay),
+        };
+
+        let transmits = coalesce_segments(&segments)
+            .into_iter()
+            .map(|(range, segment_size)| Transmit {
+                src,
+                dst,
+                payload: Cow::Borrowed(&self.batch_buffer[range]),
+                segment_size,
+            })
+            .collect();
+
+        Ok(transmits)
+    }
+
     /// Returns a pending [`Event`] from the pool.
     #[must_use]
     pub fn poll_event(&mut self) -> Option<Event<TId>> {
@@ -366,9 +760,14 @@ where
         for b in self.bindings.values_mut() {
             connection_timeout = earliest(connection_timeout, b.poll_timeout());
         }
+        connection_timeout = earliest(connection_timeout, self.nat_discovery.poll_timeout());
         for a in self.allocations.values_mut() {
             connection_timeout = earliest(connection_timeout, a.poll_timeout());
         }
+        for probe in self.turn_servers.values().filter_map(|t| t.probe.as_ref()) {
+            connection_timeout = earliest(connection_timeout, probe.poll_timeout());
+        }
+        connection_timeout = earliest(connection_timeout, self.next_relay_selection);
 
         earliest(connection_timeout, self.next_rate_limiter_reset)
     }
@@ -393,7 +792,13 @@ where
         self.bindings_and_allocations_drain_events();
 
         for (id, connection) in self.connections.iter_established_mut() {
-            connection.handle_timeout(id, now, &mut self.allocations, &mut self.buffered_transmits);
+            connection.handle_timeout(
+                id,
+                now,
+                &mut self.allocations,
+                &mut self.buffered_transmits,
+                &mut self.pending_events,
+            );
         }
 
         for (id, connection) in self.connections.initial.iter_mut() {
@@ -404,6 +809,18 @@ where
             binding.handle_timeout(now);
         }
 
+        self.nat_discovery.handle_timeout(now);
+
+        for probe in self
+            .turn_servers
+            .values_mut()
+            .filter_map(|t| t.probe.as_mut())
+        {
+            probe.handle_timeout(now);
+        }
+
+        self.select_relays(now);
+
         for allocation in self.allocations.values_mut() {
             allocation.handle_timeout(now);
         }
@@ -429,6 +846,12 @@ where
             }
         }
 
+        if let Some(transmit) = self.nat_discovery.poll_transmit() {
+            self.stats.stun_bytes_to_relays += transmit.payload.len();
+
+            return Some(transmit);
+        }
+
         for allocation in self.allocations.values_mut() {
             if let Some(transmit) = allocation.poll_transmit() {
                 self.stats.stun_bytes_to_relays += transmit.payload.len();
@@ -437,6 +860,18 @@ where
             }
         }
 
+        for probe in self
+            .turn_servers
+            .values_mut()
+            .filter_map(|t| t.probe.as_mut())
+        {
+            if let Some(transmit) = probe.poll_transmit() {
+                self.stats.stun_bytes_to_relays += transmit.payload.len();
+
+                return Some(transmit);
+            }
+        }
+
         self.buffered_transmits.pop_front()
     }
 
@@ -444,39 +879,55 @@ where
     #[allow(clippy::too_many_arguments)]
     fn init_connection(
         &mut self,
-        mut agent: IceAgent,
+        id: TId,
+        agent: IceAgent,
         remote: PublicKey,
         key: [u8; 32],
         intent_sent_at: Instant,
         now: Instant,
     ) -> Connection {
-        agent.handle_timeout(now);
-
-        /// We set a Wireguard keep-alive to ensure the WG session doesn't timeout on an idle connection.
-        ///
-        /// Without such a timeout, using a tunnel after the REKEY_TIMEOUT requires handshaking a new session which delays the new application packet by 1 RTT.
-        const WG_KEEP_ALIVE: Option<u16> = Some(10);
+        let tunnel_index = self.index.next();
 
-        Connection {
+        let mut connection = Connection {
             agent,
             tunnel: Tunn::new(
                 self.private_key.clone(),
                 remote,
                 Some(key),
                 WG_KEEP_ALIVE,
-                self.index.next(),
+                tunnel_index,
                 Some(self.rate_limiter.clone()),
             ),
+            tunnel_index,
+            remote_tunnel_index: None,
+            next_tunnel: None,
+            previous_tunnel: None,
             next_timer_update: now,
             peer_socket: None,
             possible_sockets: Default::default(),
             stats: Default::default(),
+            pending_binding_requests: Default::default(),
+            ice_rtt: None,
             buffer: Box::new([0u8; MAX_UDP_SIZE]),
             intent_sent_at,
             is_failed: false,
             signalling_completed_at: now,
             remote_pub_key: remote,
-        }
+            held_back_candidates: self.family_preference.head_start().map(|head_start| {
+                HeldBackCandidates {
+                    preference: self.family_preference,
+                    until: now + head_start,
+                    candidates: Vec::new(),
+                }
+            }),
+            won_by: None,
+            migration_deadline: None,
+        };
+
+        self.seed_connection_with_local_candidates(id, &mut connection);
+        connection.agent.handle_timeout(now);
+
+        connection
     }
 
     /// Attempt to add the `local` address as a host candidate.
@@ -492,13 +943,138 @@ where
             return Ok(());
         }
 
-        for (id, agent) in self.connections.agents_mut() {
-            let _span = info_span!("connection", %id).entered();
+        add_local_candidate_to_all(
+            host_candidate,
+            &mut self.connections,
+            self.family_preference,
+            &mut self.pending_events,
+        );
+
+        Ok(())
+    }
+
+    /// Tries to handle the packet as a response to one of our relay probes.
+    #[must_use]
+    fn probes_try_handle(
+        &mut self,
+        from: SocketAddr,
+        packet: &[u8],
+        now: Instant,
+    ) -> ControlFlow<()> {
+        let Some(probe) = self
+            .turn_servers
+            .get_mut(&from)
+            .and_then(|t| t.probe.as_mut())
+        else {
+            return ControlFlow::Continue(());
+        };
 
-            add_local_candidate(id, agent, host_candidate.clone(), &mut self.pending_events);
+        // Only STUN messages can be responses to our probes, see <https://www.rfc-editor.org/rfc/rfc8656#name-channels-2>.
+        if !matches!(packet.first(), Some(0..=3)) {
+            return ControlFlow::Continue(());
         }
 
-        Ok(())
+        if probe.handle_input(from, packet, now) {
+            return ControlFlow::Break(());
+        }
+
+        // The relay may also be one of our STUN servers or hold one of our allocations, let those handle it.
+        ControlFlow::Continue(())
+    }
+
+    fn probes(&self) -> impl Iterator<Item = (SocketAddr, &RelayProbe)> {
+        self.turn_servers
+            .iter()
+            .filter_map(|(server, t)| Some((*server, t.probe.as_ref()?)))
+    }
+
+    /// Allocates on the fastest relays of each address family, see [`Node::set_max_relays_per_family`].
+    ///
+    /// In between re-evaluations, we only allocate on relays until we reach the limit.
+    /// Upon re-evaluation, we additionally delete allocations on relays that are no longer among the fastest and not used by any connection.
+    fn select_relays(&mut self, now: Instant) {
+        let Some(max) = self.max_relays_per_family else {
+            return;
+        };
+
+        let next_relay_selection = *self
+            .next_relay_selection
+            .get_or_insert(now + RELAY_SELECTION_INTERVAL);
+        let reevaluate = now >= next_relay_selection;
+
+        for (family, ranked) in relay_selection::rank(self.probes()) {
+            let fastest = &ranked[..ranked.len().min(max)];
+
+            for server in fastest {
+                let num_allocations = self
+                    .allocations
+                    .keys()
+                    .filter(|s| AddressFamily::of(**s) == family)
+                    .count();
+
+                if self.allocations.contains_key(server) || (num_allocations >= max && !reevaluate)
+                {
+                    continue;
+                }
+
+                let Some(turn_server) = self.turn_servers.get(server) else {
+                    continue;
+                };
+
+                tracing::info!(relay = %server, rtt = ?turn_server.probe.as_ref().and_then(RelayProbe::rtt), "Allocating on relay");
+
+                self.allocations
+                    .insert(*server, turn_server.allocate(*server, now));
+            }
+
+            if !reevaluate {
+                continue;
+            }
+
+            let unused = self
+                .allocations
+                .keys()
+                .filter(|s| AddressFamily::of(**s) == family && !fastest.contains(*s))
+                .filter(|s| {
+                    !self
+                        .connections
+                        .iter_established()
+                        .any(|(_, c)| c.uses_relay(**s))
+                })
+                .copied()
+                .collect::<Vec<_>>();
+
+            for server in unused {
+                let Some(allocation) = self.allocations.remove(&server) else {
+                    continue;
+                };
+
+                tracing::info!(relay = %server, "Deleting allocation on relay that is no longer among the fastest");
+
+                for candidate in allocation.current_candidates() {
+                    self.invalidate_local_candidate(&candidate);
+                }
+
+                // Free the allocation on the relay right away instead of letting it linger until it expires.
+                if let Some(transmit) = allocation.deallocate() {
+                    self.buffered_transmits.push_back(transmit);
+                }
+            }
+        }
+
+        if reevaluate {
+            self.next_relay_selection = Some(now + RELAY_SELECTION_INTERVAL);
+        }
+    }
+
+    fn invalidate_local_candidate(&mut self, candidate: &Candidate) {
+        for (id, agent) in self.connections.agents_mut() {
+            let _span = info_span!("connection", %id).entered();
+            agent.invalidate_candidate(candidate);
+        }
+        for (_, connection) in self.connections.iter_established_mut() {
+            connection.discard_held_back_candidate(candidate);
+        }
     }
 
     #[must_use]
@@ -517,11 +1093,33 @@ where
 
         if !handled {
             tracing::debug!("Packet was a STUN message but not accepted");
+            return ControlFlow::Break(());
+        }
+
+        if let Some(candidate) = binding.candidate() {
+            self.nat_discovery
+                .handle_binding(from, candidate.addr(), local, packet, now);
         }
 
         ControlFlow::Break(())
     }
 
+    /// Tries to handle the packet as a response to one of our NAT filtering tests.
+    ///
+    /// Those come from an address of the STUN server that we don't know about, so we need to check them before all others.
+    #[must_use]
+    fn nat_discovery_try_handle(&mut self, packet: &[u8]) -> ControlFlow<()> {
+        if !matches!(packet.first(), Some(0..=3)) {
+            return ControlFlow::Continue(());
+        }
+
+        if self.nat_discovery.handle_input(packet) {
+            return ControlFlow::Break(());
+        }
+
+        ControlFlow::Continue(())
+    }
+
     /// Tries to handle the packet using one of our [`Allocation`]s.
     #[must_use]
     fn allocations_try_handle<'p>(
@@ -572,6 +1170,14 @@ where
             return ControlFlow::Continue(());
         };
 
+        if let Some(transaction_id) = binding_success_response_id(packet) {
+            for (_, conn) in self.connections.iter_established_mut() {
+                if conn.handle_binding_response(transaction_id, now) {
+                    break;
+                }
+            }
+        }
+
         for (id, agent) in self.connections.agents_mut() {
             let _span = info_span!("connection", %id).entered();
 
@@ -651,25 +1257,30 @@ where
             .bindings
             .values_mut()
             .flat_map(|binding| binding.poll_event());
+        let nat_discovery_events = std::iter::from_fn(|| self.nat_discovery.poll_event());
         let allocation_events = self
             .allocations
             .values_mut()
             .flat_map(|allocation| allocation.poll_event());
 
-        for event in binding_events.chain(allocation_events) {
+        // Invalidating a candidate needs access to all of `self`, so we can't hold on to the borrows of the iterators.
+        let events = binding_events
+            .chain(nat_discovery_events)
+            .chain(allocation_events)
+            .collect::<Vec<_>>();
+
+        for event in events {
             match event {
                 CandidateEvent::New(candidate) => {
                     add_local_candidate_to_all(
                         candidate,
                         &mut self.connections,
+                        self.family_preference,
                         &mut self.pending_events,
                     );
                 }
                 CandidateEvent::Invalid(candidate) => {
-                    for (id, agent) in self.connections.agents_mut() {
-                        let _span = info_span!("connection", %id).entered();
-                        agent.invalidate_candidate(&candidate);
-                    }
+                    self.invalidate_local_candidate(&candidate);
                 }
             }
         }
@@ -756,9 +1367,8 @@ where
             pass: answer.credentials.password,
         });
 
-        self.seed_agent_with_local_candidates(id, &mut agent);
-
         let connection = self.init_connection(
+            id,
             agent,
             remote,
             *initial.session_key.expose_secret(),
@@ -773,13 +1383,29 @@ where
 
         debug_assert!(existing.is_none());
     }
-}
 
-impl<TId> Node<Server, TId>
-where
-    TId: Eq + Hash + Copy + fmt::Display,
-{
-    /// Accept a new connection indexed by the given ID.
+    /// Rotates the preshared key of an established connection.
+    ///
+    /// The same key must be passed to [`Node::rotate_preshared_key`] on the remote, e.g. via the signalling channel.
+    /// As the client, we initiate a handshake with the new key right away, hence the server should install it first.
+    /// Until it completes, packets continue to flow over the current session, so no packets are dropped during the rotation.
+    /// If the remote doesn't complete the handshake within 90 seconds, we abandon the rotation and keep using the current key.
+    #[tracing::instrument(level = "info", skip_all, fields(%id))]
+    pub fn rotate_preshared_key(
+        &mut self,
+        id: TId,
+        key: Secret<[u8; 32]>,
+        now: Instant,
+    ) -> Result<(), Error> {
+        self.install_preshared_key(id, key, true, now)
+    }
+}
+
+impl<TId> Node<Server, TId>
+where
+    TId: Eq + Hash + Copy + fmt::Display,
+{
+    /// Accept a new connection indexed by the given ID.
     ///
     /// Out of all configured STUN and TURN servers, the connection will only use the ones provided here.
     /// The returned [`Answer`] must be passed to the remote via a signalling channel.
@@ -819,9 +1445,8 @@ where
             },
         };
 
-        self.seed_agent_with_local_candidates(id, &mut agent);
-
         let connection = self.init_connection(
+            id,
             agent,
             remote,
             *offer.session_key.expose_secret(),
@@ -836,12 +1461,62 @@ where
 
         answer
     }
+
+    /// Rotates the preshared key of an established connection.
+    ///
+    /// The same key must be passed to [`Node::rotate_preshared_key`] on the remote, e.g. via the signalling channel.
+    /// As the server, we wait for the client to initiate a handshake with the new key.
+    /// Until it completes, packets continue to flow over the current session, so no packets are dropped during the rotation.
+    /// If the remote doesn't complete the handshake within 90 seconds, we abandon the rotation and keep using the current key.
+    #[tracing::instrument(level = "info", skip_all, fields(%id))]
+    pub fn rotate_preshared_key(
+        &mut self,
+        id: TId,
+        key: Secret<[u8; 32]>,
+        now: Instant,
+    ) -> Result<(), Error> {
+        self.install_preshared_key(id, key, false, now)
+    }
 }
 
 impl<T, TId> Node<T, TId>
 where
     TId: Eq + Hash + Copy + fmt::Display,
 {
+    fn install_preshared_key(
+        &mut self,
+        id: TId,
+        key: Secret<[u8; 32]>,
+        initiate: bool,
+        now: Instant,
+    ) -> Result<(), Error> {
+        let connection = self
+            .connections
+            .get_established_mut(&id)
+            .ok_or(Error::NotConnected)?;
+
+        let index = self.index.next();
+        let tunnel = Tunn::new(
+            self.private_key.clone(),
+            connection.remote_pub_key,
+            Some(*key.expose_secret()),
+            WG_KEEP_ALIVE,
+            index,
+            Some(self.rate_limiter.clone()),
+        );
+
+        connection.rotate_tunnel(
+            tunnel,
+            index,
+            initiate,
+            &mut self.allocations,
+            &mut self.buffered_transmits,
+            now,
+        );
+
+        Ok(())
+    }
+
     fn upsert_stun_servers(&mut self, servers: &HashSet<SocketAddr>, now: Instant) {
         for server in servers {
             if !self.bindings.contains_key(server) {
@@ -868,23 +1543,40 @@ where
                 continue;
             };
 
+            let is_limited = self.max_relays_per_family.is_some();
+            let turn_server = self.turn_servers.entry(*server).or_insert_with(|| {
+                tracing::info!(address = %server, "Added new TURN server");
+
+                TurnServer {
+                    username: username.clone(),
+                    password: password.clone(),
+                    realm: realm.clone(),
+                    // We only need to measure the RTT if we have to choose between relays.
+                    probe: is_limited.then(|| RelayProbe::new(*server, now)),
+                }
+            });
+            turn_server.username = username.clone();
+            turn_server.password = password.clone();
+            turn_server.realm = realm.clone();
+
             if let Some(existing) = self.allocations.get_mut(server) {
                 existing.update_credentials(username, password, realm, now);
                 continue;
             }
 
-            self.allocations.insert(
-                *server,
-                Allocation::new(*server, username, password.clone(), realm, now),
-            );
+            // With a limit, we only allocate once we know which relays are the fastest.
+            if is_limited {
+                continue;
+            }
 
-            tracing::info!(address = %server, "Added new TURN server");
+            self.allocations
+                .insert(*server, turn_server.allocate(*server, now));
         }
     }
 
-    fn seed_agent_with_local_candidates(&mut self, connection: TId, agent: &mut IceAgent) {
+    fn seed_connection_with_local_candidates(&mut self, id: TId, connection: &mut Connection) {
         for candidate in self.host_candidates.iter().cloned() {
-            add_local_candidate(connection, agent, candidate, &mut self.pending_events);
+            connection.add_local_candidate(id, candidate, &mut self.pending_events);
         }
 
         for candidate in self
@@ -892,12 +1584,11 @@ where
             .values()
             .filter_map(|binding| binding.candidate())
         {
-            add_local_candidate(
-                connection,
-                agent,
-                candidate.clone(),
-                &mut self.pending_events,
-            );
+            connection.add_local_candidate(id, candidate.clone(), &mut self.pending_events);
+        }
+
+        for candidate in self.nat_discovery.predicted_candidates() {
+            connection.add_local_candidate(id, candidate.clone(), &mut self.pending_events);
         }
 
         for candidate in self
@@ -905,16 +1596,33 @@ where
             .values()
             .flat_map(|allocation| allocation.current_candidates())
         {
-            add_local_candidate(
-                connection,
-                agent,
-                candidate.clone(),
-                &mut self.pending_events,
-            );
+            connection.add_local_candidate(id, candidate.clone(), &mut self.pending_events);
         }
     }
 }
 
+/// A TURN server we may allocate on.
+struct TurnServer {
+    username: Username,
+    password: String,
+    realm: Realm,
+
+    /// Only present if we limit the number of relays, see [`Node::set_max_relays_per_family`].
+    probe: Option<RelayProbe>,
+}
+
+impl TurnServer {
+    fn allocate(&self, server: SocketAddr, now: Instant) -> Allocation {
+        Allocation::new(
+            server,
+            self.username.clone(),
+            self.password.clone(),
+            self.realm.clone(),
+            now,
+        )
+    }
+}
+
 struct Connections<TId> {
     initial: HashMap<TId, InitialConnection>,
     established: HashMap<TId, Connection>,
@@ -954,7 +1662,7 @@ where
     }
 
     fn stats(&self) -> impl Iterator<Item = (TId, ConnectionStats)> + '_ {
-        self.established.iter().map(move |(id, c)| (*id, c.stats))
+        self.established.iter().map(move |(id, c)| (*id, c.stats()))
     }
 
     fn agent_mut(&mut self, id: TId) -> Option<&mut IceAgent> {
@@ -1014,6 +1722,7 @@ fn encode_as_channel_data(
         src: None,
         dst: relay,
         payload: Cow::Owned(payload),
+        segment_size: None,
     })
 }
 
@@ -1026,23 +1735,29 @@ enum EncodeError {
 fn add_local_candidate_to_all<TId>(
     candidate: Candidate,
     connections: &mut Connections<TId>,
+    family_preference: AddressFamilyPreference,
     pending_events: &mut VecDeque<Event<TId>>,
 ) where
     TId: Copy + fmt::Display,
 {
-    let initial_connections = connections
-        .initial
-        .iter_mut()
-        .map(|(id, c)| (*id, &mut c.agent));
-    let established_connections = connections
-        .established
-        .iter_mut()
-        .map(|(id, c)| (*id, &mut c.agent));
-
-    for (id, agent) in initial_connections.chain(established_connections) {
+    // Initial connections are seeded with all local candidates once signalling completes, which is also when the head start begins.
+    if !family_preference.is_held_back(candidate.addr()) {
+        for (id, connection) in connections.initial.iter_mut() {
+            let _span = info_span!("connection", %id).entered();
+
+            add_local_candidate(
+                *id,
+                &mut connection.agent,
+                candidate.clone(),
+                pending_events,
+            );
+        }
+    }
+
+    for (id, connection) in connections.established.iter_mut() {
         let _span = info_span!("connection", %id).entered();
 
-        add_local_candidate(id, agent, candidate.clone(), pending_events);
+        connection.add_local_candidate(*id, candidate.clone(), pending_events);
     }
 }
 
@@ -1092,6 +1807,17 @@ pub enum Event<TId> {
     },
     ConnectionEstablished(TId),
 
+    /// A relayed connection has been upgraded to a direct path.
+    ///
+    /// The wireguard session is migrated as-is, i.e. without a new handshake.
+    ConnectionUpgraded {
+        connection: TId,
+        /// The relay we were previously using.
+        relay: SocketAddr,
+        /// The address of the remote we are now talking to directly.
+        direct: SocketAddr,
+    },
+
     /// We failed to establish a connection.
     ///
     /// All state associated with the connection has been cleared.
@@ -1111,6 +1837,10 @@ pub struct Transmit<'a> {
     pub dst: SocketAddr,
     /// The data that should be sent.
     pub payload: Cow<'a, [u8]>,
+    /// If set, `payload` consists of several datagrams of this size and should be sent with UDP GSO.
+    ///
+    /// Only the last datagram may be smaller.
+    pub segment_size: Option<usize>,
 }
 
 impl<'a> Transmit<'a> {
@@ -1119,6 +1849,7 @@ impl<'a> Transmit<'a> {
             src: self.src,
             dst: self.dst,
             payload: Cow::Owned(self.payload.into_owned()),
+            segment_size: self.segment_size,
         }
     }
 }
@@ -1162,6 +1893,14 @@ struct Connection {
     remote_pub_key: PublicKey,
 
     tunnel: Tunn,
+    /// The index we created `tunnel` with, see [`receiver_index`].
+    tunnel_index: u32,
+    /// The index of the remote's tunnel that `tunnel` has a session with, see [`sender_index`].
+    remote_tunnel_index: Option<u32>,
+    /// A tunnel with a new preshared key, waiting for its first handshake, see [`Node::rotate_preshared_key`].
+    next_tunnel: Option<NextTunnel>,
+    /// The tunnel we used before the last key rotation, only used to decrypt packets that were in-flight when we switched.
+    previous_tunnel: Option<PreviousTunnel>,
     next_timer_update: Instant,
 
     // When this is `Some`, we are connected.
@@ -1170,6 +1909,9 @@ struct Connection {
     possible_sockets: HashSet<SocketAddr>,
 
     stats: ConnectionStats,
+    /// ICE binding requests we sent to the remote, indexed by their transaction ID.
+    pending_binding_requests: HashMap<[u8; 12], Instant>,
+    ice_rtt: Option<Duration>,
 
     buffer: Box<[u8; MAX_UDP_SIZE]>,
     intent_sent_at: Instant,
@@ -1177,6 +1919,42 @@ struct Connection {
     is_failed: bool,
 
     signalling_completed_at: Instant,
+
+    /// Local candidates of the non-preferred address family, held back until the preferred one had its head start.
+    held_back_candidates: Option<HeldBackCandidates>,
+    /// The address family of the first candidate pair that got nominated.
+    won_by: Option<AddressFamily>,
+    /// Set whilst we move to a new path after our network changed, see [`Node::reconnect`].
+    migration_deadline: Option<Instant>,
+}
+
+struct NextTunnel {
+    tunnel: Tunn,
+    index: u32,
+    remote_index: Option<u32>,
+    /// Whether we initiate the handshake or wait for the remote to do so.
+    initiate: bool,
+    deadline: Instant,
+}
+
+struct PreviousTunnel {
+    tunnel: Tunn,
+    index: u32,
+    until: Instant,
+}
+
+/// Which of a connection's tunnels a wireguard packet belongs to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum TunnelSlot {
+    Current,
+    Next,
+    Previous,
+}
+
+struct HeldBackCandidates {
+    preference: AddressFamilyPreference,
+    until: Instant,
+    candidates: Vec<Candidate>,
 }
 
 /// The socket of the peer we are connected to.
@@ -1199,6 +1977,21 @@ impl PeerSocket {
             PeerSocket::Relay { relay, .. } => *relay,
         }
     }
+
+    fn outer(&self) -> Outer {
+        match *self {
+            PeerSocket::Direct { source, dest } => Outer {
+                src: Some(source),
+                dst: dest,
+                relayed: None,
+            },
+            PeerSocket::Relay { relay, dest } => Outer {
+                src: None,
+                dst: relay,
+                relayed: Some(dest),
+            },
+        }
+    }
 }
 
 impl Connection {
@@ -1225,6 +2018,65 @@ impl Connection {
         now.duration_since(self.intent_sent_at)
     }
 
+    fn stats(&self) -> ConnectionStats {
+        let (_, _, _, estimated_loss, wireguard_rtt) = self.tunnel.stats();
+        let (path, relay) = self.path().unzip();
+
+        ConnectionStats {
+            ice_rtt: self.ice_rtt,
+            wireguard_rtt: wireguard_rtt.map(|millis| Duration::from_millis(u64::from(millis))),
+            estimated_loss,
+            path,
+            relay: relay.flatten(),
+            ..self.stats
+        }
+    }
+
+    /// The type of the path we are currently using and the relay involved in it.
+    fn path(&self) -> Option<(PathType, Option<SocketAddr>)> {
+        let (source, dest) = match self.peer_socket? {
+            PeerSocket::Relay { relay, .. } => return Some((PathType::Relayed, Some(relay))),
+            PeerSocket::Direct { source, dest } => (source, dest),
+        };
+
+        let local_kind = self.local_candidate(source).map(|c| c.kind());
+        let remote_kind = self
+            .agent
+            .remote_candidates()
+            .iter()
+            .find(|c| c.addr() == dest)
+            .map(|c| c.kind())
+            .unwrap_or(CandidateKind::PeerReflexive); // Remotes we only know from their traffic are peer-reflexive.
+
+        let path = match (local_kind, remote_kind) {
+            (_, CandidateKind::Relayed) => return Some((PathType::Relayed, Some(dest))),
+            (Some(CandidateKind::Host) | None, CandidateKind::Host) => PathType::Host,
+            _ => PathType::ServerReflexive,
+        };
+
+        Some((path, None))
+    }
+
+    fn uses_relay(&self, server: SocketAddr) -> bool {
+        matches!(self.peer_socket, Some(PeerSocket::Relay { relay, .. }) if relay == server)
+    }
+
+    /// The relay in use on the current path, if any.
+    fn relay(&self) -> Option<SocketAddr> {
+        self.path().and_then(|(_, relay)| relay)
+    }
+
+    /// Records the RTT if the given transaction ID belongs to one of our binding requests.
+    fn handle_binding_response(&mut self, transaction_id: [u8; 12], now: Instant) -> bool {
+        let Some(sent_at) = self.pending_binding_requests.remove(&transaction_id) else {
+            return false;
+        };
+
+        self.ice_rtt = Some(now.duration_since(sent_at));
+
+        true
+    }
+
     fn set_remote_from_wg_activity(
         &mut self,
         local: SocketAddr,
@@ -1242,6 +2094,13 @@ impl Connection {
             },
         };
 
+        // Packets may still arrive via the relay whilst we move to a direct path, don't let them move us back.
+        if let (Some(current @ PeerSocket::Direct { .. }), PeerSocket::Relay { .. }) =
+            (self.peer_socket, remote_socket)
+        {
+            return current;
+        }
+
         if self.peer_socket != Some(remote_socket) {
             tracing::debug!(old = ?self.peer_socket, new = ?remote_socket, "Updating remote socket from WG activity");
             self.peer_socket = Some(remote_socket);
@@ -1255,8 +2114,94 @@ impl Connection {
         let agent_timeout = self.agent.poll_timeout();
         let next_wg_timer = Some(self.next_timer_update);
         let candidate_timeout = self.candidate_timeout();
+        let head_start_timeout = self.held_back_candidates.as_ref().map(|h| h.until);
+        let key_rotation_timeout = earliest(
+            self.next_tunnel.as_ref().map(|t| t.deadline),
+            self.previous_tunnel.as_ref().map(|t| t.until),
+        );
+
+        earliest(
+            agent_timeout,
+            earliest(
+                next_wg_timer,
+                earliest(
+                    candidate_timeout,
+                    earliest(
+                        head_start_timeout,
+                        earliest(self.migration_deadline, key_rotation_timeout),
+                    ),
+                ),
+            ),
+        )
+    }
+
+    /// Starts moving to a new path because the current one may belong to a network we are no longer connected to.
+    ///
+    /// Until ICE nominates a new path, we keep sending on the current one in case it still works.
+    /// The wireguard session is kept either way.
+    fn start_migration(&mut self, now: Instant) {
+        tracing::info!(current = ?self.peer_socket, "Migrating connection to new network");
+
+        self.migration_deadline = Some(now + MIGRATION_TIMEOUT);
+    }
+
+    /// Adds a local candidate to the agent unless it needs to be held back.
+    fn add_local_candidate<TId>(
+        &mut self,
+        id: TId,
+        candidate: Candidate,
+        pending_events: &mut VecDeque<Event<TId>>,
+    ) where
+        TId: fmt::Display,
+    {
+        if let Some(held_back) = self.held_back_candidates.as_mut() {
+            if held_back.preference.is_held_back(candidate.addr()) {
+                if !held_back.candidates.contains(&candidate) {
+                    held_back.candidates.push(candidate);
+                }
 
-        earliest(agent_timeout, earliest(next_wg_timer, candidate_timeout))
+                return;
+            }
+        }
+
+        add_local_candidate(id, &mut self.agent, candidate, pending_events);
+    }
+
+    fn discard_held_back_candidate(&mut self, candidate: &Candidate) {
+        if let Some(held_back) = self.held_back_candidates.as_mut() {
+            held_back.candidates.retain(|c| c != candidate);
+        }
+    }
+
+    /// Adds all held back candidates to the agent once the head start of the preferred address family is over.
+    fn release_held_back_candidates<TId>(
+        &mut self,
+        id: TId,
+        now: Instant,
+        pending_events: &mut VecDeque<Event<TId>>,
+    ) where
+        TId: fmt::Display + Copy,
+    {
+        if self
+            .held_back_candidates
+            .as_ref()
+            .is_some_and(|h| now < h.until)
+        {
+            return;
+        }
+
+        let Some(held_back) = self.held_back_candidates.take() else {
+            return;
+        };
+
+        tracing::debug!(
+            num_candidates = held_back.candidates.len(),
+            "Head start of preferred address family is over"
+        );
+
+        for candidate in held_back.candidates {
+            add_local_candidate(id, &mut self.agent, candidate, pending_events);
+        }
     }
 
     fn candidate_timeout(&self) -> Option<Instant> {
@@ -1274,10 +2219,14 @@ impl Connection {
         now: Instant,
         allocations: &mut HashMap<SocketAddr, Allocation>,
         transmits: &mut VecDeque<Transmit<'static>>,
+        events: &mut VecDeque<Event<TId>>,
     ) where
         TId: fmt::Display + Copy,
     {
+        self.release_held_back_candidates(id, now, events);
         self.agent.handle_timeout(now);
+        self.pending_binding_requests
+            .retain(|_, sent_at| now.duration_since(*sent_at) < ICE_RTT_TIMEOUT);
 
         if self
             .candidate_timeout()
@@ -1288,6 +2237,31 @@ impl Connection {
             return;
         }
 
+        if self
+            .migration_deadline
+            .is_some_and(|deadline| now >= deadline)
+        {
+            tracing::info!("Connection failed (no new path after network change)");
+            self.is_failed = true;
+            return;
+        }
+
+        if self.next_tunnel.as_ref().is_some_and(|t| now >= t.deadline) {
+            tracing::warn!(
+                "Remote did not complete handshake with new preshared key, keeping current key"
+            );
+            self.next_tunnel = None;
+        }
+
+        if self
+            .previous_tunnel
+            .as_ref()
+            .is_some_and(|t| now >= t.until)
+        {
+            tracing::debug!("Discarding tunnel of previous preshared key");
+            self.previous_tunnel = None;
+        }
+
         // TODO: `boringtun` is impure because it calls `Instant::now`.
 
         if now >= self.next_timer_update {
@@ -1298,12 +2272,7 @@ impl Connection {
                 return;
             };
 
-            /// [`boringtun`] requires us to pass buffers in where it can construct its packets.
-            ///
-            /// When updating the timers, the largest packet that we may have to send is `148` bytes as per `HANDSHAKE_INIT_SZ` constant in [`boringtun`].
-            const MAX_SCRATCH_SPACE: usize = 148;
-
-            let mut buf = [0u8; MAX_SCRATCH_SPACE];
+            let mut buf = [0u8; WG_HANDSHAKE_INIT_SIZE];
 
             match self.tunnel.update_timers(&mut buf) {
                 TunnResult::Done => {}
@@ -1321,6 +2290,19 @@ impl Connection {
                     panic!("Unexpected result from update_timers")
                 }
             };
+
+            // Only the initiating side needs to retransmit its handshake, the other one just waits for it.
+            if let Some(next) = self.next_tunnel.as_mut().filter(|t| t.initiate) {
+                match next.tunnel.update_timers(&mut buf) {
+                    TunnResult::WriteToNetwork(b) => {
+                        transmits.extend(make_owned_transmit(peer_socket, b, allocations, now));
+                    }
+                    TunnResult::Done | TunnResult::Err(_) => {} // We enforce our own timeout, see `KEY_ROTATION_TIMEOUT`.
+                    TunnResult::WriteToTunnelV4(..) | TunnResult::WriteToTunnelV6(..) => {
+                        panic!("Unexpected result from update_timers")
+                    }
+                }
+            }
         }
 
         while let Some(event) = self.agent.poll_event() {
@@ -1329,6 +2311,12 @@ impl Connection {
                     self.possible_sockets.insert(source);
                 }
                 IceAgentEvent::IceConnectionStateChange(IceConnectionState::Disconnected) => {
+                    // The old path is expected to fail whilst we migrate, ICE may still find a new one.
+                    if self.migration_deadline.is_some() {
+                        tracing::debug!("ICE disconnected whilst migrating to new path");
+                        continue;
+                    }
+
                     tracing::info!("Connection failed (ICE timeout)");
                     self.is_failed = true;
                 }
@@ -1371,12 +2359,40 @@ impl Connection {
                         }
                     };
 
+                    if self.migration_deadline.take().is_some() {
+                        tracing::info!(new = ?remote_socket, "Migrated connection to new path");
+                    }
+
                     if self.peer_socket != Some(remote_socket) {
                         tracing::info!(old = ?self.peer_socket, new = ?remote_socket, duration_since_intent = ?self.duration_since_intent(now), "Updating remote socket");
+
+                        self.won_by.get_or_insert(AddressFamily::of(destination));
+
+                        let previous_relay = self.relay();
                         self.peer_socket = Some(remote_socket);
+                        let current_relay = self.relay();
+
+                        // As long as we are relayed, keep all candidates so ICE can still find a direct path.
+                        if current_relay.is_none() {
+                            self.invalidate_candiates();
+                        }
+
+                        // An established wireguard session can simply roam to the new path, otherwise we need to handshake on it.
+                        if self.wg_handshake_complete() {
+                            self.send_keepalive(allocations, transmits, now);
+                        } else {
+                            self.force_handshake(allocations, transmits, now);
+                        }
 
-                        self.invalidate_candiates();
-                        self.force_handshake(allocations, transmits, now);
+                        if let (Some(relay), None) = (previous_relay, current_relay) {
+                            tracing::info!(%relay, direct = %destination, "Upgraded connection to direct path");
+
+                            events.push_back(Event::ConnectionUpgraded {
+                                connection: id,
+                                relay,
+                                direct: destination,
+                            });
+                        }
                     }
                 }
                 IceAgentEvent::IceRestart(_) | IceAgentEvent::IceConnectionStateChange(_) => {}
@@ -1388,6 +2404,10 @@ impl Connection {
             let dst = transmit.destination;
             let packet = transmit.contents;
 
+            if let Some(transaction_id) = binding_request_id(&packet) {
+                self.pending_binding_requests.insert(transaction_id, now);
+            }
+
             // Check if `str0m` wants us to send from a "remote" socket, i.e. one that we allocated with a relay.
             let allocation = allocations
                 .iter_mut()
@@ -1401,6 +2421,7 @@ impl Connection {
                     src: Some(source),
                     dst,
                     payload: Cow::Owned(packet.into()),
+                    segment_size: None,
                 });
                 continue;
             };
@@ -1418,6 +2439,7 @@ impl Connection {
                 src: None,
                 dst: *relay,
                 payload: Cow::Owned(channel_data),
+                segment_size: None,
             });
         }
     }
@@ -1436,6 +2458,9 @@ impl Connection {
             }
         };
 
+        self.stats.tx_packets += 1;
+        self.stats.tx_bytes += packet.len();
+
         Ok(Some(&buffer[..len]))
     }
 
@@ -1451,7 +2476,10 @@ impl Connection {
         transmits: &mut VecDeque<Transmit<'static>>,
         now: Instant,
     ) -> ControlFlow<Result<(), Error>, MutableIpPacket<'b>> {
-        match self.tunnel.decapsulate(None, packet, buffer) {
+        let slot = self.tunnel_slot(packet);
+        let remote_index = sender_index(packet);
+
+        let control_flow = match self.tunnel_mut(slot).decapsulate(None, packet, buffer) {
             TunnResult::Done => ControlFlow::Break(Ok(())),
             TunnResult::Err(e) => ControlFlow::Break(Err(Error::Decapsulate(e))),
 
@@ -1461,6 +2489,8 @@ impl Connection {
             // Thus, the caller can query whatever data they'd like, not just the source IP so we don't return it in addition.
             TunnResult::WriteToTunnelV4(packet, ip) => {
                 self.set_remote_from_wg_activity(local, from, relayed);
+                self.stats.rx_packets += 1;
+                self.stats.rx_bytes += packet.len();
 
                 let ipv4_packet =
                     MutableIpv4Packet::new(packet).expect("boringtun verifies validity");
@@ -1470,6 +2500,8 @@ impl Connection {
             }
             TunnResult::WriteToTunnelV6(packet, ip) => {
                 self.set_remote_from_wg_activity(local, from, relayed);
+                self.stats.rx_packets += 1;
+                self.stats.rx_bytes += packet.len();
 
                 let ipv6_packet =
                     MutableIpv6Packet::new(packet).expect("boringtun verifies validity");
@@ -1487,29 +2519,187 @@ impl Connection {
 
                 transmits.extend(make_owned_transmit(socket, bytes, allocations, now));
 
-                while let TunnResult::WriteToNetwork(packet) =
-                    self.tunnel.decapsulate(None, &[], self.buffer.as_mut())
-                {
+                loop {
+                    let (tunnel, buffer) = self.tunnel_and_buffer_mut(slot);
+
+                    let TunnResult::WriteToNetwork(packet) = tunnel.decapsulate(None, &[], buffer)
+                    else {
+                        break;
+                    };
+
                     transmits.extend(make_owned_transmit(socket, packet, allocations, now));
                 }
 
                 ControlFlow::Break(Ok(()))
             }
+        };
+
+        // Only trust the sender of handshake messages that the tunnel accepted.
+        let is_accepted = !matches!(control_flow, ControlFlow::Break(Err(_)));
+
+        if let Some(remote_index) = remote_index.filter(|_| is_accepted) {
+            self.set_remote_index(slot, remote_index);
         }
+
+        if slot == TunnelSlot::Next {
+            self.promote_next_tunnel_if_ready(now);
+        }
+
+        control_flow
     }
 
-    fn force_handshake(
+    /// Installs a tunnel with a new preshared key next to the current one.
+    ///
+    /// Without an established session, there is nothing to preserve and we replace the current tunnel right away.
+    fn rotate_tunnel(
         &mut self,
+        tunnel: Tunn,
+        index: u32,
+        initiate: bool,
         allocations: &mut HashMap<SocketAddr, Allocation>,
         transmits: &mut VecDeque<Transmit<'static>>,
         now: Instant,
     ) {
-        /// [`boringtun`] requires us to pass buffers in where it can construct its packets.
-        ///
-        /// When updating the timers, the largest packet that we may have to send is `148` bytes as per `HANDSHAKE_INIT_SZ` constant in [`boringtun`].
-        const MAX_SCRATCH_SPACE: usize = 148;
+        if !self.wg_handshake_complete() {
+            tracing::info!("Replacing preshared key of connection without session");
+
+            self.tunnel = tunnel;
+            self.tunnel_index = index;
+            self.next_tunnel = None;
+
+            if initiate && self.peer_socket.is_some() {
+                self.force_handshake(allocations, transmits, now);
+            }
+
+            return;
+        }
+
+        if self.next_tunnel.is_some() {
+            tracing::info!("Replacing pending preshared key rotation");
+        }
+
+        let mut next = NextTunnel {
+            tunnel,
+            index,
+            remote_index: None,
+            initiate,
+            deadline: now + KEY_ROTATION_TIMEOUT,
+        };
+
+        if let (true, Some(socket)) = (initiate, self.peer_socket) {
+            let mut buf = [0u8; WG_HANDSHAKE_INIT_SIZE];
+
+            if let TunnResult::WriteToNetwork(bytes) =
+                next.tunnel.format_handshake_initiation(&mut buf, false)
+            {
+                transmits.extend(make_owned_transmit(socket, bytes, allocations, now));
+            }
+        }
+
+        tracing::info!(%initiate, "Rotating preshared key");
+
+        self.next_tunnel = Some(next);
+    }
+
+    /// Switches to the tunnel with the new preshared key once its handshake is complete.
+    ///
+    /// The current tunnel is kept for a bit to decrypt packets that the remote sent before it switched as well.
+    fn promote_next_tunnel_if_ready(&mut self, now: Instant) {
+        if !self
+            .next_tunnel
+            .as_ref()
+            .is_some_and(|t| t.tunnel.time_since_last_handshake().is_some())
+        {
+            return;
+        }
+
+        let Some(next) = self.next_tunnel.take() else {
+            return;
+        };
+
+        let previous = std::mem::replace(&mut self.tunnel, next.tunnel);
+        let previous_index = std::mem::replace(&mut self.tunnel_index, next.index);
+        self.remote_tunnel_index = next.remote_index;
 
-        let mut buf = [0u8; MAX_SCRATCH_SPACE];
+        self.previous_tunnel = Some(PreviousTunnel {
+            tunnel: previous,
+            index: previous_index,
+            until: now + PREVIOUS_TUNNEL_GRACE_PERIOD,
+        });
+
+        tracing::info!("Completed handshake with new preshared key");
+    }
+
+    /// Determines which of our tunnels should handle the given wireguard packet.
+    ///
+    /// Handshake initiations aren't addressed to one of our sessions yet.
+    /// Ones from the remote's tunnel of our current session are routine rekeys (e.g. of a remote that hasn't installed the new key yet) and stay on the current tunnel.
+    /// During a rotation, all others are expected to use the new key.
+    /// Responses, cookie replies and data packets carry our index of the session they belong to.
+    fn tunnel_slot(&self, packet: &[u8]) -> TunnelSlot {
+        let Some(index) = receiver_index(packet) else {
+            let is_rekey =
+                sender_index(packet).is_some_and(|i| Some(i) == self.remote_tunnel_index);
+
+            if is_rekey || self.next_tunnel.is_none() {
+                return TunnelSlot::Current;
+            }
+
+            return TunnelSlot::Next;
+        };
+
+        if index == self.tunnel_index {
+            return TunnelSlot::Current;
+        }
+
+        if self.next_tunnel.as_ref().is_some_and(|t| t.index == index) {
+            return TunnelSlot::Next;
+        }
+
+        if self
+            .previous_tunnel
+            .as_ref()
+            .is_some_and(|t| t.index == index)
+        {
+            return TunnelSlot::Previous;
+        }
+
+        TunnelSlot::Current
+    }
+
+    /// Remembers which of the remote's tunnels the tunnel of the given slot has a session with.
+    fn set_remote_index(&mut self, slot: TunnelSlot, remote_index: u32) {
+        match (slot, self.next_tunnel.as_mut()) {
+            (TunnelSlot::Current, _) => self.remote_tunnel_index = Some(remote_index),
+            (TunnelSlot::Next, Some(next)) => next.remote_index = Some(remote_index),
+            (TunnelSlot::Next, None) | (TunnelSlot::Previous, _) => {}
+        }
+    }
+
+    fn tunnel_mut(&mut self, slot: TunnelSlot) -> &mut Tunn {
+        self.tunnel_and_buffer_mut(slot).0
+    }
+
+    /// Borrows the tunnel of the given slot together with our scratch buffer.
+    fn tunnel_and_buffer_mut(&mut self, slot: TunnelSlot) -> (&mut Tunn, &mut [u8]) {
+        let tunnel = match (slot, &mut self.next_tunnel, &mut self.previous_tunnel) {
+            (TunnelSlot::Next, Some(next), _) => &mut next.tunnel,
+            (TunnelSlot::Previous, _, Some(previous)) => &mut previous.tunnel,
+            (TunnelSlot::Current | TunnelSlot::Next | TunnelSlot::Previous, _, _) => {
+                &mut self.tunnel
+            }
+        };
+
+        (tunnel, self.buffer.as_mut())
+    }
+
+    fn force_handshake(
+        &mut self,
+        allocations: &mut HashMap<SocketAddr, Allocation>,
+        transmits: &mut VecDeque<Transmit<'static>>,
+        now: Instant,
+    ) {
+        let mut buf = [0u8; WG_HANDSHAKE_INIT_SIZE];
 
         let TunnResult::WriteToNetwork(bytes) =
             self.tunnel.format_handshake_initiation(&mut buf, true)
@@ -1524,6 +2714,26 @@ impl Connection {
         transmits.extend(make_owned_transmit(socket, bytes, allocations, now));
     }
 
+    /// Sends a wireguard keepalive on the current path.
+    fn send_keepalive(
+        &mut self,
+        allocations: &mut HashMap<SocketAddr, Allocation>,
+        transmits: &mut VecDeque<Transmit<'static>>,
+        now: Instant,
+    ) {
+        let mut buf = [0u8; WG_HANDSHAKE_INIT_SIZE]; // A keepalive turns into a handshake initiation if the session expired in the meantime.
+
+        let TunnResult::WriteToNetwork(bytes) = self.tunnel.encapsulate(&[], &mut buf) else {
+            return;
+        };
+
+        let socket = self
+            .peer_socket
+            .expect("cannot send keepalive without socket");
+
+        transmits.extend(make_owned_transmit(socket, bytes, allocations, now));
+    }
+
     /// Invalidates all local candidates with a lower or equal priority compared to the nominated one.
     ///
     /// Each time we nominate a candidate pair, we don't really want to keep all the others active because it creates a lot of noise.
@@ -1561,6 +2771,77 @@ impl Connection {
     }
 }
 
+/// Groups consecutive datagrams of the given sizes into GSO batches.
+///
+/// Returns the range of each batch within the buffer holding all datagrams back-to-back, together with its segment size if it contains more than one datagram.
+fn coalesce_segments(segments: &[usize]) -> Vec<(Range<usize>, Option<usize>)> {
+    let mut batches = Vec::new();
+    let mut segments = segments.iter().copied().peekable();
+    let mut offset = 0;
+
+    while let Some(segment_size) = segments.next() {
+        let mut len = segment_size;
+        let mut num_segments = 1;
+
+        while let Some(next) = segments.peek().copied() {
+            if next > segment_size || len + next > MAX_UDP_SIZE || num_segments == MAX_GSO_SEGMENTS
+            {
+                break;
+            }
+
+            segments.next();
+            len += next;
+            num_segments += 1;
+
+            // Only the last segment may be smaller than the others.
+            if next < segment_size {
+                break;
+            }
+        }
+
+        batches.push((
+            offset..offset + len,
+            (num_segments > 1).then_some(segment_size),
+        ));
+        offset += len;
+    }
+
+    batches
+}
+
+/// Extracts the index of our tunnel that a wireguard packet is addressed to.
+///
+/// Returns `None` for handshake initiations because they are not addressed to a session yet.
+/// The lower 8 bits of a session index are a counter of sessions within the same [`Tunn`], hence we discard them.
+fn receiver_index(packet: &[u8]) -> Option<u32> {
+    const HANDSHAKE_RESPONSE: u8 = 2;
+    const COOKIE_REPLY: u8 = 3;
+    const DATA: u8 = 4;
+
+    let receiver_index = match *packet.first()? {
+        HANDSHAKE_RESPONSE => packet.get(8..12)?,
+        COOKIE_REPLY | DATA => packet.get(4..8)?,
+        _ => return None,
+    };
+
+    Some(u32::from_le_bytes(receiver_index.try_into().ok()?) >> 8)
+}
+
+/// Extracts the index of the remote's tunnel that sent a wireguard handshake message.
+///
+/// Like in [`receiver_index`], we discard the lower 8 bits.
+fn sender_index(packet: &[u8]) -> Option<u32> {
+    const HANDSHAKE_INITIATION: u8 = 1;
+    const HANDSHAKE_RESPONSE: u8 = 2;
+
+    let sender_index = match *packet.first()? {
+        HANDSHAKE_INITIATION | HANDSHAKE_RESPONSE => packet.get(4..8)?,
+        _ => return None,
+    };
+
+    Some(u32::from_le_bytes(sender_index.try_into().ok()?) >> 8)
+}
+
 #[must_use]
 fn make_owned_transmit(
     socket: PeerSocket,
@@ -1576,6 +2857,7 @@ fn make_owned_transmit(
             src: Some(source),
             dst: remote,
             payload: Cow::Owned(message.into()),
+            segment_size: None,
         },
         PeerSocket::Relay { relay, dest: peer } => {
             encode_as_channel_data(relay, peer, message, allocations, now).ok()?
@@ -1584,3 +2866,289 @@ fn make_owned_transmit(
 
     Some(transmit)
 }
+
+/// The magic cookie of STUN messages, see <https://www.rfc-editor.org/rfc/rfc5389#section-6>.
+const STUN_MAGIC_COOKIE: [u8; 4] = [0x21, 0x12, 0xA4, 0x42];
+const STUN_BINDING_REQUEST: [u8; 2] = [0x00, 0x01];
+const STUN_BINDING_SUCCESS_RESPONSE: [u8; 2] = [0x01, 0x01];
+
+/// Returns the transaction ID if the packet is a STUN binding request.
+fn binding_request_id(packet: &[u8]) -> Option<[u8; 12]> {
+    stun_transaction_id(packet, STUN_BINDING_REQUEST)
+}
+
+/// Returns the transaction ID if the packet is a successful STUN binding response.
+fn binding_success_response_id(packet: &[u8]) -> Option<[u8; 12]> {
+    stun_transaction_id(packet, STUN_BINDING_SUCCESS_RESPONSE)
+}
+
+fn stun_transaction_id(packet: &[u8], message_type: [u8; 2]) -> Option<[u8; 12]> {
+    if packet.len() < 20 || packet[0..2] != message_type || packet[4..8] != STUN_MAGIC_COOKIE {
+        return None;
+    }
+
+    packet[8..20].try_into().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_transaction_id_of_binding_messages() {
+        let mut request = [0u8; 20];
+        request[0..2].copy_from_slice(&STUN_BINDING_REQUEST);
+        request[4..8].copy_from_slice(&STUN_MAGIC_COOKIE);
+        request[8..20].copy_from_slice(&[7u8; 12]);
+
+        let mut response = request;
+        response[0..2].copy_from_slice(&STUN_BINDING_SUCCESS_RESPONSE);
+
+        assert_eq!(binding_request_id(&request), Some([7u8; 12]));
+        assert_eq!(binding_success_response_id(&request), None);
+        assert_eq!(binding_success_response_id(&response), Some([7u8; 12]));
+        assert_eq!(binding_request_id(&response), None);
+    }
+
+    #[test]
+    fn ignores_non_stun_packets() {
+        assert_eq!(binding_request_id(&[0u8; 19]), None);
+        assert_eq!(binding_request_id(&[0u8; 100]), None);
+    }
+
+    #[test]
+    fn coalesces_equally_sized_datagrams() {
+        let batches = coalesce_segments(&[1300, 1300, 1300]);
+
+        assert_eq!(batches, vec![(0..3900, Some(1300))]);
+    }
+
+    #[test]
+    fn smaller_datagram_ends_batch() {
+        let batches = coalesce_segments(&[1300, 1300, 100, 1300]);
+
+        assert_eq!(batches, vec![(0..2700, Some(1300)), (2700..4000, None)]);
+    }
+
+    #[test]
+    fn larger_datagram_starts_new_batch() {
+        let batches = coalesce_segments(&[100, 1300, 1300]);
+
+        assert_eq!(batches, vec![(0..100, None), (100..2700, Some(1300))]);
+    }
+
+    #[test]
+    fn batches_respect_gso_limits() {
+        let batches = coalesce_segments(&[100; MAX_GSO_SEGMENTS + 1]);
+        assert_eq!(batches.len(), 2);
+        assert_eq!(batches[0].0.len(), 100 * MAX_GSO_SEGMENTS);
+
+        let batches = coalesce_segments(&[1300; 60]);
+        assert!(batches.iter().all(|(range, _)| range.len() <= MAX_UDP_SIZE));
+        assert_eq!(
+            batches.iter().map(|(range, _)| range.len()).sum::<usize>(),
+            60 * 1300
+        );
+    }
+
+    #[test]
+    fn routine_rekey_of_remote_mid_rotation_stays_on_current_tunnel() {
+        let now = Instant::now();
+        let (mut alice, mut bob) = alice_and_bob(now);
+
+        bob.connection
+            .force_handshake(&mut HashMap::new(), &mut bob.transmits, now);
+        exchange(&mut alice, &mut bob, now);
+
+        // Bob installs the new key first, Alice hasn't received it yet and rekeys her current session.
+        bob.rotate(3, NEW_KEY, false, now);
+        alice
+            .connection
+            .force_handshake(&mut HashMap::new(), &mut alice.transmits, now);
+        exchange(&mut alice, &mut bob, now);
+
+        assert!(
+            bob.connection.next_tunnel.is_some(),
+            "rotation should still be pending"
+        );
+
+        // Once Alice installs the key as well, the rotation completes.
+        alice.rotate(4, NEW_KEY, true, now);
+        exchange(&mut alice, &mut bob, now);
+
+        assert!(alice.connection.next_tunnel.is_none());
+        assert_eq!(alice.connection.tunnel_index, 4);
+        assert_eq!(alice.connection.remote_tunnel_index, Some(3));
+    }
+
+    #[test]
+    fn handshake_initiations_are_routed_by_their_sender_during_rotation() {
+        let now = Instant::now();
+        let (mut alice, mut bob) = alice_and_bob(now);
+
+        bob.connection
+            .force_handshake(&mut HashMap::new(), &mut bob.transmits, now);
+        exchange(&mut alice, &mut bob, now);
+
+        bob.rotate(3, NEW_KEY, false, now);
+
+        assert_eq!(bob.connection.remote_tunnel_index, Some(1));
+        assert_eq!(
+            bob.connection.tunnel_slot(&handshake_initiation(1)),
+            TunnelSlot::Current
+        );
+        assert_eq!(
+            bob.connection.tunnel_slot(&handshake_initiation(5)),
+            TunnelSlot::Next
+        );
+    }
+
+    /// The start of a handshake initiation from the remote's tunnel with the given index, enough to route it.
+    fn handshake_initiation(index: u32) -> [u8; 8] {
+        let mut packet = [1, 0, 0, 0, 0, 0, 0, 0];
+        packet[4..8].copy_from_slice(&(index << 8).to_le_bytes());
+
+        packet
+    }
+
+    const OLD_KEY: [u8; 32] = [1; 32];
+    const NEW_KEY: [u8; 32] = [2; 32];
+
+    struct Peer {
+        connection: Connection,
+        private_key: StaticSecret,
+        socket: SocketAddr,
+        transmits: VecDeque<Transmit<'static>>,
+    }
+
+    impl Peer {
+        fn rotate(&mut self, index: u32, key: [u8; 32], initiate: bool, now: Instant) {
+            let tunnel = Tunn::new(
+                self.private_key.clone(),
+                self.connection.remote_pub_key,
+                Some(key),
+                None,
+                index,
+                None,
+            );
+
+            self.connection.rotate_tunnel(
+                tunnel,
+                index,
+                initiate,
+                &mut HashMap::new(),
+                &mut self.transmits,
+                now,
+            );
+        }
+
+        /// Delivers everything we want to send to `other`.
+        fn flush(&mut self, other: &mut Peer, now: Instant) {
+            while let Some(transmit) = self.transmits.pop_front() {
+                let mut buffer = [0u8; MAX_UDP_SIZE];
+
+                let result = other.connection.decapsulate(
+                    self.socket,
+                    other.socket,
+                    &transmit.payload,
+                    None,
+                    &mut buffer,
+                    &mut HashMap::new(),
+                    &mut other.transmits,
+                    now,
+                );
+
+                if let ControlFlow::Break(Err(e)) = result {
+                    panic!("Failed to decapsulate packet: {e}")
+                }
+            }
+        }
+    }
+
+    /// Delivers packets back and forth until neither side has anything left to send.
+    fn exchange(alice: &mut Peer, bob: &mut Peer, now: Instant) {
+        while !alice.transmits.is_empty() || !bob.transmits.is_empty() {
+            alice.flush(bob, now);
+            bob.flush(alice, now);
+        }
+    }
+
+    /// Alice's tunnel has index 1, Bob's index 2, both use [`OLD_KEY`].
+    fn alice_and_bob(now: Instant) -> (Peer, Peer) {
+        let alice_key = StaticSecret::random_from_rng(rand::thread_rng());
+        let bob_key = StaticSecret::random_from_rng(rand::thread_rng());
+        let alice_socket = SocketAddr::from(([192, 0, 2, 1], 52625));
+        let bob_socket = SocketAddr::from(([198, 51, 100, 1], 61122));
+
+        let alice = Peer {
+            connection: connection(
+                &alice_key,
+                PublicKey::from(&bob_key),
+                1,
+                alice_socket,
+                bob_socket,
+                now,
+            ),
+            private_key: alice_key,
+            socket: alice_socket,
+            transmits: VecDeque::new(),
+        };
+        let bob = Peer {
+            connection: connection(
+                &bob_key,
+                PublicKey::from(&alice.private_key),
+                2,
+                bob_socket,
+                alice_socket,
+                now,
+            ),
+            private_key: bob_key,
+            socket: bob_socket,
+            transmits: VecDeque::new(),
+        };
+
+        (alice, bob)
+    }
+
+    fn connection(
+        private_key: &StaticSecret,
+        remote: PublicKey,
+        index: u32,
+        local: SocketAddr,
+        dest: SocketAddr,
+        now: Instant,
+    ) -> Connection {
+        Connection {
+            agent: IceAgent::new(),
+            remote_pub_key: remote,
+            tunnel: Tunn::new(
+                private_key.clone(),
+                remote,
+                Some(OLD_KEY),
+                None,
+                index,
+                None,
+            ),
+            tunnel_index: index,
+            remote_tunnel_index: None,
+            next_tunnel: None,
+            previous_tunnel: None,
+            next_timer_update: now,
+            peer_socket: Some(PeerSocket::Direct {
+                source: local,
+                dest,
+            }),
+            possible_sockets: HashSet::new(),
+            stats: Default::default(),
+            pending_binding_requests: HashMap::new(),
+            ice_rtt: None,
+            buffer: Box::new([0u8; MAX_UDP_SIZE]),
+            intent_sent_at: now,
+            is_failed: false,
+            signalling_completed_at: now,
+            held_back_candidates: None,
+            won_by: None,
+            migration_deadline: None,
+        }
+    }
+}
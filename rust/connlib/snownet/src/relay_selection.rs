@@ -0,0 +1,277 @@
+use crate::node::Transmit;
+use crate::stats::AddressFamily;
+use bytecodec::{DecodeExt, EncodeExt};
+use std::{
+    collections::{HashMap, VecDeque},
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+use stun_codec::{rfc5389, Message, MessageClass, TransactionId};
+
+/// How long we wait for a response to a probe before sending another one.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+/// How many unanswered probes we send before we consider a relay unreachable.
+const MAX_PROBE_ATTEMPTS: u32 = 3;
+/// How often we re-measure the RTT to a relay.
+const PROBE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// A SANS-IO state machine that measures the RTT to a relay using STUN binding requests.
+#[derive(Debug)]
+pub struct RelayProbe {
+    server: SocketAddr,
+    /// The smoothed RTT to the relay, `None` if we haven't received a response yet or the relay is unreachable.
+    rtt: Option<Duration>,
+    state: State,
+
+    buffered_transmits: VecDeque<Transmit<'static>>,
+}
+
+impl RelayProbe {
+    pub fn new(server: SocketAddr, now: Instant) -> Self {
+        let mut probe = Self {
+            server,
+            rtt: None,
+            state: State::Idle { next_probe: now },
+            buffered_transmits: Default::default(),
+        };
+        probe.send_probe(1, now);
+
+        probe
+    }
+
+    pub fn rtt(&self) -> Option<Duration> {
+        self.rtt
+    }
+
+    /// Immediately re-measures the RTT, e.g. because our network changed.
+    pub fn refresh(&mut self, now: Instant) {
+        self.send_probe(1, now);
+    }
+
+    /// Handles a response to one of our probes.
+    ///
+    /// Other STUN messages from the relay, e.g. the ones belonging to our allocation, are not accepted.
+    pub fn handle_input(&mut self, from: SocketAddr, packet: &[u8], now: Instant) -> bool {
+        if from != self.server {
+            return false;
+        }
+
+        let State::SentRequest { id, at, .. } = self.state else {
+            return false;
+        };
+
+        let Ok(Ok(message)) =
+            stun_codec::MessageDecoder::<rfc5389::Attribute>::default().decode_from_bytes(packet)
+        else {
+            return false;
+        };
+
+        if message.transaction_id() != id {
+            return false;
+        }
+
+        let sample = now.duration_since(at);
+
+        // Smoothed the same way as TCP does, see <https://www.rfc-editor.org/rfc/rfc6298#section-2>.
+        let rtt = match self.rtt {
+            Some(rtt) => (rtt * 7 + sample) / 8,
+            None => sample,
+        };
+
+        tracing::debug!(relay = %self.server, ?sample, ?rtt, "Measured RTT to relay");
+
+        self.rtt = Some(rtt);
+        self.state = State::Idle {
+            next_probe: now + PROBE_INTERVAL,
+        };
+
+        true
+    }
+
+    pub fn handle_timeout(&mut self, now: Instant) {
+        match self.state {
+            State::SentRequest { at, attempt, .. } if at + PROBE_TIMEOUT <= now => {
+                if attempt >= MAX_PROBE_ATTEMPTS {
+                    tracing::debug!(relay = %self.server, "Relay did not respond to probes");
+
+                    self.rtt = None;
+                    self.state = State::Idle {
+                        next_probe: now + PROBE_INTERVAL,
+                    };
+                    return;
+                }
+
+                self.send_probe(attempt + 1, now);
+            }
+            State::Idle { next_probe } if next_probe <= now => {
+                self.send_probe(1, now);
+            }
+            State::SentRequest { .. } | State::Idle { .. } => {}
+        }
+    }
+
+    pub fn poll_timeout(&self) -> Option<Instant> {
+        match self.state {
+            State::SentRequest { at, .. } => Some(at + PROBE_TIMEOUT),
+            State::Idle { next_probe } => Some(next_probe),
+        }
+    }
+
+    pub fn poll_transmit(&mut self) -> Option<Transmit<'static>> {
+        self.buffered_transmits.pop_front()
+    }
+
+    fn send_probe(&mut self, attempt: u32, now: Instant) {
+        let request = Message::<rfc5389::Attribute>::new(
+            MessageClass::Request,
+            rfc5389::methods::BINDING,
+            TransactionId::new(rand::random()),
+        );
+
+        self.state = State::SentRequest {
+            id: request.transaction_id(),
+            at: now,
+            attempt,
+        };
+        self.buffered_transmits.push_back(Transmit {
+            src: None,
+            dst: self.server,
+            payload: stun_codec::MessageEncoder::<rfc5389::Attribute>::default()
+                .encode_into_bytes(request)
+                .expect("binding requests can always be encoded")
+                .into(),
+            segment_size: None,
+        });
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum State {
+    SentRequest {
+        id: TransactionId,
+        at: Instant,
+        attempt: u32,
+    },
+    Idle {
+        next_probe: Instant,
+    },
+}
+
+/// Ranks the relays of each address family by their RTT, fastest first.
+///
+/// Relays without a measured RTT are not ranked.
+pub fn rank<'a>(
+    probes: impl Iterator<Item = (SocketAddr, &'a RelayProbe)>,
+) -> HashMap<AddressFamily, Vec<SocketAddr>> {
+    let mut measured = probes
+        .filter_map(|(server, probe)| Some((server, probe.rtt()?)))
+        .collect::<Vec<_>>();
+    measured.sort_by_key(|(server, rtt)| (*rtt, *server));
+
+    let mut ranking = HashMap::<AddressFamily, Vec<SocketAddr>>::new();
+
+    for (server, _) in measured {
+        ranking
+            .entry(AddressFamily::of(server))
+            .or_default()
+            .push(server);
+    }
+
+    ranking
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr, SocketAddrV4, SocketAddrV6};
+
+    const RELAY1: SocketAddr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 3478));
+    const RELAY2: SocketAddr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 2), 3478));
+    const RELAY3: SocketAddr = SocketAddr::V6(SocketAddrV6::new(Ipv6Addr::LOCALHOST, 3478, 0, 0));
+
+    #[test]
+    fn measures_rtt_from_response() {
+        let start = Instant::now();
+        let mut probe = RelayProbe::new(RELAY1, start);
+
+        let request = probe.poll_transmit().unwrap();
+        let response = response_to(&request.payload);
+
+        assert!(probe.handle_input(RELAY1, &response, start + Duration::from_millis(40)));
+        assert_eq!(probe.rtt(), Some(Duration::from_millis(40)));
+    }
+
+    #[test]
+    fn ignores_unrelated_messages() {
+        let start = Instant::now();
+        let mut probe = RelayProbe::new(RELAY1, start);
+        let _ = probe.poll_transmit().unwrap();
+
+        let unrelated = encode(Message::<rfc5389::Attribute>::new(
+            MessageClass::SuccessResponse,
+            rfc5389::methods::BINDING,
+            TransactionId::new([1; 12]),
+        ));
+
+        assert!(!probe.handle_input(RELAY1, &unrelated, start));
+        assert_eq!(probe.rtt(), None);
+    }
+
+    #[test]
+    fn unresponsive_relay_has_no_rtt() {
+        let mut now = Instant::now();
+        let mut probe = RelayProbe::new(RELAY1, now);
+
+        for _ in 0..MAX_PROBE_ATTEMPTS {
+            now += PROBE_TIMEOUT;
+            probe.handle_timeout(now);
+        }
+
+        assert_eq!(probe.rtt(), None);
+        assert_eq!(probe.poll_timeout(), Some(now + PROBE_INTERVAL));
+    }
+
+    #[test]
+    fn ranks_relays_per_address_family() {
+        let start = Instant::now();
+
+        let fast = measured_probe(RELAY1, start, Duration::from_millis(10));
+        let slow = measured_probe(RELAY2, start, Duration::from_millis(80));
+        let ipv6 = measured_probe(RELAY3, start, Duration::from_millis(50));
+        let unmeasured = RelayProbe::new(RELAY2, start);
+
+        let ranking = rank([(RELAY2, &slow), (RELAY1, &fast), (RELAY3, &ipv6)].into_iter());
+
+        assert_eq!(ranking[&AddressFamily::V4], vec![RELAY1, RELAY2]);
+        assert_eq!(ranking[&AddressFamily::V6], vec![RELAY3]);
+        assert!(rank([(RELAY2, &unmeasured)].into_iter()).is_empty());
+    }
+
+    fn measured_probe(server: SocketAddr, start: Instant, rtt: Duration) -> RelayProbe {
+        let mut probe = RelayProbe::new(server, start);
+        let request = probe.poll_transmit().unwrap();
+
+        assert!(probe.handle_input(server, &response_to(&request.payload), start + rtt));
+
+        probe
+    }
+
+    fn response_to(request: &[u8]) -> Vec<u8> {
+        let request = stun_codec::MessageDecoder::<rfc5389::Attribute>::default()
+            .decode_from_bytes(request)
+            .unwrap()
+            .unwrap();
+
+        encode(Message::<rfc5389::Attribute>::new(
+            MessageClass::SuccessResponse,
+            rfc5389::methods::BINDING,
+            request.transaction_id(),
+        ))
+    }
+
+    fn encode(message: Message<rfc5389::Attribute>) -> Vec<u8> {
+        stun_codec::MessageEncoder::<rfc5389::Attribute>::default()
+            .encode_into_bytes(message)
+            .unwrap()
+    }
+}
@@ -1,9 +1,31 @@
+use std::net::SocketAddr;
 use std::ops::AddAssign;
+use std::time::Duration;
 
-#[derive(Default, Debug, Clone, Copy)]
+#[derive(Default, Debug, Clone)]
 pub struct NodeStats {
     /// How many bytes we sent as part of exchanging STUN messages with relays (control messages only).
     pub stun_bytes_to_relays: HumanBytes,
+
+    /// How many of the current connections first nominated an IPv4 candidate pair.
+    pub connections_won_by_ipv4: usize,
+    /// How many of the current connections first nominated an IPv6 candidate pair.
+    pub connections_won_by_ipv6: usize,
+
+    /// All TURN servers, ordered by address family and then by their RTT, fastest first.
+    pub relays: Vec<RelayStats>,
+
+    /// The behaviour of the NAT we are behind, as far as we could detect it via our STUN servers.
+    pub nat_type: NatType,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RelayStats {
+    pub server: SocketAddr,
+    /// The smoothed RTT of our STUN binding requests to the relay, `None` if it didn't respond (yet).
+    pub rtt: Option<Duration>,
+    /// Whether we currently have an allocation on this relay.
+    pub allocated: bool,
 }
 
 #[derive(Default, Debug, Clone, Copy)]
@@ -12,6 +34,90 @@ pub struct ConnectionStats {
     pub stun_bytes_to_peer_direct: HumanBytes,
     /// How many bytes we sent as part of exchanging STUN messages to other peers via relays.
     pub stun_bytes_to_peer_relayed: HumanBytes,
+
+    /// The most recent round-trip time of an ICE binding request (i.e. a consent check) to the remote.
+    pub ice_rtt: Option<Duration>,
+    /// The round-trip time of the most recent wireguard handshake, as estimated by [`boringtun`].
+    pub wireguard_rtt: Option<Duration>,
+    /// The estimated fraction of packets from the remote that were lost, based on the wireguard packet counters.
+    pub estimated_loss: f32,
+
+    /// How many IP packets we sent through the tunnel.
+    pub tx_packets: u64,
+    /// How many bytes of IP packets we sent through the tunnel.
+    pub tx_bytes: HumanBytes,
+    /// How many IP packets we received through the tunnel.
+    pub rx_packets: u64,
+    /// How many bytes of IP packets we received through the tunnel.
+    pub rx_bytes: HumanBytes,
+
+    /// The type of path we are using to talk to the remote, `None` if we are not yet connected.
+    pub path: Option<PathType>,
+    /// The relay in use, if any.
+    ///
+    /// If we send via one of our allocations, this is the address of the TURN server.
+    /// If we send directly to the relayed candidate of the remote, this is the address of that candidate.
+    pub relay: Option<SocketAddr>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum AddressFamily {
+    V4,
+    V6,
+}
+
+impl AddressFamily {
+    pub(crate) fn of(addr: SocketAddr) -> Self {
+        match addr {
+            SocketAddr::V4(_) => AddressFamily::V4,
+            SocketAddr::V6(_) => AddressFamily::V6,
+        }
+    }
+}
+
+/// The type of the selected candidate pair.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathType {
+    /// Both sides talk to each other via their host candidates, i.e. without any NAT in-between.
+    Host,
+    /// At least one side talks via its server-reflexive (or peer-reflexive) candidate, i.e. through a NAT.
+    ServerReflexive,
+    /// At least one side talks via a relay.
+    Relayed,
+}
+
+/// The behaviour of a NAT, classified according to <https://www.rfc-editor.org/rfc/rfc5780>.
+///
+/// Each part is `None` until we have enough information to classify it.
+/// Classifying the mapping requires at least two STUN servers on different IPs.
+/// Classifying the filtering requires a STUN server that supports RFC 5780.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NatType {
+    pub mapping: Option<NatBehaviour>,
+    pub filtering: Option<NatBehaviour>,
+}
+
+impl NatType {
+    /// Whether the NAT uses a different mapping for each destination, also known as a "symmetric" NAT.
+    pub fn is_symmetric(&self) -> bool {
+        matches!(
+            self.mapping,
+            Some(NatBehaviour::AddressDependent | NatBehaviour::AddressAndPortDependent)
+        )
+    }
+}
+
+/// What the mapping or filtering of a NAT depends on, see <https://www.rfc-editor.org/rfc/rfc4787#section-4>.
+///
+/// Ordered from the least to the most restrictive behaviour.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum NatBehaviour {
+    /// The NAT treats traffic to and from all destinations the same.
+    EndpointIndependent,
+    /// The NAT treats traffic depending on the IP of the destination.
+    AddressDependent,
+    /// The NAT treats traffic depending on the IP and port of the destination.
+    AddressAndPortDependent,
 }
 
 #[derive(Default, Clone, Copy)]
@@ -122,9 +122,13 @@ impl StunBinding {
         true
     }
 
+    /// Sends a new binding request, e.g. because our network changed.
+    ///
+    /// The resulting candidate is always reported as new because the previous one was likely invalidated.
     pub(crate) fn refresh(&mut self, now: Instant) {
         self.last_now = now;
         self.backoff.clock.now = now;
+        self.last_candidate = None;
 
         self.backoff.reset();
         let backoff = self
@@ -223,6 +227,7 @@ fn new_binding_request(
         src: None,
         dst: server,
         payload: encode(request).into(),
+        segment_size: None,
     };
 
     (state, transmit)
@@ -1,9 +1,14 @@
 use boringtun::x25519::{PublicKey, StaticSecret};
 use firezone_relay::{AddressFamily, AllocationPort, ClientSocket, IpStack, PeerSocket};
+use pnet_packet::ipv4::MutableIpv4Packet;
 use rand::rngs::OsRng;
-use snownet::{Answer, ClientNode, Event, MutableIpPacket, ServerNode, Transmit};
+use secrecy::Secret;
+use snownet::{
+    AddressFamilyPreference, Answer, CaptureFilter, ClientNode, ConnectionStats, Event, IpPacket,
+    MutableIpPacket, NodeStats, PathType, ServerNode, Transmit,
+};
 use std::{
-    collections::HashSet,
+    collections::{HashSet, VecDeque},
     iter,
     net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4},
     time::{Duration, Instant, SystemTime},
@@ -64,6 +69,249 @@ fn smoke_relayed() {
     }
 }
 
+#[test]
+fn direct_connection_reports_host_path() {
+    let _guard = setup_tracing();
+
+    let (alice, bob) = alice_and_bob();
+
+    let mut alice =
+        TestNode::new(info_span!("Alice"), alice, "1.1.1.1:80").with_primary_as_host_candidate();
+    let mut bob =
+        TestNode::new(info_span!("Bob"), bob, "1.1.1.2:80").with_primary_as_host_candidate();
+    let firewall = Firewall::default();
+    let mut clock = Clock::new();
+
+    handshake(&mut alice, &mut bob, &[], &clock);
+
+    while !(alice.is_connected_to(&bob) && bob.is_connected_to(&alice)) {
+        progress(&mut alice, &mut bob, &mut [], &firewall, &mut clock);
+    }
+
+    let stats = alice.connection_stats(1);
+
+    assert_eq!(stats.path, Some(PathType::Host));
+    assert_eq!(stats.relay, None);
+    assert!(stats.ice_rtt.is_some());
+}
+
+#[test]
+fn relayed_connection_reports_relay_path() {
+    let _guard = setup_tracing();
+
+    let (alice, bob) = alice_and_bob();
+
+    let relay = TestRelay::new(IpAddr::V4(Ipv4Addr::LOCALHOST), debug_span!("Roger"));
+    let mut alice = TestNode::new(debug_span!("Alice"), alice, "1.1.1.1:80");
+    let mut bob = TestNode::new(debug_span!("Bob"), bob, "2.2.2.2:80");
+    let firewall = Firewall::default()
+        .with_block_rule("1.1.1.1:80", "2.2.2.2:80")
+        .with_block_rule("2.2.2.2:80", "1.1.1.1:80");
+    let mut clock = Clock::new();
+
+    let mut relays = [relay];
+
+    handshake(&mut alice, &mut bob, &relays, &clock);
+
+    while !(alice.is_connected_to(&bob) && bob.is_connected_to(&alice)) {
+        progress(&mut alice, &mut bob, &mut relays, &firewall, &mut clock);
+    }
+
+    for stats in [alice.connection_stats(1), bob.connection_stats(1)] {
+        assert_eq!(stats.path, Some(PathType::Relayed));
+        assert!(stats.relay.is_some());
+    }
+}
+
+#[test]
+fn relayed_connection_upgrades_to_direct_path() {
+    let _guard = setup_tracing();
+
+    let (alice, bob) = alice_and_bob();
+
+    let relay = TestRelay::new(IpAddr::V4(Ipv4Addr::LOCALHOST), debug_span!("Roger"));
+    let mut alice =
+        TestNode::new(debug_span!("Alice"), alice, "1.1.1.1:80").with_primary_as_host_candidate();
+    let mut bob =
+        TestNode::new(debug_span!("Bob"), bob, "2.2.2.2:80").with_primary_as_host_candidate();
+    let mut firewall = Firewall::default()
+        .with_block_rule("1.1.1.1:80", "2.2.2.2:80")
+        .with_block_rule("2.2.2.2:80", "1.1.1.1:80");
+    let mut clock = Clock::new();
+
+    let mut relays = [relay];
+
+    handshake(&mut alice, &mut bob, &relays, &clock);
+
+    while !(alice.is_connected_to(&bob) && bob.is_connected_to(&alice)) {
+        progress(&mut alice, &mut bob, &mut relays, &firewall, &mut clock);
+    }
+
+    assert_eq!(alice.connection_stats(1).path, Some(PathType::Relayed));
+
+    // The NAT mappings warmed up, hole-punching now succeeds.
+    firewall = Firewall::default();
+
+    while alice.upgraded_connections().next().is_none() {
+        progress(&mut alice, &mut bob, &mut relays, &firewall, &mut clock);
+    }
+
+    assert_eq!(alice.connection_stats(1).path, Some(PathType::Host));
+    assert!(alice.failed_connections().next().is_none());
+    assert!(alice.is_connected_to(&bob));
+}
+
+#[test]
+fn ipv6_first_preference_gives_ipv6_a_head_start() {
+    let _guard = setup_tracing();
+
+    let (mut alice, mut bob) = alice_and_bob();
+
+    let preference = AddressFamilyPreference::Ipv6First {
+        head_start: Duration::from_secs(1),
+    };
+    alice.set_address_family_preference(preference);
+    bob.set_address_family_preference(preference);
+
+    let mut alice = TestNode::new(info_span!("Alice"), alice, "1.1.1.1:80")
+        .with_primary_as_host_candidate()
+        .with_host_candidate("[2001:db8::1]:80");
+    let mut bob = TestNode::new(info_span!("Bob"), bob, "1.1.1.2:80")
+        .with_primary_as_host_candidate()
+        .with_host_candidate("[2001:db8::2]:80");
+    let firewall = Firewall::default();
+    let mut clock = Clock::new();
+
+    handshake(&mut alice, &mut bob, &[], &clock);
+
+    while !(alice.is_connected_to(&bob) && bob.is_connected_to(&alice)) {
+        progress(&mut alice, &mut bob, &mut [], &firewall, &mut clock);
+    }
+
+    assert_eq!(alice.node_stats().connections_won_by_ipv6, 1);
+    assert_eq!(alice.node_stats().connections_won_by_ipv4, 0);
+}
+
+#[test]
+fn ipv6_first_preference_falls_back_to_ipv4_after_head_start() {
+    let _guard = setup_tracing();
+
+    let (mut alice, mut bob) = alice_and_bob();
+
+    let head_start = Duration::from_secs(1);
+    let preference = AddressFamilyPreference::Ipv6First { head_start };
+    alice.set_address_family_preference(preference);
+    bob.set_address_family_preference(preference);
+
+    let mut alice = TestNode::new(info_span!("Alice"), alice, "1.1.1.1:80")
+        .with_primary_as_host_candidate()
+        .with_host_candidate("[2001:db8::1]:80");
+    let mut bob = TestNode::new(info_span!("Bob"), bob, "1.1.1.2:80")
+        .with_primary_as_host_candidate()
+        .with_host_candidate("[2001:db8::2]:80");
+    let firewall = Firewall::default()
+        .with_block_rule("[2001:db8::1]:80", "[2001:db8::2]:80")
+        .with_block_rule("[2001:db8::2]:80", "[2001:db8::1]:80");
+    let mut clock = Clock::new();
+
+    handshake(&mut alice, &mut bob, &[], &clock);
+
+    while !(alice.is_connected_to(&bob) && bob.is_connected_to(&alice)) {
+        progress(&mut alice, &mut bob, &mut [], &firewall, &mut clock);
+    }
+
+    let setup_time = clock.now.duration_since(clock.start);
+
+    assert!(
+        setup_time >= head_start,
+        "IPv4 must not be tried before the head start is over"
+    );
+    assert!(
+        setup_time < head_start * 2,
+        "IPv4 should connect right after the head start, took {setup_time:?}"
+    );
+    assert_eq!(alice.node_stats().connections_won_by_ipv4, 1);
+    assert_eq!(alice.node_stats().connections_won_by_ipv6, 0);
+}
+
+#[test]
+fn allocates_only_on_fastest_relay_per_family() {
+    let _guard = setup_tracing();
+
+    let (mut alice, mut bob) = alice_and_bob();
+    let mut clock = Clock::new();
+    alice.set_max_relays_per_family(1, clock.now);
+    bob.set_max_relays_per_family(1, clock.now);
+
+    let roger = TestRelay::new(
+        IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+        debug_span!("Roger"),
+    )
+    .with_latency(Duration::from_millis(300));
+    let rachel = TestRelay::new(
+        IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2)),
+        debug_span!("Rachel"),
+    );
+    let rachel_addr = rachel.listen_addr;
+    let mut alice = TestNode::new(debug_span!("Alice"), alice, "1.1.1.1:80");
+    let mut bob = TestNode::new(debug_span!("Bob"), bob, "2.2.2.2:80");
+    let firewall = Firewall::default()
+        .with_block_rule("1.1.1.1:80", "2.2.2.2:80")
+        .with_block_rule("2.2.2.2:80", "1.1.1.1:80");
+
+    let mut relays = [roger, rachel];
+
+    handshake(&mut alice, &mut bob, &relays, &clock);
+
+    while !(alice.is_connected_to(&bob) && bob.is_connected_to(&alice)) {
+        progress(&mut alice, &mut bob, &mut relays, &firewall, &mut clock);
+    }
+
+    let ranking = alice.node_stats().relays;
+
+    assert_eq!(ranking.len(), 2);
+    assert!(ranking.iter().all(|r| r.rtt.is_some()));
+    assert_eq!(ranking.iter().filter(|r| r.allocated).count(), 1);
+    assert_eq!(ranking[0].server, rachel_addr);
+    assert!(ranking[0].rtt < ranking[1].rtt);
+    assert!(ranking[0].allocated);
+}
+
+#[test]
+fn does_not_probe_relays_without_limit() {
+    let _guard = setup_tracing();
+
+    let (alice, bob) = alice_and_bob();
+
+    let roger = TestRelay::new(
+        IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+        debug_span!("Roger"),
+    );
+    let rachel = TestRelay::new(
+        IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2)),
+        debug_span!("Rachel"),
+    );
+    let mut alice = TestNode::new(debug_span!("Alice"), alice, "1.1.1.1:80");
+    let mut bob = TestNode::new(debug_span!("Bob"), bob, "2.2.2.2:80");
+    let firewall = Firewall::default()
+        .with_block_rule("1.1.1.1:80", "2.2.2.2:80")
+        .with_block_rule("2.2.2.2:80", "1.1.1.1:80");
+    let mut clock = Clock::new();
+
+    let mut relays = [roger, rachel];
+
+    handshake(&mut alice, &mut bob, &relays, &clock);
+
+    while !(alice.is_connected_to(&bob) && bob.is_connected_to(&alice)) {
+        progress(&mut alice, &mut bob, &mut relays, &firewall, &mut clock);
+    }
+
+    let ranking = alice.node_stats().relays;
+
+    assert_eq!(ranking.len(), 2);
+    assert!(ranking.iter().all(|r| r.rtt.is_none() && r.allocated));
+}
+
 #[test]
 fn reconnect_discovers_new_interface() {
     let _guard = setup_tracing();
@@ -103,6 +351,63 @@ fn reconnect_discovers_new_interface() {
     assert_eq!(bob.failed_connections().count(), 0);
 }
 
+#[test]
+fn connection_migrates_to_new_network_without_failing() {
+    let _guard = setup_tracing();
+
+    let (alice, bob) = alice_and_bob();
+
+    let relay = TestRelay::new(IpAddr::V4(Ipv4Addr::LOCALHOST), debug_span!("Roger"));
+    let mut alice = TestNode::new(debug_span!("Alice"), alice, "1.1.1.1:80");
+    let mut bob = TestNode::new(debug_span!("Bob"), bob, "2.2.2.2:80");
+    let mut firewall = Firewall::default();
+    let mut clock = Clock::new();
+
+    let mut relays = [relay];
+
+    handshake(&mut alice, &mut bob, &relays, &clock);
+
+    while !(alice.is_connected_to(&bob) && bob.is_connected_to(&alice)) {
+        progress(&mut alice, &mut bob, &mut relays, &firewall, &mut clock);
+    }
+
+    let handshakes_before_migration = alice.handshake_initiations + bob.handshake_initiations;
+
+    // Alice leaves her old network, nothing can reach it anymore.
+    alice.switch_network("10.0.0.1:80");
+    alice.node.reconnect(clock.now);
+    firewall = firewall
+        .with_block_rule("1.1.1.1:80", "2.2.2.2:80")
+        .with_block_rule("2.2.2.2:80", "1.1.1.1:80");
+
+    // Until a new path is nominated, we keep sending on the old one.
+    alice.send(1, &ip_packet(1), clock.now);
+
+    let one_minute_later = clock.now + Duration::from_secs(60);
+    while clock.now < one_minute_later {
+        progress(&mut alice, &mut bob, &mut relays, &firewall, &mut clock);
+    }
+
+    assert_eq!(alice.failed_connections().count(), 0);
+    assert_eq!(bob.failed_connections().count(), 0);
+    assert!(alice.is_connected_to(&bob));
+    assert!(alice.connection_stats(1).path.is_some());
+    assert_eq!(
+        alice.handshake_initiations + bob.handshake_initiations,
+        handshakes_before_migration,
+        "migrating should keep the wireguard session"
+    );
+
+    // The old path is blocked, so this can only arrive via the new one.
+    let received_before = bob.received_packets.len();
+    alice.send(1, &ip_packet(2), clock.now);
+    for _ in 0..10 {
+        progress(&mut alice, &mut bob, &mut relays, &firewall, &mut clock);
+    }
+
+    assert_eq!(bob.received_packets.len(), received_before + 1);
+}
+
 #[test]
 fn connection_times_out_after_20_seconds() {
     let (mut alice, _) = alice_and_bob();
@@ -217,6 +522,73 @@ fn only_generate_candidate_event_after_answer() {
         }));
 }
 
+#[test]
+fn rotating_preshared_key_does_not_drop_packets() {
+    let _guard = setup_tracing();
+
+    let (alice, bob) = alice_and_bob();
+
+    let mut alice =
+        TestNode::new(info_span!("Alice"), alice, "1.1.1.1:80").with_primary_as_host_candidate();
+    let mut bob =
+        TestNode::new(info_span!("Bob"), bob, "1.1.1.2:80").with_primary_as_host_candidate();
+    let firewall = Firewall::default();
+    let mut clock = Clock::new();
+
+    handshake(&mut alice, &mut bob, &[], &clock);
+
+    while !(alice.is_connected_to(&bob) && bob.is_connected_to(&alice)) {
+        progress(&mut alice, &mut bob, &mut [], &firewall, &mut clock);
+    }
+
+    let sent =
+        exchange_packets_whilst_rotating_key(&mut alice, &mut bob, &mut [], &firewall, &mut clock);
+
+    assert_eq!(alice.received_packets.len(), sent);
+    assert_eq!(bob.received_packets.len(), sent);
+    assert_eq!(alice.failed_connections().count(), 0);
+    assert_eq!(bob.failed_connections().count(), 0);
+}
+
+#[test]
+fn rotating_preshared_key_of_relayed_connection_does_not_drop_packets() {
+    let _guard = setup_tracing();
+
+    let (alice, bob) = alice_and_bob();
+
+    let relay = TestRelay::new(IpAddr::V4(Ipv4Addr::LOCALHOST), debug_span!("Roger"));
+    let mut alice = TestNode::new(debug_span!("Alice"), alice, "1.1.1.1:80");
+    let mut bob = TestNode::new(debug_span!("Bob"), bob, "2.2.2.2:80");
+    let firewall = Firewall::default()
+        .with_block_rule("1.1.1.1:80", "2.2.2.2:80")
+        .with_block_rule("2.2.2.2:80", "1.1.1.1:80");
+    let mut clock = Clock::new();
+
+    let mut relays = [relay];
+
+    handshake(&mut alice, &mut bob, &relays, &clock);
+
+    while !(alice.is_connected_to(&bob) && bob.is_connected_to(&alice)) {
+        progress(&mut alice, &mut bob, &mut relays, &firewall, &mut clock);
+    }
+
+    let sent = exchange_packets_whilst_rotating_key(
+        &mut alice,
+        &mut bob,
+        &mut relays,
+        &firewall,
+        &mut clock,
+    );
+
+    assert_eq!(alice.received_packets.len(), sent);
+    assert_eq!(bob.received_packets.len(), sent);
+    assert_eq!(
+        alice.connection_stats(1).path,
+        Some(PathType::Relayed),
+        "connection should stay relayed"
+    );
+}
+
 #[test]
 fn second_connection_with_same_relay_reuses_allocation() {
     let mut alice = ClientNode::<u64>::new(StaticSecret::random_from_rng(rand::thread_rng()));
@@ -244,6 +616,139 @@ fn second_connection_with_same_relay_reuses_allocation() {
     assert!(alice.poll_transmit().is_none());
 }
 
+#[test]
+fn capture_records_sent_and_received_packets() {
+    let _guard = setup_tracing();
+
+    let (alice, bob) = alice_and_bob();
+
+    let mut alice =
+        TestNode::new(info_span!("Alice"), alice, "1.1.1.1:80").with_primary_as_host_candidate();
+    let mut bob =
+        TestNode::new(info_span!("Bob"), bob, "1.1.1.2:80").with_primary_as_host_candidate();
+    let firewall = Firewall::default();
+    let mut clock = Clock::new();
+
+    handshake(&mut alice, &mut bob, &[], &clock);
+
+    while !(alice.is_connected_to(&bob) && bob.is_connected_to(&alice)) {
+        progress(&mut alice, &mut bob, &mut [], &firewall, &mut clock);
+    }
+
+    alice.node.start_capture(
+        CaptureFilter::AllConnections,
+        Duration::from_secs(60),
+        1024 * 1024,
+        clock.now,
+        SystemTime::now(),
+    );
+
+    alice.send(1, &ip_packet(1), clock.now);
+    progress(&mut alice, &mut bob, &mut [], &firewall, &mut clock);
+    bob.send(1, &ip_packet(2), clock.now);
+    progress(&mut alice, &mut bob, &mut [], &firewall, &mut clock);
+
+    let capture = alice.node.stop_capture().unwrap();
+
+    assert_eq!(bob.received_packets.len(), 1);
+    assert_eq!(alice.received_packets.len(), 1);
+    assert_eq!(captured_packets(&capture), vec![ip_packet(1), ip_packet(2)]);
+    assert!(alice.node.stop_capture().is_none());
+}
+
+/// Sends a packet in both directions every tick, rotates the preshared key on both ends after 5 seconds and keeps sending until the previous key was discarded.
+///
+/// Returns the number of packets sent in each direction.
+fn exchange_packets_whilst_rotating_key(
+    alice: &mut TestNode,
+    bob: &mut TestNode,
+    relays: &mut [TestRelay],
+    firewall: &Firewall,
+    clock: &mut Clock,
+) -> usize {
+    let start = clock.now;
+    let rotate_at = start + Duration::from_secs(5);
+    let stop_at = start + Duration::from_secs(30);
+
+    let mut rotated = false;
+    let mut sent = 0;
+
+    while clock.now < stop_at {
+        // The server installs the key first so it is ready for the client's handshake.
+        if !rotated && clock.now >= rotate_at {
+            let key = Secret::new(rand::random());
+
+            bob.rotate_preshared_key(1, key.clone(), clock.now);
+            alice.rotate_preshared_key(1, key, clock.now);
+            rotated = true;
+        }
+
+        alice.send(1, &ip_packet(sent as u16), clock.now);
+        bob.send(1, &ip_packet(sent as u16), clock.now);
+        sent += 1;
+
+        progress(alice, bob, relays, firewall, clock);
+    }
+
+    // Deliver everything that is still in-flight.
+    for _ in 0..10 {
+        progress(alice, bob, relays, firewall, clock);
+    }
+
+    sent
+}
+
+/// Whether the payload is a wireguard handshake initiation, either as-is or wrapped in a TURN channel-data message.
+fn is_handshake_initiation(payload: &[u8]) -> bool {
+    const HANDSHAKE_INITIATION_LEN: usize = 148;
+
+    let message = match payload.first() {
+        Some(0x40..=0x4F) => payload.get(4..).unwrap_or_default(), // Skip the channel-data header.
+        _ => payload,
+    };
+
+    message.len() == HANDSHAKE_INITIATION_LEN && message.starts_with(&[1, 0, 0, 0])
+}
+
+/// Extracts the packets of all enhanced packet blocks of a pcapng file.
+fn captured_packets(mut file: &[u8]) -> Vec<Vec<u8>> {
+    const ENHANCED_PACKET_BLOCK: u32 = 6;
+
+    let u32_at = |bytes: &[u8], offset: usize| {
+        u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap()) as usize
+    };
+
+    let mut packets = Vec::new();
+
+    while !file.is_empty() {
+        let (block, rest) = file.split_at(u32_at(file, 4));
+
+        if u32_at(block, 0) == ENHANCED_PACKET_BLOCK as usize {
+            let captured_len = u32_at(block, 20);
+            packets.push(block[28..28 + captured_len].to_vec());
+        }
+
+        file = rest;
+    }
+
+    packets
+}
+
+fn ip_packet(id: u16) -> Vec<u8> {
+    let mut buf = vec![0u8; 20];
+
+    let mut packet = MutableIpv4Packet::new(&mut buf).unwrap();
+    packet.set_version(4);
+    packet.set_header_length(5);
+    packet.set_total_length(20);
+    packet.set_identification(id);
+    packet.set_ttl(64);
+    packet.set_source(Ipv4Addr::new(100, 64, 0, 1));
+    packet.set_destination(Ipv4Addr::new(100, 64, 0, 2));
+
+    buf
+}
+
 fn setup_tracing() -> tracing::subscriber::DefaultGuard {
     tracing_subscriber::fmt()
         .with_test_writer()
@@ -303,6 +808,10 @@ struct TestNode {
     /// All local interfaces.
     local: Vec<SocketAddr>,
     events: Vec<(Event<u64>, Instant)>,
+    /// Packets we encapsulated, waiting to be dispatched alongside the node's own transmits.
+    buffered_transmits: VecDeque<Transmit<'static>>,
+    /// How many wireguard handshake initiations we sent, directly or via a relay.
+    handshake_initiations: usize,
 
     buffer: Box<[u8; 10_000]>,
 }
@@ -314,6 +823,11 @@ struct TestRelay {
 
     allocations: HashSet<(AddressFamily, AllocationPort)>,
     buffer: Vec<u8>,
+
+    /// How long messages of the relay to its clients (e.g. responses to probes) take to arrive.
+    latency: Duration,
+    /// Messages to clients that are still in flight, in the order they were sent.
+    delayed_messages: VecDeque<(Instant, SocketAddr, Vec<u8>)>,
 }
 
 #[derive(Default)]
@@ -377,9 +891,17 @@ impl TestRelay {
             span,
             allocations: HashSet::default(),
             buffer: vec![0u8; (1 << 16) - 1],
+            latency: Duration::ZERO,
+            delayed_messages: VecDeque::default(),
         }
     }
 
+    fn with_latency(mut self, latency: Duration) -> Self {
+        self.latency = latency;
+
+        self
+    }
+
     fn wants(&self, dst: SocketAddr) -> bool {
         self.listen_addr == dst
             || self.allocations.contains(&match dst {
@@ -459,7 +981,7 @@ impl TestRelay {
     ) {
         if let Some((client, channel)) = self
             .span
-            .in_scope(|| self.inner.handle_peer_traffic(payload, peer, port))
+            .in_scope(|| self.inner.handle_peer_traffic(payload, peer, port, now))
         {
             let full_length = firezone_relay::ChannelData::encode_header_to_slice(
                 channel,
@@ -481,18 +1003,11 @@ impl TestRelay {
         while let Some(command) = self.inner.next_command() {
             match command {
                 firezone_relay::Command::SendMessage { payload, recipient } => {
-                    let recipient = recipient.into_socket();
-                    if a1.local.contains(&recipient) {
-                        a1.receive(recipient, self.listen_addr, &payload, now);
-                        continue;
-                    }
-
-                    if a2.local.contains(&recipient) {
-                        a2.receive(recipient, self.listen_addr, &payload, now);
-                        continue;
-                    }
-
-                    panic!("Relay generated traffic for unknown client")
+                    self.delayed_messages.push_back((
+                        now + self.latency,
+                        recipient.into_socket(),
+                        payload,
+                    ));
                 }
                 firezone_relay::Command::CreateAllocation { port, family } => {
                     self.allocations.insert((family, port));
@@ -500,8 +1015,34 @@ impl TestRelay {
                 firezone_relay::Command::FreeAllocation { port, family } => {
                     self.allocations.remove(&(family, port));
                 }
+                firezone_relay::Command::RelayToPeer { .. } => {
+                    panic!("snownet only relays data via channels")
+                }
+                firezone_relay::Command::ForwardToRelay { .. } => {
+                    panic!("Relay peering is not configured")
+                }
             }
         }
+
+        while self
+            .delayed_messages
+            .front()
+            .is_some_and(|(arrives_at, _, _)| *arrives_at <= now)
+        {
+            let (_, recipient, payload) = self.delayed_messages.pop_front().unwrap();
+
+            if a1.local.contains(&recipient) {
+                a1.receive(recipient, self.listen_addr, &payload, now);
+                continue;
+            }
+
+            if a2.local.contains(&recipient) {
+                a2.receive(recipient, self.listen_addr, &payload, now);
+                continue;
+            }
+
+            panic!("Relay generated traffic for unknown client")
+        }
     }
 
     fn make_credentials(&self, username: &str) -> (String, String) {
@@ -537,7 +1078,7 @@ impl From<ServerNode<u64>> for EitherNode {
 }
 
 impl EitherNode {
-    fn poll_transmit(&mut self) -> Option<Transmit> {
+    fn poll_transmit(&mut self) -> Option<Transmit<'static>> {
         match self {
             EitherNode::Client(n) => n.poll_transmit(),
             EitherNode::Server(n) => n.poll_transmit(),
@@ -621,12 +1162,68 @@ impl EitherNode {
         }
     }
 
+    fn encapsulate(
+        &mut self,
+        id: u64,
+        packet: IpPacket<'_>,
+        now: Instant,
+    ) -> Result<Option<Transmit<'static>>, snownet::Error> {
+        let transmit = match self {
+            EitherNode::Client(n) => n.encapsulate(id, packet, now)?,
+            EitherNode::Server(n) => n.encapsulate(id, packet, now)?,
+        };
+
+        Ok(transmit.map(|t| t.into_owned()))
+    }
+
+    fn rotate_preshared_key(&mut self, id: u64, key: Secret<[u8; 32]>, now: Instant) {
+        match self {
+            EitherNode::Client(n) => n.rotate_preshared_key(id, key, now).unwrap(),
+            EitherNode::Server(n) => n.rotate_preshared_key(id, key, now).unwrap(),
+        }
+    }
+
+    fn start_capture(
+        &mut self,
+        filter: CaptureFilter<u64>,
+        duration: Duration,
+        max_bytes: usize,
+        now: Instant,
+        now_unix: SystemTime,
+    ) {
+        match self {
+            EitherNode::Client(n) => n.start_capture(filter, duration, max_bytes, now, now_unix),
+            EitherNode::Server(n) => n.start_capture(filter, duration, max_bytes, now, now_unix),
+        }
+    }
+
+    fn stop_capture(&mut self) -> Option<Vec<u8>> {
+        match self {
+            EitherNode::Client(n) => n.stop_capture(),
+            EitherNode::Server(n) => n.stop_capture(),
+        }
+    }
+
     fn reconnect(&mut self, now: Instant) {
         match self {
             EitherNode::Client(n) => n.reconnect(now),
             EitherNode::Server(n) => n.reconnect(now),
         }
     }
+
+    fn node_stats(&self) -> NodeStats {
+        match self {
+            EitherNode::Client(n) => n.stats().0,
+            EitherNode::Server(n) => n.stats().0,
+        }
+    }
+
+    fn connection_stats(&self, id: u64) -> Option<ConnectionStats> {
+        match self {
+            EitherNode::Client(n) => n.stats().1.find(|(c, _)| *c == id).map(|(_, s)| s),
+            EitherNode::Server(n) => n.stats().1.find(|(c, _)| *c == id).map(|(_, s)| s),
+        }
+    }
 }
 
 impl TestNode {
@@ -641,6 +1238,8 @@ impl TestNode {
             primary,
             local: vec![primary],
             events: Default::default(),
+            buffered_transmits: Default::default(),
+            handshake_initiations: 0,
         }
     }
 
@@ -653,6 +1252,14 @@ impl TestNode {
         self.node.is_connected_to(other.node.public_key())
     }
 
+    fn connection_stats(&self, id: u64) -> ConnectionStats {
+        self.node.connection_stats(id).unwrap()
+    }
+
+    fn node_stats(&self) -> NodeStats {
+        self.node.node_stats()
+    }
+
     fn signalled_candidates(&self) -> impl Iterator<Item = (u64, Candidate, Instant)> + '_ {
         self.events.iter().filter_map(|(e, instant)| match e {
             Event::SignalIceCandidate {
@@ -663,7 +1270,9 @@ impl TestNode {
                 Candidate::from_sdp_string(candidate).unwrap(),
                 *instant,
             )),
-            Event::ConnectionEstablished(_) | Event::ConnectionFailed(_) => None,
+            Event::ConnectionEstablished(_)
+            | Event::ConnectionUpgraded { .. }
+            | Event::ConnectionFailed(_) => None,
         })
     }
 
@@ -672,9 +1281,36 @@ impl TestNode {
             Event::ConnectionFailed(id) => Some((*id, *instant)),
             Event::SignalIceCandidate { .. } => None,
             Event::ConnectionEstablished(_) => None,
+            Event::ConnectionUpgraded { .. } => None,
         })
     }
 
+    fn upgraded_connections(&self) -> impl Iterator<Item = (u64, Instant)> + '_ {
+        self.events.iter().filter_map(|(e, instant)| match e {
+            Event::ConnectionUpgraded { connection, .. } => Some((*connection, *instant)),
+            Event::SignalIceCandidate { .. } => None,
+            Event::ConnectionEstablished(_) => None,
+            Event::ConnectionFailed(_) => None,
+        })
+    }
+
+    fn send(&mut self, id: u64, packet: &[u8], now: Instant) {
+        let packet = IpPacket::new(packet).unwrap();
+
+        if let Some(transmit) = self
+            .span
+            .in_scope(|| self.node.encapsulate(id, packet, now))
+            .unwrap()
+        {
+            self.buffered_transmits.push_back(transmit);
+        }
+    }
+
+    fn rotate_preshared_key(&mut self, id: u64, key: Secret<[u8; 32]>, now: Instant) {
+        self.span
+            .in_scope(|| self.node.rotate_preshared_key(id, key, now));
+    }
+
     fn receive(&mut self, local: SocketAddr, from: SocketAddr, packet: &[u8], now: Instant) {
         if let Some((_, packet)) = self
             .span
@@ -698,6 +1334,7 @@ impl TestNode {
                     candidate,
                 } => other.node.add_remote_candidate(connection, candidate, now),
                 Event::ConnectionEstablished(_) => {}
+                Event::ConnectionUpgraded { .. } => {}
                 Event::ConnectionFailed(_) => {}
             };
         }
@@ -710,10 +1347,18 @@ impl TestNode {
         firewall: &Firewall,
         now: Instant,
     ) {
-        while let Some(trans) = self.span.in_scope(|| self.node.poll_transmit()) {
+        while let Some(trans) = self
+            .span
+            .in_scope(|| self.node.poll_transmit())
+            .or_else(|| self.buffered_transmits.pop_front())
+        {
             let payload = &trans.payload;
             let dst = trans.dst;
 
+            if is_handshake_initiation(payload) {
+                self.handshake_initiations += 1;
+            }
+
             if let Some(relay) = relays.iter_mut().find(|r| r.wants(trans.dst)) {
                 relay.handle_packet(payload, self.primary, dst, other, now);
                 continue;
@@ -740,6 +1385,16 @@ impl TestNode {
 
         self
     }
+
+    fn with_host_candidate(mut self, socket: &str) -> Self {
+        let socket = s(socket);
+
+        self.local.push(socket);
+        self.span
+            .in_scope(|| self.node.add_local_host_candidate(socket));
+
+        self
+    }
 }
 
 fn handshake(client: &mut TestNode, server: &mut TestNode, relays: &[TestRelay], clock: &Clock) {
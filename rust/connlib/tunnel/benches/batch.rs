@@ -0,0 +1,203 @@
+use boringtun::x25519::StaticSecret;
+use bytes::Bytes;
+use pnet_packet::ipv4::MutableIpv4Packet;
+use quinn_udp::{UdpSockRef, UdpSocketState};
+use rand_core::OsRng;
+use snownet::{ClientNode, Event, IpPacket, ServerNode, Transmit};
+use std::collections::HashSet;
+use std::error::Error;
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+type Result<T> = std::result::Result<T, Box<dyn Error>>;
+
+const CONNECTION: u64 = 1;
+const NUM_PACKETS: usize = 500_000;
+const BATCH_SIZE: usize = 32;
+const PACKET_SIZE: usize = 1280;
+
+/// Compares encapsulating and sending packets one by one with batching them via `encapsulate_batch` and UDP GSO.
+///
+/// Both variants encrypt the same packets for an established direct connection and send them over loopback.
+/// The throughput is measured at the sender in bytes of plaintext IP packets.
+#[allow(clippy::print_stdout)]
+fn main() -> Result<()> {
+    let per_packet = measure(Mode::PerPacket)?;
+    println!("per packet: {per_packet:.2} MB/s");
+
+    if max_gso_segments()? <= 1 {
+        println!("batched:    skipped, GSO is not supported on this platform");
+
+        return Ok(());
+    }
+
+    let batched = measure(Mode::Batched)?;
+    println!(
+        "batched:    {batched:.2} MB/s ({:.2}x)",
+        batched / per_packet
+    );
+
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Mode {
+    PerPacket,
+    Batched,
+}
+
+fn measure(mode: Mode) -> Result<f64> {
+    let sender = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))?;
+    let receiver = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))?;
+    let state = UdpSocketState::new(UdpSockRef::from(&sender))?;
+
+    let (mut alice, now) = connect(sender.local_addr()?, receiver.local_addr()?)?;
+    let packet = ip_packet(PACKET_SIZE);
+
+    let start = Instant::now();
+
+    for _ in 0..NUM_PACKETS / BATCH_SIZE {
+        let transmits = match mode {
+            Mode::PerPacket => {
+                let mut transmits = Vec::with_capacity(BATCH_SIZE);
+
+                for _ in 0..BATCH_SIZE {
+                    if let Some(transmit) = alice.encapsulate(CONNECTION, ip(&packet), now)? {
+                        transmits.push(to_quinn(transmit));
+                    }
+                }
+
+                transmits
+            }
+            Mode::Batched => alice
+                .encapsulate_batch(CONNECTION, (0..BATCH_SIZE).map(|_| ip(&packet)), now)?
+                .into_iter()
+                .map(to_quinn)
+                .collect(),
+        };
+
+        let mut sent = 0;
+        while sent < transmits.len() {
+            sent += state.send(UdpSockRef::from(&sender), &transmits[sent..])?;
+        }
+    }
+
+    let bytes = (NUM_PACKETS / BATCH_SIZE * BATCH_SIZE * PACKET_SIZE) as f64;
+
+    Ok(bytes / start.elapsed().as_secs_f64() / 1_000_000.0)
+}
+
+/// Establishes a direct connection between a client and a server in memory, using the given sockets as their host candidates.
+///
+/// Returns the client and the (simulated) time at which the connection is ready.
+fn connect(alice_addr: SocketAddr, bob_addr: SocketAddr) -> Result<(ClientNode<u64>, Instant)> {
+    let mut alice = ClientNode::<u64>::new(StaticSecret::random_from_rng(OsRng));
+    let mut bob = ServerNode::<u64>::new(StaticSecret::random_from_rng(OsRng));
+    let mut now = Instant::now();
+
+    alice.add_local_host_candidate(alice_addr)?;
+    bob.add_local_host_candidate(bob_addr)?;
+
+    let offer = alice.new_connection(CONNECTION, HashSet::new(), HashSet::new(), now, now);
+    let answer = bob.accept_connection(
+        CONNECTION,
+        offer,
+        alice.public_key(),
+        HashSet::new(),
+        HashSet::new(),
+        now,
+    );
+    alice.accept_answer(CONNECTION, bob.public_key(), answer, now);
+
+    let probe = ip_packet(PACKET_SIZE);
+    let mut buffer = vec![0u8; 65535];
+
+    for _ in 0..1000 {
+        while let Some(event) = alice.poll_event() {
+            if let Event::SignalIceCandidate {
+                connection,
+                candidate,
+            } = event
+            {
+                bob.add_remote_candidate(connection, candidate, now);
+            }
+        }
+        while let Some(event) = bob.poll_event() {
+            if let Event::SignalIceCandidate {
+                connection,
+                candidate,
+            } = event
+            {
+                alice.add_remote_candidate(connection, candidate, now);
+            }
+        }
+
+        while let Some(transmit) = alice.poll_transmit() {
+            if bob
+                .decapsulate(bob_addr, alice_addr, &transmit.payload, now, &mut buffer)?
+                .is_some()
+            {
+                return Ok((alice, now));
+            }
+        }
+        while let Some(transmit) = bob.poll_transmit() {
+            alice.decapsulate(alice_addr, bob_addr, &transmit.payload, now, &mut buffer)?;
+        }
+
+        // Once the connection is established, the first packet triggers the WireGuard handshake.
+        if alice.is_connected_to(bob.public_key()) {
+            if let Some(transmit) = alice.encapsulate(CONNECTION, ip(&probe), now)? {
+                let transmit = transmit.into_owned();
+
+                if bob
+                    .decapsulate(bob_addr, alice_addr, &transmit.payload, now, &mut buffer)?
+                    .is_some()
+                {
+                    return Ok((alice, now));
+                }
+            }
+        }
+
+        now += Duration::from_millis(10);
+        alice.handle_timeout(now);
+        bob.handle_timeout(now);
+    }
+
+    Err("failed to establish connection".into())
+}
+
+/// How many segments a single send may carry on this platform, `1` means GSO is not supported.
+fn max_gso_segments() -> Result<usize> {
+    let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))?;
+    let state = UdpSocketState::new(UdpSockRef::from(&socket))?;
+
+    Ok(state.max_gso_segments())
+}
+
+fn to_quinn(transmit: Transmit<'_>) -> quinn_udp::Transmit {
+    quinn_udp::Transmit {
+        destination: transmit.dst,
+        ecn: None,
+        contents: Bytes::copy_from_slice(&transmit.payload),
+        segment_size: transmit.segment_size,
+        src_ip: None,
+    }
+}
+
+fn ip(packet: &[u8]) -> IpPacket<'_> {
+    IpPacket::new(packet).expect("valid IP packet")
+}
+
+fn ip_packet(len: usize) -> Vec<u8> {
+    let mut buf = vec![0u8; len];
+
+    let mut packet = MutableIpv4Packet::new(&mut buf).expect("buffer to be large enough");
+    packet.set_version(4);
+    packet.set_header_length(5);
+    packet.set_total_length(len as u16);
+    packet.set_ttl(64);
+    packet.set_source(Ipv4Addr::new(100, 64, 0, 1));
+    packet.set_destination(Ipv4Addr::new(100, 64, 0, 2));
+
+    buf
+}
@@ -1,30 +1,33 @@
+use crate::dns::{self, DnsAnswer, DnsCache, DnsCacheStats, DnsQuery};
 use crate::ip_packet::{IpPacket, MutableIpPacket};
 use crate::peer::{PacketTransformClient, Peer};
 use crate::peer_store::PeerStore;
-use crate::{dns, dns::DnsQuery};
+use crate::sockets::Received;
 use bimap::BiMap;
 use connlib_shared::error::{ConnlibError as Error, ConnlibError};
 use connlib_shared::messages::{
     Answer, ClientPayload, DnsServer, DomainResponse, GatewayId, Interface as InterfaceConfig,
     IpDnsServer, Key, Offer, Relay, RequestConnection, ResourceDescription,
     ResourceDescriptionCidr, ResourceDescriptionDns, ResourceId, ReuseConnection,
+    RotatePresharedKey,
 };
 use connlib_shared::{Callbacks, Dname, PublicKey, StaticSecret};
 use domain::base::Rtype;
 use ip_network::{IpNetwork, Ipv4Network, Ipv6Network};
 use ip_network_table::IpNetworkTable;
 use itertools::Itertools;
+use rand_core::{OsRng, RngCore as _};
 
 use crate::utils::{earliest, stun, turn};
 use crate::{ClientEvent, ClientTunnel};
 use secrecy::{ExposeSecret as _, Secret};
-use snownet::ClientNode;
+use snownet::{CaptureFilter, ClientNode};
 use std::collections::hash_map::Entry;
 use std::collections::{HashMap, HashSet, VecDeque};
 use std::iter;
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 use std::str::FromStr;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime};
 
 // Using str here because Ipv4/6Network doesn't support `const` 🙃
 const IPV4_RESOURCES: &str = "100.96.0.0/11";
@@ -39,6 +42,9 @@ const DNS_SENTINELS_V6: &str = "fd00:2021:1111:8000:100:100:111:0/120";
 // therefore, only the first time it's added that happens, after that it doesn't matter.
 const DNS_REFRESH_INTERVAL: Duration = Duration::from_secs(300);
 
+/// How many answers to forwarded DNS queries we cache at most.
+const MAX_DNS_CACHE_ENTRIES: usize = 1024;
+
 #[derive(Debug, Clone, Hash, PartialEq, Eq)]
 pub struct DnsResource {
     pub id: ResourceId,
@@ -124,6 +130,33 @@ where
         Ok(())
     }
 
+    /// Sets the TTL of our answers for DNS resources.
+    ///
+    /// Clients cache these answers, so a longer TTL saves queries at the cost of noticing later that a resource needs a connection.
+    pub fn set_dns_resource_ttl(&mut self, ttl: Duration) {
+        self.role_state.set_dns_resource_ttl(ttl);
+    }
+
+    /// Sets upstream DNS servers configured on this device, these take precedence over the ones configured in the portal.
+    ///
+    /// Pass an empty list to fall back to the portal's upstream DNS servers.
+    pub fn set_upstream_dns(&mut self, upstream_dns: Vec<DnsServer>) -> connlib_shared::Result<()> {
+        let dns_changed = self.role_state.update_local_upstream_dns(upstream_dns);
+
+        if !dns_changed {
+            return Ok(());
+        }
+
+        self.io
+            .set_upstream_dns_servers(self.role_state.dns_mapping());
+
+        if let Some(config) = self.role_state.interface_config.as_ref().cloned() {
+            self.update_device(config, self.role_state.dns_mapping())?;
+        };
+
+        Ok(())
+    }
+
     #[tracing::instrument(level = "trace", skip(self))]
     pub fn set_new_interface_config(
         &mut self,
@@ -175,6 +208,68 @@ where
             .add_remote_candidate(conn_id, ice_candidate, Instant::now());
     }
 
+    /// Starts capturing the plaintext packets of the given connections, or all of them if `None`.
+    ///
+    /// See [`snownet::Node::start_capture`].
+    pub fn start_capture(
+        &mut self,
+        connections: Option<HashSet<GatewayId>>,
+        duration: Duration,
+        max_bytes: usize,
+    ) {
+        let filter = match connections {
+            Some(ids) => CaptureFilter::Connections(ids),
+            None => CaptureFilter::AllConnections,
+        };
+
+        self.role_state.node.start_capture(
+            filter,
+            duration,
+            max_bytes,
+            Instant::now(),
+            SystemTime::now(),
+        );
+    }
+
+    /// Stops the current capture and returns it as a pcapng file.
+    pub fn stop_capture(&mut self) -> Option<Vec<u8>> {
+        self.role_state.node.stop_capture()
+    }
+
+    /// Rotates the preshared keys of the connections to all gateways, see [`snownet::Node::rotate_preshared_key`].
+    ///
+    /// The returned messages must be sent to the portal, which forwards them to the gateways, see [`RotatePresharedKey`].
+    pub fn rotate_preshared_keys(&mut self) -> Vec<RotatePresharedKey> {
+        let gateways = self
+            .role_state
+            .peers
+            .iter()
+            .map(|p| p.conn_id)
+            .collect_vec();
+
+        gateways
+            .into_iter()
+            .filter_map(|gateway_id| {
+                let mut key = [0u8; 32];
+                OsRng.fill_bytes(&mut key);
+
+                if let Err(e) = self.role_state.node.rotate_preshared_key(
+                    gateway_id,
+                    Secret::new(key),
+                    Instant::now(),
+                ) {
+                    tracing::debug!(%gateway_id, "Failed to rotate preshared key: {e}");
+                    return None;
+                }
+
+                Some(RotatePresharedKey {
+                    gateway_id,
+                    client_preshared_key: Secret::new(Key(key)),
+                })
+            })
+            .collect()
+    }
+
     pub fn create_or_reuse_connection(
         &mut self,
         resource_id: ResourceId,
@@ -231,7 +326,8 @@ fn send_dns_answer(
         .deferred_dns_queries
         .remove(&(resource_description.clone(), qtype));
     if let Some(packet) = packet {
-        let Some(packet) = dns::create_local_answer(addrs, packet) else {
+        let Some(packet) = dns::create_local_answer(addrs, role_state.dns_resource_ttl, packet)
+        else {
             return;
         };
         role_state.buffered_packets.push_back(packet);
@@ -266,6 +362,13 @@ pub struct ClientState {
     next_dns_refresh: Option<Instant>,
 
     system_resolvers: Vec<IpAddr>,
+    /// Upstream DNS servers configured on this device, these take precedence over [`InterfaceConfig::upstream_dns`].
+    local_upstream_dns: Vec<DnsServer>,
+
+    /// Answers to forwarded DNS queries.
+    dns_cache: DnsCache,
+    /// The TTL of our answers for DNS resources.
+    dns_resource_ttl: Duration,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -295,6 +398,9 @@ impl ClientState {
             next_dns_refresh: Default::default(),
             node: ClientNode::new(private_key),
             system_resolvers: Default::default(),
+            local_upstream_dns: Default::default(),
+            dns_cache: DnsCache::new(MAX_DNS_CACHE_ENTRIES),
+            dns_resource_ttl: dns::DEFAULT_RESOURCE_TTL,
         }
     }
 
@@ -302,11 +408,25 @@ impl ClientState {
         self.resource_ids.values().sorted().cloned().collect_vec()
     }
 
-    pub(crate) fn encapsulate<'s>(
-        &'s mut self,
-        packet: MutableIpPacket<'_>,
+    /// Determines the connection of each packet read from the device, see [`ClientState::encapsulate_batch`].
+    ///
+    /// DNS queries are answered or forwarded, packets for resources we aren't connected to yet trigger a connection intent.
+    pub(crate) fn route_packets<'a>(
+        &mut self,
+        packets: impl IntoIterator<Item = MutableIpPacket<'a>>,
         now: Instant,
-    ) -> Option<snownet::Transmit<'s>> {
+    ) -> HashMap<GatewayId, Vec<MutableIpPacket<'a>>> {
+        packets
+            .into_iter()
+            .filter_map(|packet| self.route_packet(packet, now))
+            .into_group_map()
+    }
+
+    fn route_packet<'a>(
+        &mut self,
+        packet: MutableIpPacket<'a>,
+        now: Instant,
+    ) -> Option<(GatewayId, MutableIpPacket<'a>)> {
         let (packet, dest) = match self.handle_dns(packet, now) {
             Ok(response) => {
                 self.buffered_packets.push_back(response?.to_owned());
@@ -322,49 +442,59 @@ impl ClientState {
 
         let packet = peer.transform(packet)?;
 
-        let transmit = self
-            .node
-            .encapsulate(peer.conn_id, packet.as_immutable().into(), Instant::now())
-            .inspect_err(|e| tracing::debug!("Failed to encapsulate: {e}"))
-            .ok()??;
+        Some((peer.conn_id, packet))
+    }
 
-        Some(transmit)
+    /// Encapsulates packets of the same connection together, see [`snownet::Node::encapsulate_batch`].
+    pub(crate) fn encapsulate_batch<'s>(
+        &'s mut self,
+        connection: GatewayId,
+        packets: &[MutableIpPacket<'_>],
+        now: Instant,
+    ) -> Vec<snownet::Transmit<'s>> {
+        self.node
+            .encapsulate_batch(
+                connection,
+                packets.iter().map(|p| p.as_immutable().into()),
+                now,
+            )
+            .inspect_err(|e| tracing::debug!(%connection, "Failed to encapsulate: {e}"))
+            .unwrap_or_default()
     }
 
-    pub(crate) fn decapsulate<'b>(
+    /// Decapsulates all packets received from the network at once, see [`snownet::Node::decapsulate_batch`].
+    pub(crate) fn decapsulate_batch<'b, 'p>(
         &mut self,
-        local: SocketAddr,
-        from: SocketAddr,
-        packet: &[u8],
+        packets: impl IntoIterator<Item = Received<'p>>,
         now: Instant,
         buffer: &'b mut [u8],
-    ) -> Option<IpPacket<'b>> {
-        let (conn_id, packet) = self.node.decapsulate(
-            local,
-            from,
-            packet.as_ref(),
-            now,
-            buffer,
-        )
-        .inspect_err(|e| tracing::warn!(%local, %from, num_bytes = %packet.len(), "Failed to decapsulate incoming packet: {e}"))
-        .ok()??;
-
-        let Some(peer) = self.peers.get_mut(&conn_id) else {
-            tracing::error!(%conn_id, %local, %from, "Couldn't find connection");
+    ) -> Vec<IpPacket<'b>> {
+        self.node
+            .decapsulate_batch(
+                packets.into_iter().map(|r| (r.local, r.from, r.packet)),
+                now,
+                buffer,
+            )
+            .into_iter()
+            .filter_map(|(conn_id, packet)| {
+                let Some(peer) = self.peers.get_mut(&conn_id) else {
+                    tracing::error!(%conn_id, "Couldn't find connection");
 
-            return None;
-        };
+                    return None;
+                };
 
-        let packet = match peer.untransform(packet.into()) {
-            Ok(packet) => packet,
-            Err(e) => {
-                tracing::warn!(%conn_id, %local, %from, "Failed to transform packet: {e}");
+                let packet = match peer.untransform(packet.into()) {
+                    Ok(packet) => packet,
+                    Err(e) => {
+                        tracing::warn!(%conn_id, "Failed to transform packet: {e}");
 
-                return None;
-            }
-        };
+                        return None;
+                    }
+                };
 
-        Some(packet.into_immutable())
+                Some(packet.into_immutable())
+            })
+            .collect()
     }
 
     #[tracing::instrument(level = "trace", skip_all, fields(%resource_id))]
@@ -563,6 +693,7 @@ impl ClientState {
             &self.dns_resources,
             &self.dns_resources_internal_ips,
             &self.dns_mapping,
+            self.dns_resource_ttl,
             packet.as_immutable(),
         ) {
             Some(dns::ResolveStrategy::LocalResponse(query)) => Ok(Some(query)),
@@ -570,14 +701,22 @@ impl ClientState {
                 // There's an edge case here, where the resolver's ip has been resolved before as
                 // a dns resource... we will ignore that weird case for now.
                 // Assuming a single upstream dns until #3123 lands
-                if let Some(upstream_dns) = self.dns_mapping.get_by_left(&query.query.destination())
+                // Encrypted upstreams are always queried by our own resolvers, see `DnsServer::Tls` and `DnsServer::Https` for why they can't be CIDR resources.
+                if let Some(DnsServer::IpPort(upstream_dns)) =
+                    self.dns_mapping.get_by_left(&query.query.destination())
                 {
                     if self
                         .cidr_resources
-                        .longest_match(upstream_dns.ip())
+                        .longest_match(upstream_dns.address.ip())
                         .is_some()
                     {
-                        return Err((packet, upstream_dns.ip()));
+                        return Err((packet, upstream_dns.address.ip()));
+                    }
+                }
+
+                if let Some(answer) = self.dns_cache.get(&query.name, query.record_type, now) {
+                    if let Some(response) = dns::build_response_from_answer(&query.query, &answer) {
+                        return Ok(Some(response));
                     }
                 }
 
@@ -694,6 +833,7 @@ impl ClientState {
 
     fn set_dns_mapping(&mut self, new_mapping: BiMap<IpAddr, DnsServer>) {
         self.dns_mapping = new_mapping.clone();
+        self.dns_cache.clear();
         self.peers
             .iter_mut()
             .for_each(|p| p.transform.set_dns(new_mapping.clone()));
@@ -768,6 +908,13 @@ impl ClientState {
         self.update_dns_mapping()
     }
 
+    #[must_use]
+    fn update_local_upstream_dns(&mut self, upstream_dns: Vec<DnsServer>) -> bool {
+        self.local_upstream_dns = upstream_dns;
+
+        self.update_dns_mapping()
+    }
+
     #[must_use]
     fn update_interface_config(&mut self, config: InterfaceConfig) -> bool {
         self.interface_config = Some(config);
@@ -783,6 +930,32 @@ impl ClientState {
         self.buffered_dns_queries.pop_front()
     }
 
+    /// Handles the answer of an upstream resolver to a query from [`ClientState::poll_dns_queries`].
+    ///
+    /// The answer is cached and the response to the query is queued, see [`ClientState::poll_packets`].
+    pub(crate) fn on_dns_answer(
+        &mut self,
+        query: DnsQuery<'static>,
+        answer: DnsAnswer,
+        now: Instant,
+    ) {
+        match dns::build_response_from_answer(&query.query, &answer) {
+            Some(response) => self.buffered_packets.push_back(response),
+            None => tracing::debug!("Failed to build DNS response from lookup result"),
+        }
+
+        self.dns_cache
+            .insert(&query.name, query.record_type, answer, now);
+    }
+
+    pub fn set_dns_resource_ttl(&mut self, ttl: Duration) {
+        self.dns_resource_ttl = ttl;
+    }
+
+    pub(crate) fn dns_cache_stats(&self) -> DnsCacheStats {
+        self.dns_cache.stats()
+    }
+
     pub fn poll_timeout(&mut self) -> Option<Instant> {
         earliest(self.next_dns_refresh, self.node.poll_timeout())
     }
@@ -817,6 +990,14 @@ impl ClientState {
                 self.buffered_events
                     .push_back(ClientEvent::RefreshResources { connections });
 
+                let stats = self.dns_cache_stats();
+                tracing::debug!(
+                    hits = stats.hits,
+                    misses = stats.misses,
+                    entries = stats.entries,
+                    "DNS cache statistics"
+                );
+
                 self.next_dns_refresh = Some(now + DNS_REFRESH_INTERVAL);
             }
             None => self.next_dns_refresh = Some(now + DNS_REFRESH_INTERVAL),
@@ -837,6 +1018,13 @@ impl ClientState {
                         conn_id: connection,
                         candidate,
                     }),
+                snownet::Event::ConnectionUpgraded {
+                    connection,
+                    relay,
+                    direct,
+                } => {
+                    tracing::info!(%connection, %relay, %direct, "Connection upgraded to direct path");
+                }
                 _ => {}
             }
         }
@@ -847,7 +1035,7 @@ impl ClientState {
     }
 
     pub(crate) fn reconnect(&mut self, now: Instant) {
-        tracing::info!("Network change detected, refreshing connections");
+        tracing::info!("Network change detected, migrating connections");
         self.node.reconnect(now)
     }
 
@@ -963,8 +1151,14 @@ impl ClientState {
             return false;
         };
 
+        let upstream_dns = if self.local_upstream_dns.is_empty() {
+            config.upstream_dns.clone()
+        } else {
+            self.local_upstream_dns.clone()
+        };
+
         let effective_dns_servers =
-            effective_dns_servers(config.upstream_dns.clone(), self.system_resolvers.clone());
+            effective_dns_servers(upstream_dns, self.system_resolvers.clone());
 
         if HashSet::<&DnsServer>::from_iter(effective_dns_servers.iter())
             == HashSet::from_iter(self.dns_mapping.right_values())
@@ -972,6 +1166,17 @@ impl ClientState {
             return false;
         }
 
+        for server in &effective_dns_servers {
+            let encrypted = match server {
+                DnsServer::IpPort(_) => false,
+                DnsServer::Tls(_) | DnsServer::Https(_) => true,
+            };
+
+            if encrypted && self.cidr_resources.longest_match(server.ip()).is_some() {
+                tracing::warn!(address = %server.address(), "Encrypted upstream DNS server is within a CIDR resource; queries will be sent outside of the tunnel");
+            }
+        }
+
         let dns_mapping = sentinel_dns_mapping(
             &effective_dns_servers,
             self.dns_mapping()
@@ -1125,6 +1330,14 @@ impl IpProvider {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use connlib_shared::messages::TlsDnsServer;
+    use domain::base::MessageBuilder;
+    use hickory_resolver::proto::op::ResponseCode;
+    use hickory_resolver::proto::rr::{rdata::A, Name, RData, Record};
+    use pnet_packet::ip::IpNextHeaderProtocols;
+    use pnet_packet::ipv4::MutableIpv4Packet;
+    use pnet_packet::udp::MutableUdpPacket;
+    use pnet_packet::MutablePacket as _;
     use rand_core::OsRng;
 
     #[test]
@@ -1265,6 +1478,121 @@ mod tests {
         dns_mapping_is_exactly(client_state.dns_mapping(), vec![dns("1.0.0.1:53")]);
     }
 
+    #[test]
+    fn local_upstream_dns_wins_over_portal() {
+        let mut client_state = ClientState::for_test();
+        let _ = client_state.update_interface_config(interface_config_with_dns());
+
+        let dns_changed = client_state.update_local_upstream_dns(vec![tls_dns("9.9.9.9:853")]);
+
+        assert!(dns_changed);
+        dns_mapping_is_exactly(client_state.dns_mapping(), vec![tls_dns("9.9.9.9:853")]);
+    }
+
+    #[test]
+    fn portal_takes_over_when_local_upstream_dns_is_unset() {
+        let mut client_state = ClientState::for_test();
+        let _ = client_state.update_interface_config(interface_config_with_dns());
+        let _ = client_state.update_local_upstream_dns(vec![tls_dns("9.9.9.9:853")]);
+
+        let dns_changed = client_state.update_local_upstream_dns(vec![]);
+
+        assert!(dns_changed);
+        dns_mapping_is_exactly(client_state.dns_mapping(), dns_list());
+    }
+
+    #[test]
+    fn forwarded_dns_answers_are_cached() {
+        let mut client_state = ClientState::for_test();
+        let _ = client_state.update_interface_config(interface_config_with_dns());
+        let sentinel = sentinel_of(&client_state, "1.1.1.1:53");
+        let now = Instant::now();
+
+        forward_dns_query(&mut client_state, sentinel, a_record_answer(60), now);
+        let response = query_dns(&mut client_state, sentinel, now + Duration::from_secs(10));
+
+        assert!(client_state.poll_dns_queries().is_none());
+        assert_eq!(
+            dns::as_dns_message(&response.unwrap()).unwrap().answers()[0].ttl(),
+            50
+        );
+        assert_eq!(
+            client_state.dns_cache_stats(),
+            DnsCacheStats {
+                hits: 1,
+                misses: 1,
+                entries: 1
+            }
+        );
+    }
+
+    #[test]
+    fn expired_dns_answers_are_forwarded_again() {
+        let mut client_state = ClientState::for_test();
+        let _ = client_state.update_interface_config(interface_config_with_dns());
+        let sentinel = sentinel_of(&client_state, "1.1.1.1:53");
+        let now = Instant::now();
+
+        forward_dns_query(&mut client_state, sentinel, a_record_answer(60), now);
+        let response = query_dns(&mut client_state, sentinel, now + Duration::from_secs(60));
+
+        assert!(response.is_none());
+        assert!(client_state.poll_dns_queries().is_some());
+    }
+
+    #[test]
+    fn negative_dns_answers_are_cached() {
+        let mut client_state = ClientState::for_test();
+        let _ = client_state.update_interface_config(interface_config_with_dns());
+        let sentinel = sentinel_of(&client_state, "1.1.1.1:53");
+        let now = Instant::now();
+
+        forward_dns_query(&mut client_state, sentinel, nxdomain_answer(30), now);
+        let response = query_dns(&mut client_state, sentinel, now + Duration::from_secs(10));
+
+        assert!(client_state.poll_dns_queries().is_none());
+        assert_eq!(
+            dns::as_dns_message(&response.unwrap())
+                .unwrap()
+                .response_code(),
+            ResponseCode::NXDomain
+        );
+    }
+
+    #[test]
+    fn changing_upstream_dns_clears_cache() {
+        let mut client_state = ClientState::for_test();
+        let _ = client_state.update_interface_config(interface_config_with_dns());
+        let sentinel = sentinel_of(&client_state, "1.1.1.1:53");
+        let now = Instant::now();
+
+        forward_dns_query(&mut client_state, sentinel, a_record_answer(60), now);
+        let _ = client_state.update_interface_config(InterfaceConfig {
+            upstream_dns: vec![dns("8.8.8.8:53")],
+            ..interface_config_without_dns()
+        });
+
+        assert_eq!(client_state.dns_cache_stats().entries, 0);
+    }
+
+    #[test]
+    fn resource_answers_use_configured_ttl() {
+        let mut query = dns_query(ip("100.100.111.1"));
+        let packet = MutableIpPacket::new(&mut query).unwrap();
+
+        let response = dns::create_local_answer(
+            &HashSet::from([ip("100.96.0.1")]),
+            Duration::from_secs(30),
+            packet.into_immutable(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            dns::as_dns_message(&response).unwrap().answers()[0].ttl(),
+            30
+        );
+    }
+
     #[test]
     fn sentinel_dns_works() {
         let servers = dns_list();
@@ -1340,6 +1668,96 @@ mod tests {
         ]
     }
 
+    fn sentinel_of(client_state: &ClientState, upstream: &str) -> IpAddr {
+        *client_state
+            .dns_mapping()
+            .get_by_right(&dns(upstream))
+            .unwrap()
+    }
+
+    /// Sends a DNS query for `example.com` to the given sentinel, returning the response if there is one.
+    fn query_dns(
+        client_state: &mut ClientState,
+        sentinel: IpAddr,
+        now: Instant,
+    ) -> Option<IpPacket<'static>> {
+        let mut query = dns_query(sentinel);
+        let _ = client_state.route_packets([MutableIpPacket::new(&mut query).unwrap()], now);
+
+        client_state.poll_packets()
+    }
+
+    /// Sends a DNS query for `example.com` to the given sentinel and answers it as the upstream resolver.
+    fn forward_dns_query(
+        client_state: &mut ClientState,
+        sentinel: IpAddr,
+        answer: DnsAnswer,
+        now: Instant,
+    ) {
+        assert!(query_dns(client_state, sentinel, now).is_none());
+
+        let query = client_state.poll_dns_queries().unwrap();
+        client_state.on_dns_answer(query, answer, now);
+
+        assert!(client_state.poll_packets().is_some());
+    }
+
+    fn dns_query(dst: IpAddr) -> Vec<u8> {
+        let IpAddr::V4(dst) = dst else {
+            panic!("Only IPv4 is supported");
+        };
+
+        let mut builder = MessageBuilder::new_vec().question();
+        builder.header_mut().set_rd(true);
+        builder
+            .push((Dname::vec_from_str("example.com").unwrap(), Rtype::A))
+            .unwrap();
+        let payload = builder.finish();
+
+        let len = 20 + 8 + payload.len();
+        let mut buf = vec![0u8; len];
+
+        let mut packet = MutableIpv4Packet::new(&mut buf).unwrap();
+        packet.set_version(4);
+        packet.set_header_length(5);
+        packet.set_total_length(len as u16);
+        packet.set_ttl(64);
+        packet.set_next_level_protocol(IpNextHeaderProtocols::Udp);
+        packet.set_source(Ipv4Addr::new(100, 64, 0, 1));
+        packet.set_destination(dst);
+
+        let mut datagram = MutableUdpPacket::new(packet.payload_mut()).unwrap();
+        datagram.set_source(5353);
+        datagram.set_destination(DNS_PORT);
+        datagram.set_length((8 + payload.len()) as u16);
+        datagram.set_payload(&payload);
+
+        buf
+    }
+
+    fn a_record_answer(ttl: u32) -> DnsAnswer {
+        DnsAnswer::Records(vec![Record::from_rdata(
+            Name::from_ascii("example.com.").unwrap(),
+            ttl,
+            RData::A(A::new(93, 184, 216, 34)),
+        )])
+    }
+
+    fn nxdomain_answer(negative_ttl: u32) -> DnsAnswer {
+        DnsAnswer::NoRecords {
+            soa: None,
+            response_code: ResponseCode::NXDomain,
+            negative_ttl: Some(negative_ttl),
+        }
+    }
+
+    fn tls_dns(address: &str) -> DnsServer {
+        DnsServer::Tls(TlsDnsServer {
+            address: address.parse().unwrap(),
+            server_name: "dns.example.com".to_owned(),
+        })
+    }
+
     fn dns(address: &str) -> DnsServer {
         DnsServer::IpPort(IpDnsServer {
             address: address.parse().unwrap(),
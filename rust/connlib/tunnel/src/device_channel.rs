@@ -139,7 +139,7 @@ impl Device {
     }
 
     #[cfg(target_family = "unix")]
-    pub(crate) fn poll_read<'b>(
+    fn poll_read<'b>(
         &mut self,
         buf: &'b mut [u8],
         cx: &mut Context<'_>,
@@ -157,7 +157,7 @@ impl Device {
             self.mtu_refreshed_at = Instant::now();
         }
 
-        let n = std::task::ready!(tun.poll_read(&mut buf[..self.mtu], cx))?;
+        let n = std::task::ready!(tun.poll_read(&mut buf[..self.mtu.min(buf.len())], cx))?;
 
         if n == 0 {
             return Poll::Ready(Err(io::Error::new(
@@ -179,7 +179,7 @@ impl Device {
     }
 
     #[cfg(target_family = "windows")]
-    pub(crate) fn poll_read<'b>(
+    fn poll_read<'b>(
         &mut self,
         buf: &'b mut [u8],
         cx: &mut Context<'_>,
@@ -195,7 +195,7 @@ impl Device {
             // TODO
         }
 
-        let n = std::task::ready!(tun.poll_read(&mut buf[..self.mtu], cx))?;
+        let n = std::task::ready!(tun.poll_read(&mut buf[..self.mtu.min(buf.len())], cx))?;
 
         if n == 0 {
             return Poll::Ready(Err(io::Error::new(
@@ -216,6 +216,40 @@ impl Device {
         Poll::Ready(Ok(packet))
     }
 
+    /// Reads all packets that are available right away (at least one) back-to-back into `buf`.
+    ///
+    /// This allows us to encapsulate the packets of a connection together, see [`snownet::Node::encapsulate_batch`].
+    pub(crate) fn poll_read_many<'b>(
+        &mut self,
+        mut buf: &'b mut [u8],
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<Vec<MutableIpPacket<'b>>>> {
+        let mut packets = Vec::new();
+
+        loop {
+            let packet_len = self.mtu.min(buf.len());
+            let (packet_buf, rest) = std::mem::take(&mut buf).split_at_mut(packet_len);
+            buf = rest;
+
+            match self.poll_read(packet_buf, cx) {
+                Poll::Ready(Ok(packet)) => packets.push(packet),
+                Poll::Ready(Err(e)) if packets.is_empty() => return Poll::Ready(Err(e)),
+                Poll::Ready(Err(e)) => {
+                    tracing::debug!("Failed to read packet from device: {e}");
+                    break;
+                }
+                Poll::Pending if packets.is_empty() => return Poll::Pending,
+                Poll::Pending => break,
+            }
+
+            if buf.len() < self.mtu {
+                break;
+            }
+        }
+
+        Poll::Ready(Ok(packets))
+    }
+
     pub(crate) fn name(&self) -> &str {
         self.tun
             .as_ref()
@@ -7,16 +7,26 @@ use domain::base::{
     iana::{Class, Rcode, Rtype},
     Message, MessageBuilder, Question, ToDname,
 };
+use hickory_resolver::error::{ResolveError, ResolveErrorKind, ResolveResult};
 use hickory_resolver::lookup::Lookup;
 use hickory_resolver::proto::error::{ProtoError, ProtoErrorKind};
-use hickory_resolver::proto::op::{Message as TrustDnsMessage, MessageType};
-use hickory_resolver::proto::rr::RecordType;
+use hickory_resolver::proto::op::{Message as TrustDnsMessage, MessageType, ResponseCode};
+use hickory_resolver::proto::rr::{Record, RecordType};
 use itertools::Itertools;
 use pnet_packet::{udp::MutableUdpPacket, MutablePacket, Packet as UdpPacket, PacketSize};
 use std::collections::{HashMap, HashSet};
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::time::Duration;
 
-const DNS_TTL: u32 = 1;
+pub(crate) use cache::DnsCache;
+pub(crate) use cache::DnsCacheStats;
+
+mod cache;
+
+/// The default TTL of our answers for DNS resources.
+///
+/// Kept short so that clients re-query us often enough for us to notice that a connection is needed.
+pub(crate) const DEFAULT_RESOURCE_TTL: Duration = Duration::from_secs(1);
 const UDP_HEADER_SIZE: usize = 8;
 const REVERSE_DNS_ADDRESS_END: &str = "arpa";
 const REVERSE_DNS_ADDRESS_V4: &str = "in-addr";
@@ -99,6 +109,7 @@ pub(crate) fn parse<'a>(
     dns_resources: &HashMap<String, ResourceDescriptionDns>,
     dns_resources_internal_ips: &HashMap<DnsResource, HashSet<IpAddr>>,
     dns_mapping: &bimap::BiMap<IpAddr, DnsServer>,
+    resource_ttl: Duration,
     packet: IpPacket<'a>,
 ) -> Option<ResolveStrategy<IpPacket<'static>, DnsQuery<'a>, (DnsResource, Rtype)>> {
     dns_mapping.get_by_left(&packet.destination())?;
@@ -125,14 +136,15 @@ pub(crate) fn parse<'a>(
             }
             None => None,
         };
-    let response = build_dns_with_answer(message, question.qname(), &resource)?;
+    let response = build_dns_with_answer(message, question.qname(), &resource, resource_ttl)?;
     Some(ResolveStrategy::LocalResponse(build_response(
-        packet, response,
+        &packet, response,
     )?))
 }
 
 pub(crate) fn create_local_answer<'a>(
     ips: &HashSet<IpAddr>,
+    resource_ttl: Duration,
     packet: IpPacket<'a>,
 ) -> Option<IpPacket<'a>> {
     let datagram = packet.as_udp().unwrap();
@@ -159,52 +171,112 @@ pub(crate) fn create_local_answer<'a>(
         _ => unreachable!(),
     };
 
-    let response = build_dns_with_answer(message, question.qname(), &Some(resource))?;
+    let response = build_dns_with_answer(message, question.qname(), &Some(resource), resource_ttl)?;
 
-    build_response(packet, response)
+    build_response(&packet, response)
 }
 
-pub(crate) fn build_response_from_resolve_result(
-    original_pkt: IpPacket<'_>,
-    response: hickory_resolver::error::ResolveResult<Lookup>,
-) -> Result<Option<IpPacket>, hickory_resolver::error::ResolveError> {
-    let Some(mut message) = as_dns_message(&original_pkt) else {
-        debug_assert!(false, "The original message should be a DNS query for us to ever call write_dns_lookup_response");
-        return Ok(None);
+/// The answer of an upstream resolver to a forwarded query.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum DnsAnswer {
+    Records(Vec<Record>),
+    /// The name or the requested type of records doesn't exist, see [RFC 2308](https://www.rfc-editor.org/rfc/rfc2308).
+    NoRecords {
+        soa: Option<Record>,
+        response_code: ResponseCode,
+        /// For how long this answer may be cached, `None` if it must not be cached.
+        negative_ttl: Option<u32>,
+    },
+}
+
+impl DnsAnswer {
+    pub(crate) fn from_resolve_result(
+        response: ResolveResult<Lookup>,
+    ) -> Result<Self, ResolveError> {
+        match response.map_err(|err| err.kind().clone()) {
+            Ok(response) => Ok(DnsAnswer::Records(response.records().to_vec())),
+            Err(ResolveErrorKind::Proto(ProtoError { kind, .. }))
+                if matches!(*kind, ProtoErrorKind::NoRecordsFound { .. }) =>
+            {
+                let ProtoErrorKind::NoRecordsFound {
+                    soa,
+                    response_code,
+                    negative_ttl,
+                    ..
+                } = *kind
+                else {
+                    panic!("Impossible - We matched on `ProtoErrorKind::NoRecordsFound` but then could not destructure that same variant");
+                };
+
+                Ok(DnsAnswer::NoRecords {
+                    soa: soa.map(|soa| soa.into_record_of_rdata()),
+                    response_code,
+                    negative_ttl,
+                })
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// For how long this answer may be cached, i.e. the lowest TTL of its records.
+    fn ttl(&self) -> Option<u32> {
+        match self {
+            DnsAnswer::Records(records) => records.iter().map(|r| r.ttl()).min(),
+            DnsAnswer::NoRecords { negative_ttl, .. } => *negative_ttl,
+        }
+    }
+
+    /// Reduces the TTLs of all records by the given number of seconds, e.g. the time the answer spent in a cache.
+    fn age_by(&mut self, secs: u32) {
+        fn age(record: &mut Record, secs: u32) {
+            record.set_ttl(record.ttl().saturating_sub(secs));
+        }
+
+        match self {
+            DnsAnswer::Records(records) => records.iter_mut().for_each(|r| age(r, secs)),
+            DnsAnswer::NoRecords {
+                soa, negative_ttl, ..
+            } => {
+                *negative_ttl = negative_ttl.map(|ttl| ttl.saturating_sub(secs));
+
+                if let Some(soa) = soa {
+                    age(soa, secs);
+                }
+            }
+        }
+    }
+}
+
+pub(crate) fn build_response_from_answer(
+    original_pkt: &IpPacket<'_>,
+    answer: &DnsAnswer,
+) -> Option<IpPacket<'static>> {
+    let Some(mut message) = as_dns_message(original_pkt) else {
+        debug_assert!(false, "The original message should be a DNS query for us to ever call build_response_from_answer");
+        return None;
     };
 
     message.set_message_type(MessageType::Response);
 
-    let response = match response.map_err(|err| err.kind().clone()) {
-        Ok(response) => message.add_answers(response.records().to_vec()),
-        Err(hickory_resolver::error::ResolveErrorKind::Proto(ProtoError { kind, .. }))
-            if matches!(*kind, ProtoErrorKind::NoRecordsFound { .. }) =>
-        {
-            let ProtoErrorKind::NoRecordsFound {
-                soa, response_code, ..
-            } = *kind
-            else {
-                panic!("Impossible - We matched on `ProtoErrorKind::NoRecordsFound` but then could not destructure that same variant");
-            };
+    let response = match answer {
+        DnsAnswer::Records(records) => message.add_answers(records.iter().cloned()),
+        DnsAnswer::NoRecords {
+            soa, response_code, ..
+        } => {
             if let Some(soa) = soa {
-                message.add_name_server(soa.into_record_of_rdata());
+                message.add_name_server(soa.clone());
             }
 
-            message.set_response_code(response_code)
-        }
-        Err(e) => {
-            return Err(e.into());
+            message.set_response_code(*response_code)
         }
     };
 
-    let packet = build_response(original_pkt, response.to_vec()?);
-
-    Ok(packet)
+    build_response(original_pkt, response.to_vec().ok()?)
 }
 
 /// Constructs an IP packet responding to an IP packet containing a DNS query
 fn build_response(
-    original_pkt: IpPacket<'_>,
+    original_pkt: &IpPacket<'_>,
     mut dns_answer: Vec<u8>,
 ) -> Option<IpPacket<'static>> {
     let response_len = dns_answer.len();
@@ -237,10 +309,13 @@ fn build_dns_with_answer<N>(
     message: &Message<[u8]>,
     qname: &N,
     resource: &Option<RecordData<Dname>>,
+    ttl: Duration,
 ) -> Option<Vec<u8>>
 where
     N: ToDname + ?Sized,
 {
+    let ttl = u32::try_from(ttl.as_secs()).unwrap_or(u32::MAX);
+
     let msg_buf = Vec::with_capacity(message.as_slice().len() * 2);
     let msg_builder = MessageBuilder::from_target(msg_buf).expect(
         "Developer error: we should be always be able to create a MessageBuilder from a Vec",
@@ -264,11 +339,11 @@ where
     match resource {
         RecordData::A(r) => r
             .iter()
-            .try_for_each(|r| answer_builder.push((qname, Class::In, DNS_TTL, r))),
+            .try_for_each(|r| answer_builder.push((qname, Class::In, ttl, r))),
         RecordData::Aaaa(r) => r
             .iter()
-            .try_for_each(|r| answer_builder.push((qname, Class::In, DNS_TTL, r))),
-        RecordData::Ptr(r) => answer_builder.push((qname, Class::In, DNS_TTL, r)),
+            .try_for_each(|r| answer_builder.push((qname, Class::In, ttl, r))),
+        RecordData::Ptr(r) => answer_builder.push((qname, Class::In, ttl, r)),
     }
     .ok()?;
 
@@ -0,0 +1,308 @@
+use super::DnsAnswer;
+use hickory_resolver::proto::rr::RecordType;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Upper bound for how long we cache an answer, regardless of its TTL.
+const MAX_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Statistics of the cache for forwarded DNS queries.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct DnsCacheStats {
+    /// Queries answered from the cache.
+    pub hits: u64,
+    /// Queries that had to be forwarded to an upstream resolver.
+    pub misses: u64,
+    /// Answers currently in the cache, including expired ones that haven't been evicted yet.
+    pub entries: usize,
+}
+
+/// Caches the answers of upstream resolvers for as long as their TTL allows.
+///
+/// Negative answers are cached according to [RFC 2308](https://www.rfc-editor.org/rfc/rfc2308).
+pub(crate) struct DnsCache {
+    entries: HashMap<(String, RecordType), Entry>,
+    max_entries: usize,
+
+    hits: u64,
+    misses: u64,
+}
+
+struct Entry {
+    answer: DnsAnswer,
+    inserted_at: Instant,
+    expires_at: Instant,
+}
+
+impl DnsCache {
+    pub(crate) fn new(max_entries: usize) -> Self {
+        Self {
+            entries: HashMap::default(),
+            max_entries,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    /// Returns the cached answer for the given query, with its TTLs reduced by the time it spent in the cache.
+    pub(crate) fn get(
+        &mut self,
+        name: &str,
+        record_type: RecordType,
+        now: Instant,
+    ) -> Option<DnsAnswer> {
+        let key = key(name, record_type);
+
+        let Some(entry) = self.entries.get(&key) else {
+            self.misses += 1;
+            return None;
+        };
+
+        if now >= entry.expires_at {
+            self.entries.remove(&key);
+            self.misses += 1;
+            return None;
+        }
+
+        let age = now.duration_since(entry.inserted_at).as_secs();
+        let mut answer = entry.answer.clone();
+        answer.age_by(u32::try_from(age).unwrap_or(u32::MAX));
+
+        self.hits += 1;
+
+        Some(answer)
+    }
+
+    pub(crate) fn insert(
+        &mut self,
+        name: &str,
+        record_type: RecordType,
+        answer: DnsAnswer,
+        now: Instant,
+    ) {
+        let Some(ttl) = answer.ttl() else {
+            return;
+        };
+        let ttl = Duration::from_secs(ttl.into()).min(MAX_TTL);
+        if ttl.is_zero() {
+            return;
+        }
+
+        let key = key(name, record_type);
+
+        if !self.entries.contains_key(&key) && self.entries.len() >= self.max_entries {
+            self.evict(now);
+        }
+
+        self.entries.insert(
+            key,
+            Entry {
+                answer,
+                inserted_at: now,
+                expires_at: now + ttl,
+            },
+        );
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    pub(crate) fn stats(&self) -> DnsCacheStats {
+        DnsCacheStats {
+            hits: self.hits,
+            misses: self.misses,
+            entries: self.entries.len(),
+        }
+    }
+
+    /// Makes room for a new entry by removing all expired ones or, if there are none, the one that expires soonest.
+    fn evict(&mut self, now: Instant) {
+        self.entries.retain(|_, entry| now < entry.expires_at);
+
+        if self.entries.len() < self.max_entries {
+            return;
+        }
+
+        let Some(soonest) = self
+            .entries
+            .iter()
+            .min_by_key(|(_, entry)| entry.expires_at)
+            .map(|(key, _)| key.clone())
+        else {
+            return;
+        };
+
+        self.entries.remove(&soonest);
+    }
+}
+
+/// DNS names are case-insensitive, see <https://www.rfc-editor.org/rfc/rfc4343>.
+fn key(name: &str, record_type: RecordType) -> (String, RecordType) {
+    (name.to_ascii_lowercase(), record_type)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_resolver::proto::op::ResponseCode;
+    use hickory_resolver::proto::rr::{rdata::A, Name, RData, Record};
+
+    #[test]
+    fn returns_answer_until_it_expires() {
+        let mut cache = DnsCache::new(10);
+        let now = Instant::now();
+
+        cache.insert("example.com", RecordType::A, a_record(60), now);
+
+        assert!(cache
+            .get("example.com", RecordType::A, now + Duration::from_secs(59))
+            .is_some());
+        assert!(cache
+            .get("example.com", RecordType::A, now + Duration::from_secs(60))
+            .is_none());
+    }
+
+    #[test]
+    fn reduces_ttl_by_time_spent_in_cache() {
+        let mut cache = DnsCache::new(10);
+        let now = Instant::now();
+
+        cache.insert("example.com", RecordType::A, a_record(60), now);
+        let answer = cache
+            .get("example.com", RecordType::A, now + Duration::from_secs(20))
+            .unwrap();
+
+        assert_eq!(answer.ttl(), Some(40));
+    }
+
+    #[test]
+    fn caches_per_record_type() {
+        let mut cache = DnsCache::new(10);
+        let now = Instant::now();
+
+        cache.insert("example.com", RecordType::A, a_record(60), now);
+
+        assert!(cache.get("example.com", RecordType::AAAA, now).is_none());
+    }
+
+    #[test]
+    fn ignores_case_of_name() {
+        let mut cache = DnsCache::new(10);
+        let now = Instant::now();
+
+        cache.insert("Example.COM", RecordType::A, a_record(60), now);
+
+        assert!(cache.get("example.com", RecordType::A, now).is_some());
+        assert!(cache.get("EXAMPLE.com", RecordType::A, now).is_some());
+    }
+
+    #[test]
+    fn caches_negative_answers_for_their_negative_ttl() {
+        let mut cache = DnsCache::new(10);
+        let now = Instant::now();
+
+        cache.insert("example.com", RecordType::A, no_records(Some(30)), now);
+
+        assert!(cache
+            .get("example.com", RecordType::A, now + Duration::from_secs(29))
+            .is_some());
+        assert!(cache
+            .get("example.com", RecordType::A, now + Duration::from_secs(30))
+            .is_none());
+    }
+
+    #[test]
+    fn does_not_cache_answers_without_ttl() {
+        let mut cache = DnsCache::new(10);
+        let now = Instant::now();
+
+        cache.insert("example.com", RecordType::A, no_records(None), now);
+        cache.insert("example.org", RecordType::A, a_record(0), now);
+
+        assert_eq!(cache.stats().entries, 0);
+    }
+
+    #[test]
+    fn caps_ttl() {
+        let mut cache = DnsCache::new(10);
+        let now = Instant::now();
+
+        cache.insert("example.com", RecordType::A, a_record(u32::MAX), now);
+
+        assert!(cache
+            .get("example.com", RecordType::A, now + MAX_TTL)
+            .is_none());
+    }
+
+    #[test]
+    fn evicts_expired_answers_first() {
+        let mut cache = DnsCache::new(2);
+        let now = Instant::now();
+
+        cache.insert("a.example.com", RecordType::A, a_record(10), now);
+        cache.insert("b.example.com", RecordType::A, a_record(60), now);
+        cache.insert(
+            "c.example.com",
+            RecordType::A,
+            a_record(5),
+            now + Duration::from_secs(20),
+        );
+
+        let now = now + Duration::from_secs(20);
+        assert!(cache.get("a.example.com", RecordType::A, now).is_none());
+        assert!(cache.get("b.example.com", RecordType::A, now).is_some());
+        assert!(cache.get("c.example.com", RecordType::A, now).is_some());
+    }
+
+    #[test]
+    fn evicts_answer_that_expires_soonest_when_full() {
+        let mut cache = DnsCache::new(2);
+        let now = Instant::now();
+
+        cache.insert("a.example.com", RecordType::A, a_record(60), now);
+        cache.insert("b.example.com", RecordType::A, a_record(30), now);
+        cache.insert("c.example.com", RecordType::A, a_record(90), now);
+
+        assert_eq!(cache.stats().entries, 2);
+        assert!(cache.get("a.example.com", RecordType::A, now).is_some());
+        assert!(cache.get("b.example.com", RecordType::A, now).is_none());
+        assert!(cache.get("c.example.com", RecordType::A, now).is_some());
+    }
+
+    #[test]
+    fn counts_hits_and_misses() {
+        let mut cache = DnsCache::new(10);
+        let now = Instant::now();
+
+        cache.get("example.com", RecordType::A, now);
+        cache.insert("example.com", RecordType::A, a_record(60), now);
+        cache.get("example.com", RecordType::A, now);
+        cache.get("example.com", RecordType::A, now);
+
+        assert_eq!(
+            cache.stats(),
+            DnsCacheStats {
+                hits: 2,
+                misses: 1,
+                entries: 1
+            }
+        );
+    }
+
+    fn a_record(ttl: u32) -> DnsAnswer {
+        DnsAnswer::Records(vec![Record::from_rdata(
+            Name::from_ascii("example.com.").unwrap(),
+            ttl,
+            RData::A(A::new(1, 1, 1, 1)),
+        )])
+    }
+
+    fn no_records(negative_ttl: Option<u32>) -> DnsAnswer {
+        DnsAnswer::NoRecords {
+            soa: None,
+            response_code: ResponseCode::NXDomain,
+            negative_ttl,
+        }
+    }
+}
@@ -1,6 +1,7 @@
 use crate::ip_packet::{IpPacket, MutableIpPacket};
 use crate::peer::{PacketTransformGateway, Peer};
 use crate::peer_store::PeerStore;
+use crate::sockets::Received;
 use crate::utils::{earliest, stun, turn};
 use crate::{GatewayEvent, GatewayTunnel};
 use boringtun::x25519::PublicKey;
@@ -11,11 +12,11 @@ use connlib_shared::messages::{
 };
 use connlib_shared::{Callbacks, Dname, Error, Result, StaticSecret};
 use ip_network::IpNetwork;
+use itertools::Itertools;
 use secrecy::{ExposeSecret as _, Secret};
-use snownet::ServerNode;
-use std::collections::{HashSet, VecDeque};
-use std::net::SocketAddr;
-use std::time::{Duration, Instant};
+use snownet::{CaptureFilter, ServerNode};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::{Duration, Instant, SystemTime};
 
 const PEERS_IPV4: &str = "100.64.0.0/11";
 const PEERS_IPV6: &str = "fd00:2021:1111::/107";
@@ -169,6 +170,47 @@ where
             .add_remote_candidate(conn_id, ice_candidate, Instant::now());
     }
 
+    /// Starts capturing the plaintext packets of the given connections, or all of them if `None`.
+    ///
+    /// See [`snownet::Node::start_capture`].
+    pub fn start_capture(
+        &mut self,
+        connections: Option<HashSet<ClientId>>,
+        duration: Duration,
+        max_bytes: usize,
+    ) {
+        let filter = match connections {
+            Some(ids) => CaptureFilter::Connections(ids),
+            None => CaptureFilter::AllConnections,
+        };
+
+        self.role_state.node.start_capture(
+            filter,
+            duration,
+            max_bytes,
+            Instant::now(),
+            SystemTime::now(),
+        );
+    }
+
+    /// Stops the current capture and returns it as a pcapng file.
+    pub fn stop_capture(&mut self) -> Option<Vec<u8>> {
+        self.role_state.node.stop_capture()
+    }
+
+    /// Rotates the preshared key of the connection to the given client, see [`snownet::Node::rotate_preshared_key`].
+    ///
+    /// The client initiates the rotation, this installs the key it sent us, see [`RotatePresharedKey`](connlib_shared::messages::RotatePresharedKey).
+    pub fn rotate_preshared_key(&mut self, conn_id: ClientId, key: Secret<Key>) -> Result<()> {
+        self.role_state.node.rotate_preshared_key(
+            conn_id,
+            key.expose_secret().0.into(),
+            Instant::now(),
+        )?;
+
+        Ok(())
+    }
+
     fn new_peer(
         &mut self,
         ips: Vec<IpNetwork>,
@@ -205,60 +247,74 @@ impl GatewayState {
         }
     }
 
-    pub(crate) fn encapsulate<'s>(
-        &'s mut self,
-        packet: MutableIpPacket<'_>,
-    ) -> Option<snownet::Transmit<'s>> {
-        let dest = packet.destination();
-
-        let peer = self.peers.peer_by_ip_mut(dest)?;
-        let packet = peer.transform(packet)?;
-
-        let transmit = self
-            .node
-            .encapsulate(peer.conn_id, packet.as_immutable().into(), Instant::now())
-            .inspect_err(|e| tracing::debug!("Failed to encapsulate: {e}"))
-            .ok()??;
+    /// Determines the connection of each packet read from the device, see [`GatewayState::encapsulate_batch`].
+    pub(crate) fn route_packets<'a>(
+        &mut self,
+        packets: impl IntoIterator<Item = MutableIpPacket<'a>>,
+    ) -> HashMap<ClientId, Vec<MutableIpPacket<'a>>> {
+        packets
+            .into_iter()
+            .filter_map(|packet| {
+                let peer = self.peers.peer_by_ip_mut(packet.destination())?;
+                let packet = peer.transform(packet)?;
+
+                Some((peer.conn_id, packet))
+            })
+            .into_group_map()
+    }
 
-        Some(transmit)
+    /// Encapsulates packets of the same connection together, see [`snownet::Node::encapsulate_batch`].
+    pub(crate) fn encapsulate_batch<'s>(
+        &'s mut self,
+        connection: ClientId,
+        packets: &[MutableIpPacket<'_>],
+        now: Instant,
+    ) -> Vec<snownet::Transmit<'s>> {
+        self.node
+            .encapsulate_batch(
+                connection,
+                packets.iter().map(|p| p.as_immutable().into()),
+                now,
+            )
+            .inspect_err(|e| tracing::debug!(%connection, "Failed to encapsulate: {e}"))
+            .unwrap_or_default()
     }
 
-    pub(crate) fn decapsulate<'b>(
+    /// Decapsulates all packets received from the network at once, see [`snownet::Node::decapsulate_batch`].
+    pub(crate) fn decapsulate_batch<'b, 'p>(
         &mut self,
-        local: SocketAddr,
-        from: SocketAddr,
-        packet: &[u8],
+        packets: impl IntoIterator<Item = Received<'p>>,
         now: Instant,
         buffer: &'b mut [u8],
-    ) -> Option<IpPacket<'b>> {
-        let (conn_id, packet) = self.node.decapsulate(
-            local,
-            from,
-            packet,
-            now,
-            buffer,
-        )
-        .inspect_err(|e| tracing::warn!(%local, %from, num_bytes = %packet.len(), "Failed to decapsulate incoming packet: {e}"))
-        .ok()??;
-
-        let Some(peer) = self.peers.get_mut(&conn_id) else {
-            tracing::error!(%conn_id, %local, %from, "Couldn't find connection");
-
-            return None;
-        };
+    ) -> Vec<IpPacket<'b>> {
+        self.node
+            .decapsulate_batch(
+                packets.into_iter().map(|r| (r.local, r.from, r.packet)),
+                now,
+                buffer,
+            )
+            .into_iter()
+            .filter_map(|(conn_id, packet)| {
+                let Some(peer) = self.peers.get_mut(&conn_id) else {
+                    tracing::error!(%conn_id, "Couldn't find connection");
 
-        let packet = match peer.untransform(packet.into()) {
-            Ok(packet) => packet,
-            Err(e) => {
-                // Note: this can happen with apps such as cURL that if started before the tunnel routes are address
-                // source ips can be sticky.
-                tracing::warn!(%conn_id, %local, %from, "Failed to transform packet: {e}");
+                    return None;
+                };
 
-                return None;
-            }
-        };
+                let packet = match peer.untransform(packet.into()) {
+                    Ok(packet) => packet,
+                    Err(e) => {
+                        // Note: this can happen with apps such as cURL that if started before the tunnel routes are address
+                        // source ips can be sticky.
+                        tracing::warn!(%conn_id, "Failed to transform packet: {e}");
+
+                        return None;
+                    }
+                };
 
-        Some(packet.into_immutable())
+                Some(packet.into_immutable())
+            })
+            .collect()
     }
 
     pub fn poll_timeout(&mut self) -> Option<Instant> {
@@ -297,6 +353,13 @@ impl GatewayState {
                             candidate,
                         });
                 }
+                snownet::Event::ConnectionUpgraded {
+                    connection,
+                    relay,
+                    direct,
+                } => {
+                    tracing::info!(%connection, %relay, %direct, "Connection upgraded to direct path");
+                }
                 _ => {}
             }
         }
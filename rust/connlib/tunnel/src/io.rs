@@ -1,6 +1,6 @@
 use crate::{
     device_channel::Device,
-    dns::{self, DnsQuery},
+    dns::{DnsAnswer, DnsQuery},
     ip_packet::{IpPacket, MutableIpPacket},
     sockets::{Received, Sockets},
 };
@@ -38,8 +38,9 @@ pub struct Io {
 
 pub enum Input<'a, I> {
     Timeout(Instant),
-    Device(MutableIpPacket<'a>),
+    Device(Vec<MutableIpPacket<'a>>),
     Network(I),
+    DnsAnswer(DnsQuery<'static>, DnsAnswer),
 }
 
 impl Io {
@@ -69,18 +70,13 @@ impl Io {
         device_buffer: &'b mut [u8],
     ) -> Poll<io::Result<Input<'b, impl Iterator<Item = Received<'b>>>>> {
         loop {
-            // FIXME: Building the DNS response in here isn't very clean because this should only be the IO component and not do business-logic.
-            // But it also seems weird to pass the DNS result out if we've got the device right here.
             match self.forwarded_dns_queries.poll_unpin(cx) {
                 Poll::Ready((Ok(response), query)) => {
-                    match dns::build_response_from_resolve_result(query.query, response) {
-                        Ok(Some(packet)) => {
-                            self.device.write(packet)?;
-                        }
-                        Ok(None) => {}
+                    match DnsAnswer::from_resolve_result(response) {
+                        Ok(answer) => return Poll::Ready(Ok(Input::DnsAnswer(query, answer))),
                         Err(_) => {
                             // The error might contain sensitive information therefore we ignore it
-                            tracing::debug!("Failed to build DNS response from lookup result");
+                            tracing::debug!("Failed to resolve DNS query");
                         }
                     }
 
@@ -105,8 +101,8 @@ impl Io {
 
             ready!(self.sockets.poll_flush(cx))?;
 
-            if let Poll::Ready(packet) = self.device.poll_read(device_buffer, cx)? {
-                return Poll::Ready(Ok(Input::Device(packet)));
+            if let Poll::Ready(packets) = self.device.poll_read_many(device_buffer, cx)? {
+                return Poll::Ready(Ok(Input::Device(packets)));
             }
 
             return Poll::Pending;
@@ -179,7 +175,7 @@ impl Io {
             destination: transmit.dst,
             ecn: None,
             contents: Bytes::copy_from_slice(&transmit.payload),
-            segment_size: None,
+            segment_size: transmit.segment_size,
             src_ip: transmit.src.map(|s| s.ip()),
         })?;
 
@@ -200,7 +196,7 @@ fn create_resolvers(
         .into_iter()
         .map(|(sentinel, srv)| {
             let mut resolver_config = ResolverConfig::new();
-            resolver_config.add_name_server(NameServerConfig::new(srv.address(), Protocol::Udp));
+            resolver_config.add_name_server(name_server_config(srv));
             (
                 sentinel,
                 TokioAsyncResolver::tokio(resolver_config, Default::default()),
@@ -208,3 +204,62 @@ fn create_resolvers(
         })
         .collect()
 }
+
+fn name_server_config(srv: DnsServer) -> NameServerConfig {
+    match srv {
+        DnsServer::IpPort(srv) => NameServerConfig::new(srv.address, Protocol::Udp),
+        DnsServer::Tls(srv) => {
+            let mut config = NameServerConfig::new(srv.address, Protocol::Tls);
+            config.tls_dns_name = Some(srv.server_name);
+
+            config
+        }
+        DnsServer::Https(srv) => {
+            let mut config = NameServerConfig::new(srv.address, Protocol::Https);
+            config.tls_dns_name = Some(srv.server_name);
+
+            config
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use connlib_shared::messages::{HttpsDnsServer, IpDnsServer, TlsDnsServer};
+
+    #[test]
+    fn ip_port_upstream_uses_udp() {
+        let config = name_server_config(DnsServer::IpPort(IpDnsServer {
+            address: "1.1.1.1:53".parse().unwrap(),
+        }));
+
+        assert_eq!(config.socket_addr, "1.1.1.1:53".parse().unwrap());
+        assert_eq!(config.protocol, Protocol::Udp);
+        assert_eq!(config.tls_dns_name, None);
+    }
+
+    #[test]
+    fn tls_upstream_uses_tls_with_server_name() {
+        let config = name_server_config(DnsServer::Tls(TlsDnsServer {
+            address: "1.1.1.1:853".parse().unwrap(),
+            server_name: "cloudflare-dns.com".to_owned(),
+        }));
+
+        assert_eq!(config.socket_addr, "1.1.1.1:853".parse().unwrap());
+        assert_eq!(config.protocol, Protocol::Tls);
+        assert_eq!(config.tls_dns_name.as_deref(), Some("cloudflare-dns.com"));
+    }
+
+    #[test]
+    fn https_upstream_uses_https_with_server_name() {
+        let config = name_server_config(DnsServer::Https(HttpsDnsServer {
+            address: "1.1.1.1:443".parse().unwrap(),
+            server_name: "cloudflare-dns.com".to_owned(),
+        }));
+
+        assert_eq!(config.socket_addr, "1.1.1.1:443".parse().unwrap());
+        assert_eq!(config.protocol, Protocol::Https);
+        assert_eq!(config.tls_dns_name.as_deref(), Some("cloudflare-dns.com"));
+    }
+}
@@ -49,9 +49,11 @@ pub struct Tunnel<CB: Callbacks, TRoleState> {
 
     io: Io,
 
-    write_buf: Box<[u8; MAX_UDP_SIZE]>,
-    ip4_read_buf: Box<[u8; MAX_UDP_SIZE]>,
-    ip6_read_buf: Box<[u8; MAX_UDP_SIZE]>,
+    /// Large enough to hold the decrypted packets of a receive from both sockets, see [`snownet::Node::decapsulate_batch`].
+    write_buf: Box<[u8]>,
+    /// Large enough to receive several GRO batches at once, see [`sockets::RECV_BUFFER_SIZE`].
+    ip4_read_buf: Box<[u8]>,
+    ip6_read_buf: Box<[u8]>,
     device_read_buf: Box<[u8; MAX_UDP_SIZE]>,
 }
 
@@ -68,9 +70,9 @@ where
             io: Io::new(sockets)?,
             callbacks,
             role_state: ClientState::new(private_key),
-            write_buf: Box::new([0u8; MAX_UDP_SIZE]),
-            ip4_read_buf: Box::new([0u8; MAX_UDP_SIZE]),
-            ip6_read_buf: Box::new([0u8; MAX_UDP_SIZE]),
+            write_buf: vec![0u8; 2 * sockets::RECV_BUFFER_SIZE].into_boxed_slice(),
+            ip4_read_buf: vec![0u8; sockets::RECV_BUFFER_SIZE].into_boxed_slice(),
+            ip6_read_buf: vec![0u8; sockets::RECV_BUFFER_SIZE].into_boxed_slice(),
             device_read_buf: Box::new([0u8; MAX_UDP_SIZE]),
         })
     }
@@ -117,27 +119,28 @@ where
                     self.role_state.handle_timeout(timeout);
                     continue;
                 }
-                Poll::Ready(io::Input::Device(packet)) => {
-                    let Some(transmit) = self.role_state.encapsulate(packet, Instant::now()) else {
-                        continue;
-                    };
-
-                    self.io.send_network(transmit)?;
+                Poll::Ready(io::Input::DnsAnswer(query, answer)) => {
+                    self.role_state.on_dns_answer(query, answer, Instant::now());
+                    continue;
+                }
+                Poll::Ready(io::Input::Device(packets)) => {
+                    let now = Instant::now();
+
+                    for (connection, packets) in self.role_state.route_packets(packets, now) {
+                        for transmit in self.role_state.encapsulate_batch(connection, &packets, now)
+                        {
+                            self.io.send_network(transmit)?;
+                        }
+                    }
 
                     continue;
                 }
                 Poll::Ready(io::Input::Network(packets)) => {
-                    for received in packets {
-                        let Some(packet) = self.role_state.decapsulate(
-                            received.local,
-                            received.from,
-                            received.packet,
-                            std::time::Instant::now(),
-                            self.write_buf.as_mut(),
-                        ) else {
-                            continue;
-                        };
-
+                    for packet in self.role_state.decapsulate_batch(
+                        packets,
+                        Instant::now(),
+                        self.write_buf.as_mut(),
+                    ) {
                         self.io.device_mut().write(packet)?;
                     }
 
@@ -164,9 +167,9 @@ where
             io: Io::new(sockets)?,
             callbacks,
             role_state: GatewayState::new(private_key),
-            write_buf: Box::new([0u8; MAX_UDP_SIZE]),
-            ip4_read_buf: Box::new([0u8; MAX_UDP_SIZE]),
-            ip6_read_buf: Box::new([0u8; MAX_UDP_SIZE]),
+            write_buf: vec![0u8; 2 * sockets::RECV_BUFFER_SIZE].into_boxed_slice(),
+            ip4_read_buf: vec![0u8; sockets::RECV_BUFFER_SIZE].into_boxed_slice(),
+            ip6_read_buf: vec![0u8; sockets::RECV_BUFFER_SIZE].into_boxed_slice(),
             device_read_buf: Box::new([0u8; MAX_UDP_SIZE]),
         })
     }
@@ -196,27 +199,28 @@ where
                     self.role_state.handle_timeout(timeout);
                     continue;
                 }
-                Poll::Ready(io::Input::Device(packet)) => {
-                    let Some(transmit) = self.role_state.encapsulate(packet) else {
-                        continue;
-                    };
-
-                    self.io.send_network(transmit)?;
+                Poll::Ready(io::Input::DnsAnswer(..)) => {
+                    debug_assert!(false, "Gateways never forward DNS queries");
+                    continue;
+                }
+                Poll::Ready(io::Input::Device(packets)) => {
+                    let now = Instant::now();
+
+                    for (connection, packets) in self.role_state.route_packets(packets) {
+                        for transmit in self.role_state.encapsulate_batch(connection, &packets, now)
+                        {
+                            self.io.send_network(transmit)?;
+                        }
+                    }
 
                     continue;
                 }
                 Poll::Ready(io::Input::Network(packets)) => {
-                    for received in packets {
-                        let Some(packet) = self.role_state.decapsulate(
-                            received.local,
-                            received.from,
-                            received.packet,
-                            std::time::Instant::now(),
-                            self.write_buf.as_mut(),
-                        ) else {
-                            continue;
-                        };
-
+                    for packet in self.role_state.decapsulate_batch(
+                        packets,
+                        Instant::now(),
+                        self.write_buf.as_mut(),
+                    ) {
                         self.io.device_mut().write(packet)?;
                     }
 
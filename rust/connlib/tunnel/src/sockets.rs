@@ -1,4 +1,3 @@
-use core::slice;
 use quinn_udp::{RecvMeta, UdpSockRef, UdpSocketState};
 use socket2::{SockAddr, Type};
 use std::{
@@ -10,6 +9,15 @@ use tokio::{io::Interest, net::UdpSocket};
 
 use crate::Result;
 
+/// How many datagrams (or GRO batches thereof) we read from a socket with a single syscall.
+const MAX_MESSAGES_PER_RECV: usize = 8;
+
+/// The largest message we may receive, either a single datagram or a GRO batch, which the kernel caps at 64KB as well.
+const MAX_MESSAGE_SIZE: usize = (1 << 16) - 1;
+
+/// How large the buffers passed to [`Sockets::poll_recv_from`] need to be to receive the maximum number of messages at once.
+pub const RECV_BUFFER_SIZE: usize = MAX_MESSAGES_PER_RECV * MAX_MESSAGE_SIZE;
+
 pub struct Sockets {
     socket_v4: Option<Socket>,
     socket_v6: Option<Socket>,
@@ -240,6 +248,9 @@ impl Socket {
         })
     }
 
+    /// Receives several messages at once, each of which may be a GRO batch of datagrams from the same sender.
+    ///
+    /// The `buffer` is split into chunks of [`MAX_MESSAGE_SIZE`], one per message, see [`RECV_BUFFER_SIZE`].
     #[allow(clippy::type_complexity)]
     fn poll_recv_from<'b>(
         &self,
@@ -252,43 +263,59 @@ impl Socket {
             state,
             ..
         } = self;
+        let port = *port;
 
-        let bufs = &mut [IoSliceMut::new(buffer)];
-        let mut meta = RecvMeta::default();
+        let max_messages = MAX_MESSAGES_PER_RECV
+            .min(quinn_udp::BATCH_SIZE)
+            .min(buffer.len().div_ceil(MAX_MESSAGE_SIZE));
 
-        loop {
+        let mut chunks = buffer.chunks_mut(MAX_MESSAGE_SIZE);
+        let mut bufs: [IoSliceMut; MAX_MESSAGES_PER_RECV] =
+            std::array::from_fn(|_| IoSliceMut::new(chunks.next().unwrap_or_default()));
+        let mut metas = [RecvMeta::default(); MAX_MESSAGES_PER_RECV];
+
+        let num_messages = loop {
             ready!(socket.poll_recv_ready(cx))?;
 
-            if let Ok(len) = socket.try_io(Interest::READABLE, || {
-                state.recv((&socket).into(), bufs, slice::from_mut(&mut meta))
+            if let Ok(num_messages) = socket.try_io(Interest::READABLE, || {
+                state.recv(
+                    (&socket).into(),
+                    &mut bufs[..max_messages],
+                    &mut metas[..max_messages],
+                )
             }) {
-                debug_assert_eq!(len, 1);
-
-                if meta.len == 0 {
-                    continue;
-                }
-
+                break num_messages;
+            }
+        };
+
+        let iter = metas
+            .into_iter()
+            .zip(buffer.chunks(MAX_MESSAGE_SIZE))
+            .take(num_messages)
+            .filter(|(meta, _)| meta.len > 0)
+            .filter_map(move |(meta, message)| {
                 let Some(local_ip) = meta.dst_ip else {
                     tracing::warn!("Skipping packet without local IP");
-                    continue;
+                    return None;
                 };
 
-                let local = SocketAddr::new(local_ip, *port);
-
-                let iter = buffer[..meta.len]
+                Some((SocketAddr::new(local_ip, port), meta, message))
+            })
+            .flat_map(|(local, meta, message)| {
+                // With GRO, a message contains several datagrams of `stride` bytes, only the last one may be shorter.
+                message[..meta.len]
                     .chunks(meta.stride)
                     .map(move |packet| Received {
                         local,
                         from: meta.addr,
                         packet,
                     })
-                    .inspect(|r| {
-                        tracing::trace!(target: "wire", from = "network", src = %r.from, dst = %r.local, num_bytes = %r.packet.len());
-                    });
+            })
+            .inspect(|r| {
+                tracing::trace!(target: "wire", from = "network", src = %r.from, dst = %r.local, num_bytes = %r.packet.len());
+            });
 
-                return Poll::Ready(Ok(iter));
-            }
-        }
+        Poll::Ready(Ok(iter))
     }
 
     fn poll_flush(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
@@ -322,9 +349,28 @@ impl Socket {
     }
 
     fn send(&mut self, transmit: quinn_udp::Transmit) {
-        tracing::trace!(target: "wire", to = "network", src = ?transmit.src_ip, dst = %transmit.destination, num_bytes = %transmit.contents.len());
-
-        self.buffered_transmits.push(transmit);
+        tracing::trace!(target: "wire", to = "network", src = ?transmit.src_ip, dst = %transmit.destination, num_bytes = %transmit.contents.len(), segment_size = ?transmit.segment_size);
+
+        let Some(segment_size) = transmit.segment_size else {
+            self.buffered_transmits.push(transmit);
+            return;
+        };
+
+        // Split the transmit into as many segments as the socket can send at once, i.e. individual datagrams without GSO support.
+        let max_segments = self.state.max_gso_segments();
+        let max_len = segment_size * max_segments;
+
+        for start in (0..transmit.contents.len()).step_by(max_len) {
+            let end = (start + max_len).min(transmit.contents.len());
+
+            self.buffered_transmits.push(quinn_udp::Transmit {
+                destination: transmit.destination,
+                ecn: transmit.ecn,
+                contents: transmit.contents.slice(start..end),
+                segment_size: (end - start > segment_size).then_some(segment_size),
+                src_ip: transmit.src_ip,
+            });
+        }
 
         debug_assert!(
             self.buffered_transmits.len() < 10_000,
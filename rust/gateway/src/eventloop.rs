@@ -1,6 +1,6 @@
 use crate::messages::{
-    AllowAccess, BroadcastClientIceCandidates, ClientIceCandidates, ConnectionReady,
-    EgressMessages, IngressMessages, RejectAccess, RequestConnection,
+    AllowAccess, BroadcastClientIceCandidates, ClientIceCandidates, ClientPresharedKey,
+    ConnectionReady, EgressMessages, IngressMessages, RejectAccess, RequestConnection,
 };
 use crate::CallbackHandler;
 use anyhow::Result;
@@ -25,19 +25,31 @@ pub const PHOENIX_TOPIC: &str = "gateway";
 pub struct Eventloop {
     tunnel: GatewayTunnel<CallbackHandler>,
     portal: PhoenixChannel<(), IngressMessages, ()>,
+    rx: tokio::sync::mpsc::UnboundedReceiver<Command>,
 
     resolve_tasks:
         futures_bounded::FuturesTupleSet<Vec<IpNetwork>, Either<RequestConnection, AllowAccess>>,
 }
 
+/// Commands that can be sent to the [`Eventloop`].
+pub enum Command {
+    StartCapture {
+        duration: Duration,
+        max_bytes: usize,
+    },
+    StopCapture(tokio::sync::oneshot::Sender<Option<Vec<u8>>>),
+}
+
 impl Eventloop {
     pub(crate) fn new(
         tunnel: GatewayTunnel<CallbackHandler>,
         portal: PhoenixChannel<(), IngressMessages, ()>,
+        rx: tokio::sync::mpsc::UnboundedReceiver<Command>,
     ) -> Self {
         Self {
             tunnel,
             portal,
+            rx,
             resolve_tasks: futures_bounded::FuturesTupleSet::new(Duration::from_secs(60), 100),
         }
     }
@@ -46,6 +58,21 @@ impl Eventloop {
 impl Eventloop {
     pub fn poll(&mut self, cx: &mut Context<'_>) -> Poll<Result<Infallible>> {
         loop {
+            match self.rx.poll_recv(cx) {
+                Poll::Ready(Some(Command::StartCapture {
+                    duration,
+                    max_bytes,
+                })) => {
+                    self.tunnel.start_capture(None, duration, max_bytes);
+                    continue;
+                }
+                Poll::Ready(Some(Command::StopCapture(tx))) => {
+                    let _ = tx.send(self.tunnel.stop_capture());
+                    continue;
+                }
+                Poll::Ready(None) | Poll::Pending => {}
+            }
+
             match self.tunnel.poll_next_event(cx) {
                 Poll::Ready(Ok(event)) => {
                     self.handle_tunnel_event(event);
@@ -150,6 +177,18 @@ impl Eventloop {
             } => {
                 self.tunnel.remove_access(&client_id, &resource_id);
             }
+            phoenix_channel::Event::InboundMessage {
+                msg:
+                    IngressMessages::RotatePresharedKey(ClientPresharedKey {
+                        client_id,
+                        preshared_key,
+                    }),
+                ..
+            } => {
+                if let Err(e) = self.tunnel.rotate_preshared_key(client_id, preshared_key) {
+                    tracing::warn!(client = %client_id, "Failed to rotate preshared key: {e}");
+                }
+            }
             phoenix_channel::Event::InboundMessage {
                 msg: IngressMessages::Init(_),
                 ..
@@ -1,4 +1,4 @@
-use crate::eventloop::{Eventloop, PHOENIX_TOPIC};
+use crate::eventloop::{Command, Eventloop, PHOENIX_TOPIC};
 use crate::messages::InitGateway;
 use anyhow::{Context, Result};
 use backoff::ExponentialBackoffBuilder;
@@ -11,8 +11,10 @@ use secrecy::{Secret, SecretString};
 use std::convert::Infallible;
 use std::path::Path;
 use std::pin::pin;
+use std::time::Duration;
 use tokio::io::AsyncWriteExt;
 use tokio::signal::ctrl_c;
+use tokio::sync::mpsc::UnboundedSender;
 use tracing_subscriber::layer;
 use uuid::Uuid;
 
@@ -20,6 +22,9 @@ mod eventloop;
 mod messages;
 
 const ID_PATH: &str = "/var/lib/firezone/gateway_id";
+const CAPTURE_PATH: &str = "/var/lib/firezone/gateway_capture.pcapng";
+const CAPTURE_DURATION: Duration = Duration::from_secs(60);
+const CAPTURE_MAX_BYTES: usize = 10 * 1024 * 1024;
 
 #[tokio::main]
 async fn main() {
@@ -111,7 +116,10 @@ async fn run(login: LoginUrl, private_key: StaticSecret) -> Result<Infallible> {
         .set_interface(&init.interface)
         .context("Failed to set interface")?;
 
-    let mut eventloop = Eventloop::new(tunnel, portal);
+    let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+    spawn_capture_trigger(tx);
+
+    let mut eventloop = Eventloop::new(tunnel, portal, rx);
 
     future::poll_fn(|cx| eventloop.poll(cx))
         .await
@@ -120,6 +128,52 @@ async fn run(login: LoginUrl, private_key: StaticSecret) -> Result<Infallible> {
     unreachable!()
 }
 
+/// Toggles a capture of the plaintext packets of all connections on `SIGUSR1`.
+///
+/// The first signal starts the capture, the second one writes it to [`CAPTURE_PATH`].
+#[cfg(unix)]
+fn spawn_capture_trigger(commands: UnboundedSender<Command>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    tokio::spawn(async move {
+        let mut signals = match signal(SignalKind::user_defined1()) {
+            Ok(signals) => signals,
+            Err(e) => {
+                tracing::warn!("Failed to listen for SIGUSR1, packet capture is unavailable: {e}");
+                return;
+            }
+        };
+
+        while signals.recv().await.is_some() {
+            let _ = commands.send(Command::StartCapture {
+                duration: CAPTURE_DURATION,
+                max_bytes: CAPTURE_MAX_BYTES,
+            });
+            tracing::info!(duration = ?CAPTURE_DURATION, "Started packet capture; send SIGUSR1 again to write it to {CAPTURE_PATH}");
+
+            if signals.recv().await.is_none() {
+                return;
+            }
+
+            let (tx, rx) = tokio::sync::oneshot::channel();
+            let _ = commands.send(Command::StopCapture(tx));
+
+            let Ok(Some(pcapng)) = rx.await else {
+                tracing::warn!("No packet capture to write");
+                continue;
+            };
+
+            match tokio::fs::write(CAPTURE_PATH, pcapng).await {
+                Ok(()) => tracing::info!("Wrote packet capture to {CAPTURE_PATH}"),
+                Err(e) => tracing::warn!("Failed to write packet capture to {CAPTURE_PATH}: {e}"),
+            }
+        }
+    });
+}
+
+#[cfg(not(unix))]
+fn spawn_capture_trigger(_: UnboundedSender<Command>) {}
+
 #[derive(Clone)]
 struct CallbackHandler;
 
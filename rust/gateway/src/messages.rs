@@ -2,7 +2,7 @@ use chrono::{serde::ts_seconds_option, DateTime, Utc};
 use connlib_shared::{
     messages::{
         ClientId, ClientPayload, GatewayResponse, Interface, Peer, Relay, ResourceDescription,
-        ResourceId,
+        ResourceId, SecretKey,
     },
     Dname,
 };
@@ -70,9 +70,25 @@ pub enum IngressMessages {
     AllowAccess(AllowAccess),
     RejectAccess(RejectAccess),
     IceCandidates(ClientIceCandidates),
+    RotatePresharedKey(ClientPresharedKey),
     Init(InitGateway),
 }
 
+/// A new preshared key for the connection to a client, see [`RotatePresharedKey`](connlib_shared::messages::RotatePresharedKey).
+#[derive(Debug, Deserialize, Clone)]
+pub struct ClientPresharedKey {
+    /// Client's id the preshared key is for
+    pub client_id: ClientId,
+    /// The new preshared key the client generated
+    pub preshared_key: SecretKey,
+}
+
+impl PartialEq for ClientPresharedKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.client_id == other.client_id
+    }
+}
+
 /// A client's ice candidate message.
 #[derive(Debug, Serialize, Clone, PartialEq, Eq)]
 pub struct BroadcastClientIceCandidates {
@@ -112,6 +128,7 @@ mod test {
     use super::*;
     use phoenix_channel::InitMessage;
     use phoenix_channel::PhoenixMessage;
+    use secrecy::ExposeSecret as _;
 
     #[test]
     fn request_connection_message() {
@@ -166,6 +183,30 @@ mod test {
         let _: PhoenixMessage<IngressMessages, ()> = serde_json::from_str(message).unwrap();
     }
 
+    #[test]
+    fn rotate_preshared_key_message() {
+        let message = r#"{"event":"rotate_preshared_key","ref":null,"topic":"gateway","payload":{"client_id":"3b1d86a0-4737-4814-8add-cfec42669511","preshared_key":"sMeTuiJ3mezfpVdan948CmisIWbwBZ1z7jBNnbVtfVg="}}"#;
+        let ingress_message =
+            serde_json::from_str::<PhoenixMessage<IngressMessages, ()>>(message).unwrap();
+
+        let rotation = serde_json::from_str::<ClientPresharedKey>(
+            r#"{"client_id":"3b1d86a0-4737-4814-8add-cfec42669511","preshared_key":"sMeTuiJ3mezfpVdan948CmisIWbwBZ1z7jBNnbVtfVg="}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            rotation.preshared_key.expose_secret().to_string(),
+            "sMeTuiJ3mezfpVdan948CmisIWbwBZ1z7jBNnbVtfVg="
+        );
+        assert_eq!(
+            ingress_message,
+            PhoenixMessage::new_message(
+                "gateway",
+                IngressMessages::RotatePresharedKey(rotation),
+                None
+            )
+        );
+    }
+
     #[test]
     fn init_phoenix_message() {
         let m = InitMessage::Init(InitGateway {
@@ -1,29 +1,88 @@
-use axum::http::StatusCode;
+use axum::http::{header, StatusCode};
 use axum::routing::get;
 use axum::Router;
 use std::net::SocketAddr;
 
+/// The content type of the Prometheus text exposition format.
+///
+/// See <https://prometheus.io/docs/instrumenting/exposition_formats/#text-based-format>.
+const PROMETHEUS_TEXT_FORMAT: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// The response to `GET /healthz`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HealthStatus {
+    /// Whether we are healthy, i.e. whether we respond with 200 OK or 400 BAD REQUEST.
+    pub is_healthy: bool,
+    /// Human-readable details, sent as the response body.
+    pub details: String,
+}
+
+impl From<bool> for HealthStatus {
+    fn from(is_healthy: bool) -> Self {
+        Self {
+            is_healthy,
+            details: String::new(),
+        }
+    }
+}
+
 /// Runs an HTTP server that responds to `GET /healthz` with 200 OK or 400 BAD REQUEST, depending on the return value of `is_healthy`.
 pub async fn serve(
     addr: impl Into<SocketAddr>,
     is_healthy: impl Fn() -> bool + Clone + Send + Sync + 'static,
 ) -> std::io::Result<()> {
-    let addr = addr.into();
-
-    let service = Router::new()
-        .route(
-            "/healthz",
-            get(move || async move {
-                if is_healthy() {
-                    StatusCode::OK
-                } else {
-                    StatusCode::BAD_REQUEST
-                }
-            }),
-        )
-        .into_make_service();
-
-    axum::serve(tokio::net::TcpListener::bind(addr).await?, service).await?;
+    run(
+        addr.into(),
+        health_check_router(move || HealthStatus::from(is_healthy())),
+    )
+    .await
+}
+
+/// Like [`serve`] but additionally responds to `GET /metrics` with the output of `metrics`.
+///
+/// The response to `GET /healthz` includes the [`HealthStatus::details`] returned by `health`.
+/// `metrics` must return the metrics encoded in the Prometheus text exposition format.
+pub async fn serve_with_metrics(
+    addr: impl Into<SocketAddr>,
+    health: impl Fn() -> HealthStatus + Clone + Send + Sync + 'static,
+    metrics: impl Fn() -> String + Clone + Send + Sync + 'static,
+) -> std::io::Result<()> {
+    let router = health_check_router(health).route(
+        "/metrics",
+        get(move || async move { ([(header::CONTENT_TYPE, PROMETHEUS_TEXT_FORMAT)], metrics()) }),
+    );
+
+    run(addr.into(), router).await
+}
+
+fn health_check_router(
+    health: impl Fn() -> HealthStatus + Clone + Send + Sync + 'static,
+) -> Router {
+    Router::new().route(
+        "/healthz",
+        get(move || async move {
+            let HealthStatus {
+                is_healthy,
+                details,
+            } = health();
+
+            let status = if is_healthy {
+                StatusCode::OK
+            } else {
+                StatusCode::BAD_REQUEST
+            };
+
+            (status, details)
+        }),
+    )
+}
+
+async fn run(addr: SocketAddr, router: Router) -> std::io::Result<()> {
+    axum::serve(
+        tokio::net::TcpListener::bind(addr).await?,
+        router.into_make_service(),
+    )
+    .await?;
 
     Ok(())
 }
@@ -0,0 +1,222 @@
+//! A minimal writer for [pcapng](https://www.ietf.org/archive/id/draft-ietf-opsawg-pcapng-01.html) files of raw IP packets, e.g. for Wireshark.
+
+use std::time::Duration;
+
+/// See <https://www.tcpdump.org/linktypes.html>.
+const LINKTYPE_RAW: u16 = 101;
+
+const SECTION_HEADER_BLOCK: u32 = 0x0A0D_0D0A;
+const INTERFACE_DESCRIPTION_BLOCK: u32 = 0x0000_0001;
+const ENHANCED_PACKET_BLOCK: u32 = 0x0000_0006;
+const BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+
+const OPT_END_OF_OPT: u16 = 0;
+const OPT_COMMENT: u16 = 1;
+const OPT_EPB_FLAGS: u16 = 2;
+
+/// A pcapng file with a single interface of raw IP packets, kept in memory.
+#[derive(Debug, Clone)]
+pub struct Writer {
+    file: Vec<u8>,
+}
+
+/// An option of a packet, see <https://www.ietf.org/archive/id/draft-ietf-opsawg-pcapng-01.html#name-enhanced-packet-block-option>.
+#[derive(Debug, Clone, Copy)]
+pub enum PacketOption<'a> {
+    /// A human-readable comment, shown by Wireshark next to the packet.
+    Comment(&'a str),
+    Direction(Direction),
+}
+
+/// Whether a packet was received or sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Inbound,
+    Outbound,
+}
+
+impl Default for Writer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Writer {
+    /// Starts a new file, consisting of the section header and the description of our only interface.
+    pub fn new() -> Self {
+        let mut file = Vec::with_capacity(1024);
+
+        write_block(&mut file, SECTION_HEADER_BLOCK, |body| {
+            body.extend_from_slice(&BYTE_ORDER_MAGIC.to_le_bytes());
+            body.extend_from_slice(&1u16.to_le_bytes()); // Major version.
+            body.extend_from_slice(&0u16.to_le_bytes()); // Minor version.
+            body.extend_from_slice(&(-1i64).to_le_bytes()); // Section length is unknown.
+        });
+        write_block(&mut file, INTERFACE_DESCRIPTION_BLOCK, |body| {
+            body.extend_from_slice(&LINKTYPE_RAW.to_le_bytes());
+            body.extend_from_slice(&0u16.to_le_bytes()); // Reserved.
+            body.extend_from_slice(&0u32.to_le_bytes()); // No snapshot length.
+        });
+
+        Self { file }
+    }
+
+    /// The size of the file in bytes.
+    pub fn num_bytes(&self) -> usize {
+        self.file.len()
+    }
+
+    /// Appends an IP packet, captured at `timestamp` (since the UNIX epoch).
+    ///
+    /// Returns `false` without writing anything if the packet would grow the file beyond `max_bytes`.
+    pub fn write_packet(
+        &mut self,
+        timestamp: Duration,
+        packet: &[u8],
+        options: &[PacketOption<'_>],
+        max_bytes: usize,
+    ) -> bool {
+        let timestamp = timestamp.as_micros() as u64;
+
+        let mut block = Vec::with_capacity(64 + packet.len());
+        write_block(&mut block, ENHANCED_PACKET_BLOCK, |body| {
+            body.extend_from_slice(&0u32.to_le_bytes()); // Interface ID.
+            body.extend_from_slice(&((timestamp >> 32) as u32).to_le_bytes());
+            body.extend_from_slice(&(timestamp as u32).to_le_bytes());
+            body.extend_from_slice(&(packet.len() as u32).to_le_bytes()); // Captured length.
+            body.extend_from_slice(&(packet.len() as u32).to_le_bytes()); // Original length.
+            body.extend_from_slice(packet);
+            pad_to_32_bits(body);
+
+            if options.is_empty() {
+                return;
+            }
+
+            for option in options {
+                match option {
+                    PacketOption::Comment(comment) => {
+                        write_option(body, OPT_COMMENT, comment.as_bytes())
+                    }
+                    PacketOption::Direction(direction) => {
+                        let flags: u32 = match direction {
+                            Direction::Inbound => 0b01,
+                            Direction::Outbound => 0b10,
+                        };
+
+                        write_option(body, OPT_EPB_FLAGS, &flags.to_le_bytes())
+                    }
+                }
+            }
+            write_option(body, OPT_END_OF_OPT, &[]);
+        });
+
+        if self.file.len() + block.len() > max_bytes {
+            return false;
+        }
+
+        self.file.extend_from_slice(&block);
+
+        true
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.file
+    }
+}
+
+/// Writes a block with the given type, framing the body written by `write_body` with its length.
+fn write_block(file: &mut Vec<u8>, block_type: u32, write_body: impl FnOnce(&mut Vec<u8>)) {
+    let start = file.len();
+
+    file.extend_from_slice(&block_type.to_le_bytes());
+    file.extend_from_slice(&0u32.to_le_bytes()); // Placeholder for the length.
+    write_body(file);
+
+    let len = (file.len() - start + 4) as u32;
+    file[start + 4..start + 8].copy_from_slice(&len.to_le_bytes());
+    file.extend_from_slice(&len.to_le_bytes());
+}
+
+fn write_option(body: &mut Vec<u8>, code: u16, value: &[u8]) {
+    body.extend_from_slice(&code.to_le_bytes());
+    body.extend_from_slice(&(value.len() as u16).to_le_bytes());
+    body.extend_from_slice(value);
+    pad_to_32_bits(body);
+}
+
+fn pad_to_32_bits(buffer: &mut Vec<u8>) {
+    buffer.resize(buffer.len().next_multiple_of(4), 0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UNLIMITED: usize = usize::MAX;
+
+    #[test]
+    fn starts_with_section_header_and_interface_description() {
+        let file = Writer::new().into_bytes();
+
+        assert_eq!(&file[..4], &SECTION_HEADER_BLOCK.to_le_bytes());
+        assert_eq!(&file[8..12], &BYTE_ORDER_MAGIC.to_le_bytes());
+        assert_eq!(&file[28..32], &INTERFACE_DESCRIPTION_BLOCK.to_le_bytes());
+        assert_eq!(&file[36..38], &LINKTYPE_RAW.to_le_bytes());
+        assert_eq!(file.len(), 28 + 20);
+    }
+
+    #[test]
+    fn writes_packet_as_padded_enhanced_packet_block() {
+        let mut writer = Writer::new();
+
+        assert!(writer.write_packet(Duration::from_secs(1), b"hello", &[], UNLIMITED));
+
+        let file = writer.into_bytes();
+        let block = &file[48..];
+
+        assert_eq!(&block[..4], &ENHANCED_PACKET_BLOCK.to_le_bytes());
+        assert_eq!(block.len(), 28 + 8 + 4);
+        assert_eq!(&block[4..8], &(block.len() as u32).to_le_bytes());
+        assert_eq!(
+            &block[block.len() - 4..],
+            &(block.len() as u32).to_le_bytes()
+        );
+        assert_eq!(&block[16..20], &1_000_000u32.to_le_bytes()); // Lower half of the timestamp.
+        assert_eq!(&block[20..24], &5u32.to_le_bytes()); // Captured length.
+        assert_eq!(&block[28..33], b"hello");
+    }
+
+    #[test]
+    fn writes_options_after_packet() {
+        let mut writer = Writer::new();
+
+        writer.write_packet(
+            Duration::ZERO,
+            b"hello",
+            &[
+                PacketOption::Comment("connection=1"),
+                PacketOption::Direction(Direction::Outbound),
+            ],
+            UNLIMITED,
+        );
+
+        let file = writer.into_bytes();
+        let options = &file[48 + 28 + 8..];
+
+        assert_eq!(&options[..2], &OPT_COMMENT.to_le_bytes());
+        assert_eq!(&options[2..4], &12u16.to_le_bytes());
+        assert_eq!(&options[4..16], b"connection=1");
+        assert_eq!(&options[16..18], &OPT_EPB_FLAGS.to_le_bytes());
+        assert_eq!(&options[20..24], &0b10u32.to_le_bytes());
+        assert_eq!(&options[24..28], &[0, 0, 0, 0]); // End of options.
+    }
+
+    #[test]
+    fn does_not_write_packet_beyond_max_bytes() {
+        let mut writer = Writer::new();
+
+        assert!(!writer.write_packet(Duration::ZERO, b"hello", &[], 48 + 39));
+        assert!(writer.write_packet(Duration::ZERO, b"hello", &[], 48 + 40));
+        assert_eq!(writer.num_bytes(), 48 + 40);
+    }
+}
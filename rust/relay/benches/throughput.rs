@@ -0,0 +1,222 @@
+use anyhow::{anyhow, bail, Context, Result};
+use bytecodec::{DecodeExt, EncodeExt};
+use firezone_relay::{Attribute, TURN_PORT};
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use stun_codec::rfc5389::attributes::{MessageIntegrity, Nonce, Realm, Username};
+use stun_codec::rfc5766::attributes::{
+    ChannelNumber, RequestedTransport, XorPeerAddress, XorRelayAddress,
+};
+use stun_codec::rfc5766::methods::{ALLOCATE, CHANNEL_BIND};
+use stun_codec::{Message, MessageClass, MessageDecoder, MessageEncoder, TransactionId};
+
+const USERNAME: &str = "bench";
+const PASSWORD: &str = "bench";
+const UDP_TRANSPORT: u8 = 17;
+const CHANNEL: u16 = 0x4000;
+
+const NUM_CLIENTS: usize = 16;
+const PAYLOAD_SIZE: usize = 1200;
+const DURATION: Duration = Duration::from_secs(5);
+
+/// Compares the throughput of a single-worker relay with a multi-worker one on loopback.
+///
+/// Each client allocates on the relay, binds a channel to its own peer and then sends channel-data messages as fast as it can.
+/// The throughput is measured at the peers.
+#[allow(clippy::print_stdout)]
+fn main() -> Result<()> {
+    let max_workers = std::thread::available_parallelism()?.get().min(8);
+
+    let mut num_workers = vec![1, max_workers];
+    num_workers.dedup();
+
+    for workers in num_workers {
+        let throughput = measure(workers)?;
+
+        println!(
+            "{workers} worker(s): {:.2} MB/s",
+            throughput as f64 / DURATION.as_secs_f64() / 1_000_000.0
+        );
+    }
+
+    Ok(())
+}
+
+/// Runs a relay with the given number of workers and returns the number of bytes received by all peers.
+fn measure(workers: usize) -> Result<u64> {
+    let _relay = Relay::spawn(workers)?;
+    let relay_addr = SocketAddr::from((Ipv4Addr::LOCALHOST, TURN_PORT));
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let received = Arc::new(AtomicU64::new(0));
+    let mut threads = Vec::new();
+
+    for _ in 0..NUM_CLIENTS {
+        let client = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))?;
+        let peer = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))?;
+        client.connect(relay_addr)?;
+        client.set_read_timeout(Some(Duration::from_millis(100)))?;
+        peer.set_read_timeout(Some(Duration::from_millis(100)))?;
+
+        setup_channel(&client, peer.local_addr()?)?;
+
+        threads.push(std::thread::spawn({
+            let stop = stop.clone();
+
+            move || send_channel_data(client, stop)
+        }));
+        threads.push(std::thread::spawn({
+            let stop = stop.clone();
+            let received = received.clone();
+
+            move || receive(peer, stop, received)
+        }));
+    }
+
+    std::thread::sleep(DURATION);
+    stop.store(true, Ordering::Relaxed);
+
+    for thread in threads {
+        thread
+            .join()
+            .map_err(|_| anyhow!("Benchmark thread panicked"))??;
+    }
+
+    Ok(received.load(Ordering::Relaxed))
+}
+
+fn send_channel_data(client: UdpSocket, stop: Arc<AtomicBool>) -> Result<()> {
+    let mut packet = vec![0u8; 4 + PAYLOAD_SIZE];
+    packet[..2].copy_from_slice(&CHANNEL.to_be_bytes());
+    packet[2..4].copy_from_slice(&(PAYLOAD_SIZE as u16).to_be_bytes());
+
+    while !stop.load(Ordering::Relaxed) {
+        client.send(&packet)?;
+    }
+
+    Ok(())
+}
+
+fn receive(peer: UdpSocket, stop: Arc<AtomicBool>, received: Arc<AtomicU64>) -> Result<()> {
+    let mut buffer = vec![0u8; 65536];
+
+    while !stop.load(Ordering::Relaxed) {
+        match peer.recv(&mut buffer) {
+            Ok(num_bytes) => {
+                received.fetch_add(num_bytes as u64, Ordering::Relaxed);
+            }
+            Err(e)
+                if e.kind() == std::io::ErrorKind::WouldBlock
+                    || e.kind() == std::io::ErrorKind::TimedOut => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    Ok(())
+}
+
+/// Allocates on the relay and binds [`CHANNEL`] to `peer`.
+fn setup_channel(client: &UdpSocket, peer: SocketAddr) -> Result<()> {
+    // The relay may still be starting up, retry the first request until it answers.
+    let challenge = (0..50)
+        .find_map(|_| {
+            let mut request = Message::new(MessageClass::Request, ALLOCATE, random_id());
+            request.add_attribute(RequestedTransport::new(UDP_TRANSPORT));
+
+            request_response(client, request).ok()
+        })
+        .context("Relay did not respond")?;
+    let nonce = challenge
+        .get_attribute::<Nonce>()
+        .cloned()
+        .context("Missing nonce in challenge")?;
+
+    let mut allocate = Message::new(MessageClass::Request, ALLOCATE, random_id());
+    allocate.add_attribute(RequestedTransport::new(UDP_TRANSPORT));
+    let response = authenticated_request_response(client, allocate, &nonce)?;
+    if response.get_attribute::<XorRelayAddress>().is_none() {
+        bail!("Failed to allocate: {response:?}");
+    }
+
+    let mut channel_bind = Message::new(MessageClass::Request, CHANNEL_BIND, random_id());
+    channel_bind.add_attribute(ChannelNumber::new(CHANNEL).expect("valid channel number"));
+    channel_bind.add_attribute(XorPeerAddress::new(peer));
+    let response = authenticated_request_response(client, channel_bind, &nonce)?;
+    if response.class() != MessageClass::SuccessResponse {
+        bail!("Failed to bind channel: {response:?}");
+    }
+
+    Ok(())
+}
+
+fn authenticated_request_response(
+    client: &UdpSocket,
+    mut request: Message<Attribute>,
+    nonce: &Nonce,
+) -> Result<Message<Attribute>> {
+    let username = Username::new(USERNAME.to_owned()).expect("valid username");
+    let realm = Realm::new("firezone".to_owned()).expect("valid realm");
+
+    request.add_attribute(username.clone());
+    request.add_attribute(nonce.clone());
+    let message_integrity =
+        MessageIntegrity::new_long_term_credential(&request, &username, &realm, PASSWORD)
+            .map_err(|e| anyhow!("Failed to compute message integrity: {e}"))?;
+    request.add_attribute(message_integrity);
+
+    request_response(client, request)
+}
+
+fn request_response(client: &UdpSocket, request: Message<Attribute>) -> Result<Message<Attribute>> {
+    let bytes = MessageEncoder::default()
+        .encode_into_bytes(request)
+        .map_err(|e| anyhow!("Failed to encode request: {e}"))?;
+    client.send(&bytes)?;
+
+    let mut buffer = [0u8; 1024];
+    let num_bytes = client.recv(&mut buffer)?;
+
+    MessageDecoder::<Attribute>::default()
+        .decode_from_bytes(&buffer[..num_bytes])
+        .map_err(|e| anyhow!("Failed to decode response: {e}"))?
+        .map_err(|e| anyhow!("Broken response: {e:?}"))
+}
+
+fn random_id() -> TransactionId {
+    TransactionId::new(rand::random())
+}
+
+/// A relay process, killed on drop.
+struct Relay(Child);
+
+impl Relay {
+    fn spawn(workers: usize) -> Result<Self> {
+        let credentials = std::env::temp_dir().join("firezone-relay-bench-credentials");
+        std::fs::write(&credentials, format!("{USERNAME}:{PASSWORD}\n"))?;
+
+        let child = Command::new(env!("CARGO_BIN_EXE_firezone-relay"))
+            .env_remove("FIREZONE_TOKEN")
+            .env("RUST_LOG", "warn")
+            .args(["--public-ip4-addr", "127.0.0.1"])
+            .args(["--health-check-addr", "127.0.0.1:0"])
+            .args(["--auth-scheme", "static"])
+            .arg("--static-credentials-file")
+            .arg(&credentials)
+            .args(["--workers", &workers.to_string()])
+            .stdout(Stdio::null())
+            .spawn()
+            .context("Failed to spawn relay")?;
+
+        Ok(Self(child))
+    }
+}
+
+impl Drop for Relay {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
@@ -0,0 +1,268 @@
+use crate::{AllocationInfo, AllocationPort, ChannelInfo};
+use axum::extract::{Path, State};
+use axum::http::{header, StatusCode};
+use axum::response::IntoResponse;
+use axum::routing::{delete, get, post};
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, oneshot};
+
+/// A request from the admin API to the event-loop of a worker, owning a [`Server`](crate::Server).
+#[derive(Debug)]
+pub enum Request {
+    /// Stop accepting new allocations and shut down once all existing ones are gone.
+    Drain,
+    /// List all active allocations, see [`Server::allocations`](crate::Server::allocations).
+    ListAllocations {
+        respond_to: oneshot::Sender<Vec<AllocationInfo>>,
+    },
+    /// Delete an allocation, see [`Server::force_delete_allocation`](crate::Server::force_delete_allocation).
+    DeleteAllocation {
+        port: AllocationPort,
+        respond_to: oneshot::Sender<bool>,
+    },
+    /// Start capturing the traffic of an allocation, see [`Server::start_capture`](crate::Server::start_capture).
+    StartCapture {
+        port: AllocationPort,
+        duration: Duration,
+        max_bytes: usize,
+        respond_to: oneshot::Sender<bool>,
+    },
+    /// Stop capturing the traffic of an allocation, see [`Server::stop_capture`](crate::Server::stop_capture).
+    StopCapture {
+        port: AllocationPort,
+        respond_to: oneshot::Sender<Option<Vec<u8>>>,
+    },
+}
+
+/// Runs an HTTP API for operating the relay on the given address, forwarding all requests to every worker via `workers`.
+///
+/// The API is unauthenticated and must therefore only be served on a local interface.
+///
+/// - `POST /drain`: Starts draining the relay. Responds with 202 ACCEPTED.
+/// - `GET /allocations`: Lists all active allocations as JSON.
+/// - `DELETE /allocations/:port`: Deletes the allocation on the given port. Responds with 204 NO CONTENT or 404 NOT FOUND.
+/// - `POST /allocations/:port/capture`: Starts capturing the traffic of the allocation on the given port, see [`CaptureOptions`]. Responds with 202 ACCEPTED or 404 NOT FOUND.
+/// - `DELETE /allocations/:port/capture`: Stops the capture of the allocation on the given port and responds with the pcapng file or 404 NOT FOUND.
+pub async fn serve(addr: SocketAddr, workers: Vec<mpsc::Sender<Request>>) -> std::io::Result<()> {
+    let service = Router::new()
+        .route("/drain", post(drain))
+        .route("/allocations", get(list_allocations))
+        .route("/allocations/:port", delete(delete_allocation))
+        .route(
+            "/allocations/:port/capture",
+            post(start_capture).delete(stop_capture),
+        )
+        .with_state(Arc::new(workers))
+        .into_make_service();
+
+    axum::serve(tokio::net::TcpListener::bind(addr).await?, service).await?;
+
+    Ok(())
+}
+
+type Workers = Arc<Vec<mpsc::Sender<Request>>>;
+
+async fn drain(State(workers): State<Workers>) -> StatusCode {
+    for requests in workers.iter() {
+        if requests.send(Request::Drain).await.is_err() {
+            return StatusCode::SERVICE_UNAVAILABLE;
+        }
+    }
+
+    StatusCode::ACCEPTED
+}
+
+async fn list_allocations(
+    State(workers): State<Workers>,
+) -> Result<Json<Vec<Allocation>>, StatusCode> {
+    let mut allocations = Vec::new();
+
+    for requests in workers.iter() {
+        let (respond_to, response) = oneshot::channel();
+
+        requests
+            .send(Request::ListAllocations { respond_to })
+            .await
+            .map_err(|_| StatusCode::SERVICE_UNAVAILABLE)?;
+        allocations.extend(
+            response
+                .await
+                .map_err(|_| StatusCode::SERVICE_UNAVAILABLE)?,
+        );
+    }
+
+    let now = Instant::now();
+
+    Ok(Json(
+        allocations
+            .into_iter()
+            .map(|a| Allocation::new(a, now))
+            .collect(),
+    ))
+}
+
+async fn delete_allocation(State(workers): State<Workers>, Path(port): Path<u16>) -> StatusCode {
+    // Each worker owns a disjoint range of ports so at most one of them can have the allocation.
+    for requests in workers.iter() {
+        let (respond_to, response) = oneshot::channel();
+
+        if requests
+            .send(Request::DeleteAllocation {
+                port: AllocationPort::new(port),
+                respond_to,
+            })
+            .await
+            .is_err()
+        {
+            return StatusCode::SERVICE_UNAVAILABLE;
+        }
+
+        match response.await {
+            Ok(true) => return StatusCode::NO_CONTENT,
+            Ok(false) => {}
+            Err(_) => return StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+
+    StatusCode::NOT_FOUND
+}
+
+/// The JSON body of `POST /allocations/:port/capture`.
+#[derive(Deserialize)]
+#[serde(default)]
+struct CaptureOptions {
+    /// How long to capture for.
+    duration_secs: u64,
+    /// The maximum size of the pcapng file.
+    max_bytes: usize,
+}
+
+impl Default for CaptureOptions {
+    fn default() -> Self {
+        Self {
+            duration_secs: 60,
+            max_bytes: 10 * 1024 * 1024,
+        }
+    }
+}
+
+async fn start_capture(
+    State(workers): State<Workers>,
+    Path(port): Path<u16>,
+    Json(options): Json<CaptureOptions>,
+) -> StatusCode {
+    // Each worker owns a disjoint range of ports so at most one of them can have the allocation.
+    for requests in workers.iter() {
+        let (respond_to, response) = oneshot::channel();
+
+        if requests
+            .send(Request::StartCapture {
+                port: AllocationPort::new(port),
+                duration: Duration::from_secs(options.duration_secs),
+                max_bytes: options.max_bytes,
+                respond_to,
+            })
+            .await
+            .is_err()
+        {
+            return StatusCode::SERVICE_UNAVAILABLE;
+        }
+
+        match response.await {
+            Ok(true) => return StatusCode::ACCEPTED,
+            Ok(false) => {}
+            Err(_) => return StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+
+    StatusCode::NOT_FOUND
+}
+
+async fn stop_capture(
+    State(workers): State<Workers>,
+    Path(port): Path<u16>,
+) -> Result<impl IntoResponse, StatusCode> {
+    for requests in workers.iter() {
+        let (respond_to, response) = oneshot::channel();
+
+        requests
+            .send(Request::StopCapture {
+                port: AllocationPort::new(port),
+                respond_to,
+            })
+            .await
+            .map_err(|_| StatusCode::SERVICE_UNAVAILABLE)?;
+
+        if let Some(pcapng) = response
+            .await
+            .map_err(|_| StatusCode::SERVICE_UNAVAILABLE)?
+        {
+            return Ok((
+                [
+                    (header::CONTENT_TYPE, "application/octet-stream".to_owned()),
+                    (
+                        header::CONTENT_DISPOSITION,
+                        format!("attachment; filename=\"allocation-{port}.pcapng\""),
+                    ),
+                ],
+                pcapng,
+            ));
+        }
+    }
+
+    Err(StatusCode::NOT_FOUND)
+}
+
+/// The JSON representation of an [`AllocationInfo`].
+#[derive(Serialize)]
+struct Allocation {
+    client: SocketAddr,
+    username: String,
+    port: u16,
+    relay_addresses: Vec<IpAddr>,
+    channels: Vec<Channel>,
+    bytes_relayed: u64,
+    expires_in_secs: u64,
+}
+
+impl Allocation {
+    fn new(info: AllocationInfo, now: Instant) -> Self {
+        Self {
+            client: info.client.into_socket(),
+            username: info.username,
+            port: info.port.value(),
+            relay_addresses: info.relay_addresses,
+            channels: info
+                .channels
+                .into_iter()
+                .map(|c| Channel::new(c, now))
+                .collect(),
+            bytes_relayed: info.bytes_relayed,
+            expires_in_secs: info.expires_at.saturating_duration_since(now).as_secs(),
+        }
+    }
+}
+
+/// The JSON representation of a [`ChannelInfo`].
+#[derive(Serialize)]
+struct Channel {
+    number: u16,
+    peer: SocketAddr,
+    bound: bool,
+    expires_in_secs: u64,
+}
+
+impl Channel {
+    fn new(info: ChannelInfo, now: Instant) -> Self {
+        Self {
+            number: info.number,
+            peer: info.peer.into_socket(),
+            bound: info.bound,
+            expires_in_secs: info.expires_at.saturating_duration_since(now).as_secs(),
+        }
+    }
+}
@@ -1,18 +1,60 @@
-use base64::prelude::BASE64_STANDARD_NO_PAD;
+mod redis;
+
+pub use redis::{NonceLookups, RedisNonces};
+
+use anyhow::{Context as _, Result};
+use base64::prelude::{BASE64_STANDARD, BASE64_STANDARD_NO_PAD};
 use base64::Engine;
+use hmac::{Hmac, Mac as _};
 use once_cell::sync::Lazy;
 use secrecy::{ExposeSecret, SecretString};
 use sha2::digest::FixedOutput;
 use sha2::Sha256;
 use std::collections::hash_map::Entry;
 use std::collections::HashMap;
-use std::time::{Duration, SystemTime};
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime};
 use stun_codec::rfc5389::attributes::{MessageIntegrity, Realm, Username};
 use uuid::Uuid;
 
 // TODO: Upstream a const constructor to `stun-codec`.
 pub static FIREZONE: Lazy<Realm> = Lazy::new(|| Realm::new("firezone".to_owned()).unwrap());
 
+/// How the relay authenticates its clients.
+#[derive(Debug, Default)]
+pub enum AuthScheme {
+    /// Usernames are `<expiry>:<salt>` and passwords are derived from the relay's secret, see [`generate_password`].
+    ///
+    /// This is the only scheme supported when connected to the portal.
+    #[default]
+    Firezone,
+    /// The TURN REST API as implemented by coturn's `use-auth-secret` option.
+    ///
+    /// Usernames are `<expiry>:<user>` and passwords are derived from a shared secret, see [`generate_turn_rest_api_password`].
+    /// See <https://datatracker.ietf.org/doc/html/draft-uberti-behave-turn-rest-00>.
+    TurnRestApi { shared_secret: SecretString },
+    /// A fixed set of usernames and their passwords.
+    Static {
+        passwords: HashMap<String, SecretString>,
+    },
+}
+
+impl AuthScheme {
+    /// Reads a static set of credentials from a file.
+    ///
+    /// Each line is of the form `<username>:<password>`.
+    /// Empty lines and lines starting with `#` are ignored.
+    pub fn static_from_file(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+
+        let passwords = parse_static_credentials(&content)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+
+        Ok(Self::Static { passwords })
+    }
+}
+
 pub(crate) trait MessageIntegrityExt {
     fn verify(
         &self,
@@ -20,6 +62,19 @@ pub(crate) trait MessageIntegrityExt {
         username: &str,
         now: SystemTime,
     ) -> Result<(), Error>;
+
+    fn verify_turn_rest_api(
+        &self,
+        shared_secret: &SecretString,
+        username: &str,
+        now: SystemTime,
+    ) -> Result<(), Error>;
+
+    fn verify_static(
+        &self,
+        passwords: &HashMap<String, SecretString>,
+        username: &str,
+    ) -> Result<(), Error>;
 }
 
 impl MessageIntegrityExt for MessageIntegrity {
@@ -48,44 +103,162 @@ impl MessageIntegrityExt for MessageIntegrity {
 
         Ok(())
     }
+
+    fn verify_turn_rest_api(
+        &self,
+        shared_secret: &SecretString,
+        username: &str,
+        now: SystemTime,
+    ) -> Result<(), Error> {
+        // The user part is optional, i.e. the username may consist of only the timestamp.
+        let expiry_unix_timestamp = username
+            .split_once(':')
+            .map_or(username, |(expiry, _)| expiry)
+            .parse::<u64>()
+            .map_err(|_| Error::InvalidUsername)?;
+
+        if systemtime_from_unix(expiry_unix_timestamp) < now {
+            return Err(Error::Expired);
+        }
+
+        let password = generate_turn_rest_api_password(shared_secret, username);
+
+        self.check_long_term_credential(
+            &Username::new(username.to_owned()).map_err(|_| Error::InvalidUsername)?,
+            &FIREZONE,
+            &password,
+        )
+        .map_err(|_| Error::InvalidPassword)?;
+
+        Ok(())
+    }
+
+    fn verify_static(
+        &self,
+        passwords: &HashMap<String, SecretString>,
+        username: &str,
+    ) -> Result<(), Error> {
+        let password = passwords.get(username).ok_or(Error::InvalidUsername)?;
+
+        self.check_long_term_credential(
+            &Username::new(username.to_owned()).map_err(|_| Error::InvalidUsername)?,
+            &FIREZONE,
+            password.expose_secret(),
+        )
+        .map_err(|_| Error::InvalidPassword)?;
+
+        Ok(())
+    }
 }
 
 /// Tracks valid nonces for the TURN relay.
 ///
 /// The semantic nature of nonces is an implementation detail of the relay in TURN.
-/// This could just as easily also be a time-based logic (i.e. nonces are valid for 10min).
 ///
-/// For simplicity reasons, we use a count-based strategy.
+/// We use a count-based strategy combined with a lifetime, see [`NoncePolicy`].
 /// Each nonce can be used for a certain number of requests before it is invalid.
+///
+/// Relays that share a [`NonceStore`] accept each other's nonces.
+/// This allows running several relays behind the same address or restarting a relay without clients running into `438 Stale Nonce` errors.
+pub trait NonceStore: Send {
+    /// Registers a new nonce, valid according to the given [`NoncePolicy`].
+    fn add_new(&mut self, nonce: Uuid, policy: NoncePolicy, now: Instant);
+
+    /// Record the usage of a nonce in a request.
+    fn handle_nonce_used(&mut self, nonce: Uuid, now: Instant) -> Result<(), InvalidNonce>;
+
+    /// Whether the nonce has to be looked up before it can be used.
+    ///
+    /// Stores that are shared with other relays may not know about a nonce yet.
+    /// If this returns `true`, the store starts looking up the nonce and the result is passed to [`NonceStore::handle_lookup`] once it is available.
+    fn needs_lookup(&mut self, _nonce: Uuid) -> bool {
+        false
+    }
+
+    /// Handles the result of a lookup started by [`NonceStore::needs_lookup`].
+    fn handle_lookup(&mut self, _lookup: NonceLookup, _now: Instant) {}
+}
+
+/// How often a [`NonceStore`] removes expired nonces that were never used again.
+const EXPIRED_NONCES_CLEANUP_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The result of looking up a nonce in a shared [`NonceStore`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct NonceLookup {
+    pub nonce: Uuid,
+    /// How often and for how much longer the nonce can be used.
+    ///
+    /// `None` if the nonce is unknown, used up or the lookup failed.
+    pub remaining: Option<NoncePolicy>,
+}
+
+/// How long a nonce stays valid.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NoncePolicy {
+    /// How many requests a client can perform with the same nonce.
+    pub num_requests: u64,
+    /// How long a nonce is valid, regardless of how often it has been used.
+    pub lifetime: Duration,
+}
+
+/// The nonce is unknown, expired or has been used too many times.
+#[derive(Debug, PartialEq)]
+pub struct InvalidNonce;
+
+/// A [`NonceStore`] that keeps all nonces in memory.
+///
+/// This is the default and cannot be shared between relays.
 #[derive(Default)]
-pub(crate) struct Nonces {
-    inner: HashMap<Uuid, u64>,
+pub struct Nonces {
+    inner: HashMap<Uuid, RemainingUses>,
+    /// When we next remove expired nonces, see [`EXPIRED_NONCES_CLEANUP_INTERVAL`].
+    next_cleanup_at: Option<Instant>,
 }
 
 impl Nonces {
-    /// How many requests a client can perform with the same nonce.
-    const NUM_REQUESTS: u64 = 100;
+    /// Removes nonces that expired without being used again, at most every [`EXPIRED_NONCES_CLEANUP_INTERVAL`].
+    fn remove_expired(&mut self, now: Instant) {
+        if self.next_cleanup_at.is_some_and(|at| now < at) {
+            return;
+        }
+
+        self.inner.retain(|_, remaining| remaining.expires_at > now);
+        self.next_cleanup_at = Some(now + EXPIRED_NONCES_CLEANUP_INTERVAL);
+    }
+}
 
-    pub(crate) fn add_new(&mut self, nonce: Uuid) {
-        self.inner.insert(nonce, Self::NUM_REQUESTS);
+struct RemainingUses {
+    num_requests: u64,
+    expires_at: Instant,
+}
+
+impl NonceStore for Nonces {
+    fn add_new(&mut self, nonce: Uuid, policy: NoncePolicy, now: Instant) {
+        self.remove_expired(now);
+        self.inner.insert(
+            nonce,
+            RemainingUses {
+                num_requests: policy.num_requests,
+                expires_at: now + policy.lifetime,
+            },
+        );
     }
 
-    /// Record the usage of a nonce in a request.
-    pub(crate) fn handle_nonce_used(&mut self, nonce: Uuid) -> Result<(), Error> {
+    fn handle_nonce_used(&mut self, nonce: Uuid, now: Instant) -> Result<(), InvalidNonce> {
         let mut entry = match self.inner.entry(nonce) {
-            Entry::Vacant(_) => return Err(Error::InvalidNonce),
+            Entry::Vacant(_) => return Err(InvalidNonce),
             Entry::Occupied(entry) => entry,
         };
 
-        let remaining_requests = entry.get_mut();
+        let remaining = entry.get_mut();
 
-        if *remaining_requests == 0 {
+        if remaining.num_requests == 0 || remaining.expires_at <= now {
             entry.remove();
 
-            return Err(Error::InvalidNonce);
+            return Err(InvalidNonce);
         }
 
-        *remaining_requests -= 1;
+        remaining.num_requests -= 1;
 
         Ok(())
     }
@@ -96,7 +269,6 @@ pub(crate) enum Error {
     Expired,
     InvalidPassword,
     InvalidUsername,
-    InvalidNonce,
 }
 
 pub(crate) fn split_username(username: &str) -> Result<(u64, &str), Error> {
@@ -136,6 +308,31 @@ pub fn generate_password(
     BASE64_STANDARD_NO_PAD.encode(array.as_slice())
 }
 
+/// Generates the password for a username of the TURN REST API: `base64(hmac-sha1(shared_secret, username))`.
+pub fn generate_turn_rest_api_password(shared_secret: &SecretString, username: &str) -> String {
+    let mut mac = Hmac::<sha1::Sha1>::new_from_slice(shared_secret.expose_secret().as_bytes())
+        .expect("HMAC accepts keys of any size");
+    mac.update(username.as_bytes());
+
+    BASE64_STANDARD.encode(mac.finalize().into_bytes())
+}
+
+fn parse_static_credentials(content: &str) -> Result<HashMap<String, SecretString>> {
+    content
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(line_number, line)| {
+            let (username, password) = line.split_once(':').with_context(|| {
+                format!("Line {line_number} is not of the form `<username>:<password>`")
+            })?;
+
+            Ok((username.to_owned(), SecretString::from(password.to_owned())))
+        })
+        .collect()
+}
+
 pub(crate) fn systemtime_from_unix(seconds: u64) -> SystemTime {
     SystemTime::UNIX_EPOCH + Duration::from_secs(seconds)
 }
@@ -240,31 +437,193 @@ mod tests {
     }
 
     #[test]
-    fn nonces_are_valid_for_100_requests() {
+    fn nonces_are_valid_for_configured_number_of_requests() {
         let mut nonces = Nonces::default();
         let nonce = Uuid::new_v4();
-
-        nonces.add_new(nonce);
+        let now = Instant::now();
+
+        nonces.add_new(
+            nonce,
+            NoncePolicy {
+                num_requests: 100,
+                lifetime: Duration::from_secs(3600),
+            },
+            now,
+        );
 
         for _ in 0..100 {
-            nonces.handle_nonce_used(nonce).unwrap();
+            nonces.handle_nonce_used(nonce, now).unwrap();
         }
 
         assert_eq!(
-            nonces.handle_nonce_used(nonce).unwrap_err(),
-            Error::InvalidNonce
+            nonces.handle_nonce_used(nonce, now).unwrap_err(),
+            InvalidNonce
         );
     }
 
+    #[test]
+    fn expired_nonces_are_invalid() {
+        let mut nonces = Nonces::default();
+        let nonce = Uuid::new_v4();
+        let now = Instant::now();
+
+        nonces.add_new(
+            nonce,
+            NoncePolicy {
+                num_requests: 100,
+                lifetime: Duration::from_secs(60),
+            },
+            now,
+        );
+
+        nonces
+            .handle_nonce_used(nonce, now + Duration::from_secs(59))
+            .unwrap();
+        assert_eq!(
+            nonces
+                .handle_nonce_used(nonce, now + Duration::from_secs(60))
+                .unwrap_err(),
+            InvalidNonce
+        );
+    }
+
+    #[test]
+    fn expired_nonces_are_removed_when_adding_new_ones() {
+        let mut nonces = Nonces::default();
+        let now = Instant::now();
+        let policy = NoncePolicy {
+            num_requests: 100,
+            lifetime: Duration::from_secs(60),
+        };
+
+        nonces.add_new(Uuid::new_v4(), policy, now);
+        nonces.add_new(Uuid::new_v4(), policy, now + Duration::from_secs(30));
+        nonces.add_new(Uuid::new_v4(), policy, now + Duration::from_secs(61));
+
+        assert_eq!(nonces.inner.len(), 2);
+    }
+
     #[test]
     fn unknown_nonces_are_invalid() {
         let mut nonces = Nonces::default();
         let nonce = Uuid::new_v4();
 
         assert_eq!(
-            nonces.handle_nonce_used(nonce).unwrap_err(),
-            Error::InvalidNonce
+            nonces.handle_nonce_used(nonce, Instant::now()).unwrap_err(),
+            InvalidNonce
+        );
+    }
+
+    #[test]
+    fn generate_turn_rest_api_password_test_vector() {
+        let password =
+            generate_turn_rest_api_password(&"north".parse().unwrap(), "1700000000:alice");
+
+        assert_eq!(password, "Cd/49soE35ICqcJF/bCTn8Z4OyE=")
+    }
+
+    #[test]
+    fn turn_rest_api_smoke() {
+        let shared_secret = "north".parse().unwrap();
+        let username = "1700000000:alice";
+        let message_integrity = long_term_credential(
+            username,
+            &generate_turn_rest_api_password(&shared_secret, username),
         );
+
+        let result = message_integrity.verify_turn_rest_api(
+            &shared_secret,
+            username,
+            systemtime_from_unix(1700000000 - 1000),
+        );
+
+        result.expect("credentials to be valid");
+    }
+
+    #[test]
+    fn turn_rest_api_expired_is_not_valid() {
+        let shared_secret = "north".parse().unwrap();
+        let username = "1700000000:alice";
+        let message_integrity = long_term_credential(
+            username,
+            &generate_turn_rest_api_password(&shared_secret, username),
+        );
+
+        let result = message_integrity.verify_turn_rest_api(
+            &shared_secret,
+            username,
+            systemtime_from_unix(1700000000 + 1),
+        );
+
+        assert_eq!(result.unwrap_err(), Error::Expired)
+    }
+
+    #[test]
+    fn turn_rest_api_different_secret_makes_password_invalid() {
+        let username = "1700000000:alice";
+        let message_integrity = long_term_credential(
+            username,
+            &generate_turn_rest_api_password(&"south".parse().unwrap(), username),
+        );
+
+        let result = message_integrity.verify_turn_rest_api(
+            &"north".parse().unwrap(),
+            username,
+            systemtime_from_unix(1700000000 - 1000),
+        );
+
+        assert_eq!(result.unwrap_err(), Error::InvalidPassword)
+    }
+
+    #[test]
+    fn static_credentials_smoke() {
+        let passwords = parse_static_credentials("alice:secret1\nbob:secret2").unwrap();
+        let message_integrity = long_term_credential("bob", "secret2");
+
+        message_integrity
+            .verify_static(&passwords, "bob")
+            .expect("credentials to be valid");
+        assert_eq!(
+            message_integrity
+                .verify_static(&passwords, "alice")
+                .unwrap_err(),
+            Error::InvalidPassword
+        );
+        assert_eq!(
+            message_integrity
+                .verify_static(&passwords, "carol")
+                .unwrap_err(),
+            Error::InvalidUsername
+        );
+    }
+
+    #[test]
+    fn static_credentials_skip_comments_and_empty_lines() {
+        let passwords =
+            parse_static_credentials("# Our users\n\nalice:pass:with:colons\n").unwrap();
+
+        assert_eq!(passwords.len(), 1);
+        assert_eq!(passwords["alice"].expose_secret(), "pass:with:colons");
+    }
+
+    #[test]
+    fn static_credentials_without_password_are_invalid() {
+        let error = parse_static_credentials("alice:secret1\nbob").unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "Line 2 is not of the form `<username>:<password>`"
+        );
+    }
+
+    fn long_term_credential(username: &str, password: &str) -> MessageIntegrity {
+        MessageIntegrity::new_long_term_credential(
+            &sample_message(),
+            &Username::new(username.to_owned()).unwrap(),
+            &FIREZONE,
+            password,
+        )
+        .unwrap()
     }
 
     fn message_integrity(
@@ -0,0 +1,625 @@
+use super::{InvalidNonce, NonceLookup, NoncePolicy, NonceStore, EXPIRED_NONCES_CLEANUP_INTERVAL};
+use backoff::backoff::Backoff;
+use backoff::{ExponentialBackoff, ExponentialBackoffBuilder};
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+/// How long we wait for the Redis server before we give up on a request.
+const TIMEOUT: Duration = Duration::from_millis(100);
+
+/// How many requests may wait for the Redis server before we start dropping them.
+const MAX_QUEUED_REQUESTS: usize = 1000;
+
+/// How long we remember that a nonce is invalid after looking it up.
+const INVALID_NONCE_TTL: Duration = Duration::from_secs(60);
+
+/// The maximum time between two attempts to connect to the Redis server.
+const MAX_RECONNECT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How often we at most warn about failing requests, e.g. whilst the Redis server is unreachable.
+const WARNING_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Atomically decrements the remaining requests of a nonce, deleting it once it is used up.
+///
+/// Returns the remaining requests or `-1` if the nonce is unknown or used up.
+const USE_NONCE_SCRIPT: &str = r#"
+if redis.call('EXISTS', KEYS[1]) == 0 then return -1 end
+local remaining = redis.call('DECR', KEYS[1])
+if remaining < 0 then redis.call('DEL', KEYS[1]) end
+return remaining
+"#;
+
+/// Returns the remaining requests of a nonce or `-1` if the nonce is unknown.
+const GET_NONCE_SCRIPT: &str = r#"
+local remaining = redis.call('GET', KEYS[1])
+if not remaining then return -1 end
+return tonumber(remaining)
+"#;
+
+/// The results of nonce lookups of a [`RedisNonces`], to be passed to [`Server::handle_nonce_lookup`](crate::Server::handle_nonce_lookup).
+pub type NonceLookups = mpsc::Receiver<NonceLookup>;
+
+/// A [`NonceStore`] backed by a server speaking the Redis protocol (RESP).
+///
+/// All relays connected to the same server accept each other's nonces.
+///
+/// We never talk to the Redis server from within the event-loop.
+/// Instead, a background task performs all requests and nonces are validated against a local view of the server's state:
+///
+/// - New nonces and their usage are written to the server in the background.
+/// - Nonces we don't know about (e.g. because another relay created them) are looked up, see [`NonceStore::needs_lookup`].
+///
+/// Relays using the same nonce at the same time may therefore accept slightly more requests than the policy allows.
+/// If the server is unavailable, nonces we don't know about are considered invalid and we reconnect with an exponential backoff.
+pub struct RedisNonces {
+    requests: mpsc::Sender<Request>,
+
+    known: HashMap<Uuid, RemainingUses>,
+    lookups_in_flight: HashSet<Uuid>,
+    /// When we next remove expired nonces from `known`, see [`EXPIRED_NONCES_CLEANUP_INTERVAL`].
+    next_cleanup_at: Option<Instant>,
+
+    dropped_requests: RateLimitedWarning,
+}
+
+struct RemainingUses {
+    num_requests: u64,
+    expires_at: Instant,
+}
+
+#[derive(Debug)]
+enum Request {
+    Store { nonce: Uuid, policy: NoncePolicy },
+    Use { nonce: Uuid },
+    Lookup { nonce: Uuid },
+}
+
+impl RedisNonces {
+    /// Spawns the background task that talks to the Redis server at `addr`.
+    ///
+    /// Must be called from within a tokio runtime.
+    pub fn spawn(addr: SocketAddr) -> (Self, NonceLookups) {
+        let (requests_tx, requests_rx) = mpsc::channel(MAX_QUEUED_REQUESTS);
+        let (lookups_tx, lookups_rx) = mpsc::channel(MAX_QUEUED_REQUESTS);
+
+        tokio::spawn(handle_requests(
+            Connection::new(addr),
+            requests_rx,
+            lookups_tx,
+        ));
+
+        (
+            Self {
+                requests: requests_tx,
+                known: HashMap::default(),
+                lookups_in_flight: HashSet::default(),
+                next_cleanup_at: None,
+                dropped_requests: RateLimitedWarning::default(),
+            },
+            lookups_rx,
+        )
+    }
+
+    fn send(&mut self, request: Request) -> bool {
+        let error = match self.requests.try_send(request) {
+            Ok(()) => return true,
+            Err(e) => e,
+        };
+
+        if let Some(suppressed) = self.dropped_requests.should_warn(Instant::now()) {
+            match error {
+                mpsc::error::TrySendError::Full(request) => {
+                    tracing::warn!(target: "relay", ?request, %suppressed, "Too many pending requests to Redis, dropping request");
+                }
+                mpsc::error::TrySendError::Closed(request) => {
+                    tracing::warn!(target: "relay", ?request, %suppressed, "Redis task is gone, dropping request");
+                }
+            }
+        }
+
+        false
+    }
+
+    /// Removes nonces that expired without being used again, at most every [`EXPIRED_NONCES_CLEANUP_INTERVAL`].
+    fn remove_expired(&mut self, now: Instant) {
+        if self.next_cleanup_at.is_some_and(|at| now < at) {
+            return;
+        }
+
+        self.known.retain(|_, remaining| remaining.expires_at > now);
+        self.next_cleanup_at = Some(now + EXPIRED_NONCES_CLEANUP_INTERVAL);
+    }
+}
+
+/// Aggregates a warning that may be logged for every request into at most one per [`WARNING_INTERVAL`].
+#[derive(Default)]
+struct RateLimitedWarning {
+    suppressed: u64,
+    next_warning_at: Option<Instant>,
+}
+
+impl RateLimitedWarning {
+    /// Returns how many warnings were suppressed since the last one if we should warn now.
+    fn should_warn(&mut self, now: Instant) -> Option<u64> {
+        if self.next_warning_at.is_some_and(|at| now < at) {
+            self.suppressed += 1;
+
+            return None;
+        }
+
+        self.next_warning_at = Some(now + WARNING_INTERVAL);
+
+        Some(std::mem::take(&mut self.suppressed))
+    }
+}
+
+impl NonceStore for RedisNonces {
+    fn add_new(&mut self, nonce: Uuid, policy: NoncePolicy, now: Instant) {
+        self.remove_expired(now);
+        self.known.insert(
+            nonce,
+            RemainingUses {
+                num_requests: policy.num_requests,
+                expires_at: now + policy.lifetime,
+            },
+        );
+        self.send(Request::Store { nonce, policy });
+    }
+
+    fn handle_nonce_used(&mut self, nonce: Uuid, now: Instant) -> Result<(), InvalidNonce> {
+        self.remove_expired(now);
+
+        let remaining = self.known.get_mut(&nonce).ok_or(InvalidNonce)?;
+
+        if remaining.expires_at <= now {
+            self.known.remove(&nonce);
+
+            return Err(InvalidNonce);
+        }
+
+        // Keep invalid nonces around until they expire so we don't look them up again.
+        if remaining.num_requests == 0 {
+            return Err(InvalidNonce);
+        }
+
+        remaining.num_requests -= 1;
+        self.send(Request::Use { nonce });
+
+        Ok(())
+    }
+
+    fn needs_lookup(&mut self, nonce: Uuid) -> bool {
+        if self.known.contains_key(&nonce) {
+            return false;
+        }
+        if self.lookups_in_flight.contains(&nonce) {
+            return true;
+        }
+        if !self.send(Request::Lookup { nonce }) {
+            return false;
+        }
+
+        self.lookups_in_flight.insert(nonce);
+
+        true
+    }
+
+    fn handle_lookup(&mut self, lookup: NonceLookup, now: Instant) {
+        self.lookups_in_flight.remove(&lookup.nonce);
+        self.remove_expired(now);
+
+        let remaining = match lookup.remaining {
+            Some(policy) => RemainingUses {
+                num_requests: policy.num_requests,
+                expires_at: now + policy.lifetime,
+            },
+            None => RemainingUses {
+                num_requests: 0,
+                expires_at: now + INVALID_NONCE_TTL,
+            },
+        };
+
+        self.known.insert(lookup.nonce, remaining);
+    }
+}
+
+/// Performs the requests of a [`RedisNonces`] one after the other until it is dropped.
+async fn handle_requests(
+    mut connection: Connection,
+    mut requests: mpsc::Receiver<Request>,
+    lookups: mpsc::Sender<NonceLookup>,
+) {
+    while let Some(request) = requests.recv().await {
+        match request {
+            Request::Store { nonce, policy } => store(&mut connection, nonce, policy).await,
+            Request::Use { nonce } => use_nonce(&mut connection, nonce).await,
+            Request::Lookup { nonce } => {
+                let remaining = lookup(&mut connection, nonce).await;
+
+                if lookups
+                    .send(NonceLookup { nonce, remaining })
+                    .await
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+async fn store(connection: &mut Connection, nonce: Uuid, policy: NoncePolicy) {
+    let key = key(nonce);
+    let value = policy.num_requests.to_string();
+    let lifetime = policy.lifetime.as_secs().max(1).to_string(); // Redis rejects an expiry of 0.
+
+    match connection
+        .query(&[
+            b"SET",
+            key.as_bytes(),
+            value.as_bytes(),
+            b"EX",
+            lifetime.as_bytes(),
+        ])
+        .await
+    {
+        Ok(Reply::Simple(status)) if status == "OK" => {}
+        Ok(reply) => {
+            tracing::warn!(target: "relay", %nonce, "Unexpected reply when storing nonce: {reply:?}");
+        }
+        Err(e) => {
+            connection.warn_failed_request(nonce, "store nonce", &e);
+        }
+    }
+}
+
+async fn use_nonce(connection: &mut Connection, nonce: Uuid) {
+    let key = key(nonce);
+
+    match connection
+        .query(&[b"EVAL", USE_NONCE_SCRIPT.as_bytes(), b"1", key.as_bytes()])
+        .await
+    {
+        Ok(Reply::Integer(_)) => {}
+        Ok(reply) => {
+            tracing::warn!(target: "relay", %nonce, "Unexpected reply when using nonce: {reply:?}");
+        }
+        Err(e) => {
+            connection.warn_failed_request(nonce, "use nonce", &e);
+        }
+    }
+}
+
+async fn lookup(connection: &mut Connection, nonce: Uuid) -> Option<NoncePolicy> {
+    let key = key(nonce);
+
+    let num_requests = match connection
+        .query(&[b"EVAL", GET_NONCE_SCRIPT.as_bytes(), b"1", key.as_bytes()])
+        .await
+    {
+        Ok(Reply::Integer(remaining)) => u64::try_from(remaining).ok()?,
+        Ok(reply) => {
+            tracing::warn!(target: "relay", %nonce, "Unexpected reply when looking up nonce: {reply:?}");
+
+            return None;
+        }
+        Err(e) => {
+            connection.warn_failed_request(nonce, "look up nonce", &e);
+
+            return None;
+        }
+    };
+
+    let lifetime = match connection.query(&[b"PTTL", key.as_bytes()]).await {
+        Ok(Reply::Integer(millis)) => Duration::from_millis(u64::try_from(millis).ok()?), // Negative if the key is gone or has no expiry.
+        Ok(reply) => {
+            tracing::warn!(target: "relay", %nonce, "Unexpected reply when looking up expiry of nonce: {reply:?}");
+
+            return None;
+        }
+        Err(e) => {
+            connection.warn_failed_request(nonce, "look up expiry of nonce", &e);
+
+            return None;
+        }
+    };
+
+    Some(NoncePolicy {
+        num_requests,
+        lifetime,
+    })
+}
+
+/// A lazily established connection to the Redis server.
+struct Connection {
+    addr: SocketAddr,
+    stream: Option<BufReader<TcpStream>>,
+
+    backoff: ExponentialBackoff,
+    /// When we may try to connect again after a failed attempt.
+    reconnect_at: Option<Instant>,
+
+    failed_requests: RateLimitedWarning,
+}
+
+impl Connection {
+    fn new(addr: SocketAddr) -> Self {
+        Self {
+            addr,
+            stream: None,
+            backoff: ExponentialBackoffBuilder::default()
+                .with_initial_interval(TIMEOUT)
+                .with_max_interval(MAX_RECONNECT_INTERVAL)
+                .with_max_elapsed_time(None)
+                .build(),
+            reconnect_at: None,
+            failed_requests: RateLimitedWarning::default(),
+        }
+    }
+
+    /// Warns about a failed request, every request fails whilst the server is unreachable.
+    fn warn_failed_request(&mut self, nonce: Uuid, action: &str, e: &io::Error) {
+        if let Some(suppressed) = self.failed_requests.should_warn(Instant::now()) {
+            tracing::warn!(target: "relay", %nonce, %suppressed, "Failed to {action}: {e}");
+        }
+    }
+
+    async fn query(&mut self, args: &[&[u8]]) -> io::Result<Reply> {
+        let stream = match self.stream.take() {
+            Some(stream) => stream,
+            None => self.connect().await?,
+        };
+        let stream = self.stream.insert(stream);
+
+        let result = tokio::time::timeout(TIMEOUT, async {
+            stream.get_mut().write_all(&encode_command(args)).await?;
+
+            read_reply(stream).await
+        })
+        .await
+        .unwrap_or_else(|_| Err(io::ErrorKind::TimedOut.into()));
+
+        if result.is_err() {
+            self.stream = None; // The connection may be in an inconsistent state, reconnect on the next request.
+        }
+
+        result
+    }
+
+    async fn connect(&mut self) -> io::Result<BufReader<TcpStream>> {
+        if self.reconnect_at.is_some_and(|at| Instant::now() < at) {
+            return Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "waiting to reconnect",
+            ));
+        }
+
+        let result = tokio::time::timeout(TIMEOUT, TcpStream::connect(self.addr))
+            .await
+            .unwrap_or_else(|_| Err(io::ErrorKind::TimedOut.into()))
+            .and_then(|stream| {
+                stream.set_nodelay(true)?;
+
+                Ok(stream)
+            });
+
+        match result {
+            Ok(stream) => {
+                self.backoff.reset();
+                self.reconnect_at = None;
+
+                Ok(BufReader::new(stream))
+            }
+            Err(e) => {
+                let delay = self
+                    .backoff
+                    .next_backoff()
+                    .unwrap_or(MAX_RECONNECT_INTERVAL);
+                self.reconnect_at = Some(Instant::now() + delay);
+
+                tracing::debug!(target: "relay", addr = %self.addr, ?delay, "Failed to connect to Redis: {e}");
+
+                Err(e)
+            }
+        }
+    }
+}
+
+fn key(nonce: Uuid) -> String {
+    format!("firezone:relay:nonce:{nonce}")
+}
+
+/// A reply from the Redis server.
+///
+/// We only need to support the subset of types that our commands return.
+/// Error replies are turned into [`io::Error`]s.
+#[derive(Debug, PartialEq)]
+enum Reply {
+    Simple(String),
+    Integer(i64),
+}
+
+/// Encodes a command as an array of bulk strings.
+///
+/// See <https://redis.io/docs/reference/protocol-spec/#sending-commands-to-a-redis-server>.
+fn encode_command(args: &[&[u8]]) -> Vec<u8> {
+    let mut buffer = format!("*{}\r\n", args.len()).into_bytes();
+
+    for arg in args {
+        buffer.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+        buffer.extend_from_slice(arg);
+        buffer.extend_from_slice(b"\r\n");
+    }
+
+    buffer
+}
+
+async fn read_reply(reader: &mut (impl AsyncBufRead + Unpin)) -> io::Result<Reply> {
+    let line = read_line(reader).await?;
+    let (kind, rest) = line.split_at(1.min(line.len()));
+
+    match kind {
+        "+" => Ok(Reply::Simple(rest.to_owned())),
+        "-" => Err(io::Error::new(io::ErrorKind::Other, rest.to_owned())),
+        ":" => rest.parse().map(Reply::Integer).map_err(invalid_data),
+        other => Err(invalid_data(format!("unsupported reply type '{other}'"))),
+    }
+}
+
+async fn read_line(reader: &mut (impl AsyncBufRead + Unpin)) -> io::Result<String> {
+    let mut line = String::new();
+
+    if reader.read_line(&mut line).await? == 0 {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+
+    let Some(line) = line.strip_suffix("\r\n") else {
+        return Err(invalid_data("missing CRLF"));
+    };
+
+    Ok(line.to_owned())
+}
+
+fn invalid_data(e: impl ToString) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_command_as_array_of_bulk_strings() {
+        let command = encode_command(&[b"SET", b"key", b"100"]);
+
+        assert_eq!(command, b"*3\r\n$3\r\nSET\r\n$3\r\nkey\r\n$3\r\n100\r\n");
+    }
+
+    #[tokio::test]
+    async fn reads_simple_string() {
+        let reply = read_reply(&mut &b"+OK\r\n"[..]).await.unwrap();
+
+        assert_eq!(reply, Reply::Simple("OK".to_owned()));
+    }
+
+    #[tokio::test]
+    async fn reads_negative_integer() {
+        let reply = read_reply(&mut &b":-1\r\n"[..]).await.unwrap();
+
+        assert_eq!(reply, Reply::Integer(-1));
+    }
+
+    #[tokio::test]
+    async fn reads_consecutive_replies() {
+        let mut input = &b"+OK\r\n:99\r\n"[..];
+
+        assert_eq!(
+            read_reply(&mut input).await.unwrap(),
+            Reply::Simple("OK".to_owned())
+        );
+        assert_eq!(read_reply(&mut input).await.unwrap(), Reply::Integer(99));
+    }
+
+    #[tokio::test]
+    async fn error_reply_is_an_error() {
+        let error = read_reply(&mut &b"-ERR unknown command\r\n"[..])
+            .await
+            .unwrap_err();
+
+        assert_eq!(error.to_string(), "ERR unknown command");
+    }
+
+    #[tokio::test]
+    async fn unavailable_server_invalidates_nonce() {
+        let (mut nonces, mut lookups) = RedisNonces::spawn(unused_addr());
+        let nonce = Uuid::new_v4();
+
+        assert!(nonces.needs_lookup(nonce));
+        assert!(nonces.needs_lookup(nonce)); // Lookup is still in flight.
+
+        let lookup = lookups.recv().await.unwrap();
+        assert_eq!(
+            lookup,
+            NonceLookup {
+                nonce,
+                remaining: None
+            }
+        );
+
+        let now = Instant::now();
+        nonces.handle_lookup(lookup, now);
+
+        assert!(!nonces.needs_lookup(nonce));
+        assert_eq!(nonces.handle_nonce_used(nonce, now), Err(InvalidNonce));
+    }
+
+    #[tokio::test]
+    async fn own_nonces_do_not_need_lookup() {
+        let (mut nonces, _lookups) = RedisNonces::spawn(unused_addr());
+        let nonce = Uuid::new_v4();
+        let now = Instant::now();
+
+        nonces.add_new(
+            nonce,
+            NoncePolicy {
+                num_requests: 1,
+                lifetime: Duration::from_secs(60),
+            },
+            now,
+        );
+
+        assert!(!nonces.needs_lookup(nonce));
+        assert_eq!(nonces.handle_nonce_used(nonce, now), Ok(()));
+        assert_eq!(nonces.handle_nonce_used(nonce, now), Err(InvalidNonce));
+    }
+
+    #[tokio::test]
+    async fn removes_expired_nonces_when_adding_new_ones() {
+        let (mut nonces, _lookups) = RedisNonces::spawn(unused_addr());
+        let now = Instant::now();
+        let policy = NoncePolicy {
+            num_requests: 1,
+            lifetime: Duration::from_secs(60),
+        };
+
+        nonces.add_new(Uuid::new_v4(), policy, now);
+        nonces.add_new(Uuid::new_v4(), policy, now + Duration::from_secs(30));
+        nonces.add_new(Uuid::new_v4(), policy, now + Duration::from_secs(61));
+
+        assert_eq!(nonces.known.len(), 2);
+    }
+
+    #[test]
+    fn aggregates_warnings_within_interval() {
+        let mut warning = RateLimitedWarning::default();
+        let now = Instant::now();
+
+        assert_eq!(warning.should_warn(now), Some(0));
+        assert_eq!(warning.should_warn(now + Duration::from_secs(1)), None);
+        assert_eq!(warning.should_warn(now + Duration::from_secs(2)), None);
+        assert_eq!(warning.should_warn(now + WARNING_INTERVAL), Some(2));
+    }
+
+    #[tokio::test]
+    async fn backs_off_after_failing_to_connect() {
+        let mut connection = Connection::new(unused_addr());
+
+        connection.query(&[b"PING"]).await.unwrap_err();
+        let error = connection.query(&[b"PING"]).await.unwrap_err();
+
+        assert_eq!(error.kind(), io::ErrorKind::NotConnected);
+    }
+
+    fn unused_addr() -> SocketAddr {
+        std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+    }
+}
@@ -0,0 +1,152 @@
+use bytes::{Bytes, BytesMut};
+
+/// The length of a STUN message header.
+///
+/// See <https://www.rfc-editor.org/rfc/rfc5389#section-6>.
+const STUN_HEADER_LEN: usize = 20;
+
+/// The length of a `ChannelData` message header.
+///
+/// See <https://www.rfc-editor.org/rfc/rfc8656#name-the-channeldata-message>.
+const CHANNEL_DATA_HEADER_LEN: usize = 4;
+
+/// Splits a byte-stream into individual STUN and `ChannelData` messages.
+///
+/// Over stream-based transports (TCP and TLS), there is no framing other than the length fields embedded in the messages themselves.
+/// STUN messages are always a multiple of 4 bytes long.
+/// `ChannelData` messages MUST be padded to a multiple of 4 bytes on stream-based transports which we account for when computing the length of a frame.
+///
+/// See <https://www.rfc-editor.org/rfc/rfc8656#section-12.5>.
+#[derive(Default)]
+pub(crate) struct Framer {
+    buffer: BytesMut,
+}
+
+impl Framer {
+    pub(crate) fn handle_input(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Returns the next complete message in the stream.
+    ///
+    /// A returned `ChannelData` message includes its padding.
+    /// Errors are not recoverable because we can no longer find the start of the next message.
+    pub(crate) fn next_message(&mut self) -> Result<Option<Bytes>, UnknownMessageType> {
+        let Some(message_len) = message_len(&self.buffer)? else {
+            return Ok(None);
+        };
+
+        if self.buffer.len() < message_len {
+            return Ok(None);
+        }
+
+        Ok(Some(self.buffer.split_to(message_len).freeze()))
+    }
+}
+
+/// Pads the given message with zeros to a multiple of 4 bytes.
+///
+/// This is a no-op for STUN messages because those are always a multiple of 4 bytes long.
+pub(crate) fn pad_to_multiple_of_four(message: &mut Vec<u8>) {
+    let padded_len = padded(message.len());
+
+    message.resize(padded_len, 0);
+}
+
+/// Computes the length of the message at the start of `buffer`, including padding.
+///
+/// Returns [`None`] if we don't have enough bytes yet to know the length.
+fn message_len(buffer: &[u8]) -> Result<Option<usize>, UnknownMessageType> {
+    if buffer.len() < CHANNEL_DATA_HEADER_LEN {
+        return Ok(None);
+    }
+
+    let length = u16::from_be_bytes([buffer[2], buffer[3]]) as usize;
+
+    // De-multiplex as per <https://www.rfc-editor.org/rfc/rfc8656#name-channels-2>.
+    match buffer[0] {
+        0..=3 => Ok(Some(STUN_HEADER_LEN + length)),
+        64..=79 => Ok(Some(CHANNEL_DATA_HEADER_LEN + padded(length))),
+        other => Err(UnknownMessageType(other)),
+    }
+}
+
+fn padded(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) struct UnknownMessageType(pub(crate) u8);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn yields_nothing_for_partial_header() {
+        let mut framer = Framer::default();
+        framer.handle_input(&[0x40, 0x00]);
+
+        assert_eq!(framer.next_message(), Ok(None));
+    }
+
+    #[test]
+    fn yields_padded_channel_data_message() {
+        let mut framer = Framer::default();
+        framer.handle_input(&[0x40, 0x00, 0x00, 0x05, 1, 2, 3, 4, 5, 0, 0, 0]);
+
+        assert_eq!(
+            framer.next_message().unwrap().unwrap().as_ref(),
+            &[0x40, 0x00, 0x00, 0x05, 1, 2, 3, 4, 5, 0, 0, 0]
+        );
+        assert_eq!(framer.next_message(), Ok(None));
+    }
+
+    #[test]
+    fn waits_for_padding_of_channel_data_message() {
+        let mut framer = Framer::default();
+        framer.handle_input(&[0x40, 0x00, 0x00, 0x05, 1, 2, 3, 4, 5]);
+
+        assert_eq!(framer.next_message(), Ok(None));
+
+        framer.handle_input(&[0, 0, 0]);
+
+        assert!(framer.next_message().unwrap().is_some());
+    }
+
+    #[test]
+    fn splits_stun_message_followed_by_channel_data() {
+        let mut stun_message = vec![0x00, 0x01, 0x00, 0x00];
+        stun_message.extend_from_slice(&[0u8; 16]);
+
+        let mut framer = Framer::default();
+        framer.handle_input(&stun_message);
+        framer.handle_input(&[0x40, 0x01, 0x00, 0x04, 1, 2, 3, 4]);
+
+        assert_eq!(
+            framer.next_message().unwrap().unwrap().as_ref(),
+            stun_message.as_slice()
+        );
+        assert_eq!(
+            framer.next_message().unwrap().unwrap().as_ref(),
+            &[0x40, 0x01, 0x00, 0x04, 1, 2, 3, 4]
+        );
+    }
+
+    #[test]
+    fn unknown_message_type_is_an_error() {
+        let mut framer = Framer::default();
+        framer.handle_input(&[0xFF, 0x00, 0x00, 0x00]);
+
+        assert_eq!(framer.next_message(), Err(UnknownMessageType(0xFF)));
+    }
+
+    #[test]
+    fn pads_to_multiple_of_four() {
+        let mut message = vec![1, 2, 3, 4, 5];
+
+        pad_to_multiple_of_four(&mut message);
+
+        assert_eq!(message, vec![1, 2, 3, 4, 5, 0, 0, 0]);
+    }
+}
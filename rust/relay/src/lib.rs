@@ -1,16 +1,21 @@
+mod framing;
 mod net_ext;
 mod server;
 mod sleep;
 
+pub mod admin;
 pub mod auth;
+pub mod peering;
 #[cfg(feature = "proptest")]
 pub mod proptest;
 pub mod sockets;
+pub mod streams;
 
 pub use net_ext::IpAddrExt;
 pub use server::{
-    Allocate, AllocationPort, Attribute, Binding, ChannelBind, ChannelData, ClientMessage, Command,
-    CreatePermission, Refresh, Server,
+    Allocate, AllocationInfo, AllocationPort, Attribute, Binding, ChannelBind, ChannelData,
+    ChannelInfo, ClientMessage, Command, CreatePermission, IcmpError, Limit, Limits, PortRange,
+    Refresh, SendIndication, Server, ServerConfig, UsernameUsages,
 };
 pub use sleep::Sleep;
 pub use stun_codec::rfc8656::attributes::AddressFamily;
@@ -20,6 +25,9 @@ use std::{
     net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
 };
 
+/// The port on which the relay listens for TURN traffic of clients.
+pub const TURN_PORT: u16 = 3478;
+
 /// Describes the IP stack of a relay server.
 #[derive(Debug, Copy, Clone)]
 pub enum IpStack {
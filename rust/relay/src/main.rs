@@ -1,25 +1,37 @@
 use anyhow::{anyhow, bail, Context, Result};
 use backoff::ExponentialBackoffBuilder;
 use clap::Parser;
+use firezone_relay::admin;
+use firezone_relay::auth::{AuthScheme, NonceLookups, RedisNonces};
+use firezone_relay::peering::{Peering, RelayPeer};
 use firezone_relay::sockets::Sockets;
+use firezone_relay::streams::Streams;
 use firezone_relay::{
-    sockets, AddressFamily, AllocationPort, ChannelData, ClientSocket, Command, IpStack,
-    PeerSocket, Server, Sleep,
+    sockets, streams, AddressFamily, AllocationPort, ChannelData, ClientSocket, Command, IpStack,
+    Limit, Limits, PeerSocket, PortRange, Server, ServerConfig, Sleep, UsernameUsages, TURN_PORT,
 };
 use futures::{future, FutureExt};
 use opentelemetry::KeyValue;
 use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::metrics::reader::{DefaultAggregationSelector, DefaultTemporalitySelector};
+use opentelemetry_sdk::metrics::{PeriodicReader, SdkMeterProvider};
 use phoenix_channel::{Event, LoginUrl, PhoenixChannel};
 use rand::rngs::StdRng;
 use rand::{Rng, SeedableRng};
 use secrecy::{Secret, SecretString};
+use std::future::Future;
+use std::io;
 use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
 use std::pin::Pin;
 use std::sync::{Arc, Mutex};
 use std::task::Poll;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime};
 use tokio::signal::unix;
-use tracing::{level_filters::LevelFilter, Subscriber};
+use tokio::sync::{mpsc, oneshot};
+use tokio_rustls::TlsAcceptor;
+use tracing::{level_filters::LevelFilter, Instrument, Subscriber};
 use tracing_core::Dispatch;
 use tracing_stackdriver::CloudTraceConfiguration;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer};
@@ -27,8 +39,6 @@ use url::Url;
 
 const STATS_LOG_INTERVAL: Duration = Duration::from_secs(10);
 
-const TURN_PORT: u16 = 3478;
-
 const MAX_PARTITION_TIME: Duration = Duration::from_secs(60 * 15);
 
 #[derive(Parser, Debug)]
@@ -83,10 +93,141 @@ struct Args {
     #[arg(long, env, hide = true)]
     google_cloud_project_id: Option<String>,
 
+    /// Path to a PEM-encoded certificate chain for TURN over TLS.
+    ///
+    /// If set together with `tls_key_file`, we will accept TURN over TLS connections on `tls_port`.
+    #[arg(long, env, requires = "tls_key_file")]
+    tls_cert_file: Option<PathBuf>,
+    /// Path to the PEM-encoded private key for TURN over TLS.
+    #[arg(long, env, requires = "tls_cert_file")]
+    tls_key_file: Option<PathBuf>,
+    /// The port to accept TURN over TLS connections on.
+    #[arg(long, env, hide = true, default_value = "443")]
+    tls_port: u16,
+
+    /// The maximum rate in bytes per second at which data is relayed for a single allocation.
+    #[arg(long, env)]
+    allocation_bandwidth_limit: Option<u64>,
+    /// The maximum number of bytes relayed for a single allocation.
+    #[arg(long, env)]
+    allocation_quota: Option<u64>,
+    /// The maximum rate in bytes per second at which data is relayed across all allocations of a username.
+    #[arg(long, env)]
+    username_bandwidth_limit: Option<u64>,
+    /// The maximum number of bytes relayed across all allocations of a username.
+    ///
+    /// Once exhausted, allocate and refresh requests for this username are rejected until its usage is forgotten, see `username_usage_retention_secs` in the config file.
+    #[arg(long, env)]
+    username_quota: Option<u64>,
+
+    /// How to authenticate clients.
+    ///
+    /// Only `firezone` is supported when connected to the portal.
+    #[arg(long, env, default_value = "firezone")]
+    auth_scheme: AuthSchemeArg,
+    /// The shared secret for the `turn-rest-api` auth scheme.
+    #[arg(long, env, required_if_eq("auth_scheme", "turn-rest-api"))]
+    turn_rest_api_secret: Option<SecretString>,
+    /// A file of `<username>:<password>` lines for the `static` auth scheme.
+    #[arg(long, env, required_if_eq("auth_scheme", "static"))]
+    static_credentials_file: Option<PathBuf>,
+
+    /// The address of a Redis-compatible server to store nonces in.
+    ///
+    /// Relays sharing the same server accept each other's nonces.
+    /// If not set, nonces are kept in memory.
+    #[arg(long, env)]
+    nonce_store_redis_addr: Option<SocketAddr>,
+
+    /// How long to keep serving existing allocations after we started draining, in seconds.
+    ///
+    /// Draining is triggered via SIGTERM or the admin API.
+    /// Once all allocations are gone or the timeout is reached, the relay shuts down.
+    #[arg(long, env, default_value = "900")]
+    drain_timeout_secs: u64,
+
+    /// The address of a local interface to serve the admin API on.
+    ///
+    /// The admin API is unauthenticated and must not be reachable from the internet.
+    /// If not set, the admin API is disabled.
+    #[arg(long, env)]
+    admin_addr: Option<SocketAddr>,
+
+    /// The port to accept data forwarded by other relays on.
+    ///
+    /// Data for allocations on relays given via `--relay-peer` is forwarded to them via this port as well.
+    #[arg(long, env, requires = "relay_peering_secret")]
+    relay_peering_port: Option<u16>,
+    /// The secret shared with all peered relays, authenticating the forwarded data.
+    #[arg(long, env, requires = "relay_peering_port")]
+    relay_peering_secret: Option<SecretString>,
+    /// A relay to forward data to, as `<public-ip>=<link-addr>`.
+    ///
+    /// Data for allocations on `<public-ip>` is sent to `<link-addr>`, i.e. the peering port of the other relay.
+    /// Can be specified multiple times.
+    #[arg(long, env, value_delimiter = ',', requires = "relay_peering_port")]
+    relay_peer: Vec<RelayPeer>,
+
+    /// A TOML file with the allocation, channel and nonce policies of the relay.
+    ///
+    /// The flags below take precedence over the values in the file.
+    #[arg(long, env)]
+    config_file: Option<PathBuf>,
+    /// The lifetime of an allocation if the client doesn't request one, in seconds.
+    #[arg(long, env)]
+    default_allocation_lifetime_secs: Option<u64>,
+    /// The maximum lifetime of an allocation, in seconds.
+    #[arg(long, env)]
+    max_allocation_lifetime_secs: Option<u64>,
+    /// The maximum number of allocations a single username may hold at the same time.
+    #[arg(long, env)]
+    max_allocations_per_username: Option<usize>,
+    /// The maximum number of channels a single allocation may have at the same time.
+    #[arg(long, env)]
+    max_channels_per_allocation: Option<usize>,
+    /// The duration of a channel binding, in seconds.
+    #[arg(long, env)]
+    channel_binding_duration_secs: Option<u64>,
+    /// How long an expired channel cannot be rebound to a different peer, in seconds.
+    #[arg(long, env)]
+    channel_rebind_timeout_secs: Option<u64>,
+    /// How many requests a client can perform with the same nonce.
+    #[arg(long, env)]
+    nonce_num_requests: Option<u64>,
+    /// How long a nonce is valid, in seconds.
+    #[arg(long, env)]
+    nonce_lifetime_secs: Option<u64>,
+    /// Ports within the allocation port range that must not be used for allocations, as `<port>` or `<lowest>-<highest>`.
+    ///
+    /// Can be specified multiple times, in addition to the ports excluded in the config file.
+    #[arg(long, env, value_delimiter = ',')]
+    excluded_ports: Vec<PortRange>,
+
+    /// The number of worker threads relaying traffic.
+    ///
+    /// Each worker binds the UDP TURN port via `SO_REUSEPORT` and owns an equal share of the allocation port range.
+    /// The kernel distributes clients across workers, meaning all traffic of an allocation is handled by a single thread.
+    /// Per-username limits are shared by all workers.
+    ///
+    /// TCP and TLS clients never leave the first worker: their connections are only accepted there and all their allocations are made on it.
+    /// Relays serving mostly TCP or TLS clients therefore don't benefit from additional workers.
+    #[arg(long, env, default_value = "1")]
+    workers: NonZeroUsize,
+
     #[command(flatten)]
     health_check: http_health_check::HealthCheckArgs,
 }
 
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq)]
+enum AuthSchemeArg {
+    /// Firezone's own credentials, handed out by the portal.
+    Firezone,
+    /// coturn-compatible TURN REST API credentials (HMAC-SHA1 over `<expiry>:<user>`).
+    TurnRestApi,
+    /// A static list of usernames and passwords.
+    Static,
+}
+
 #[derive(clap::ValueEnum, Debug, Clone, Copy)]
 enum LogFormat {
     Human,
@@ -99,6 +240,7 @@ async fn main() -> Result<()> {
     let args = Args::parse();
 
     setup_tracing(&args)?;
+    let metrics_registry = setup_metrics(&args)?;
 
     let public_addr = match (args.public_ip4_addr, args.public_ip6_addr) {
         (Some(ip4), Some(ip6)) => IpStack::Dual { ip4, ip6 },
@@ -109,20 +251,87 @@ async fn main() -> Result<()> {
         }
     };
 
-    let server = Server::new(
-        public_addr,
-        make_rng(args.rng_seed),
-        args.lowest_port,
-        args.highest_port,
-    );
+    if args.token.is_some() && args.auth_scheme != AuthSchemeArg::Firezone {
+        bail!("Only the `firezone` auth scheme is supported when connecting to the portal");
+    }
 
+    let config = load_server_config(&args)?;
+    let num_workers = args.workers.get();
+
+    if args.relay_peering_port.is_some() && num_workers > 1 {
+        bail!("Relay peering is only supported with a single worker");
+    }
+    if args
+        .relay_peering_port
+        .is_some_and(|port| (args.lowest_port..=args.highest_port).contains(&port))
+    {
+        bail!("The relay peering port must not be within the allocation port range");
+    }
+    let port_ranges = partition_port_range(args.lowest_port, args.highest_port, num_workers)
+        .with_context(|| {
+            format!(
+                "Port range {}-{} is too small for {num_workers} workers",
+                args.lowest_port, args.highest_port
+            )
+        })?;
+
+    let username_usages = UsernameUsages::default();
+    let mut servers = port_ranges
+        .into_iter()
+        .enumerate()
+        .map(|(worker, (lowest_port, highest_port))| {
+            let rng = make_rng(args.rng_seed.map(|seed| seed.wrapping_add(worker as u64)));
+
+            make_server(
+                &args,
+                &config,
+                public_addr,
+                rng,
+                lowest_port,
+                highest_port,
+                username_usages.clone(),
+            )
+        })
+        .collect::<Result<Vec<_>>>()?
+        .into_iter();
+    let (server, nonce_lookups) = servers.next().expect("at least one worker");
+
+    let drain_timeout = Duration::from_secs(args.drain_timeout_secs);
     let last_heartbeat_sent = Arc::new(Mutex::new(Option::<Instant>::None));
+    let drain_progress = Arc::new(Mutex::new(Option::<DrainProgress>::None));
+    let (admin_requests_tx, admin_requests_rx) = mpsc::channel(10);
+
+    let mut all_drain_progress = vec![drain_progress.clone()];
+    let mut all_admin_requests = vec![admin_requests_tx];
+    let mut secondary_workers = Vec::new();
+
+    for (id, (secondary, nonce_lookups)) in (1..).zip(servers) {
+        use secrecy::ExposeSecret;
+
+        // All workers must accept the credentials handed out by the portal, regardless of which one a client ends up on.
+        let secondary = secondary.with_auth_secret(SecretString::new(
+            server.auth_secret().expose_secret().clone(),
+        ));
+
+        let worker = spawn_worker(id, secondary, nonce_lookups, public_addr, drain_timeout)?;
+
+        all_drain_progress.push(worker.drain_progress);
+        all_admin_requests.push(worker.admin_requests);
+        secondary_workers.push(worker.result);
+    }
 
-    tokio::spawn(http_health_check::serve(
+    tokio::spawn(http_health_check::serve_with_metrics(
         args.health_check.health_check_addr,
-        make_is_healthy(last_heartbeat_sent.clone()),
+        make_health(last_heartbeat_sent.clone(), all_drain_progress),
+        move || encode_metrics(&metrics_registry),
     ));
 
+    if let Some(admin_addr) = args.admin_addr {
+        tokio::spawn(admin::serve(admin_addr, all_admin_requests));
+
+        tracing::info!(target: "relay", "Serving admin API on {admin_addr}");
+    }
+
     let channel = if let Some(token) = args.token.as_ref() {
         use secrecy::ExposeSecret;
 
@@ -151,19 +360,285 @@ async fn main() -> Result<()> {
         None
     };
 
-    let mut eventloop = Eventloop::new(server, channel, public_addr, last_heartbeat_sent)?;
+    let tls = match (args.tls_cert_file.as_deref(), args.tls_key_file.as_deref()) {
+        (Some(cert_file), Some(key_file)) => Some((
+            streams::tls_acceptor_from_pem_files(cert_file, key_file)?,
+            args.tls_port,
+        )),
+        _ => None,
+    };
 
-    tracing::info!(target: "relay", "Listening for incoming traffic on UDP port {TURN_PORT}");
+    let mut eventloop = Eventloop::new(
+        server,
+        nonce_lookups,
+        channel,
+        public_addr,
+        Listeners {
+            reuse_port: num_workers > 1,
+            tcp: true,
+            tls,
+            peering_port: args.relay_peering_port,
+        },
+        last_heartbeat_sent,
+        drain_progress,
+        admin_requests_rx,
+        drain_timeout,
+    )?;
+
+    tracing::info!(target: "relay", "Listening for incoming traffic on UDP and TCP port {TURN_PORT}");
+    if let Some(tls_port) = eventloop.tls_port {
+        tracing::info!(target: "relay", "Listening for incoming traffic on TLS port {tls_port}");
+    }
+    if num_workers > 1 {
+        tracing::info!(target: "relay", "Relaying UDP traffic on {num_workers} workers");
+    }
 
-    future::poll_fn(|cx| eventloop.poll(cx))
-        .await
-        .context("event loop failed")?;
+    let primary_worker =
+        future::poll_fn(|cx| eventloop.poll(cx)).map(|result| result.context("event loop failed"));
+    let secondary_workers = future::try_join_all(secondary_workers);
+
+    future::try_join(primary_worker, secondary_workers).await?;
 
     tracing::info!("Goodbye!");
 
     Ok(())
 }
 
+/// Constructs a [`Server`] for a single worker, owning the allocation ports `lowest_port..highest_port`.
+///
+/// If nonces are stored in Redis, also returns the results of nonce lookups which must be passed to the server.
+fn make_server(
+    args: &Args,
+    config: &ServerConfig,
+    public_addr: IpStack,
+    rng: StdRng,
+    lowest_port: u16,
+    highest_port: u16,
+    username_usages: UsernameUsages,
+) -> Result<(Server<StdRng>, Option<NonceLookups>)> {
+    let mut server = Server::new(public_addr, rng, lowest_port, highest_port)
+        .with_config(config.clone())
+        .with_username_usages(username_usages)
+        .with_limits(Limits {
+            per_allocation: Limit {
+                bytes_per_second: args.allocation_bandwidth_limit,
+                quota_bytes: args.allocation_quota,
+            },
+            per_username: Limit {
+                bytes_per_second: args.username_bandwidth_limit,
+                quota_bytes: args.username_quota,
+            },
+        });
+
+    match args.auth_scheme {
+        AuthSchemeArg::Firezone => {}
+        AuthSchemeArg::TurnRestApi => {
+            use secrecy::ExposeSecret;
+
+            let shared_secret = args
+                .turn_rest_api_secret
+                .as_ref()
+                .context("Missing `--turn-rest-api-secret`")?;
+
+            server = server.with_auth_scheme(AuthScheme::TurnRestApi {
+                shared_secret: SecretString::new(shared_secret.expose_secret().clone()),
+            });
+        }
+        AuthSchemeArg::Static => {
+            let file = args
+                .static_credentials_file
+                .as_deref()
+                .context("Missing `--static-credentials-file`")?;
+
+            server = server.with_auth_scheme(AuthScheme::static_from_file(file)?);
+        }
+    }
+
+    let mut nonce_lookups = None;
+    if let Some(redis_addr) = args.nonce_store_redis_addr {
+        tracing::info!(target: "relay", "Storing nonces in Redis at {redis_addr}");
+
+        let (nonces, lookups) = RedisNonces::spawn(redis_addr);
+
+        server = server.with_nonce_store(nonces);
+        nonce_lookups = Some(lookups);
+    }
+
+    if let Some(secret) = args.relay_peering_secret.as_ref() {
+        use secrecy::ExposeSecret;
+
+        server = server.with_peering(Peering::new(
+            SecretString::new(secret.expose_secret().clone()),
+            args.relay_peer.clone(),
+            SystemTime::now(),
+        ));
+    }
+
+    Ok((server, nonce_lookups))
+}
+
+/// Reads the [`ServerConfig`] from `--config-file`, if any, and applies the overrides from the remaining flags.
+fn load_server_config(args: &Args) -> Result<ServerConfig> {
+    let mut config = match args.config_file.as_deref() {
+        Some(path) => {
+            let toml = std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+
+            ServerConfig::from_toml(&toml).with_context(|| format!("Invalid {}", path.display()))?
+        }
+        None => ServerConfig::default(),
+    };
+
+    if let Some(secs) = args.default_allocation_lifetime_secs {
+        config.default_allocation_lifetime = Duration::from_secs(secs);
+    }
+    if let Some(secs) = args.max_allocation_lifetime_secs {
+        config.max_allocation_lifetime = Duration::from_secs(secs);
+    }
+    if let Some(max) = args.max_allocations_per_username {
+        config.max_allocations_per_username = Some(max);
+    }
+    if let Some(max) = args.max_channels_per_allocation {
+        config.max_channels_per_allocation = Some(max);
+    }
+    if let Some(secs) = args.channel_binding_duration_secs {
+        config.channel_binding_duration = Duration::from_secs(secs);
+    }
+    if let Some(secs) = args.channel_rebind_timeout_secs {
+        config.channel_rebind_timeout = Duration::from_secs(secs);
+    }
+    if let Some(num_requests) = args.nonce_num_requests {
+        config.nonce_num_requests = num_requests;
+    }
+    if let Some(secs) = args.nonce_lifetime_secs {
+        config.nonce_lifetime = Duration::from_secs(secs);
+    }
+    config
+        .excluded_ports
+        .extend(args.excluded_ports.iter().copied());
+
+    config.validate()?;
+
+    if (args.lowest_port..args.highest_port)
+        .all(|port| config.excluded_ports.iter().any(|r| r.contains(port)))
+    {
+        bail!(
+            "All ports between {} and {} are excluded",
+            args.lowest_port,
+            args.highest_port
+        );
+    }
+
+    Ok(config)
+}
+
+/// Splits the allocation port range into one disjoint range per worker.
+///
+/// [`Server`] never allocates its highest port, so adjacent ranges can share their boundary.
+/// Returns `None` if the range is too small to give each worker at least one port.
+fn partition_port_range(
+    lowest_port: u16,
+    highest_port: u16,
+    num_workers: usize,
+) -> Option<Vec<(u16, u16)>> {
+    let num_ports = usize::from(highest_port.checked_sub(lowest_port)?);
+    let ports_per_worker = num_ports / num_workers;
+
+    if ports_per_worker == 0 {
+        return None;
+    }
+
+    let boundary = |worker: usize| {
+        if worker == num_workers {
+            return highest_port;
+        }
+
+        lowest_port + (worker * ports_per_worker) as u16
+    };
+
+    Some(
+        (0..num_workers)
+            .map(|worker| (boundary(worker), boundary(worker + 1)))
+            .collect(),
+    )
+}
+
+/// A secondary worker, running on its own thread.
+struct Worker {
+    admin_requests: mpsc::Sender<admin::Request>,
+    drain_progress: Arc<Mutex<Option<DrainProgress>>>,
+    /// Resolves once the worker's event loop exits.
+    result: Pin<Box<dyn Future<Output = Result<()>> + Send>>,
+}
+
+/// Runs an [`Eventloop`] for `server` on a dedicated thread.
+///
+/// Secondary workers share the UDP TURN port with the primary one via `SO_REUSEPORT`.
+/// They don't connect to the portal and don't accept TCP or TLS connections.
+fn spawn_worker(
+    id: usize,
+    server: Server<StdRng>,
+    nonce_lookups: Option<NonceLookups>,
+    public_addr: IpStack,
+    drain_timeout: Duration,
+) -> Result<Worker> {
+    let (admin_requests_tx, admin_requests_rx) = mpsc::channel(10);
+    let drain_progress = Arc::new(Mutex::new(Option::<DrainProgress>::None));
+    let (result_tx, result_rx) = oneshot::channel();
+
+    std::thread::Builder::new()
+        .name(format!("relay-worker-{id}"))
+        .spawn({
+            let drain_progress = drain_progress.clone();
+
+            move || {
+                let result = tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                    .context("Failed to create runtime")
+                    .and_then(|runtime| {
+                        runtime.block_on(
+                            async move {
+                                let mut eventloop = Eventloop::new(
+                                    server,
+                                    nonce_lookups,
+                                    None,
+                                    public_addr,
+                                    Listeners {
+                                        reuse_port: true,
+                                        tcp: false,
+                                        tls: None,
+                                        peering_port: None,
+                                    },
+                                    Arc::new(Mutex::new(None)),
+                                    drain_progress,
+                                    admin_requests_rx,
+                                    drain_timeout,
+                                )?;
+
+                                future::poll_fn(|cx| eventloop.poll(cx)).await
+                            }
+                            .instrument(tracing::info_span!("worker", %id)),
+                        )
+                    });
+
+                let _ = result_tx.send(result);
+            }
+        })
+        .context("Failed to spawn worker thread")?;
+
+    Ok(Worker {
+        admin_requests: admin_requests_tx,
+        drain_progress,
+        result: Box::pin(async move {
+            result_rx
+                .await
+                .with_context(|| format!("Worker {id} panicked"))?
+                .with_context(|| format!("event loop of worker {id} failed"))
+        }),
+    })
+}
+
 /// Sets up our tracing infrastructure.
 ///
 /// See [`log_layer`] for details on the base log layer.
@@ -200,18 +675,6 @@ fn setup_tracing(args: &Args) -> Result<()> {
 
             tracing::trace!(target: "relay", "Successfully initialized trace provider on tokio runtime");
 
-            let exporter = opentelemetry_otlp::new_exporter()
-                .tonic()
-                .with_endpoint(grpc_endpoint);
-
-            opentelemetry_otlp::new_pipeline()
-                .metrics(opentelemetry_sdk::runtime::Tokio)
-                .with_exporter(exporter)
-                .build()
-                .context("Failed to create OTLP metrics pipeline")?;
-
-            tracing::trace!(target: "relay", "Successfully initialized metric controller on tokio runtime");
-
             tracing_subscriber::registry()
                 .with(log_layer(args))
                 .with(
@@ -232,6 +695,53 @@ fn setup_tracing(args: &Args) -> Result<()> {
     Ok(())
 }
 
+/// Sets up the global meter provider.
+///
+/// Metrics are always collected into the returned [`prometheus::Registry`] which is served on `/metrics` by our health-check server.
+/// If the user has specified an OTLP collector via `Args.otlp_grpc_endpoint`, metrics are additionally exported to it.
+fn setup_metrics(args: &Args) -> Result<prometheus::Registry> {
+    let registry = prometheus::Registry::new();
+
+    let prometheus_exporter = opentelemetry_prometheus::exporter()
+        .with_registry(registry.clone())
+        .build()
+        .context("Failed to create Prometheus exporter")?;
+
+    let mut meter_provider = SdkMeterProvider::builder().with_reader(prometheus_exporter);
+
+    if let Some(endpoint) = args.otlp_grpc_endpoint {
+        let otlp_exporter = opentelemetry_otlp::new_exporter()
+            .tonic()
+            .with_endpoint(format!("http://{endpoint}"))
+            .build_metrics_exporter(
+                Box::new(DefaultAggregationSelector::new()),
+                Box::new(DefaultTemporalitySelector::new()),
+            )
+            .context("Failed to create OTLP metrics exporter")?;
+
+        meter_provider = meter_provider.with_reader(
+            PeriodicReader::builder(otlp_exporter, opentelemetry_sdk::runtime::Tokio).build(),
+        );
+
+        tracing::trace!(target: "relay", "Successfully initialized OTLP metrics exporter on tokio runtime");
+    }
+
+    opentelemetry::global::set_meter_provider(meter_provider.build());
+
+    Ok(registry)
+}
+
+/// Encodes all metrics in the given registry in the Prometheus text exposition format.
+fn encode_metrics(registry: &prometheus::Registry) -> String {
+    prometheus::TextEncoder::new()
+        .encode_to_string(&registry.gather())
+        .unwrap_or_else(|e| {
+            tracing::warn!(target: "relay", "Failed to encode metrics: {e}");
+
+            String::new()
+        })
+}
+
 /// Constructs the base log layer.
 ///
 /// The user has a choice between:
@@ -285,15 +795,36 @@ fn make_rng(seed: Option<u64>) -> StdRng {
 
 const MAX_UDP_SIZE: usize = 65536;
 
+/// On which sockets an [`Eventloop`] accepts TURN clients.
+struct Listeners {
+    /// Whether to share the UDP TURN port with other workers via `SO_REUSEPORT`.
+    reuse_port: bool,
+    /// Whether to accept TURN over TCP on the TURN port.
+    tcp: bool,
+    /// The acceptor and port for TURN over TLS.
+    tls: Option<(TlsAcceptor, u16)>,
+    /// The port to exchange forwarded data with other relays on.
+    peering_port: Option<u16>,
+}
+
 struct Eventloop<R> {
     sockets: Sockets,
+    streams: Streams,
+    tls_port: Option<u16>,
+    peering_port: Option<u16>,
 
     server: Server<R>,
+    nonce_lookups: Option<NonceLookups>,
     channel: Option<PhoenixChannel<JoinMessage, (), ()>>,
     sleep: Sleep,
 
     sigterm: unix::Signal,
-    shutting_down: bool,
+    admin_requests: mpsc::Receiver<admin::Request>,
+
+    drain_timeout: Duration,
+    /// Set once we started draining; we shut down when it fires.
+    drain_deadline: Option<Pin<Box<tokio::time::Sleep>>>,
+    drain_progress: Arc<Mutex<Option<DrainProgress>>>,
 
     stats_log_interval: tokio::time::Interval,
     last_num_bytes_relayed: u64,
@@ -307,32 +838,64 @@ impl<R> Eventloop<R>
 where
     R: Rng,
 {
+    #[allow(clippy::too_many_arguments)]
     fn new(
         server: Server<R>,
+        nonce_lookups: Option<NonceLookups>,
         channel: Option<PhoenixChannel<JoinMessage, (), ()>>,
         public_address: IpStack,
+        listeners: Listeners,
         last_heartbeat_sent: Arc<Mutex<Option<Instant>>>,
+        drain_progress: Arc<Mutex<Option<DrainProgress>>>,
+        admin_requests: mpsc::Receiver<admin::Request>,
+        drain_timeout: Duration,
     ) -> Result<Self> {
         let mut sockets = Sockets::new();
+        let mut streams = Streams::new();
+
+        let families = [
+            public_address.as_v4().map(|_| AddressFamily::V4),
+            public_address.as_v6().map(|_| AddressFamily::V6),
+        ];
+
+        for family in families.into_iter().flatten() {
+            if listeners.reuse_port {
+                sockets.bind_shared(TURN_PORT, family)
+            } else {
+                sockets.bind(TURN_PORT, family)
+            }
+            .with_context(|| {
+                format!("Failed to bind to port {TURN_PORT} on {family} interfaces")
+            })?;
 
-        if public_address.as_v4().is_some() {
-            sockets
-                .bind(TURN_PORT, AddressFamily::V4)
-                .with_context(|| {
-                    format!("Failed to bind to port {TURN_PORT} on IPv4 interfaces")
+            if listeners.tcp {
+                streams.listen_tcp(TURN_PORT, family).with_context(|| {
+                    format!("Failed to listen on TCP port {TURN_PORT} on {family} interfaces")
                 })?;
-        }
-        if public_address.as_v6().is_some() {
-            sockets
-                .bind(TURN_PORT, AddressFamily::V6)
-                .with_context(|| {
-                    format!("Failed to bind to port {TURN_PORT} on IPv6 interfaces")
+            }
+
+            if let Some(port) = listeners.peering_port {
+                sockets.bind(port, family).with_context(|| {
+                    format!("Failed to bind to peering port {port} on {family} interfaces")
                 })?;
+            }
+
+            if let Some((acceptor, port)) = listeners.tls.clone() {
+                streams
+                    .listen_tls(port, family, acceptor)
+                    .with_context(|| {
+                        format!("Failed to listen on TLS port {port} on {family} interfaces")
+                    })?;
+            }
         }
 
         Ok(Self {
             server,
+            nonce_lookups,
             channel,
+            streams,
+            tls_port: listeners.tls.map(|(_, port)| port),
+            peering_port: listeners.peering_port,
             sleep: Sleep::default(),
             stats_log_interval: tokio::time::interval(STATS_LOG_INTERVAL),
             last_num_bytes_relayed: 0,
@@ -340,24 +903,37 @@ where
             buffer: [0u8; MAX_UDP_SIZE],
             last_heartbeat_sent,
             sigterm: unix::signal(unix::SignalKind::terminate())?,
-            shutting_down: false,
+            admin_requests,
+            drain_timeout,
+            drain_deadline: None,
+            drain_progress,
         })
     }
 
     fn poll(&mut self, cx: &mut std::task::Context<'_>) -> Poll<Result<()>> {
         loop {
-            if self.shutting_down && self.channel.is_none() && self.server.num_allocations() == 0 {
+            if self.server.is_draining()
+                && self.channel.is_none()
+                && self.server.num_allocations() == 0
+            {
+                tracing::info!(target: "relay", "All allocations are gone, draining complete");
+
                 return Poll::Ready(Ok(()));
             }
 
+            if let Some(deadline) = self.drain_deadline.as_mut() {
+                if deadline.poll_unpin(cx).is_ready() {
+                    tracing::warn!(target: "relay", active_allocations = %self.server.num_allocations(), "Drain timeout reached, shutting down");
+
+                    return Poll::Ready(Ok(()));
+                }
+            }
+
             // Priority 1: Execute the pending commands of the server.
             if let Some(next_command) = self.server.next_command() {
                 match next_command {
                     Command::SendMessage { payload, recipient } => {
-                        if let Err(e) =
-                            self.sockets
-                                .try_send(TURN_PORT, recipient.into_socket(), &payload)
-                        {
+                        if let Err(e) = self.send_to_client(recipient, &payload) {
                             tracing::warn!(target: "relay", %recipient, "Failed to send message: {e}");
                         }
                     }
@@ -381,12 +957,48 @@ where
 
                         tracing::info!(target: "relay", %port, %family, "Freeing allocation");
                     }
+                    Command::ForwardToRelay { payload, link } => {
+                        let Some(port) = self.peering_port else {
+                            tracing::warn!(target: "relay", %link, "Cannot forward data to relay without a peering port");
+                            continue;
+                        };
+
+                        if let Err(e) = self.sockets.try_send(port, link, &payload) {
+                            tracing::warn!(target: "relay", %link, "Failed to forward data to relay: {e}");
+                        }
+                    }
+                    Command::RelayToPeer {
+                        payload,
+                        allocation,
+                        peer,
+                    } => {
+                        if let Err(e) =
+                            self.sockets
+                                .try_send(allocation.value(), peer.into_socket(), &payload)
+                        {
+                            tracing::warn!(target: "relay", %peer, "Failed to relay data to peer: {e}");
+                        }
+                    }
                 }
 
                 continue; // Attempt to process more commands.
             }
 
-            // Priority 2: Read from our sockets.
+            // Priority 2: Process the requests that were waiting for their nonce to be looked up.
+            if let Some(lookups) = self.nonce_lookups.as_mut() {
+                match lookups.poll_recv(cx) {
+                    Poll::Ready(Some(lookup)) => {
+                        self.server.handle_nonce_lookup(lookup, Instant::now());
+                        continue;
+                    }
+                    Poll::Ready(None) => {
+                        return Poll::Ready(Err(anyhow!("Nonce lookups stopped")));
+                    }
+                    Poll::Pending => {}
+                }
+            }
+
+            // Priority 3: Read from our sockets.
             //
             // We read the packet with an offset of 4 bytes so we can encode the channel-data header into that without re-allocating.
             // This only matters for relaying from an allocation to a client because the data coming in on an allocation is "raw" (i.e. unwrapped) application data.
@@ -433,6 +1045,26 @@ where
                     };
                     continue;
                 }
+                Poll::Ready(Ok(sockets::Received { port, from, packet }))
+                    if Some(port) == self.peering_port =>
+                {
+                    if let Some((client, channel, payload_length)) = self
+                        .server
+                        .handle_relay_forwarded_traffic(packet, from, Instant::now())
+                    {
+                        // The payload is at the start of the frame, i.e. right after the space reserved for the header.
+                        let total_length = ChannelData::encode_header_to_slice(
+                            channel,
+                            payload_length as u16,
+                            header,
+                        );
+
+                        if let Err(e) = self.send_to_client(client, &self.buffer[..total_length]) {
+                            tracing::warn!(target: "relay", %client, "Failed to relay data to client: {e}");
+                        };
+                    };
+                    continue;
+                }
                 Poll::Ready(Ok(sockets::Received {
                     port, // Packets coming in on any other port are from peers.
                     from,
@@ -442,6 +1074,7 @@ where
                         packet,
                         PeerSocket::new(from),
                         AllocationPort::new(port),
+                        Instant::now(),
                     ) {
                         let total_length = ChannelData::encode_header_to_slice(
                             channel,
@@ -449,16 +1082,29 @@ where
                             header,
                         );
 
-                        if let Err(e) = self.sockets.try_send(
-                            TURN_PORT, // Packets coming in from peers always go out on the TURN port
-                            client.into_socket(),
-                            &self.buffer[..total_length],
-                        ) {
+                        if let Err(e) = self.send_to_client(client, &self.buffer[..total_length]) {
                             tracing::warn!(target: "relay", %client, "Failed to relay data to client: {e}");
                         };
                     };
                     continue;
                 }
+                Poll::Ready(Err(sockets::Error::Icmp {
+                    port: TURN_PORT, // ICMP errors on the TURN port are about clients, there is no one to forward them to.
+                    dest,
+                    error,
+                })) => {
+                    tracing::debug!(target: "relay", %dest, ?error, "Received ICMP error for client");
+                    continue;
+                }
+                Poll::Ready(Err(sockets::Error::Icmp { port, dest, error })) => {
+                    self.server.handle_peer_icmp_error(
+                        error,
+                        PeerSocket::new(dest),
+                        AllocationPort::new(port),
+                        Instant::now(),
+                    );
+                    continue; // Handle potentially new commands.
+                }
                 Poll::Ready(Err(sockets::Error::Io(e))) => {
                     tracing::warn!(target: "relay", "Error while receiving message: {e}");
                     continue;
@@ -467,19 +1113,51 @@ where
                 Poll::Pending => {}
             }
 
-            // Priority 3: Check when we need to next be woken. This needs to happen after all state modifications.
+            // Priority 4: Read from our stream-based connections.
+            //
+            // These are already de-framed into individual messages so we don't need to do any buffer juggling here.
+            match self.streams.poll_recv(cx) {
+                Poll::Ready(streams::Received::Message { from, packet }) => {
+                    if let Some((port, peer)) = self.server.handle_client_input(
+                        &packet,
+                        ClientSocket::new(from),
+                        Instant::now(),
+                    ) {
+                        // Re-parse as `ChannelData` if we should relay it.
+                        let payload = ChannelData::parse(&packet)
+                            .expect("valid ChannelData if we should relay it")
+                            .data(); // Any padding is not part of the payload and thus stripped here.
+
+                        if let Err(e) =
+                            self.sockets
+                                .try_send(port.value(), peer.into_socket(), payload)
+                        {
+                            tracing::warn!(target: "relay", %peer, "Failed to relay data to peer: {e}");
+                        }
+                    };
+                    continue;
+                }
+                Poll::Ready(streams::Received::Closed { from }) => {
+                    self.server
+                        .handle_client_connection_closed(ClientSocket::new(from));
+                    continue;
+                }
+                Poll::Pending => {}
+            }
+
+            // Priority 5: Check when we need to next be woken. This needs to happen after all state modifications.
             if let Some(timeout) = self.server.poll_timeout() {
                 Pin::new(&mut self.sleep).reset(timeout);
                 // Purposely no `continue` because we just change the state of `sleep` and we poll it below.
             }
 
-            // Priority 4: Handle time-sensitive tasks:
+            // Priority 6: Handle time-sensitive tasks:
             if let Poll::Ready(deadline) = self.sleep.poll_unpin(cx) {
                 self.server.handle_timeout(deadline);
                 continue; // Handle potentially new commands.
             }
 
-            // Priority 5: Handle portal messages
+            // Priority 7: Handle portal messages
             match self.channel.as_mut().map(|c| c.poll(cx)) {
                 Some(Poll::Ready(Err(e))) => {
                     return Poll::Ready(Err(anyhow!("Portal connection failed: {e}")));
@@ -493,7 +1171,7 @@ where
 
             match self.sigterm.poll_recv(cx) {
                 Poll::Ready(Some(())) => {
-                    if self.shutting_down {
+                    if self.server.is_draining() {
                         // Received a repeated SIGTERM whilst shutting down
 
                         return Poll::Ready(Err(anyhow!("Forcing shutdown on repeated SIGTERM")));
@@ -501,17 +1179,44 @@ where
 
                     tracing::info!(active_allocations = %self.server.num_allocations(), "Received SIGTERM, initiating graceful shutdown");
 
-                    self.shutting_down = true;
+                    self.start_draining();
+                    continue;
+                }
+                Poll::Ready(None) | Poll::Pending => {}
+            }
 
-                    if let Some(portal) = self.channel.as_mut() {
-                        match portal.close() {
-                            Ok(()) => {}
-                            Err(phoenix_channel::Connecting) => {
-                                self.channel = None; // If we are still connecting, just discard the websocket connection.
-                            }
-                        }
-                    }
+            match self.admin_requests.poll_recv(cx) {
+                Poll::Ready(Some(admin::Request::Drain)) => {
+                    tracing::info!(active_allocations = %self.server.num_allocations(), "Received drain request via admin API");
 
+                    self.start_draining();
+                    continue;
+                }
+                Poll::Ready(Some(admin::Request::ListAllocations { respond_to })) => {
+                    let _ = respond_to.send(self.server.allocations());
+                    continue;
+                }
+                Poll::Ready(Some(admin::Request::DeleteAllocation { port, respond_to })) => {
+                    let _ = respond_to.send(self.server.force_delete_allocation(port));
+                    continue; // Handle potentially new commands.
+                }
+                Poll::Ready(Some(admin::Request::StartCapture {
+                    port,
+                    duration,
+                    max_bytes,
+                    respond_to,
+                })) => {
+                    let _ = respond_to.send(self.server.start_capture(
+                        port,
+                        duration,
+                        max_bytes,
+                        Instant::now(),
+                        SystemTime::now(),
+                    ));
+                    continue;
+                }
+                Poll::Ready(Some(admin::Request::StopCapture { port, respond_to })) => {
+                    let _ = respond_to.send(self.server.stop_capture(port));
                     continue;
                 }
                 Poll::Ready(None) | Poll::Pending => {}
@@ -529,6 +1234,8 @@ where
 
                 tracing::info!(target: "relay", "Allocations = {num_allocations} Channels = {num_channels} Throughput = {}", fmt_human_throughput(avg_throughput as f64));
 
+                self.update_drain_progress();
+
                 continue;
             }
 
@@ -536,6 +1243,50 @@ where
         }
     }
 
+    /// Stops accepting new allocations and shuts down once all existing ones are gone or [`Eventloop::drain_timeout`] passed.
+    ///
+    /// Draining is idempotent.
+    fn start_draining(&mut self) {
+        if self.server.is_draining() {
+            return;
+        }
+
+        self.server.start_draining();
+        self.drain_deadline = Some(Box::pin(tokio::time::sleep(self.drain_timeout)));
+        self.update_drain_progress();
+
+        if let Some(portal) = self.channel.as_mut() {
+            match portal.close() {
+                Ok(()) => {}
+                Err(phoenix_channel::Connecting) => {
+                    self.channel = None; // If we are still connecting, just discard the websocket connection.
+                }
+            }
+        }
+    }
+
+    fn update_drain_progress(&self) {
+        let Some(deadline) = self.drain_deadline.as_ref() else {
+            return;
+        };
+
+        *self.drain_progress.lock().unwrap() = Some(DrainProgress {
+            remaining_allocations: self.server.num_allocations(),
+            deadline: deadline.deadline().into_std(),
+        });
+    }
+
+    /// Sends a message to a client, using whichever transport the client is connected on.
+    fn send_to_client(&self, client: ClientSocket, msg: &[u8]) -> io::Result<()> {
+        let client = client.into_socket();
+
+        if self.streams.contains(client) {
+            return self.streams.try_send(client, msg.to_vec());
+        }
+
+        self.sockets.try_send(TURN_PORT, client, msg) // Packets to UDP clients always go out on the TURN port.
+    }
+
     fn handle_portal_event(&mut self, event: phoenix_channel::Event<(), ()>) {
         match event {
             Event::SuccessResponse { res: (), .. } => {}
@@ -571,11 +1322,51 @@ fn fmt_human_throughput(mut throughput: f64) -> String {
     format!("{throughput:.2} TB/s")
 }
 
-/// Factory fn for [`is_healthy`].
-fn make_is_healthy(
+/// How far along we are with draining the relay.
+#[derive(Debug, Clone, Copy)]
+struct DrainProgress {
+    remaining_allocations: usize,
+    deadline: Instant,
+}
+
+/// Factory fn for the [`HealthStatus`](http_health_check::HealthStatus) reported by our health-check endpoint.
+///
+/// While draining, we stay healthy but report our progress.
+fn make_health(
     last_heartbeat_sent: Arc<Mutex<Option<Instant>>>,
-) -> impl Fn() -> bool + Clone + Send + Sync + 'static {
-    move || is_healthy(last_heartbeat_sent.clone())
+    drain_progress: Vec<Arc<Mutex<Option<DrainProgress>>>>,
+) -> impl Fn() -> http_health_check::HealthStatus + Clone + Send + Sync + 'static {
+    move || http_health_check::HealthStatus {
+        is_healthy: is_healthy(last_heartbeat_sent.clone()),
+        details: drain_details(
+            combine_drain_progress(drain_progress.iter().map(|p| *p.lock().unwrap())),
+            Instant::now(),
+        ),
+    }
+}
+
+/// Combines the [`DrainProgress`] of all workers, i.e. how many allocations are left across all of them and when the last one shuts down.
+fn combine_drain_progress(
+    progress: impl IntoIterator<Item = Option<DrainProgress>>,
+) -> Option<DrainProgress> {
+    progress.into_iter().flatten().reduce(|a, b| DrainProgress {
+        remaining_allocations: a.remaining_allocations + b.remaining_allocations,
+        deadline: a.deadline.max(b.deadline),
+    })
+}
+
+fn drain_details(progress: Option<DrainProgress>, now: Instant) -> String {
+    let Some(DrainProgress {
+        remaining_allocations,
+        deadline,
+    }) = progress
+    else {
+        return String::new();
+    };
+
+    let remaining_secs = deadline.saturating_duration_since(now).as_secs();
+
+    format!("draining: {remaining_allocations} allocations remaining, shutting down in at most {remaining_secs}s")
 }
 
 fn is_healthy(last_heartbeat_sent: Arc<Mutex<Option<Instant>>>) -> bool {
@@ -625,4 +1416,81 @@ mod tests {
 
         assert!(!is_healthy)
     }
+
+    #[test]
+    fn given_not_draining_has_no_details() {
+        assert_eq!(drain_details(None, Instant::now()), "");
+    }
+
+    #[test]
+    fn given_draining_reports_progress() {
+        let now = Instant::now();
+
+        let details = drain_details(
+            Some(DrainProgress {
+                remaining_allocations: 3,
+                deadline: now + Duration::from_secs(60),
+            }),
+            now,
+        );
+
+        assert_eq!(
+            details,
+            "draining: 3 allocations remaining, shutting down in at most 60s"
+        );
+    }
+
+    #[test]
+    fn combines_drain_progress_of_draining_workers() {
+        let now = Instant::now();
+
+        let progress = combine_drain_progress([
+            Some(DrainProgress {
+                remaining_allocations: 3,
+                deadline: now + Duration::from_secs(60),
+            }),
+            None,
+            Some(DrainProgress {
+                remaining_allocations: 2,
+                deadline: now + Duration::from_secs(90),
+            }),
+        ]);
+
+        assert_eq!(
+            drain_details(progress, now),
+            "draining: 5 allocations remaining, shutting down in at most 90s"
+        );
+    }
+
+    #[test]
+    fn given_no_draining_worker_has_no_drain_progress() {
+        assert!(combine_drain_progress([None, None]).is_none());
+    }
+
+    #[test]
+    fn single_worker_owns_entire_port_range() {
+        assert_eq!(
+            partition_port_range(49152, 65535, 1),
+            Some(vec![(49152, 65535)])
+        );
+    }
+
+    #[test]
+    fn partitions_port_range_into_adjacent_ranges() {
+        assert_eq!(
+            partition_port_range(49152, 65535, 4),
+            Some(vec![
+                (49152, 53247),
+                (53247, 57342),
+                (57342, 61437),
+                (61437, 65535)
+            ])
+        );
+    }
+
+    #[test]
+    fn port_range_smaller_than_number_of_workers_cannot_be_partitioned() {
+        assert_eq!(partition_port_range(50000, 50002, 3), None);
+        assert_eq!(partition_port_range(50000, 49999, 1), None);
+    }
 }
@@ -0,0 +1,411 @@
+use anyhow::{Context, Result};
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, SecretString};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::str::FromStr;
+use std::time::SystemTime;
+
+/// The length of the truncated HMAC-SHA256 that authenticates a [`Frame`].
+const TAG_LEN: usize = 16;
+
+/// How far a [`Frame`] may lag behind the newest one we received from the same relay, see [`ReplayWindow`].
+const REPLAY_WINDOW_SIZE: u64 = 64;
+
+/// Configuration for forwarding data to allocations on other relays via a dedicated relay-to-relay link.
+///
+/// Data that a client sends to an allocation on a peered relay is not sent to the allocation directly but wrapped in a [`Frame`] and sent to the relay's link address.
+/// This allows relays to use a different (e.g. private) network between each other.
+pub struct Peering {
+    /// The secret shared by all peered relays, authenticating the frames on the link.
+    secret: SecretString,
+    peers: Vec<RelayPeer>,
+
+    /// The sequence number of the next frame we send.
+    next_sequence: u64,
+    /// The sequence numbers we received, per link IP of the sending relay.
+    replay_windows: HashMap<IpAddr, ReplayWindow>,
+}
+
+impl Peering {
+    /// Creates a new [`Peering`] at the given wall-clock time.
+    ///
+    /// The sequence numbers of our frames start at the number of nanoseconds since the UNIX epoch.
+    /// This ensures they keep increasing across restarts, as long as we send less than one frame per nanosecond on average.
+    pub fn new(secret: SecretString, peers: Vec<RelayPeer>, now: SystemTime) -> Self {
+        let next_sequence = now
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |since_epoch| {
+                u64::try_from(since_epoch.as_nanos()).unwrap_or(u64::MAX)
+            });
+
+        Self {
+            secret,
+            peers,
+            next_sequence,
+            replay_windows: HashMap::default(),
+        }
+    }
+
+    pub(crate) fn secret(&self) -> &SecretString {
+        &self.secret
+    }
+
+    /// Returns the sequence number for the next frame we send.
+    pub(crate) fn next_sequence(&mut self) -> u64 {
+        let sequence = self.next_sequence;
+        self.next_sequence = self.next_sequence.wrapping_add(1);
+
+        sequence
+    }
+
+    /// Records the sequence number of an authenticated frame from the given relay.
+    ///
+    /// Returns `false` if we already received this frame or it is too old to tell.
+    pub(crate) fn accept_sequence(&mut self, sender: SocketAddr, sequence: u64) -> bool {
+        self.replay_windows
+            .entry(sender.ip())
+            .or_default()
+            .accept(sequence)
+    }
+
+    /// The link address of the relay that owns allocations on the given public IP, if we peer with it.
+    pub(crate) fn link_to(&self, public_ip: IpAddr) -> Option<SocketAddr> {
+        self.peers
+            .iter()
+            .find(|p| p.public_ip == public_ip)
+            .map(|p| p.link)
+    }
+
+    /// Whether the given address is the link address of one of our peers.
+    ///
+    /// We only compare the IP because a relay sends frames from its own link port which may differ from ours.
+    pub(crate) fn is_link(&self, addr: SocketAddr) -> bool {
+        self.peers.iter().any(|p| p.link.ip() == addr.ip())
+    }
+}
+
+/// Another relay we peer with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RelayPeer {
+    /// The public IP of the relay, i.e. the IP of its allocations.
+    pub public_ip: IpAddr,
+    /// Where the relay accepts frames from other relays.
+    pub link: SocketAddr,
+}
+
+impl FromStr for RelayPeer {
+    type Err = anyhow::Error;
+
+    /// Parses a [`RelayPeer`] from `<public-ip>=<link-addr>`.
+    fn from_str(s: &str) -> Result<Self> {
+        let (public_ip, link) = s
+            .split_once('=')
+            .context("Expected `<public-ip>=<link-addr>`")?;
+
+        Ok(Self {
+            public_ip: public_ip.parse().context("Invalid public IP")?,
+            link: link.parse().context("Invalid link address")?,
+        })
+    }
+}
+
+/// Data forwarded from an allocation on one relay to an allocation on another.
+///
+/// On the wire, the metadata follows the payload so the receiving relay can prepend a channel-data header without copying the payload:
+///
+/// ```text
+/// payload | sequence (8) | source port (2) | destination port (2) | source IP (4 or 16) | IP version (1) | tag (16)
+/// ```
+///
+/// The tag is a truncated HMAC-SHA256 over everything before it.
+/// The sequence number protects against replays, see [`ReplayWindow`].
+#[derive(Debug, PartialEq)]
+pub struct Frame<'a> {
+    /// Increases with every frame the sending relay sends.
+    pub sequence: u64,
+    /// The allocation on the sending relay, i.e. the peer as seen by the receiving allocation.
+    pub source: SocketAddr,
+    /// The port of the allocation on the receiving relay.
+    pub destination_port: u16,
+    pub payload: &'a [u8],
+}
+
+#[derive(Debug, PartialEq)]
+pub enum InvalidFrame {
+    TooShort,
+    UnknownIpVersion(u8),
+    BadTag,
+}
+
+impl<'a> Frame<'a> {
+    pub fn encode(&self, secret: &SecretString) -> Vec<u8> {
+        let mut frame = Vec::with_capacity(self.payload.len() + 8 + 4 + 16 + 1 + TAG_LEN);
+
+        frame.extend_from_slice(self.payload);
+        frame.extend_from_slice(&self.sequence.to_be_bytes());
+        frame.extend_from_slice(&self.source.port().to_be_bytes());
+        frame.extend_from_slice(&self.destination_port.to_be_bytes());
+        match self.source.ip() {
+            IpAddr::V4(ip) => {
+                frame.extend_from_slice(&ip.octets());
+                frame.push(4);
+            }
+            IpAddr::V6(ip) => {
+                frame.extend_from_slice(&ip.octets());
+                frame.push(6);
+            }
+        }
+
+        let tag = make_mac(secret, &frame).finalize().into_bytes();
+        frame.extend_from_slice(&tag[..TAG_LEN]);
+
+        frame
+    }
+
+    pub fn decode(bytes: &'a [u8], secret: &SecretString) -> Result<Self, InvalidFrame> {
+        let (authenticated, tag) = bytes
+            .len()
+            .checked_sub(TAG_LEN)
+            .map(|at| bytes.split_at(at))
+            .ok_or(InvalidFrame::TooShort)?;
+
+        make_mac(secret, authenticated)
+            .verify_truncated_left(tag)
+            .map_err(|_| InvalidFrame::BadTag)?;
+
+        let (rest, version) = authenticated.split_last().ok_or(InvalidFrame::TooShort)?;
+        let ip_len = match version {
+            4 => 4,
+            6 => 16,
+            other => return Err(InvalidFrame::UnknownIpVersion(*other)),
+        };
+        let (rest, ip) = rest
+            .len()
+            .checked_sub(ip_len)
+            .map(|at| rest.split_at(at))
+            .ok_or(InvalidFrame::TooShort)?;
+        let (payload, sequence_and_ports) = rest
+            .len()
+            .checked_sub(8 + 4)
+            .map(|at| rest.split_at(at))
+            .ok_or(InvalidFrame::TooShort)?;
+        let (sequence, ports) = sequence_and_ports.split_at(8);
+
+        let ip = match <[u8; 4]>::try_from(ip) {
+            Ok(ip4) => IpAddr::from(Ipv4Addr::from(ip4)),
+            Err(_) => IpAddr::from(Ipv6Addr::from(
+                <[u8; 16]>::try_from(ip).expect("length checked above"),
+            )),
+        };
+
+        Ok(Self {
+            sequence: u64::from_be_bytes(sequence.try_into().expect("length checked above")),
+            source: SocketAddr::new(ip, u16::from_be_bytes([ports[0], ports[1]])),
+            destination_port: u16::from_be_bytes([ports[2], ports[3]]),
+            payload,
+        })
+    }
+}
+
+/// Tracks which of the recent sequence numbers of a relay we received, similar to the anti-replay window of IPsec.
+///
+/// See <https://www.rfc-editor.org/rfc/rfc4303#section-3.4.3>.
+#[derive(Debug, Default)]
+struct ReplayWindow {
+    /// The highest sequence number we received.
+    highest: Option<u64>,
+    /// Bit `n` is set if we received `highest - n`.
+    received: u64,
+}
+
+impl ReplayWindow {
+    fn accept(&mut self, sequence: u64) -> bool {
+        let Some(highest) = self.highest else {
+            self.highest = Some(sequence);
+            self.received = 1;
+
+            return true;
+        };
+
+        if sequence > highest {
+            let shift = sequence - highest;
+
+            self.received = if shift >= REPLAY_WINDOW_SIZE {
+                0
+            } else {
+                self.received << shift
+            };
+            self.received |= 1;
+            self.highest = Some(sequence);
+
+            return true;
+        }
+
+        let offset = highest - sequence;
+        if offset >= REPLAY_WINDOW_SIZE {
+            return false;
+        }
+
+        let bit = 1u64 << offset;
+        if self.received & bit != 0 {
+            return false;
+        }
+
+        self.received |= bit;
+
+        true
+    }
+}
+
+fn make_mac(secret: &SecretString, data: &[u8]) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.expose_secret().as_bytes())
+        .expect("HMAC accepts keys of any size");
+    mac.update(data);
+
+    mac
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrips_ip4_frame() {
+        let secret = SecretString::from("secret".to_owned());
+        let frame = Frame {
+            sequence: 1,
+            source: "203.0.113.1:50000".parse().unwrap(),
+            destination_port: 60000,
+            payload: b"hello",
+        };
+
+        let bytes = frame.encode(&secret);
+
+        assert_eq!(Frame::decode(&bytes, &secret), Ok(frame));
+    }
+
+    #[test]
+    fn roundtrips_ip6_frame() {
+        let secret = SecretString::from("secret".to_owned());
+        let frame = Frame {
+            sequence: 1,
+            source: "[2001:db8::1]:50000".parse().unwrap(),
+            destination_port: 60000,
+            payload: b"",
+        };
+
+        let bytes = frame.encode(&secret);
+
+        assert_eq!(Frame::decode(&bytes, &secret), Ok(frame));
+    }
+
+    #[test]
+    fn payload_is_at_start_of_frame() {
+        let secret = SecretString::from("secret".to_owned());
+        let frame = Frame {
+            sequence: 1,
+            source: "203.0.113.1:50000".parse().unwrap(),
+            destination_port: 60000,
+            payload: b"hello",
+        };
+
+        assert!(frame.encode(&secret).starts_with(b"hello"));
+    }
+
+    #[test]
+    fn rejects_frame_with_other_secret() {
+        let frame = Frame {
+            sequence: 1,
+            source: "203.0.113.1:50000".parse().unwrap(),
+            destination_port: 60000,
+            payload: b"hello",
+        };
+
+        let bytes = frame.encode(&SecretString::from("secret".to_owned()));
+
+        assert_eq!(
+            Frame::decode(&bytes, &SecretString::from("other".to_owned())),
+            Err(InvalidFrame::BadTag)
+        );
+    }
+
+    #[test]
+    fn rejects_tampered_frame() {
+        let secret = SecretString::from("secret".to_owned());
+        let frame = Frame {
+            sequence: 1,
+            source: "203.0.113.1:50000".parse().unwrap(),
+            destination_port: 60000,
+            payload: b"hello",
+        };
+
+        let mut bytes = frame.encode(&secret);
+        bytes[0] ^= 1;
+
+        assert_eq!(Frame::decode(&bytes, &secret), Err(InvalidFrame::BadTag));
+    }
+
+    #[test]
+    fn rejects_truncated_frame() {
+        let secret = SecretString::from("secret".to_owned());
+
+        assert_eq!(
+            Frame::decode(&[0u8; TAG_LEN - 1], &secret),
+            Err(InvalidFrame::TooShort)
+        );
+    }
+
+    #[test]
+    fn rejects_replayed_sequence() {
+        let mut window = ReplayWindow::default();
+
+        assert!(window.accept(100));
+        assert!(!window.accept(100));
+    }
+
+    #[test]
+    fn accepts_reordered_sequence_within_window() {
+        let mut window = ReplayWindow::default();
+
+        assert!(window.accept(100));
+        assert!(window.accept(102));
+        assert!(window.accept(101));
+        assert!(!window.accept(101));
+    }
+
+    #[test]
+    fn rejects_sequence_older_than_window() {
+        let mut window = ReplayWindow::default();
+
+        assert!(window.accept(100));
+        assert!(window.accept(100 + REPLAY_WINDOW_SIZE));
+        assert!(!window.accept(100));
+        assert!(window.accept(101));
+    }
+
+    #[test]
+    fn sequence_numbers_increase_across_restarts() {
+        let secret = SecretString::from("secret".to_owned());
+        let now = SystemTime::now();
+
+        let mut before = Peering::new(secret.clone(), vec![], now);
+        let mut after = Peering::new(secret, vec![], now + std::time::Duration::from_secs(1));
+
+        assert!(after.next_sequence() > before.next_sequence());
+    }
+
+    #[test]
+    fn parses_relay_peer() {
+        let peer = "203.0.113.1=10.0.0.1:3479".parse::<RelayPeer>().unwrap();
+
+        assert_eq!(
+            peer,
+            RelayPeer {
+                public_ip: "203.0.113.1".parse().unwrap(),
+                link: "10.0.0.1:3479".parse().unwrap(),
+            }
+        );
+    }
+}
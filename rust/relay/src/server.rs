@@ -1,14 +1,24 @@
+mod capture;
 mod channel_data;
 mod client_message;
+mod config;
+mod limits;
 
 pub use crate::server::channel_data::ChannelData;
 pub use crate::server::client_message::{
-    Allocate, Binding, ChannelBind, ClientMessage, CreatePermission, Refresh,
+    Allocate, Binding, ChannelBind, ClientMessage, CreatePermission, Refresh, SendIndication,
 };
+pub use crate::server::config::{PortRange, ServerConfig};
+pub use crate::server::limits::{Limit, Limits, UsernameUsages};
 
-use crate::auth::{MessageIntegrityExt, Nonces, FIREZONE};
+use crate::auth::{
+    AuthScheme, MessageIntegrityExt, NonceLookup, NoncePolicy, NonceStore, Nonces, FIREZONE,
+};
 use crate::net_ext::IpAddrExt;
-use crate::{ClientSocket, IpStack, PeerSocket};
+use crate::peering::{Frame, Peering};
+use crate::server::capture::{Capture, Direction};
+use crate::server::limits::{SharedUsernameUsage, Usage};
+use crate::{ClientSocket, IpStack, PeerSocket, TURN_PORT};
 use anyhow::Result;
 use bytecodec::EncodeExt;
 use core::fmt;
@@ -18,6 +28,7 @@ use rand::Rng;
 use secrecy::SecretString;
 use std::collections::{HashMap, VecDeque};
 use std::hash::Hash;
+use std::iter;
 use std::net::{IpAddr, SocketAddr};
 use std::time::{Duration, Instant, SystemTime};
 use stun_codec::rfc5389::attributes::{
@@ -26,12 +37,14 @@ use stun_codec::rfc5389::attributes::{
 use stun_codec::rfc5389::errors::{BadRequest, StaleNonce, Unauthorized};
 use stun_codec::rfc5389::methods::BINDING;
 use stun_codec::rfc5766::attributes::{
-    ChannelNumber, Lifetime, RequestedTransport, XorPeerAddress, XorRelayAddress,
+    ChannelNumber, Data, Lifetime, RequestedTransport, XorPeerAddress, XorRelayAddress,
+};
+use stun_codec::rfc5766::errors::{
+    AllocationMismatch, AllocationQuotaReached, InsufficientCapacity,
 };
-use stun_codec::rfc5766::errors::{AllocationMismatch, InsufficientCapacity};
-use stun_codec::rfc5766::methods::{ALLOCATE, CHANNEL_BIND, CREATE_PERMISSION, REFRESH};
+use stun_codec::rfc5766::methods::{ALLOCATE, CHANNEL_BIND, CREATE_PERMISSION, DATA, REFRESH};
 use stun_codec::rfc8656::attributes::{
-    AdditionalAddressFamily, AddressFamily, RequestedAddressFamily,
+    AdditionalAddressFamily, AddressFamily, Icmp, RequestedAddressFamily,
 };
 use stun_codec::rfc8656::errors::{AddressFamilyNotSupported, PeerAddressFamilyMismatch};
 use stun_codec::{Message, MessageClass, MessageEncoder, Method, TransactionId};
@@ -41,10 +54,13 @@ use uuid::Uuid;
 
 /// A sans-IO STUN & TURN server.
 ///
-/// A [`Server`] is bound to an IPv4 address and assumes to only operate on UDP.
+/// A [`Server`] is bound to an IPv4 address and can be fed messages from UDP as well as stream-based transports (TCP and TLS).
+/// The transport between server and peers is always UDP.
 /// Thus, 3 out of the 5 components of a "5-tuple" are unique to an instance of [`Server`] and
 /// we can index data simply by the sender's [`SocketAddr`].
 ///
+/// For stream-based transports, it is the caller's responsibility to split the byte-stream into individual messages before handing them to the [`Server`].
+///
 /// Additionally, we assume to have complete ownership over the port range `lowest_port` - `highest_port`.
 pub struct Server<R> {
     decoder: client_message::Decoder,
@@ -61,6 +77,8 @@ pub struct Server<R> {
 
     lowest_port: u16,
     highest_port: u16,
+    /// The number of ports between `lowest_port` and `highest_port` that are not excluded via [`ServerConfig::excluded_ports`].
+    max_available_ports: usize,
 
     /// Channel numbers are unique by client, thus indexed by both.
     channels_by_client_and_number: HashMap<(ClientSocket, ChannelNumber), Channel>,
@@ -72,12 +90,34 @@ pub struct Server<R> {
     rng: R,
 
     auth_secret: SecretString,
-
-    nonces: Nonces,
+    auth_scheme: AuthScheme,
+
+    nonces: Box<dyn NonceStore>,
+    /// Requests waiting for their nonce to be looked up, see [`NonceStore::needs_lookup`].
+    parked_requests: HashMap<Uuid, Vec<(ClientMessage<'static>, ClientSocket)>>,
+
+    peering: Option<Peering>,
+
+    config: ServerConfig,
+    limits: Limits,
+    /// Whether we are draining, i.e. reject new allocations but keep serving existing ones.
+    draining: bool,
+    /// The usage of all usernames, shared with other servers of this relay, see [`Server::with_username_usages`].
+    username_usages: UsernameUsages,
+    /// The usage of all usernames that held an allocation on this server within [`ServerConfig::username_usage_retention`].
+    usage_by_username: HashMap<String, SharedUsernameUsage>,
+    /// Packet captures started via [`Server::start_capture`], indexed by the client of the captured allocation.
+    captures: HashMap<ClientSocket, Capture>,
+    /// Captures that stopped recording but haven't been fetched yet, indexed by the captured allocation.
+    finished_captures: HashMap<AllocationPort, Capture>,
 
     allocations_up_down_counter: UpDownCounter<i64>,
+    allocations_by_family_up_down_counter: UpDownCounter<i64>,
+    channels_up_down_counter: UpDownCounter<i64>,
     data_relayed_counter: Counter<u64>,
     data_relayed: u64, // Keep a separate counter because `Counter` doesn't expose the current value :(
+    data_dropped_counter: Counter<u64>,
+    data_forwarded_counter: Counter<u64>,
     responses_counter: Counter<u64>,
 }
 
@@ -104,6 +144,19 @@ pub enum Command {
         port: AllocationPort,
         family: AddressFamily,
     },
+    /// Relay the given payload to the [`PeerSocket`] from the given [`AllocationPort`].
+    ///
+    /// This is only used for data that arrived in a Send indication.
+    /// Data arriving via a channel is returned directly from [`Server::handle_client_input`] to avoid an allocation.
+    RelayToPeer {
+        payload: Vec<u8>,
+        allocation: AllocationPort,
+        peer: PeerSocket,
+    },
+    /// Forward the given [`Frame`](crate::peering::Frame) to another relay via the relay-to-relay link.
+    ///
+    /// Frames must be sent from the port that other relays send their frames to, see [`Server::handle_relay_forwarded_traffic`].
+    ForwardToRelay { payload: Vec<u8>, link: SocketAddr },
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
@@ -128,15 +181,16 @@ impl fmt::Display for AllocationPort {
 /// See <https://www.rfc-editor.org/rfc/rfc8656#name-requested-transport>.
 const UDP_TRANSPORT: u8 = 17;
 
-/// The duration of a channel binding.
+/// The lifetime of a permission.
 ///
-/// See <https://www.rfc-editor.org/rfc/rfc8656#name-channels-2>.
-const CHANNEL_BINDING_DURATION: Duration = Duration::from_secs(600);
+/// See <https://www.rfc-editor.org/rfc/rfc8656#name-permissions>.
+const PERMISSION_LIFETIME: Duration = Duration::from_secs(300);
 
-/// The timeout before a channel be rebound.
-///
-/// See <https://www.rfc-editor.org/rfc/rfc8656#section-12-14>.
-const CHANNEL_REBIND_TIMEOUT: Duration = Duration::from_secs(300);
+/// How many requests may wait for their nonce to be looked up at the same time.
+const MAX_PARKED_REQUESTS: usize = 1000;
+
+/// How long a capture can still be fetched via [`Server::stop_capture`] after its deadline.
+const FINISHED_CAPTURE_RETENTION: Duration = Duration::from_secs(10 * 60);
 
 impl<R> Server<R>
 where
@@ -164,6 +218,14 @@ where
             .i64_up_down_counter("allocations_total")
             .with_description("The number of active allocations")
             .init();
+        let allocations_by_family_up_down_counter = meter
+            .i64_up_down_counter("allocations_by_family_total")
+            .with_description("The number of active allocations per address family")
+            .init();
+        let channels_up_down_counter = meter
+            .i64_up_down_counter("channels_total")
+            .with_description("The number of active channel bindings")
+            .init();
         let responses_counter = meter
             .u64_counter("responses_total")
             .with_description("The number of responses")
@@ -173,6 +235,16 @@ where
             .with_description("The number of bytes relayed")
             .with_unit(Unit::new("b"))
             .init();
+        let data_dropped_counter = meter
+            .u64_counter("data_dropped_bytes")
+            .with_description("The number of bytes dropped because they exceeded a limit")
+            .with_unit(Unit::new("b"))
+            .init();
+        let data_forwarded_counter = meter
+            .u64_counter("data_forwarded_bytes")
+            .with_description("The number of bytes forwarded to and from other relays")
+            .with_unit(Unit::new("b"))
+            .init();
 
         Self {
             decoder: Default::default(),
@@ -182,29 +254,132 @@ where
             clients_by_allocation: Default::default(),
             lowest_port,
             highest_port,
+            max_available_ports: usize::from(highest_port - lowest_port),
             channels_by_client_and_number: Default::default(),
             channel_numbers_by_client_and_peer: Default::default(),
             pending_commands: Default::default(),
             auth_secret: SecretString::from(hex::encode(rng.gen::<[u8; 32]>())),
+            auth_scheme: AuthScheme::default(),
             rng,
-            nonces: Default::default(),
+            nonces: Box::new(Nonces::default()),
+            parked_requests: Default::default(),
             allocations_up_down_counter,
+            allocations_by_family_up_down_counter,
+            channels_up_down_counter,
             responses_counter,
             data_relayed_counter,
             data_relayed: 0,
+            data_dropped_counter,
+            data_forwarded_counter,
+            peering: None,
+            config: ServerConfig::default(),
             channel_and_client_by_port_and_peer: Default::default(),
+            limits: Limits::default(),
+            draining: false,
+            username_usages: Default::default(),
+            usage_by_username: Default::default(),
+            captures: Default::default(),
+            finished_captures: Default::default(),
         }
     }
 
+    /// Configures how clients are authenticated.
+    ///
+    /// By default, we use [`AuthScheme::Firezone`].
+    pub fn with_auth_scheme(mut self, auth_scheme: AuthScheme) -> Self {
+        self.auth_scheme = auth_scheme;
+
+        self
+    }
+
+    /// Configures the [`NonceStore`] to use.
+    ///
+    /// By default, nonces are kept in memory.
+    pub fn with_nonce_store(mut self, nonces: impl NonceStore + 'static) -> Self {
+        self.nonces = Box::new(nonces);
+
+        self
+    }
+
+    /// Configures forwarding of data to allocations on other relays, see [`Peering`].
+    ///
+    /// By default, data for other relays is sent to their allocations directly.
+    pub fn with_peering(mut self, peering: Peering) -> Self {
+        self.peering = Some(peering);
+
+        self
+    }
+
+    /// Configures the policies of this server, see [`ServerConfig`].
+    ///
+    /// By default, we follow the recommendations of the TURN RFC.
+    /// Lifetimes only apply to allocations, channels and nonces created after this call.
+    pub fn with_config(mut self, config: ServerConfig) -> Self {
+        self.max_available_ports = (self.lowest_port..self.highest_port)
+            .filter(|port| !config.is_excluded(*port))
+            .count();
+        self.config = config;
+
+        self
+    }
+
+    /// Configures the [`Limits`] for relayed data.
+    ///
+    /// By default, there are no limits.
+    /// Limits only apply to allocations created after this call.
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+
+        self
+    }
+
+    /// Shares the usage of usernames with other servers, e.g. the other workers of the same relay.
+    ///
+    /// By default, [`Limits::per_username`] and [`ServerConfig::max_allocations_per_username`] only apply to the allocations of this server.
+    /// Must be called before any allocations are created.
+    pub fn with_username_usages(mut self, username_usages: UsernameUsages) -> Self {
+        self.username_usages = username_usages;
+
+        self
+    }
+
+    /// Overrides the secret used to verify [`AuthScheme::Firezone`] credentials.
+    ///
+    /// By default, a random secret is generated on startup.
+    /// Multiple [`Server`]s serving the same clients need to share the same secret.
+    pub fn with_auth_secret(mut self, auth_secret: SecretString) -> Self {
+        self.auth_secret = auth_secret;
+
+        self
+    }
+
     pub fn auth_secret(&self) -> &SecretString {
         &self.auth_secret
     }
 
     /// Registers a new, valid nonce.
     ///
-    /// Each nonce is valid for 10 requests.
-    pub fn add_nonce(&mut self, nonce: Uuid) {
-        self.nonces.add_new(nonce);
+    /// Each nonce is valid for [`ServerConfig::nonce_num_requests`] requests within [`ServerConfig::nonce_lifetime`].
+    pub fn add_nonce(&mut self, nonce: Uuid, now: Instant) {
+        self.nonces.add_new(
+            nonce,
+            NoncePolicy {
+                num_requests: self.config.nonce_num_requests,
+                lifetime: self.config.nonce_lifetime,
+            },
+            now,
+        );
+    }
+
+    /// Handles the result of looking up a nonce in a shared [`NonceStore`] and processes all requests that were waiting for it.
+    pub fn handle_nonce_lookup(&mut self, lookup: NonceLookup, now: Instant) {
+        let nonce = lookup.nonce;
+
+        self.nonces.handle_lookup(lookup, now);
+
+        for (message, sender) in self.parked_requests.remove(&nonce).unwrap_or_default() {
+            self.handle_client_message(message, sender, now);
+        }
     }
 
     pub fn num_relayed_bytes(&self) -> u64 {
@@ -219,6 +394,125 @@ where
         self.channels_by_client_and_number.len()
     }
 
+    /// Starts draining this server.
+    ///
+    /// A draining server rejects all new allocations with `508 Insufficient Capacity`, prompting clients to use a different relay.
+    /// Existing allocations and channel bindings are served as usual until they expire.
+    /// Draining cannot be stopped.
+    pub fn start_draining(&mut self) {
+        if self.draining {
+            return;
+        }
+
+        tracing::info!(target: "relay", active_allocations = %self.allocations.len(), "Draining server, new allocations will be rejected");
+
+        self.draining = true;
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.draining
+    }
+
+    /// Returns a snapshot of all active allocations.
+    pub fn allocations(&self) -> Vec<AllocationInfo> {
+        let mut channels_by_client = HashMap::<ClientSocket, Vec<ChannelInfo>>::new();
+
+        for ((client, number), channel) in &self.channels_by_client_and_number {
+            channels_by_client
+                .entry(*client)
+                .or_default()
+                .push(ChannelInfo {
+                    number: number.value(),
+                    peer: channel.peer_address,
+                    bound: channel.bound,
+                    expires_at: channel.expiry,
+                });
+        }
+
+        self.allocations
+            .iter()
+            .map(|(client, allocation)| AllocationInfo {
+                client: *client,
+                username: allocation.username.clone(),
+                port: allocation.port,
+                relay_addresses: iter::once(allocation.first_relay_addr)
+                    .chain(allocation.second_relay_addr)
+                    .collect(),
+                channels: channels_by_client.remove(client).unwrap_or_default(),
+                bytes_relayed: allocation.usage.bytes_relayed(),
+                expires_at: allocation.expires_at,
+            })
+            .collect()
+    }
+
+    /// Forcibly deletes the allocation on the given port, regardless of its lifetime.
+    ///
+    /// Returns `false` if there is no such allocation.
+    pub fn force_delete_allocation(&mut self, port: AllocationPort) -> bool {
+        if !self.clients_by_allocation.contains_key(&port) {
+            return false;
+        }
+
+        tracing::info!(target: "relay", %port, "Forcibly deleting allocation");
+
+        self.delete_allocation(port);
+
+        true
+    }
+
+    /// Starts capturing all traffic of the allocation on the given port, replacing any previous capture of it.
+    ///
+    /// This includes the STUN and channel-data messages exchanged with the client as well as the data exchanged with peers.
+    /// The capture stops recording after `duration`, once it reached `max_bytes` or when the allocation is deleted, whichever comes first.
+    /// `now_unix` is the wall-clock time corresponding to `now` and is used for the timestamps of the captured packets.
+    ///
+    /// Returns `false` if there is no such allocation.
+    pub fn start_capture(
+        &mut self,
+        port: AllocationPort,
+        duration: Duration,
+        max_bytes: usize,
+        now: Instant,
+        now_unix: SystemTime,
+    ) -> bool {
+        let Some(client) = self.clients_by_allocation.get(&port).copied() else {
+            return false;
+        };
+
+        tracing::info!(target: "relay", %port, %client, ?duration, %max_bytes, "Starting packet capture");
+
+        self.captures.retain(|_, capture| capture.port() != port);
+        self.finished_captures.remove(&port);
+        self.captures.insert(
+            client,
+            Capture::new(port, duration, max_bytes, now, now_unix),
+        );
+
+        true
+    }
+
+    /// Stops the capture started for the allocation on the given port and returns it as a pcapng file.
+    ///
+    /// Captures that stopped recording are kept until [`FINISHED_CAPTURE_RETENTION`] after their deadline, even if their allocation was deleted earlier.
+    /// Returns `None` if there is no capture for this port.
+    pub fn stop_capture(&mut self, port: AllocationPort) -> Option<Vec<u8>> {
+        if let Some(capture) = self.finished_captures.remove(&port) {
+            tracing::info!(target: "relay", %port, "Fetched finished packet capture");
+
+            return Some(capture.into_pcapng());
+        }
+
+        let client = self
+            .captures
+            .iter()
+            .find_map(|(client, capture)| (capture.port() == port).then_some(*client))?;
+        let capture = self.captures.remove(&client)?;
+
+        tracing::info!(target: "relay", %port, %client, "Stopped packet capture");
+
+        Some(capture.into_pcapng())
+    }
+
     /// Process the bytes received from a client.
     ///
     /// # Returns
@@ -234,6 +528,8 @@ where
     ) -> Option<(AllocationPort, PeerSocket)> {
         tracing::trace!(target: "wire", num_bytes = %bytes.len());
 
+        self.capture_client_traffic(sender, Direction::Inbound, bytes, now);
+
         match self.decoder.decode(bytes) {
             Ok(Ok(message)) => {
                 if let Some(id) = message.transaction_id() {
@@ -244,7 +540,7 @@ where
             }
             // Could parse the bytes but message was semantically invalid (like missing attribute).
             Ok(Err(error_code)) => {
-                self.queue_error_response(sender, error_code);
+                self.queue_error_response(sender, error_code, now);
             }
             // Parsing the bytes failed.
             Err(client_message::Error::BadChannelData(ref error)) => {
@@ -256,6 +552,9 @@ where
             Err(client_message::Error::UnknownMessageType(t)) => {
                 tracing::debug!(target: "relay", r#type = %t, "unknown STUN message type")
             }
+            Err(client_message::Error::InvalidIndication(reason)) => {
+                tracing::debug!(target: "relay", %reason, "discarding invalid indication")
+            }
             Err(client_message::Error::Eof) => {
                 tracing::debug!(target: "relay", "unexpected EOF while parsing message")
             }
@@ -270,6 +569,8 @@ where
         sender: ClientSocket,
         now: Instant,
     ) -> Option<(AllocationPort, PeerSocket)> {
+        let message = self.park_until_nonce_is_known(message, sender)?;
+
         let result = match message {
             ClientMessage::Allocate(request) => self.handle_allocate_request(request, sender, now),
             ClientMessage::Refresh(request) => self.handle_refresh_request(request, sender, now),
@@ -277,14 +578,18 @@ where
                 self.handle_channel_bind_request(request, sender, now)
             }
             ClientMessage::CreatePermission(request) => {
-                self.handle_create_permission_request(request, sender)
+                self.handle_create_permission_request(request, sender, now)
+            }
+            ClientMessage::SendIndication(indication) => {
+                self.handle_send_indication(indication, sender, now);
+                return None;
             }
             ClientMessage::Binding(request) => {
-                self.handle_binding_request(request, sender);
+                self.handle_binding_request(request, sender, now);
                 return None;
             }
             ClientMessage::ChannelData(msg) => {
-                return self.handle_channel_data_message(msg, sender);
+                return self.handle_channel_data_message(msg, sender, now);
             }
         };
 
@@ -292,7 +597,59 @@ where
             return None;
         };
 
-        self.queue_error_response(sender, error_response);
+        self.queue_error_response(sender, error_response, now);
+
+        None
+    }
+
+    /// Parks authenticated requests whose nonce has to be looked up first, see [`Server::handle_nonce_lookup`].
+    ///
+    /// Returns the message if it can be processed right away.
+    fn park_until_nonce_is_known<'a>(
+        &mut self,
+        message: ClientMessage<'a>,
+        sender: ClientSocket,
+    ) -> Option<ClientMessage<'a>> {
+        let (nonce, message) = match message {
+            ClientMessage::Allocate(request) => {
+                (parse_nonce(&request), ClientMessage::Allocate(request))
+            }
+            ClientMessage::Refresh(request) => {
+                (parse_nonce(&request), ClientMessage::Refresh(request))
+            }
+            ClientMessage::ChannelBind(request) => {
+                (parse_nonce(&request), ClientMessage::ChannelBind(request))
+            }
+            ClientMessage::CreatePermission(request) => (
+                parse_nonce(&request),
+                ClientMessage::CreatePermission(request),
+            ),
+            other @ (ClientMessage::ChannelData(_)
+            | ClientMessage::Binding(_)
+            | ClientMessage::SendIndication(_)) => return Some(other),
+        };
+
+        let Some(nonce) = nonce else {
+            return Some(message);
+        };
+
+        if !self.nonces.needs_lookup(nonce) {
+            return Some(message);
+        }
+
+        let num_parked = self.parked_requests.values().map(Vec::len).sum::<usize>();
+        if num_parked >= MAX_PARKED_REQUESTS {
+            tracing::debug!(target: "relay", %nonce, "Too many requests waiting for their nonce, dropping request");
+
+            return None;
+        }
+
+        tracing::debug!(target: "relay", %nonce, "Waiting for nonce to be looked up");
+
+        self.parked_requests
+            .entry(nonce)
+            .or_default()
+            .push((message, sender));
 
         None
     }
@@ -301,6 +658,7 @@ where
         &mut self,
         sender: ClientSocket,
         mut error_response: Message<Attribute>,
+        now: Instant,
     ) {
         let Some(error) = error_response.get_attribute::<ErrorCode>().cloned() else {
             debug_assert!(false, "Error response without an `ErrorCode`");
@@ -311,7 +669,7 @@ where
         if error == ErrorCode::from(Unauthorized) || error == ErrorCode::from(StaleNonce) {
             let new_nonce = Uuid::from_u128(self.rng.gen());
 
-            self.add_nonce(new_nonce);
+            self.add_nonce(new_nonce, now);
 
             error_response.add_attribute(Nonce::new(new_nonce.to_string()).unwrap());
             error_response.add_attribute((*FIREZONE).clone());
@@ -321,11 +679,13 @@ where
             tracing::warn!(target: "relay", "{} failed: {}", error_response.method(), error.reason_phrase());
         }
 
-        self.send_message(error_response, sender);
+        self.send_message(error_response, sender, now);
     }
 
     /// Process the bytes received from an allocation.
     ///
+    /// If there is no channel but a permission for this peer, the data is wrapped in a Data indication and queued as a [`Command::SendMessage`].
+    ///
     /// # Returns
     ///
     /// - [`Some`] if there is an active channel on this allocation for this peer.
@@ -336,24 +696,146 @@ where
         msg: &[u8],
         sender: PeerSocket,
         allocation: AllocationPort,
+        now: Instant,
     ) -> Option<(ClientSocket, ChannelNumber)> {
+        self.capture_peer_traffic(allocation, sender, Direction::Inbound, msg, now);
+
         let Some((client, channel_number)) = self
             .channel_and_client_by_port_and_peer
             .get(&(allocation, sender))
+            .copied()
         else {
-            tracing::debug!(target: "relay", "no channel");
+            self.relay_as_data_indication(msg, sender, allocation, now);
 
             return None;
         };
 
         Span::current().record("recipient", field::display(&client));
 
+        if !self.check_and_record_limits(client, msg.len(), now) {
+            return None;
+        }
+
         self.data_relayed_counter.add(msg.len() as u64, &[]);
         self.data_relayed += msg.len() as u64;
 
         tracing::trace!(target: "wire", num_bytes = %msg.len());
 
-        Some((*client, *channel_number))
+        if self.captures.contains_key(&client) {
+            let mut channel_data = vec![0; 4 + msg.len()];
+            ChannelData::encode_header_to_slice(
+                channel_number,
+                msg.len() as u16,
+                &mut channel_data[..4],
+            );
+            channel_data[4..].copy_from_slice(msg);
+
+            self.capture_client_traffic(client, Direction::Outbound, &channel_data, now);
+        }
+
+        Some((client, channel_number))
+    }
+
+    /// Process a [`Frame`] received on the relay-to-relay link from `sender`.
+    ///
+    /// Frames must come from one of the relays we peer with and are authenticated with the shared secret of our [`Peering`].
+    /// The contained payload is then handled as if the sending relay's allocation sent it to our allocation directly, see [`Server::handle_peer_traffic`].
+    ///
+    /// # Returns
+    ///
+    /// - [`Some`] if there is an active channel on our allocation for the sending relay's allocation.
+    ///   The payload is at the start of `frame` and has the returned length, allowing the caller to prepend a [`ChannelData`] header without copying.
+    #[tracing::instrument(level = "debug", skip_all, fields(%sender))]
+    pub fn handle_relay_forwarded_traffic(
+        &mut self,
+        frame: &[u8],
+        sender: SocketAddr,
+        now: Instant,
+    ) -> Option<(ClientSocket, ChannelNumber, usize)> {
+        let Some(peering) = self.peering.as_mut() else {
+            tracing::debug!(target: "relay", "Peering is not enabled, dropping frame");
+            return None;
+        };
+
+        if !peering.is_link(sender) {
+            tracing::debug!(target: "relay", "Frame is not from a relay we peer with");
+            return None;
+        }
+
+        let frame = match Frame::decode(frame, peering.secret()) {
+            Ok(frame) => frame,
+            Err(e) => {
+                tracing::debug!(target: "relay", "Dropping invalid frame: {e:?}");
+                return None;
+            }
+        };
+
+        if !peering.accept_sequence(sender, frame.sequence) {
+            tracing::debug!(target: "relay", sequence = %frame.sequence, "Dropping replayed frame");
+            return None;
+        }
+
+        self.data_forwarded_counter.add(
+            frame.payload.len() as u64,
+            &[KeyValue::new("direction", "inbound")],
+        );
+
+        let (client, channel) = self.handle_peer_traffic(
+            frame.payload,
+            PeerSocket(frame.source),
+            AllocationPort(frame.destination_port),
+            now,
+        )?;
+
+        Some((client, channel, frame.payload.len()))
+    }
+
+    /// Process an ICMP error received on an allocation in response to data we relayed to `peer`.
+    ///
+    /// If the client has a permission for this peer, the error is queued as a Data indication with an ICMP attribute, see <https://www.rfc-editor.org/rfc/rfc8656#section-11.7>.
+    /// This allows the client to learn about unreachable peers without waiting for a timeout.
+    #[tracing::instrument(level = "debug", skip(self), fields(%peer, %allocation, recipient))]
+    pub fn handle_peer_icmp_error(
+        &mut self,
+        error: IcmpError,
+        peer: PeerSocket,
+        allocation: AllocationPort,
+        now: Instant,
+    ) {
+        if !error.is_forwarded(peer.family()) {
+            tracing::debug!(target: "relay", "ICMP error is not forwarded");
+            return;
+        }
+
+        let Some(client) = self.clients_by_allocation.get(&allocation).copied() else {
+            tracing::debug!(target: "relay", "no allocation");
+            return;
+        };
+        let Some(allocation) = self.allocations.get(&client) else {
+            debug_assert!(false, "internal state mismatch");
+            return;
+        };
+
+        if !allocation.can_relay_to(peer, now) {
+            tracing::debug!(target: "relay", "no channel and no permission");
+            return;
+        }
+
+        Span::current().record("recipient", field::display(&client));
+
+        let mut message = Message::new(
+            MessageClass::Indication,
+            DATA,
+            TransactionId::new(self.rng.gen()),
+        );
+        message.add_attribute(XorPeerAddress::new(peer.0));
+        message.add_attribute(Icmp::new(
+            error.icmp_type,
+            error.icmp_code,
+            error.error_data,
+        ));
+
+        self.send_message(message, client, now);
     }
 
     /// An allocation failed.
@@ -362,6 +844,19 @@ where
         self.delete_allocation(allocation)
     }
 
+    /// The stream-based connection (TCP or TLS) of a client closed.
+    ///
+    /// An allocation is bound to the 5-tuple of the connection it was created on.
+    /// Once that connection is gone, the allocation can never be used again so we delete it right away.
+    #[tracing::instrument(level = "debug", skip(self), fields(%client))]
+    pub fn handle_client_connection_closed(&mut self, client: ClientSocket) {
+        let Some(port) = self.allocations.get(&client).map(|a| a.port) else {
+            return;
+        };
+
+        self.delete_allocation(port)
+    }
+
     /// Return the next command to be executed.
     pub fn next_command(&mut self) -> Option<Command> {
         self.pending_commands.pop_front()
@@ -373,13 +868,26 @@ where
             if c.bound {
                 c.expiry
             } else {
-                c.expiry + CHANNEL_REBIND_TIMEOUT
+                c.expiry + self.config.channel_rebind_timeout
             }
         });
         let allocation_expiries = self.allocations.values().map(|a| a.expires_at);
+        let username_usage_expiries = self.usage_by_username.values().filter_map(|u| {
+            let u = u.lock();
+
+            (u.num_allocations == 0).then_some(u.expires_at)
+        });
+        let capture_deadlines = self.captures.values().map(|c| c.deadline());
+        let finished_capture_expiries = self
+            .finished_captures
+            .values()
+            .map(|c| c.deadline() + FINISHED_CAPTURE_RETENTION);
 
         channel_expiries
             .chain(allocation_expiries)
+            .chain(username_usage_expiries)
+            .chain(capture_deadlines)
+            .chain(finished_capture_expiries)
             .fold(None, |current, next| earliest(current, Some(next)))
     }
 
@@ -394,6 +902,29 @@ where
             self.delete_allocation(id);
         }
 
+        for allocation in self.allocations.values_mut() {
+            allocation.remove_expired_permissions(now);
+        }
+
+        self.usage_by_username.retain(|_, u| {
+            let u = u.lock();
+
+            u.num_allocations > 0 || now < u.expires_at
+        });
+
+        let finished_captures = self
+            .captures
+            .iter()
+            .filter_map(|(client, c)| c.is_finished(now).then_some(*client))
+            .collect::<Vec<_>>();
+
+        for client in finished_captures {
+            self.finish_capture(client);
+        }
+
+        self.finished_captures
+            .retain(|_, c| now < c.deadline() + FINISHED_CAPTURE_RETENTION);
+
         for ((client, number), channel) in self
             .channels_by_client_and_number
             .iter_mut()
@@ -409,7 +940,10 @@ where
         let channels_to_delete = self
             .channels_by_client_and_number
             .iter()
-            .filter_map(|(id, c)| c.can_be_deleted(now).then_some(*id))
+            .filter_map(|(id, c)| {
+                c.can_be_deleted(now, self.config.channel_rebind_timeout)
+                    .then_some(*id)
+            })
             .collect::<Vec<_>>();
 
         for (client_socket, number) in channels_to_delete {
@@ -417,7 +951,7 @@ where
         }
     }
 
-    fn handle_binding_request(&mut self, message: Binding, sender: ClientSocket) {
+    fn handle_binding_request(&mut self, message: Binding, sender: ClientSocket, now: Instant) {
         let mut message = Message::new(
             MessageClass::SuccessResponse,
             BINDING,
@@ -425,7 +959,7 @@ where
         );
         message.add_attribute(XorMappedAddress::new(sender.0));
 
-        self.send_message(message, sender);
+        self.send_message(message, sender, now);
     }
 
     /// Handle a TURN allocate request.
@@ -437,7 +971,7 @@ where
         sender: ClientSocket,
         now: Instant,
     ) -> Result<(), Message<Attribute>> {
-        self.verify_auth(&request)?;
+        self.verify_auth(&request, now)?;
 
         if let Some(allocation) = self.allocations.get(&sender) {
             Span::current().record("allocation", display(&allocation.port));
@@ -446,8 +980,43 @@ where
             return Err(error_response(AllocationMismatch, &request));
         }
 
-        let max_available_ports = self.max_available_ports() as usize;
-        if self.clients_by_allocation.len() == max_available_ports {
+        if self.draining {
+            tracing::debug!(target: "relay", "Rejecting allocation because we are draining");
+
+            return Err(error_response(InsufficientCapacity, &request));
+        }
+
+        let username = request
+            .username()
+            .map(|u| u.name().to_owned())
+            .unwrap_or_default(); // `verify_auth` ensures we have a username.
+
+        // Other servers may have allocations of this username, hence we check the shared usage.
+        let username_usage = self.username_usages.get(&username);
+
+        if username_usage
+            .as_ref()
+            .is_some_and(|u| u.lock().usage.is_quota_exhausted())
+        {
+            tracing::warn!(target: "relay", %username, "Username has exhausted its quota");
+
+            return Err(error_response(AllocationQuotaReached, &request));
+        }
+
+        if let Some(max_allocations) = self.config.max_allocations_per_username {
+            let num_allocations = username_usage
+                .as_ref()
+                .map_or(0, |u| u.lock().num_allocations);
+
+            if num_allocations >= max_allocations {
+                tracing::warn!(target: "relay", %username, %max_allocations, "Username has too many allocations");
+
+                return Err(error_response(AllocationQuotaReached, &request));
+            }
+        }
+
+        let max_available_ports = self.max_available_ports;
+        if self.clients_by_allocation.len() >= max_available_ports {
             tracing::warn!(target: "relay", %max_available_ports, "No more ports available");
 
             return Err(error_response(InsufficientCapacity, &request));
@@ -469,13 +1038,14 @@ where
 
         // TODO: Do we need to handle DONT-FRAGMENT?
         // TODO: Do we need to handle EVEN/ODD-PORT?
-        let effective_lifetime = request.effective_lifetime();
+        let effective_lifetime = request.effective_lifetime(&self.config);
 
         let allocation = self.create_new_allocation(
             now,
             &effective_lifetime,
             first_relay_address,
             maybe_second_relay_addr,
+            username,
         );
 
         let mut message = Message::new(
@@ -510,7 +1080,7 @@ where
                 family: second_relay_addr.family(),
             });
         }
-        self.send_message(message, sender);
+        self.send_message(message, sender, now);
 
         Span::current().record("allocation", display(&allocation.port));
 
@@ -531,9 +1101,30 @@ where
             )
         }
 
+        let mut username_usage = self
+            .usage_by_username
+            .entry(allocation.username.clone())
+            .or_insert_with(|| {
+                self.username_usages.get_or_insert(
+                    &allocation.username,
+                    self.limits.per_username,
+                    now,
+                )
+            })
+            .lock();
+        username_usage.num_allocations += 1;
+        username_usage.retain_until(allocation.expires_at + self.config.username_usage_retention);
+        drop(username_usage);
+
         self.clients_by_allocation.insert(allocation.port, sender);
-        self.allocations.insert(sender, allocation);
         self.allocations_up_down_counter.add(1, &[]);
+        self.allocations_by_family_up_down_counter
+            .add(1, &[family_attribute(allocation.first_relay_addr)]);
+        if let Some(second_relay_addr) = allocation.second_relay_addr {
+            self.allocations_by_family_up_down_counter
+                .add(1, &[family_attribute(second_relay_addr)]);
+        }
+        self.allocations.insert(sender, allocation);
 
         Ok(())
     }
@@ -547,7 +1138,7 @@ where
         sender: ClientSocket,
         now: Instant,
     ) -> Result<(), Message<Attribute>> {
-        self.verify_auth(&request)?;
+        self.verify_auth(&request, now)?;
 
         // TODO: Verify that this is the correct error code.
         let allocation = self
@@ -557,7 +1148,7 @@ where
 
         Span::current().record("allocation", display(&allocation.port));
 
-        let effective_lifetime = request.effective_lifetime();
+        let effective_lifetime = request.effective_lifetime(&self.config);
 
         if effective_lifetime.lifetime().is_zero() {
             let port = allocation.port;
@@ -566,13 +1157,35 @@ where
             self.send_message(
                 refresh_success_response(effective_lifetime, request.transaction_id()),
                 sender,
+                now,
             );
 
             return Ok(());
         }
 
+        let mut username_usage = self
+            .usage_by_username
+            .get(&allocation.username)
+            .map(|u| u.lock());
+
+        if allocation.usage.is_quota_exhausted()
+            || username_usage
+                .as_ref()
+                .is_some_and(|u| u.usage.is_quota_exhausted())
+        {
+            tracing::warn!(target: "relay", username = %allocation.username, "Refusing to refresh allocation that has exhausted its quota");
+
+            return Err(error_response(AllocationQuotaReached, &request));
+        }
+
         allocation.expires_at = now + effective_lifetime.lifetime();
 
+        if let Some(username_usage) = username_usage.as_deref_mut() {
+            username_usage
+                .retain_until(allocation.expires_at + self.config.username_usage_retention);
+        }
+        drop(username_usage);
+
         tracing::info!(
             target: "relay",
             port = %allocation.port,
@@ -582,6 +1195,7 @@ where
         self.send_message(
             refresh_success_response(effective_lifetime, request.transaction_id()),
             sender,
+            now,
         );
 
         Ok(())
@@ -596,7 +1210,7 @@ where
         sender: ClientSocket,
         now: Instant,
     ) -> Result<(), Message<Attribute>> {
-        self.verify_auth(&request)?;
+        self.verify_auth(&request, now)?;
 
         let allocation = self
             .allocations
@@ -612,7 +1226,7 @@ where
         Span::current().record("channel", display(&requested_channel.value()));
 
         // Check that our allocation can handle the requested peer addr.
-        if !allocation.can_relay_to(peer_address) {
+        if !allocation.supports_family_of(peer_address) {
             tracing::warn!(target: "relay", "Allocation cannot relay to peer");
 
             return Err(error_response(PeerAddressFamilyMismatch, &request));
@@ -643,13 +1257,15 @@ where
 
             // Binding requests for existing channels act as a refresh for the binding.
 
-            channel.refresh(now);
+            channel.refresh(now, self.config.channel_binding_duration);
+            allocation.add_permission(peer_address, now);
 
             tracing::info!(target: "relay", "Refreshed channel binding");
 
             self.send_message(
                 channel_bind_success_response(request.transaction_id()),
                 sender,
+                now,
             );
 
             return Ok(());
@@ -658,13 +1274,30 @@ where
         // Channel binding does not exist yet, create it.
 
         // TODO: Any additional validations would go here.
-        // TODO: Capacity checking would go here.
+
+        if let Some(max_channels) = self.config.max_channels_per_allocation {
+            // Unbound channels count too because their number and peer are blocked until they can be rebound.
+            let num_channels = self
+                .channels_by_client_and_number
+                .keys()
+                .filter(|(client, _)| *client == sender)
+                .count();
+
+            if num_channels >= max_channels {
+                tracing::warn!(target: "relay", %max_channels, "Allocation has too many channels");
+
+                return Err(error_response(InsufficientCapacity, &request));
+            }
+        }
+
+        allocation.add_permission(peer_address, now);
 
         let port = allocation.port;
         self.create_channel_binding(sender, requested_channel, peer_address, port, now);
         self.send_message(
             channel_bind_success_response(request.transaction_id()),
             sender,
+            now,
         );
 
         tracing::info!(target: "relay", "Successfully bound channel");
@@ -675,29 +1308,155 @@ where
     /// Handle a TURN create permission request.
     ///
     /// See <https://www.rfc-editor.org/rfc/rfc8656#name-receiving-a-createpermissio> for details.
-    ///
-    /// This TURN server implementation does not support relaying data other than through channels.
-    /// Thus, creating a permission is a no-op that always succeeds.
     #[tracing::instrument(level = "debug", skip_all, fields(%sender))]
     fn handle_create_permission_request(
         &mut self,
         message: CreatePermission,
         sender: ClientSocket,
+        now: Instant,
     ) -> Result<(), Message<Attribute>> {
-        self.verify_auth(&message)?;
+        self.verify_auth(&message, now)?;
+
+        let allocation = self
+            .allocations
+            .get_mut(&sender)
+            .ok_or(error_response(AllocationMismatch, &message))?;
+
+        let peers = message
+            .xor_peer_addresses()
+            .iter()
+            .map(|a| PeerSocket(a.address()))
+            .collect::<Vec<_>>();
+
+        // The request is atomic: Either all permissions are installed or none.
+        if let Some(peer) = peers.iter().find(|p| !allocation.supports_family_of(**p)) {
+            tracing::warn!(target: "relay", %peer, "Allocation cannot relay to peer");
+
+            return Err(error_response(PeerAddressFamilyMismatch, &message));
+        }
+
+        for peer in peers {
+            allocation.add_permission(peer, now);
+
+            tracing::info!(target: "relay", allocation = %allocation.port, peer = %peer.0.ip(), "Installed permission");
+        }
 
         self.send_message(
             create_permission_success_response(message.transaction_id()),
             sender,
+            now,
         );
 
         Ok(())
     }
 
+    /// Handle a TURN send indication.
+    ///
+    /// Indications never generate a response, so all failures simply discard the data.
+    ///
+    /// See <https://www.rfc-editor.org/rfc/rfc8656#name-receiving-a-send-indication> for details.
+    fn handle_send_indication(
+        &mut self,
+        indication: SendIndication,
+        sender: ClientSocket,
+        now: Instant,
+    ) {
+        let Some(allocation) = self.allocations.get(&sender) else {
+            tracing::debug!(target: "relay", "Client has no allocation, discarding send indication");
+            return;
+        };
+
+        let peer = PeerSocket(indication.xor_peer_address().address());
+        let port = allocation.port;
+
+        Span::current().record("allocation", field::display(&port));
+        Span::current().record("recipient", field::display(&peer));
+
+        if !allocation.can_relay_to(peer, now) {
+            tracing::debug!(target: "relay", "No permission for peer, discarding send indication");
+            return;
+        }
+
+        let data = indication.data();
+
+        if !self.check_and_record_limits(sender, data.len(), now) {
+            return;
+        }
+
+        tracing::trace!(target: "wire", num_bytes = %data.len());
+
+        self.data_relayed_counter.add(data.len() as u64, &[]);
+        self.data_relayed += data.len() as u64;
+
+        self.capture_peer_traffic(port, peer, Direction::Outbound, data, now);
+
+        if self.try_forward_to_relay(data, port, peer) {
+            return;
+        }
+
+        self.pending_commands.push_back(Command::RelayToPeer {
+            payload: data.to_vec(),
+            allocation: port,
+            peer,
+        });
+    }
+
+    /// Wraps data from a peer in a Data indication if the allocation has a permission for the peer.
+    ///
+    /// See <https://www.rfc-editor.org/rfc/rfc8656#name-receiving-data-on-an-alloca> for details.
+    fn relay_as_data_indication(
+        &mut self,
+        msg: &[u8],
+        sender: PeerSocket,
+        allocation: AllocationPort,
+        now: Instant,
+    ) {
+        let Some(client) = self.clients_by_allocation.get(&allocation).copied() else {
+            tracing::debug!(target: "relay", "no allocation");
+            return;
+        };
+        let Some(allocation) = self.allocations.get(&client) else {
+            debug_assert!(false, "internal state mismatch");
+            return;
+        };
+
+        if !allocation.can_relay_to(sender, now) {
+            tracing::debug!(target: "relay", "no channel and no permission");
+            return;
+        }
+
+        if !self.check_and_record_limits(client, msg.len(), now) {
+            return;
+        }
+
+        let Ok(data) = Data::new(msg.to_vec()) else {
+            tracing::debug!(target: "relay", num_bytes = %msg.len(), "Data too large for a Data indication");
+            return;
+        };
+
+        Span::current().record("recipient", field::display(&client));
+
+        let mut message = Message::new(
+            MessageClass::Indication,
+            DATA,
+            TransactionId::new(self.rng.gen()),
+        );
+        message.add_attribute(XorPeerAddress::new(sender.0));
+        message.add_attribute(data);
+
+        self.data_relayed_counter.add(msg.len() as u64, &[]);
+        self.data_relayed += msg.len() as u64;
+
+        tracing::trace!(target: "wire", num_bytes = %msg.len());
+
+        self.send_message(message, client, now);
+    }
+
     fn handle_channel_data_message(
         &mut self,
         message: ChannelData,
         sender: ClientSocket,
+        now: Instant,
     ) -> Option<(AllocationPort, PeerSocket)> {
         let channel_number = message.channel();
         let data = message.data();
@@ -718,21 +1477,173 @@ where
             return None;
         }
 
-        Span::current().record("allocation", field::display(&channel.allocation));
-        Span::current().record("recipient", field::display(&channel.peer_address));
+        let allocation = channel.allocation;
+        let peer = channel.peer_address;
+
+        Span::current().record("allocation", field::display(&allocation));
+        Span::current().record("recipient", field::display(&peer));
         Span::current().record("channel", field::display(&channel_number.value()));
 
+        if !self.check_and_record_limits(sender, data.len(), now) {
+            return None;
+        }
+
         tracing::trace!(target: "wire", num_bytes = %data.len());
 
         self.data_relayed_counter.add(data.len() as u64, &[]);
         self.data_relayed += data.len() as u64;
 
-        Some((channel.allocation, channel.peer_address))
+        self.capture_peer_traffic(allocation, peer, Direction::Outbound, data, now);
+
+        if self.try_forward_to_relay(data, allocation, peer) {
+            return None;
+        }
+
+        Some((allocation, peer))
+    }
+
+    /// Records traffic between the relay and a client if it is being captured.
+    fn capture_client_traffic(
+        &mut self,
+        client: ClientSocket,
+        direction: Direction,
+        bytes: &[u8],
+        now: Instant,
+    ) {
+        let Some(relay_ip) = self.public_ip(client.family()) else {
+            return;
+        };
+        let Some(capture) = self.captures.get_mut(&client) else {
+            return;
+        };
+
+        let (src, dst) = direction.orient(client.0, SocketAddr::new(relay_ip, TURN_PORT));
+        capture.record(src, dst, bytes, now);
+    }
+
+    /// Records traffic between an allocation and a peer if the allocation is being captured.
+    fn capture_peer_traffic(
+        &mut self,
+        allocation: AllocationPort,
+        peer: PeerSocket,
+        direction: Direction,
+        bytes: &[u8],
+        now: Instant,
+    ) {
+        if self.captures.is_empty() {
+            return; // Fast-path: Avoid the lookups below for all relayed data.
+        }
+
+        let Some(relay_ip) = self.public_ip(peer.family()) else {
+            return;
+        };
+        let Some(client) = self.clients_by_allocation.get(&allocation) else {
+            return;
+        };
+        let Some(capture) = self.captures.get_mut(client) else {
+            return;
+        };
+
+        let (src, dst) = direction.orient(peer.0, SocketAddr::new(relay_ip, allocation.0));
+        capture.record(src, dst, bytes, now);
+    }
+
+    /// Our public IP of the given family, if we have one.
+    fn public_ip(&self, family: AddressFamily) -> Option<IpAddr> {
+        match family {
+            AddressFamily::V4 => self.public_address.as_v4().copied().map(IpAddr::from),
+            AddressFamily::V6 => self.public_address.as_v6().copied().map(IpAddr::from),
+        }
+    }
+
+    /// Forwards data to the relay-to-relay link if `peer` is an allocation on a relay we peer with.
+    ///
+    /// Returns `false` if we don't peer with the relay of `peer`, in which case the data should be sent to `peer` directly.
+    fn try_forward_to_relay(
+        &mut self,
+        data: &[u8],
+        allocation: AllocationPort,
+        peer: PeerSocket,
+    ) -> bool {
+        let Some(source_ip) = self.public_ip(peer.family()) else {
+            debug_assert!(
+                false,
+                "allocation cannot relay to a peer of an unsupported address family"
+            );
+            return false;
+        };
+        let Some(peering) = self.peering.as_mut() else {
+            return false;
+        };
+        let Some(link) = peering.link_to(peer.0.ip()) else {
+            return false;
+        };
+
+        let frame = Frame {
+            sequence: peering.next_sequence(),
+            source: SocketAddr::new(source_ip, allocation.0),
+            destination_port: peer.0.port(),
+            payload: data,
+        };
+
+        self.data_forwarded_counter
+            .add(data.len() as u64, &[KeyValue::new("direction", "outbound")]);
+        self.pending_commands.push_back(Command::ForwardToRelay {
+            payload: frame.encode(peering.secret()),
+            link,
+        });
+
+        true
+    }
+
+    /// Checks the [`Limits`] of the client's allocation and its username.
+    ///
+    /// If the data is within all limits, it is recorded as relayed and we return `true`.
+    /// Otherwise, the data is recorded as dropped and we return `false`.
+    fn check_and_record_limits(
+        &mut self,
+        client: ClientSocket,
+        num_bytes: usize,
+        now: Instant,
+    ) -> bool {
+        let Some(allocation) = self.allocations.get_mut(&client) else {
+            return false;
+        };
+        let mut username_usage = self
+            .usage_by_username
+            .get(&allocation.username)
+            .map(|u| u.lock());
+
+        let result = allocation.usage.check(num_bytes, now).and_then(|()| {
+            match username_usage.as_deref_mut() {
+                Some(username) => username.usage.check(num_bytes, now),
+                None => Ok(()),
+            }
+        });
+
+        if let Err(exceeded) = result {
+            tracing::debug!(target: "relay", %num_bytes, reason = %exceeded.as_str(), "Dropping data exceeding limit");
+
+            self.data_dropped_counter.add(
+                num_bytes as u64,
+                &[KeyValue::new("reason", exceeded.as_str())],
+            );
+
+            return false;
+        }
+
+        allocation.usage.record(num_bytes);
+        if let Some(username) = username_usage.as_deref_mut() {
+            username.usage.record(num_bytes);
+        }
+
+        true
     }
 
     fn verify_auth(
         &mut self,
         request: &(impl StunRequest + ProtectedRequest),
+        now: Instant,
     ) -> Result<(), Message<Attribute>> {
         let message_integrity = request
             .message_integrity()
@@ -750,12 +1661,23 @@ where
             })?;
 
         self.nonces
-            .handle_nonce_used(nonce)
+            .handle_nonce_used(nonce, now)
             .map_err(|_| error_response(StaleNonce, request))?;
 
-        message_integrity
-            .verify(&self.auth_secret, username.name(), SystemTime::now()) // This is impure but we don't need to control this in our tests.
-            .map_err(|_| error_response(Unauthorized, request))?;
+        let wall_clock = SystemTime::now(); // This is impure but we don't need to control this in our tests.
+
+        match &self.auth_scheme {
+            AuthScheme::Firezone => {
+                message_integrity.verify(&self.auth_secret, username.name(), wall_clock)
+            }
+            AuthScheme::TurnRestApi { shared_secret } => {
+                message_integrity.verify_turn_rest_api(shared_secret, username.name(), wall_clock)
+            }
+            AuthScheme::Static { passwords } => {
+                message_integrity.verify_static(passwords, username.name())
+            }
+        }
+        .map_err(|_| error_response(Unauthorized, request))?;
 
         Ok(())
     }
@@ -766,32 +1688,31 @@ where
         lifetime: &Lifetime,
         first_relay_addr: IpAddr,
         second_relay_addr: Option<IpAddr>,
+        username: String,
     ) -> Allocation {
-        assert!(
-            self.clients_by_allocation.len() < self.max_available_ports() as usize,
-            "No more ports available; this would loop forever"
-        );
-
-        let port = loop {
-            let candidate = AllocationPort(self.rng.gen_range(self.lowest_port..self.highest_port));
-
-            if !self.clients_by_allocation.contains_key(&candidate) {
-                break candidate;
-            }
-        };
+        // Start at a random port and take the next one that is neither excluded nor in use.
+        // Unlike picking random ports until we find a free one, this terminates even if most of the range is excluded.
+        let num_ports = u32::from(self.highest_port - self.lowest_port);
+        let start = self.rng.gen_range(0..num_ports);
+        let port = (0..num_ports)
+            .map(|offset| AllocationPort(self.lowest_port + ((start + offset) % num_ports) as u16))
+            .find(|candidate| {
+                !self.config.is_excluded(candidate.value())
+                    && !self.clients_by_allocation.contains_key(candidate)
+            })
+            .expect("No more ports available; callers must check `max_available_ports`");
 
         Allocation {
             port,
             expires_at: now + lifetime.lifetime(),
             first_relay_addr,
             second_relay_addr,
+            permissions: Default::default(),
+            username,
+            usage: Usage::new(self.limits.per_allocation, now),
         }
     }
 
-    fn max_available_ports(&self) -> u16 {
-        self.highest_port - self.lowest_port
-    }
-
     fn create_channel_binding(
         &mut self,
         client: ClientSocket,
@@ -800,7 +1721,7 @@ where
         id: AllocationPort,
         now: Instant,
     ) {
-        let expiry = now + CHANNEL_BINDING_DURATION;
+        let expiry = now + self.config.channel_binding_duration;
 
         let existing = self.channels_by_client_and_number.insert(
             (client, requested_channel),
@@ -812,6 +1733,7 @@ where
             },
         );
         debug_assert!(existing.is_none());
+        self.channels_up_down_counter.add(1, &[]);
 
         let existing = self
             .channel_numbers_by_client_and_peer
@@ -826,7 +1748,7 @@ where
         debug_assert!(existing.is_none());
     }
 
-    fn send_message(&mut self, message: Message<Attribute>, recipient: ClientSocket) {
+    fn send_message(&mut self, message: Message<Attribute>, recipient: ClientSocket, now: Instant) {
         let method = message.method();
         let class = message.class();
         tracing::trace!(target: "relay",  method = %message.method(), class = %message.class(), "Sending message");
@@ -838,6 +1760,8 @@ where
 
         tracing::trace!(target: "wire", num_bytes = %bytes.len());
 
+        self.capture_client_traffic(recipient, Direction::Outbound, &bytes, now);
+
         self.pending_commands.push_back(Command::SendMessage {
             payload: bytes,
             recipient,
@@ -879,12 +1803,29 @@ where
 
         let port = allocation.port;
 
+        if self
+            .captures
+            .get(&client)
+            .is_some_and(|capture| capture.port() == port)
+        {
+            self.finish_capture(client);
+        }
+
+        // The usage itself is kept around so that deleting an allocation doesn't reset the quota of its username.
+        if let Some(usage) = self.usage_by_username.get(&allocation.username) {
+            usage.lock().num_allocations -= 1;
+        }
+
         self.allocations_up_down_counter.add(-1, &[]);
+        self.allocations_by_family_up_down_counter
+            .add(-1, &[family_attribute(allocation.first_relay_addr)]);
         self.pending_commands.push_back(Command::FreeAllocation {
             port,
             family: allocation.first_relay_addr.family(),
         });
         if let Some(second_relay_addr) = allocation.second_relay_addr {
+            self.allocations_by_family_up_down_counter
+                .add(-1, &[family_attribute(second_relay_addr)]);
             self.pending_commands.push_back(Command::FreeAllocation {
                 port,
                 family: second_relay_addr.family(),
@@ -894,6 +1835,18 @@ where
         tracing::info!(target: "relay", %port, "Deleted allocation");
     }
 
+    /// Stops recording the capture of the given client and keeps it around until it is fetched or expires.
+    fn finish_capture(&mut self, client: ClientSocket) {
+        let Some(capture) = self.captures.remove(&client) else {
+            return;
+        };
+        let port = capture.port();
+
+        tracing::info!(target: "relay", %port, %client, "Packet capture finished");
+
+        self.finished_captures.insert(port, capture);
+    }
+
     fn delete_channel_binding(&mut self, client: ClientSocket, chan: ChannelNumber) {
         let Some(channel) = self.channels_by_client_and_number.get(&(client, chan)) else {
             return;
@@ -912,11 +1865,21 @@ where
         );
 
         self.channels_by_client_and_number.remove(&(client, chan));
+        self.channels_up_down_counter.add(-1, &[]);
 
         tracing::info!(target: "relay", channel = %chan.value(), %client, %peer, %allocation, "Channel binding is now deleted (and can be rebound)");
     }
 }
 
+fn family_attribute(addr: IpAddr) -> KeyValue {
+    let family = match addr {
+        IpAddr::V4(_) => "ip4",
+        IpAddr::V6(_) => "ip6",
+    };
+
+    KeyValue::new("family", family)
+}
+
 fn refresh_success_response(
     effective_lifetime: Lifetime,
     transaction_id: TransactionId,
@@ -938,14 +1901,75 @@ fn create_permission_success_response(transaction_id: TransactionId) -> Message<
     )
 }
 
+/// An ICMP error, received in response to data we relayed to a peer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IcmpError {
+    pub icmp_type: u8,
+    pub icmp_code: u8,
+    /// Type-specific data, e.g. the next-hop MTU for "fragmentation needed" errors.
+    pub error_data: u32,
+}
+
+impl IcmpError {
+    /// Whether this error is forwarded to clients.
+    ///
+    /// We only forward errors that are about the peer or the path to it, see <https://www.rfc-editor.org/rfc/rfc8656#section-11.7>.
+    fn is_forwarded(&self, family: AddressFamily) -> bool {
+        match family {
+            // Destination unreachable, time exceeded & parameter problem.
+            AddressFamily::V4 => matches!(self.icmp_type, 3 | 11 | 12),
+            // Destination unreachable, packet too big, time exceeded & parameter problem.
+            AddressFamily::V6 => matches!(self.icmp_type, 1..=4),
+        }
+    }
+}
+
+/// A snapshot of an allocation, see [`Server::allocations`].
+///
+/// This is a copy of the allocation's state at the time of the call and does not reflect any later changes, e.g. a refresh.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AllocationInfo {
+    /// The client that owns this allocation.
+    pub client: ClientSocket,
+    /// The username the allocation was created with.
+    pub username: String,
+    pub port: AllocationPort,
+    /// The addresses of the relay that this allocation listens on; one per address family.
+    pub relay_addresses: Vec<IpAddr>,
+    pub channels: Vec<ChannelInfo>,
+    /// The number of bytes relayed in both directions.
+    pub bytes_relayed: u64,
+    /// When the allocation expires unless it is refreshed.
+    pub expires_at: Instant,
+}
+
+/// A snapshot of a channel binding, see [`Server::allocations`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChannelInfo {
+    pub number: u16,
+    pub peer: PeerSocket,
+    /// Whether data can currently be relayed through this channel.
+    pub bound: bool,
+    pub expires_at: Instant,
+}
+
 /// Represents an allocation of a client.
 struct Allocation {
-    /// Data arriving on this port will be forwarded to the client iff there is an active data channel.
+    /// Data arriving on this port will be forwarded to the client iff there is an active data channel or permission.
     port: AllocationPort,
     expires_at: Instant,
 
     first_relay_addr: IpAddr,
     second_relay_addr: Option<IpAddr>,
+
+    /// The permissions of this allocation and when they expire.
+    ///
+    /// Permissions are keyed by IP address only, i.e. the port of the peer is not considered.
+    permissions: HashMap<IpAddr, Instant>,
+
+    /// The username this allocation was created with.
+    username: String,
+    usage: Usage,
 }
 
 struct Channel {
@@ -960,36 +1984,48 @@ struct Channel {
 
     /// Whether the channel is currently bound.
     ///
-    /// Channels are active for 10 minutes. During this time, data can be relayed through the channel.
-    /// After 10 minutes, the channel is considered unbound.
+    /// Channels are active for [`ServerConfig::channel_binding_duration`] (10 minutes by default). During this time, data can be relayed through the channel.
+    /// Afterwards, the channel is considered unbound.
     ///
-    /// To prevent race conditions, we MUST NOT use the same channel number for a different peer and vice versa for another 5 minutes after the channel becomes unbound.
-    /// Once it becomes unbound, we simply flip this bool and only completely remove the channel after another 5 minutes.
+    /// To prevent race conditions, we MUST NOT use the same channel number for a different peer and vice versa for another [`ServerConfig::channel_rebind_timeout`] (5 minutes by default) after the channel becomes unbound.
+    /// Once it becomes unbound, we simply flip this bool and only completely remove the channel once the rebind timeout has passed.
     ///
     /// With the data structure still existing while the channel is unbound, our existing validations cover the above requirement.
     bound: bool,
 }
 
 impl Channel {
-    fn refresh(&mut self, now: Instant) {
-        self.expiry = now + CHANNEL_BINDING_DURATION;
+    fn refresh(&mut self, now: Instant, binding_duration: Duration) {
+        self.expiry = now + binding_duration;
     }
 
     fn is_expired(&self, now: Instant) -> bool {
         self.expiry <= now
     }
 
-    fn can_be_deleted(&self, now: Instant) -> bool {
-        self.expiry + CHANNEL_REBIND_TIMEOUT <= now
+    fn can_be_deleted(&self, now: Instant, rebind_timeout: Duration) -> bool {
+        self.expiry + rebind_timeout <= now
     }
 }
 
 impl Allocation {
     /// Checks whether this [`Allocation`] can relay to the given address.
     ///
-    /// This is called in the context of a channel binding with the requested peer address.
-    /// We can only relay to the address if the allocation supports the same version of the IP protocol.
-    fn can_relay_to(&self, addr: PeerSocket) -> bool {
+    /// We can only relay to the address if the allocation supports the same version of the IP protocol and has an active permission for the peer's IP.
+    ///
+    /// Traffic on bound channels is not subject to this check because binding a channel requires (and refreshes) a permission.
+    fn can_relay_to(&self, addr: PeerSocket, now: Instant) -> bool {
+        self.supports_family_of(addr)
+            && self
+                .permissions
+                .get(&addr.0.ip())
+                .is_some_and(|expiry| *expiry > now)
+    }
+
+    /// Checks whether this [`Allocation`] supports the IP version of the given address.
+    ///
+    /// This is called in the context of a channel binding or permission with the requested peer address.
+    fn supports_family_of(&self, addr: PeerSocket) -> bool {
         match addr.0 {
             SocketAddr::V4(_) => self.first_relay_addr.is_ipv4(), // If we have an IPv4 address, it is in `first_relay_addr`, no need to check `second_relay_addr`.
             SocketAddr::V6(_) => {
@@ -1004,6 +2040,16 @@ impl Allocation {
     fn is_expired(&self, now: Instant) -> bool {
         self.expires_at <= now
     }
+
+    /// Installs or refreshes the permission for the given peer.
+    fn add_permission(&mut self, peer: PeerSocket, now: Instant) {
+        self.permissions
+            .insert(peer.0.ip(), now + PERMISSION_LIFETIME);
+    }
+
+    fn remove_expired_permissions(&mut self, now: Instant) {
+        self.permissions.retain(|_, expiry| *expiry > now);
+    }
 }
 
 fn error_response(
@@ -1168,10 +2214,19 @@ stun_codec::define_attribute_enums!(
         Realm,
         Username,
         RequestedAddressFamily,
-        AdditionalAddressFamily
+        AdditionalAddressFamily,
+        Data,
+        Icmp
     ]
 );
 
+/// Parses the nonce of a request, ignoring requests without a valid one.
+///
+/// Those are rejected once we actually process the request.
+fn parse_nonce(request: &impl ProtectedRequest) -> Option<Uuid> {
+    request.nonce().ok()?.value().parse().ok()
+}
+
 fn earliest(left: Option<Instant>, right: Option<Instant>) -> Option<Instant> {
     match (left, right) {
         (None, None) => None,
@@ -0,0 +1,311 @@
+use crate::AllocationPort;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::{Duration, Instant, SystemTime};
+
+const UDP_PROTOCOL: u8 = 17;
+const TTL: u8 = 64;
+
+/// Whether traffic is received or sent by the relay.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Direction {
+    Inbound,
+    Outbound,
+}
+
+impl Direction {
+    /// Returns the source and destination of traffic between `remote` and our `local` socket.
+    pub(crate) fn orient(self, remote: SocketAddr, local: SocketAddr) -> (SocketAddr, SocketAddr) {
+        match self {
+            Direction::Inbound => (remote, local),
+            Direction::Outbound => (local, remote),
+        }
+    }
+}
+
+/// A packet capture of all traffic of a single allocation, in the [pcapng](https://www.ietf.org/archive/id/draft-ietf-opsawg-pcapng-01.html) format.
+///
+/// We only see UDP payloads (or messages framed from a TCP / TLS stream), so each packet is wrapped in synthesized IP and UDP headers.
+/// Captures are bounded in time and size and silently stop recording once either is exhausted.
+/// A [`Server`](crate::Server) doesn't know which port its clients talk to, so client traffic always uses [`TURN_PORT`](crate::TURN_PORT) as the relay's port.
+pub(crate) struct Capture {
+    port: AllocationPort,
+    file: pcapng_writer::Writer,
+
+    started_at: Instant,
+    /// `started_at` as a UNIX timestamp, used to derive the timestamps of all packets.
+    started_at_unix: Duration,
+    deadline: Instant,
+    max_bytes: usize,
+}
+
+impl Capture {
+    pub(crate) fn new(
+        port: AllocationPort,
+        duration: Duration,
+        max_bytes: usize,
+        now: Instant,
+        now_unix: SystemTime,
+    ) -> Self {
+        Self {
+            port,
+            file: pcapng_writer::Writer::new(),
+            started_at: now,
+            started_at_unix: now_unix
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default(),
+            deadline: now + duration,
+            max_bytes,
+        }
+    }
+
+    /// The allocation this capture was started for.
+    pub(crate) fn port(&self) -> AllocationPort {
+        self.port
+    }
+
+    /// When this capture stops (or stopped) recording.
+    pub(crate) fn deadline(&self) -> Instant {
+        self.deadline
+    }
+
+    /// Whether this capture stopped recording, either because it ran out of time or bytes.
+    pub(crate) fn is_finished(&self, now: Instant) -> bool {
+        now >= self.deadline
+    }
+
+    /// Records a UDP packet from `src` to `dst`.
+    ///
+    /// Packets are dropped once the capture ran out of time or bytes.
+    pub(crate) fn record(
+        &mut self,
+        src: SocketAddr,
+        dst: SocketAddr,
+        payload: &[u8],
+        now: Instant,
+    ) {
+        if self.is_finished(now) {
+            return;
+        }
+
+        let Some(packet) = make_udp_packet(src, dst, payload) else {
+            return;
+        };
+
+        let timestamp = self.started_at_unix + now.duration_since(self.started_at);
+
+        if !self
+            .file
+            .write_packet(timestamp, &packet, &[], self.max_bytes)
+        {
+            self.deadline = now; // Stop the capture instead of skipping just this packet to avoid confusing gaps.
+        }
+    }
+
+    /// The pcapng file of everything recorded so far.
+    pub(crate) fn into_pcapng(self) -> Vec<u8> {
+        self.file.into_bytes()
+    }
+}
+
+/// Builds an IP packet containing a UDP datagram from `src` to `dst`.
+///
+/// Returns `None` if the addresses are of different families or the payload doesn't fit into a single datagram.
+fn make_udp_packet(src: SocketAddr, dst: SocketAddr, payload: &[u8]) -> Option<Vec<u8>> {
+    let udp_len = u16::try_from(8 + payload.len()).ok()?;
+
+    let mut udp = Vec::with_capacity(usize::from(udp_len));
+    udp.extend_from_slice(&src.port().to_be_bytes());
+    udp.extend_from_slice(&dst.port().to_be_bytes());
+    udp.extend_from_slice(&udp_len.to_be_bytes());
+    udp.extend_from_slice(&[0, 0]); // Placeholder for the checksum.
+    udp.extend_from_slice(payload);
+
+    let mut packet = match (src.ip(), dst.ip()) {
+        (IpAddr::V4(src), IpAddr::V4(dst)) => {
+            let total_len = u16::try_from(20 + udp.len()).ok()?;
+
+            ip4_header(src, dst, total_len)
+        }
+        (IpAddr::V6(src), IpAddr::V6(dst)) => ip6_header(src, dst, udp_len),
+        (IpAddr::V4(_), IpAddr::V6(_)) | (IpAddr::V6(_), IpAddr::V4(_)) => return None,
+    };
+
+    let checksum = udp_checksum(src.ip(), dst.ip(), &udp);
+    udp[6..8].copy_from_slice(&checksum.to_be_bytes());
+    packet.extend_from_slice(&udp);
+
+    Some(packet)
+}
+
+fn ip4_header(src: Ipv4Addr, dst: Ipv4Addr, total_len: u16) -> Vec<u8> {
+    let mut header = Vec::with_capacity(20 + usize::from(total_len));
+    header.extend_from_slice(&[0x45, 0]); // Version, IHL and DSCP.
+    header.extend_from_slice(&total_len.to_be_bytes());
+    header.extend_from_slice(&[0, 0, 0, 0]); // Identification, flags and fragment offset.
+    header.extend_from_slice(&[TTL, UDP_PROTOCOL]);
+    header.extend_from_slice(&[0, 0]); // Placeholder for the checksum.
+    header.extend_from_slice(&src.octets());
+    header.extend_from_slice(&dst.octets());
+
+    let checksum = !fold(sum_words(&header));
+    header[10..12].copy_from_slice(&checksum.to_be_bytes());
+
+    header
+}
+
+fn ip6_header(src: Ipv6Addr, dst: Ipv6Addr, payload_len: u16) -> Vec<u8> {
+    let mut header = Vec::with_capacity(40 + usize::from(payload_len));
+    header.extend_from_slice(&[0x60, 0, 0, 0]); // Version, traffic class and flow label.
+    header.extend_from_slice(&payload_len.to_be_bytes());
+    header.extend_from_slice(&[UDP_PROTOCOL, TTL]);
+    header.extend_from_slice(&src.octets());
+    header.extend_from_slice(&dst.octets());
+
+    header
+}
+
+fn udp_checksum(src: IpAddr, dst: IpAddr, udp: &[u8]) -> u16 {
+    let pseudo_header = match (src, dst) {
+        (IpAddr::V4(src), IpAddr::V4(dst)) => sum_words(&src.octets()) + sum_words(&dst.octets()),
+        (IpAddr::V6(src), IpAddr::V6(dst)) => sum_words(&src.octets()) + sum_words(&dst.octets()),
+        (IpAddr::V4(_), IpAddr::V6(_)) | (IpAddr::V6(_), IpAddr::V4(_)) => 0,
+    };
+
+    let checksum =
+        !fold(pseudo_header + u64::from(UDP_PROTOCOL) + udp.len() as u64 + sum_words(udp));
+
+    // An all-zero checksum means "no checksum", the RFC says to transmit it as all ones instead.
+    if checksum == 0 {
+        return 0xFFFF;
+    }
+
+    checksum
+}
+
+/// Sums up the 16-bit big-endian words of `bytes`, padding an odd trailing byte with zero.
+fn sum_words(bytes: &[u8]) -> u64 {
+    bytes
+        .chunks(2)
+        .map(|chunk| {
+            u64::from(u16::from_be_bytes([
+                chunk[0],
+                chunk.get(1).copied().unwrap_or(0),
+            ]))
+        })
+        .sum()
+}
+
+/// Folds a sum of words into a 16-bit ones' complement sum.
+fn fold(mut sum: u64) -> u16 {
+    while sum > 0xFFFF {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+
+    sum as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLIENT: &str = "192.0.2.1:50000";
+    const RELAY: &str = "203.0.113.1:3478";
+
+    /// The size of the section header and interface description.
+    const HEADER_LEN: usize = 48;
+    /// The size of the block of a packet with a 5-byte payload, padded to 32 bits.
+    const HELLO_BLOCK_LEN: usize = 28 + 36 + 4;
+
+    #[test]
+    fn records_packet_with_ip_and_udp_headers() {
+        let mut capture = make_capture(1024);
+        let now = capture.started_at;
+
+        capture.record(
+            CLIENT.parse().unwrap(),
+            RELAY.parse().unwrap(),
+            b"hello",
+            now,
+        );
+
+        let file = capture.into_pcapng();
+        let block = &file[HEADER_LEN..];
+
+        assert_eq!(block.len(), HELLO_BLOCK_LEN);
+        assert_eq!(&block[20..24], &33u32.to_le_bytes()); // Captured length.
+        assert_eq!(&block[28 + 28..28 + 33], b"hello");
+    }
+
+    #[test]
+    fn stops_recording_after_deadline() {
+        let mut capture = make_capture(1024);
+        let later = capture.started_at + Duration::from_secs(61);
+
+        capture.record(
+            CLIENT.parse().unwrap(),
+            RELAY.parse().unwrap(),
+            b"hello",
+            later,
+        );
+
+        assert!(capture.is_finished(later));
+        assert_eq!(capture.into_pcapng().len(), HEADER_LEN);
+    }
+
+    #[test]
+    fn stops_recording_once_out_of_bytes() {
+        let mut capture = make_capture(HEADER_LEN + HELLO_BLOCK_LEN);
+        let now = capture.started_at;
+
+        capture.record(
+            CLIENT.parse().unwrap(),
+            RELAY.parse().unwrap(),
+            b"hello",
+            now,
+        );
+        capture.record(
+            CLIENT.parse().unwrap(),
+            RELAY.parse().unwrap(),
+            b"hello",
+            now,
+        );
+
+        assert!(capture.is_finished(now));
+        assert_eq!(capture.into_pcapng().len(), HEADER_LEN + HELLO_BLOCK_LEN);
+    }
+
+    #[test]
+    fn ip4_header_checksum_is_valid() {
+        let packet =
+            make_udp_packet(CLIENT.parse().unwrap(), RELAY.parse().unwrap(), b"hello").unwrap();
+
+        assert_eq!(fold(sum_words(&packet[..20])), 0xFFFF);
+    }
+
+    #[test]
+    fn udp_checksum_is_valid() {
+        let src = "[2001:db8::1]:50000".parse::<SocketAddr>().unwrap();
+        let dst = "[2001:db8::2]:3478".parse::<SocketAddr>().unwrap();
+
+        let packet = make_udp_packet(src, dst, b"hello").unwrap();
+        let udp = &packet[40..];
+
+        let sum = sum_words(&[0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1])
+            + sum_words(&[0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2])
+            + u64::from(UDP_PROTOCOL)
+            + udp.len() as u64
+            + sum_words(udp);
+
+        assert_eq!(fold(sum), 0xFFFF);
+    }
+
+    fn make_capture(max_bytes: usize) -> Capture {
+        Capture::new(
+            AllocationPort::new(49152),
+            Duration::from_secs(60),
+            max_bytes,
+            Instant::now(),
+            SystemTime::UNIX_EPOCH,
+        )
+    }
+}
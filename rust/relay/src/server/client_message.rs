@@ -1,6 +1,7 @@
 use crate::auth::{generate_password, split_username, systemtime_from_unix, FIREZONE};
 use crate::server::channel_data::ChannelData;
-use crate::server::UDP_TRANSPORT;
+use crate::server::config::MAX_ALLOCATION_LIFETIME;
+use crate::server::{ServerConfig, UDP_TRANSPORT};
 use crate::Attribute;
 use bytecodec::DecodeExt;
 use secrecy::SecretString;
@@ -10,23 +11,15 @@ use stun_codec::rfc5389::attributes::{ErrorCode, MessageIntegrity, Nonce, Userna
 use stun_codec::rfc5389::errors::BadRequest;
 use stun_codec::rfc5389::methods::BINDING;
 use stun_codec::rfc5766::attributes::{
-    ChannelNumber, Lifetime, RequestedTransport, XorPeerAddress,
+    ChannelNumber, Data, Lifetime, RequestedTransport, XorPeerAddress,
 };
-use stun_codec::rfc5766::methods::{ALLOCATE, CHANNEL_BIND, CREATE_PERMISSION, REFRESH};
+use stun_codec::rfc5766::methods::{ALLOCATE, CHANNEL_BIND, CREATE_PERMISSION, REFRESH, SEND};
 use stun_codec::rfc8656::attributes::{
     AdditionalAddressFamily, AddressFamily, RequestedAddressFamily,
 };
 use stun_codec::{Message, MessageClass, Method, TransactionId};
 use uuid::Uuid;
 
-/// The maximum lifetime of an allocation.
-const MAX_ALLOCATION_LIFETIME: Duration = Duration::from_secs(3600);
-
-/// The default lifetime of an allocation.
-///
-/// See <https://www.rfc-editor.org/rfc/rfc8656#name-allocations-2>.
-const DEFAULT_ALLOCATION_LIFETIME: Duration = Duration::from_secs(600);
-
 #[derive(Default)]
 pub struct Decoder {
     stun_message_decoder: stun_codec::MessageDecoder<Attribute>,
@@ -65,8 +58,11 @@ impl Decoder {
                     (CHANNEL_BIND, Request) => {
                         Ok(ChannelBind::parse(&message).map(ClientMessage::ChannelBind))
                     }
-                    (CREATE_PERMISSION, Request) => Ok(Ok(ClientMessage::CreatePermission(
-                        CreatePermission::parse(&message),
+                    (CREATE_PERMISSION, Request) => {
+                        Ok(CreatePermission::parse(&message).map(ClientMessage::CreatePermission))
+                    }
+                    (SEND, Indication) => Ok(Ok(ClientMessage::SendIndication(
+                        SendIndication::parse(&message)?,
                     ))),
                     (_, Request) => Ok(Err(bad_request(&message))),
                     (method, class) => {
@@ -95,6 +91,7 @@ pub enum ClientMessage<'a> {
     Refresh(Refresh),
     ChannelBind(ChannelBind),
     CreatePermission(CreatePermission),
+    SendIndication(SendIndication),
 }
 
 impl ClientMessage<'_> {
@@ -105,6 +102,7 @@ impl ClientMessage<'_> {
             ClientMessage::Refresh(request) => Some(request.transaction_id),
             ClientMessage::ChannelBind(request) => Some(request.transaction_id),
             ClientMessage::CreatePermission(request) => Some(request.transaction_id),
+            ClientMessage::SendIndication(indication) => Some(indication.transaction_id),
             ClientMessage::ChannelData(_) => None,
         }
     }
@@ -300,8 +298,8 @@ impl Allocate {
         &self.requested_transport
     }
 
-    pub fn effective_lifetime(&self) -> Lifetime {
-        compute_effective_lifetime(self.lifetime.as_ref())
+    pub fn effective_lifetime(&self, config: &ServerConfig) -> Lifetime {
+        compute_effective_lifetime(self.lifetime.as_ref(), config)
     }
 
     pub fn username(&self) -> Option<&Username> {
@@ -389,8 +387,8 @@ impl Refresh {
         self.message_integrity.as_ref()
     }
 
-    pub fn effective_lifetime(&self) -> Lifetime {
-        compute_effective_lifetime(self.lifetime.as_ref())
+    pub fn effective_lifetime(&self, config: &ServerConfig) -> Lifetime {
+        compute_effective_lifetime(self.lifetime.as_ref(), config)
     }
 
     pub fn username(&self) -> Option<&Username> {
@@ -502,27 +500,83 @@ pub struct CreatePermission {
     message_integrity: Option<MessageIntegrity>,
     username: Option<Username>,
     nonce: Option<Nonce>,
+    xor_peer_addresses: Vec<XorPeerAddress>,
 }
 
 impl CreatePermission {
-    pub fn parse(message: &Message<Attribute>) -> Self {
+    pub fn new(
+        transaction_id: TransactionId,
+        xor_peer_addresses: Vec<XorPeerAddress>,
+        username: Username,
+        relay_secret: &SecretString,
+        nonce: Uuid,
+    ) -> Self {
+        let nonce = Nonce::new(nonce.as_hyphenated().to_string()).expect("len(uuid) < 128");
+
+        let mut message =
+            Message::<Attribute>::new(MessageClass::Request, CREATE_PERMISSION, transaction_id);
+        message.add_attribute(username.clone());
+        for xor_peer_address in &xor_peer_addresses {
+            message.add_attribute(xor_peer_address.clone());
+        }
+        message.add_attribute(nonce.clone());
+
+        let (expiry, salt) = split_username(username.name()).expect("a valid username");
+        let expiry_systemtime = systemtime_from_unix(expiry);
+
+        let password = generate_password(relay_secret, expiry_systemtime, salt);
+
+        let message_integrity =
+            MessageIntegrity::new_long_term_credential(&message, &username, &FIREZONE, &password)
+                .unwrap();
+
+        Self {
+            transaction_id,
+            message_integrity: Some(message_integrity),
+            username: Some(username),
+            nonce: Some(nonce),
+            xor_peer_addresses,
+        }
+    }
+
+    pub fn parse(message: &Message<Attribute>) -> Result<Self, Message<Attribute>> {
         let transaction_id = message.transaction_id();
         let message_integrity = message.get_attribute::<MessageIntegrity>().cloned();
         let username = message.get_attribute::<Username>().cloned();
         let nonce = message.get_attribute::<Nonce>().cloned();
+        let xor_peer_addresses = message
+            .attributes()
+            .filter_map(|a| {
+                if let Attribute::XorPeerAddress(a) = a {
+                    Some(a.clone())
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+
+        // A request MUST contain at least one `XOR-PEER-ADDRESS`.
+        if xor_peer_addresses.is_empty() {
+            return Err(bad_request(message));
+        }
 
-        CreatePermission {
+        Ok(CreatePermission {
             transaction_id,
             message_integrity,
             username,
             nonce,
-        }
+            xor_peer_addresses,
+        })
     }
 
     pub fn transaction_id(&self) -> TransactionId {
         self.transaction_id
     }
 
+    pub fn xor_peer_addresses(&self) -> &[XorPeerAddress] {
+        &self.xor_peer_addresses
+    }
+
     pub fn message_integrity(&self) -> Option<&MessageIntegrity> {
         self.message_integrity.as_ref()
     }
@@ -536,15 +590,82 @@ impl CreatePermission {
     }
 }
 
+/// A Send indication, carrying data from a client to a peer.
+///
+/// Indications are not authenticated.
+/// Instead, the server only relays them if the client has an allocation with a permission for the peer.
+///
+/// See <https://www.rfc-editor.org/rfc/rfc8656#name-receiving-a-send-indication>.
+pub struct SendIndication {
+    transaction_id: TransactionId,
+    xor_peer_address: XorPeerAddress,
+    data: Data,
+}
+
+impl SendIndication {
+    pub fn new(
+        transaction_id: TransactionId,
+        xor_peer_address: XorPeerAddress,
+        data: Data,
+    ) -> Self {
+        Self {
+            transaction_id,
+            xor_peer_address,
+            data,
+        }
+    }
+
+    /// Parses a Send indication.
+    ///
+    /// Indications never trigger a response so we can only discard them if they are malformed.
+    pub fn parse(message: &Message<Attribute>) -> Result<Self, Error> {
+        let transaction_id = message.transaction_id();
+        let xor_peer_address = message
+            .get_attribute::<XorPeerAddress>()
+            .ok_or(Error::InvalidIndication("missing `XOR-PEER-ADDRESS`"))?
+            .clone();
+        let data = message
+            .get_attribute::<Data>()
+            .ok_or(Error::InvalidIndication("missing `DATA`"))?
+            .clone();
+
+        Ok(SendIndication {
+            transaction_id,
+            xor_peer_address,
+            data,
+        })
+    }
+
+    pub fn transaction_id(&self) -> TransactionId {
+        self.transaction_id
+    }
+
+    pub fn xor_peer_address(&self) -> &XorPeerAddress {
+        &self.xor_peer_address
+    }
+
+    pub fn data(&self) -> &[u8] {
+        self.data.data()
+    }
+}
+
 /// Computes the effective lifetime of an allocation.
-fn compute_effective_lifetime(requested_lifetime: Option<&Lifetime>) -> Lifetime {
-    let Some(requested) = requested_lifetime else {
-        return Lifetime::new(DEFAULT_ALLOCATION_LIFETIME).unwrap();
+fn compute_effective_lifetime(
+    requested_lifetime: Option<&Lifetime>,
+    config: &ServerConfig,
+) -> Lifetime {
+    let effective_lifetime = match requested_lifetime {
+        Some(requested) => requested.lifetime().min(config.max_allocation_lifetime),
+        None => config.default_allocation_lifetime,
     };
 
-    let effective_lifetime = requested.lifetime().min(MAX_ALLOCATION_LIFETIME);
+    // `ServerConfig::validate` rejects lifetimes that don't fit into a `LIFETIME` attribute but we don't want to panic on an unvalidated config either.
+    let seconds = effective_lifetime
+        .as_secs()
+        .min(MAX_ALLOCATION_LIFETIME.as_secs());
 
-    Lifetime::new(effective_lifetime).unwrap()
+    Lifetime::new(Duration::from_secs(seconds))
+        .expect("whole seconds within u32 are a valid lifetime")
 }
 
 fn bad_request(message: &Message<Attribute>) -> Message<Attribute> {
@@ -571,6 +692,7 @@ pub enum Error {
     BadChannelData(io::Error),
     DecodeStun(bytecodec::Error),
     UnknownMessageType(u8),
+    InvalidIndication(&'static str),
     Eof,
 }
 
@@ -589,13 +711,31 @@ impl From<io::Error> for Error {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::time::Duration;
 
     #[test]
     fn requested_lifetime_is_capped_at_max_lifetime() {
         let requested_lifetime = Lifetime::new(Duration::from_secs(10_000_000)).unwrap();
 
-        let effective_lifetime = compute_effective_lifetime(Some(&requested_lifetime));
+        let config = ServerConfig::default();
+
+        let effective_lifetime = compute_effective_lifetime(Some(&requested_lifetime), &config);
+
+        assert_eq!(
+            effective_lifetime.lifetime(),
+            config.max_allocation_lifetime
+        )
+    }
+
+    #[test]
+    fn missing_lifetime_uses_configured_default() {
+        let config = ServerConfig {
+            default_allocation_lifetime: Duration::from_secs(120),
+            ..ServerConfig::default()
+        };
+
+        let effective_lifetime = compute_effective_lifetime(None, &config);
 
-        assert_eq!(effective_lifetime.lifetime(), MAX_ALLOCATION_LIFETIME)
+        assert_eq!(effective_lifetime.lifetime(), Duration::from_secs(120))
     }
 }
@@ -0,0 +1,276 @@
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Deserializer};
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// The longest lifetime that can be expressed in a `LIFETIME` attribute.
+///
+/// See <https://www.rfc-editor.org/rfc/rfc8656#name-lifetime>.
+pub(crate) const MAX_ALLOCATION_LIFETIME: Duration = Duration::from_secs(u32::MAX as u64);
+
+/// The policies of a [`Server`](crate::Server).
+///
+/// The defaults follow the recommendations of the [TURN RFC](https://www.rfc-editor.org/rfc/rfc8656).
+/// Deployments with many short-lived (e.g. mobile) clients may want shorter lifetimes whereas long-lived servers may want longer ones.
+///
+/// A [`ServerConfig`] can be read from a TOML file with the same keys as the fields, durations are given in seconds with a `_secs` suffix:
+///
+/// ```toml
+/// default_allocation_lifetime_secs = 300
+/// max_allocations_per_username = 10
+/// excluded_ports = ["50000-50100", "60000"]
+/// ```
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// The lifetime of an allocation if the client doesn't request one.
+    ///
+    /// See <https://www.rfc-editor.org/rfc/rfc8656#name-allocations-2>.
+    #[serde(
+        rename = "default_allocation_lifetime_secs",
+        deserialize_with = "deserialize_secs"
+    )]
+    pub default_allocation_lifetime: Duration,
+    /// The maximum lifetime of an allocation, requested lifetimes are capped at this value.
+    #[serde(
+        rename = "max_allocation_lifetime_secs",
+        deserialize_with = "deserialize_secs"
+    )]
+    pub max_allocation_lifetime: Duration,
+    /// How many allocations a single username may hold at the same time.
+    pub max_allocations_per_username: Option<usize>,
+    /// How many channels a single allocation may have at the same time, including channels that are waiting to be rebound.
+    pub max_channels_per_allocation: Option<usize>,
+    /// The duration of a channel binding.
+    ///
+    /// See <https://www.rfc-editor.org/rfc/rfc8656#name-channels-2>.
+    #[serde(
+        rename = "channel_binding_duration_secs",
+        deserialize_with = "deserialize_secs"
+    )]
+    pub channel_binding_duration: Duration,
+    /// The timeout before an expired channel can be rebound.
+    ///
+    /// See <https://www.rfc-editor.org/rfc/rfc8656#section-12-14>.
+    #[serde(
+        rename = "channel_rebind_timeout_secs",
+        deserialize_with = "deserialize_secs"
+    )]
+    pub channel_rebind_timeout: Duration,
+    /// How many requests a client can perform with the same nonce.
+    pub nonce_num_requests: u64,
+    /// How long a nonce is valid, regardless of how often it has been used.
+    #[serde(rename = "nonce_lifetime_secs", deserialize_with = "deserialize_secs")]
+    pub nonce_lifetime: Duration,
+    /// How long the usage of a username is remembered after its last allocation expired.
+    ///
+    /// Quotas of a username can only be reset by not using it for this long, deleting and re-creating allocations doesn't reset them.
+    #[serde(
+        rename = "username_usage_retention_secs",
+        deserialize_with = "deserialize_secs"
+    )]
+    pub username_usage_retention: Duration,
+    /// Ports within the allocation port range that must not be used for allocations, e.g. because other services listen on them.
+    pub excluded_ports: Vec<PortRange>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            default_allocation_lifetime: Duration::from_secs(600),
+            max_allocation_lifetime: Duration::from_secs(3600),
+            max_allocations_per_username: None,
+            max_channels_per_allocation: None,
+            channel_binding_duration: Duration::from_secs(600),
+            channel_rebind_timeout: Duration::from_secs(300),
+            nonce_num_requests: 100,
+            nonce_lifetime: Duration::from_secs(3600),
+            username_usage_retention: Duration::from_secs(24 * 3600),
+            excluded_ports: Vec::new(),
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Parses a [`ServerConfig`] from TOML, using the defaults for all missing keys.
+    pub fn from_toml(toml: &str) -> Result<Self> {
+        let config = toml::from_str::<Self>(toml).context("Failed to parse config")?;
+
+        Ok(config)
+    }
+
+    /// Checks that the policies are consistent with each other.
+    pub fn validate(&self) -> Result<()> {
+        if self.default_allocation_lifetime.is_zero() {
+            bail!("The default allocation lifetime must not be zero");
+        }
+        if self.default_allocation_lifetime > self.max_allocation_lifetime {
+            bail!(
+                "The default allocation lifetime must not exceed the maximum allocation lifetime"
+            );
+        }
+        if self.max_allocation_lifetime > MAX_ALLOCATION_LIFETIME {
+            bail!(
+                "The maximum allocation lifetime must not exceed {} seconds",
+                MAX_ALLOCATION_LIFETIME.as_secs()
+            );
+        }
+        if self.channel_binding_duration.is_zero() {
+            bail!("The channel binding duration must not be zero");
+        }
+        if self.nonce_num_requests == 0 {
+            bail!("Nonces must be valid for at least one request");
+        }
+        if self.nonce_lifetime.is_zero() {
+            bail!("The nonce lifetime must not be zero");
+        }
+
+        Ok(())
+    }
+
+    /// Whether the given port must not be used for allocations.
+    pub(crate) fn is_excluded(&self, port: u16) -> bool {
+        self.excluded_ports.iter().any(|range| range.contains(port))
+    }
+}
+
+/// An inclusive range of ports, parsed from `<port>` or `<lowest>-<highest>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct PortRange {
+    pub lowest: u16,
+    pub highest: u16,
+}
+
+impl PortRange {
+    pub fn contains(&self, port: u16) -> bool {
+        (self.lowest..=self.highest).contains(&port)
+    }
+}
+
+impl FromStr for PortRange {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (lowest, highest) = s.split_once('-').unwrap_or((s, s));
+
+        let lowest = lowest.trim().parse().context("Invalid lowest port")?;
+        let highest = highest.trim().parse().context("Invalid highest port")?;
+
+        if lowest > highest {
+            bail!("Lowest port {lowest} is greater than highest port {highest}");
+        }
+
+        Ok(Self { lowest, highest })
+    }
+}
+
+impl TryFrom<String> for PortRange {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self> {
+        value.parse()
+    }
+}
+
+impl fmt::Display for PortRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.lowest == self.highest {
+            return write!(f, "{}", self.lowest);
+        }
+
+        write!(f, "{}-{}", self.lowest, self.highest)
+    }
+}
+
+fn deserialize_secs<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(Duration::from_secs(u64::deserialize(deserializer)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_toml_yields_defaults() {
+        let config = ServerConfig::from_toml("").unwrap();
+
+        assert_eq!(config, ServerConfig::default());
+    }
+
+    #[test]
+    fn parses_toml() {
+        let config = ServerConfig::from_toml(
+            r#"
+            default_allocation_lifetime_secs = 300
+            max_allocation_lifetime_secs = 1200
+            max_allocations_per_username = 5
+            max_channels_per_allocation = 20
+            nonce_lifetime_secs = 60
+            excluded_ports = ["50000-50100", "60000"]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config,
+            ServerConfig {
+                default_allocation_lifetime: Duration::from_secs(300),
+                max_allocation_lifetime: Duration::from_secs(1200),
+                max_allocations_per_username: Some(5),
+                max_channels_per_allocation: Some(20),
+                nonce_lifetime: Duration::from_secs(60),
+                excluded_ports: vec![
+                    PortRange {
+                        lowest: 50000,
+                        highest: 50100
+                    },
+                    PortRange {
+                        lowest: 60000,
+                        highest: 60000
+                    }
+                ],
+                ..ServerConfig::default()
+            }
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_keys() {
+        assert!(ServerConfig::from_toml("allocation_lifetime = 300").is_err());
+    }
+
+    #[test]
+    fn rejects_inverted_port_range() {
+        assert!("60000-50000".parse::<PortRange>().is_err());
+    }
+
+    #[test]
+    fn default_lifetime_must_not_exceed_max_lifetime() {
+        let config = ServerConfig {
+            default_allocation_lifetime: Duration::from_secs(7200),
+            ..ServerConfig::default()
+        };
+
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn max_lifetime_must_fit_into_lifetime_attribute() {
+        let config = ServerConfig {
+            max_allocation_lifetime: MAX_ALLOCATION_LIFETIME + Duration::from_secs(1),
+            ..ServerConfig::default()
+        };
+
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn default_config_is_valid() {
+        ServerConfig::default().validate().unwrap();
+    }
+}
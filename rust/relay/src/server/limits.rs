@@ -0,0 +1,340 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+use std::time::Instant;
+
+/// The largest datagram we may have to relay.
+///
+/// Token buckets always hold at least this many tokens so that a low rate slows down large datagrams instead of dropping them forever.
+const MAX_DATAGRAM_SIZE: u64 = 65535;
+
+/// Limits for the data relayed through a [`Server`](crate::Server).
+///
+/// Limits apply to the sum of data relayed in both directions, i.e. client-to-peer and peer-to-client.
+/// Data exceeding a limit is dropped.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Limits {
+    /// The limit for each individual allocation.
+    pub per_allocation: Limit,
+    /// The limit across all allocations made with the same username.
+    pub per_username: Limit,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Limit {
+    /// The sustained rate in bytes per second.
+    ///
+    /// Bursts of up to one second worth of data (but at least one maximum-size datagram) are allowed.
+    pub bytes_per_second: Option<u64>,
+    /// The total number of bytes that may be relayed.
+    pub quota_bytes: Option<u64>,
+}
+
+/// Why data could not be relayed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Exceeded {
+    Rate,
+    Quota,
+}
+
+impl Exceeded {
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            Exceeded::Rate => "rate_limit",
+            Exceeded::Quota => "quota",
+        }
+    }
+}
+
+/// Tracks the usage of a [`Limit`].
+#[derive(Debug)]
+pub(crate) struct Usage {
+    bucket: Option<TokenBucket>,
+    quota_bytes: Option<u64>,
+    bytes_relayed: u64,
+}
+
+impl Usage {
+    pub(crate) fn new(limit: Limit, now: Instant) -> Self {
+        Self {
+            bucket: limit
+                .bytes_per_second
+                .map(|rate| TokenBucket::new(rate, now)),
+            quota_bytes: limit.quota_bytes,
+            bytes_relayed: 0,
+        }
+    }
+
+    /// Checks whether we can relay `num_bytes` without recording them.
+    ///
+    /// This allows checking several [`Usage`]s before committing to any of them.
+    pub(crate) fn check(&mut self, num_bytes: usize, now: Instant) -> Result<(), Exceeded> {
+        if self
+            .quota_bytes
+            .is_some_and(|quota| self.bytes_relayed + num_bytes as u64 > quota)
+        {
+            return Err(Exceeded::Quota);
+        }
+
+        if let Some(bucket) = self.bucket.as_mut() {
+            bucket.refill(now);
+
+            if !bucket.has(num_bytes) {
+                return Err(Exceeded::Rate);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Records that we relayed `num_bytes`.
+    ///
+    /// Must only be called after a successful [`Usage::check`].
+    pub(crate) fn record(&mut self, num_bytes: usize) {
+        self.bytes_relayed += num_bytes as u64;
+
+        if let Some(bucket) = self.bucket.as_mut() {
+            bucket.consume(num_bytes);
+        }
+    }
+
+    pub(crate) fn bytes_relayed(&self) -> u64 {
+        self.bytes_relayed
+    }
+
+    pub(crate) fn is_quota_exhausted(&self) -> bool {
+        self.quota_bytes
+            .is_some_and(|quota| self.bytes_relayed >= quota)
+    }
+}
+
+/// The usage of all usernames, shared between all [`Server`](crate::Server)s of a relay.
+///
+/// Each worker of a relay runs its own [`Server`](crate::Server) and the kernel spreads the allocations of a username across them.
+/// Sharing their usage ensures a username cannot exceed its [`Limits::per_username`] by using several workers.
+#[derive(Debug, Clone, Default)]
+pub struct UsernameUsages {
+    inner: Arc<Mutex<HashMap<String, Weak<Mutex<UsernameUsage>>>>>,
+}
+
+impl UsernameUsages {
+    /// Returns the usage of `username` if any server still tracks it.
+    pub(crate) fn get(&self, username: &str) -> Option<SharedUsernameUsage> {
+        let usages = self.inner.lock().unwrap();
+
+        usages
+            .get(username)
+            .and_then(Weak::upgrade)
+            .map(SharedUsernameUsage)
+    }
+
+    /// Returns the usage of `username`, starting to track it with the given limit if no server does so yet.
+    ///
+    /// A username is tracked for as long as any server holds on to its usage.
+    pub(crate) fn get_or_insert(
+        &self,
+        username: &str,
+        limit: Limit,
+        now: Instant,
+    ) -> SharedUsernameUsage {
+        let mut usages = self.inner.lock().unwrap();
+
+        if let Some(usage) = usages.get(username).and_then(Weak::upgrade) {
+            return SharedUsernameUsage(usage);
+        }
+
+        usages.retain(|_, usage| usage.strong_count() > 0);
+
+        let usage = Arc::new(Mutex::new(UsernameUsage {
+            usage: Usage::new(limit, now),
+            num_allocations: 0,
+            expires_at: now,
+        }));
+        usages.insert(username.to_owned(), Arc::downgrade(&usage));
+
+        SharedUsernameUsage(usage)
+    }
+}
+
+/// A handle to the usage of a username, see [`UsernameUsages`].
+#[derive(Debug, Clone)]
+pub(crate) struct SharedUsernameUsage(Arc<Mutex<UsernameUsage>>);
+
+impl SharedUsernameUsage {
+    pub(crate) fn lock(&self) -> MutexGuard<'_, UsernameUsage> {
+        self.0.lock().unwrap()
+    }
+}
+
+/// The usage of a username across all its allocations.
+///
+/// This is tracked until [`ServerConfig::username_usage_retention`](crate::ServerConfig::username_usage_retention) after the last allocation of the username expired.
+#[derive(Debug)]
+pub(crate) struct UsernameUsage {
+    pub(crate) usage: Usage,
+    /// The allocations of this username across all servers.
+    pub(crate) num_allocations: usize,
+    /// When we can forget about this username once it no longer holds any allocations.
+    pub(crate) expires_at: Instant,
+}
+
+impl UsernameUsage {
+    pub(crate) fn retain_until(&mut self, expires_at: Instant) {
+        self.expires_at = self.expires_at.max(expires_at);
+    }
+}
+
+/// A classic token bucket, refilled at a constant rate and capped at one second worth of tokens or [`MAX_DATAGRAM_SIZE`], whichever is larger.
+#[derive(Debug)]
+struct TokenBucket {
+    bytes_per_second: u64,
+    capacity: u64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(bytes_per_second: u64, now: Instant) -> Self {
+        let capacity = bytes_per_second.max(MAX_DATAGRAM_SIZE);
+
+        Self {
+            bytes_per_second,
+            capacity,
+            tokens: capacity as f64,
+            last_refill: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last_refill);
+
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * self.bytes_per_second as f64)
+            .min(self.capacity as f64);
+        self.last_refill = now.max(self.last_refill);
+    }
+
+    fn has(&self, num_bytes: usize) -> bool {
+        self.tokens >= num_bytes as f64
+    }
+
+    fn consume(&mut self, num_bytes: usize) {
+        self.tokens -= num_bytes as f64;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn unlimited_usage_always_passes() {
+        let now = Instant::now();
+        let mut usage = Usage::new(Limit::default(), now);
+
+        for _ in 0..1000 {
+            usage.check(65535, now).unwrap();
+            usage.record(65535);
+        }
+
+        assert!(!usage.is_quota_exhausted());
+    }
+
+    #[test]
+    fn quota_is_enforced() {
+        let now = Instant::now();
+        let mut usage = Usage::new(
+            Limit {
+                bytes_per_second: None,
+                quota_bytes: Some(1000),
+            },
+            now,
+        );
+
+        usage.check(600, now).unwrap();
+        usage.record(600);
+
+        assert_eq!(usage.check(600, now), Err(Exceeded::Quota));
+
+        usage.check(400, now).unwrap();
+        usage.record(400);
+
+        assert!(usage.is_quota_exhausted());
+    }
+
+    #[test]
+    fn rate_is_enforced_and_refilled_over_time() {
+        let now = Instant::now();
+        let mut usage = Usage::new(
+            Limit {
+                bytes_per_second: Some(100_000),
+                quota_bytes: None,
+            },
+            now,
+        );
+
+        usage.check(100_000, now).unwrap();
+        usage.record(100_000);
+
+        assert_eq!(usage.check(1, now), Err(Exceeded::Rate));
+
+        let now = now + Duration::from_millis(500);
+
+        usage.check(50_000, now).unwrap();
+        assert_eq!(usage.check(50_001, now), Err(Exceeded::Rate));
+    }
+
+    #[test]
+    fn bucket_does_not_accumulate_more_than_one_second() {
+        let now = Instant::now();
+        let mut usage = Usage::new(
+            Limit {
+                bytes_per_second: Some(100_000),
+                quota_bytes: None,
+            },
+            now,
+        );
+
+        let now = now + Duration::from_secs(60);
+
+        assert_eq!(usage.check(100_001, now), Err(Exceeded::Rate));
+    }
+
+    #[test]
+    fn low_rate_still_admits_max_size_datagram() {
+        let now = Instant::now();
+        let mut usage = Usage::new(
+            Limit {
+                bytes_per_second: Some(1000),
+                quota_bytes: None,
+            },
+            now,
+        );
+
+        usage.check(65535, now).unwrap();
+        usage.record(65535);
+
+        assert_eq!(usage.check(1000, now), Err(Exceeded::Rate));
+
+        let now = now + Duration::from_secs(66);
+
+        usage.check(65535, now).unwrap();
+    }
+
+    #[test]
+    fn check_does_not_record_usage() {
+        let now = Instant::now();
+        let mut usage = Usage::new(
+            Limit {
+                bytes_per_second: Some(1000),
+                quota_bytes: Some(1000),
+            },
+            now,
+        );
+
+        usage.check(1000, now).unwrap();
+        usage.check(1000, now).unwrap();
+
+        assert_eq!(usage.bytes_relayed(), 0);
+        assert!(!usage.is_quota_exhausted());
+    }
+}
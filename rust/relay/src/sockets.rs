@@ -1,3 +1,4 @@
+use crate::IcmpError;
 use anyhow::{bail, Result};
 use std::{
     collections::HashMap,
@@ -60,8 +61,25 @@ impl Sockets {
     ///  - full (not expected to happen in production)
     ///  - disconnected (we can't operate without the [`mio`] worker thread)
     pub fn bind(&mut self, port: u16, address_family: AddressFamily) -> Result<()> {
-        self.cmd_tx
-            .try_send(Command::NewSocket((port, address_family)))?;
+        self.cmd_tx.try_send(Command::NewSocket {
+            port,
+            address_family,
+            reuse_port: false,
+        })?;
+
+        Ok(())
+    }
+
+    /// Like [`Sockets::bind`] but sets `SO_REUSEPORT` on the socket.
+    ///
+    /// This allows other instances of [`Sockets`] (i.e. other threads) to bind the same port.
+    /// The kernel distributes incoming packets across all sockets bound to the port based on the hash of their 4-tuple, meaning all packets from one remote address end up on the same socket.
+    pub fn bind_shared(&mut self, port: u16, address_family: AddressFamily) -> Result<()> {
+        self.cmd_tx.try_send(Command::NewSocket {
+            port,
+            address_family,
+            reuse_port: true,
+        })?;
 
         Ok(())
     }
@@ -110,6 +128,8 @@ impl Sockets {
         loop {
             if let Some(current) = self.current_ready_socket {
                 if let Some(socket) = self.inner.get(&current) {
+                    let (port, _) = token_to_port_and_address_family(current);
+
                     let (num_bytes, from) = match socket.recv_from(buf) {
                         Ok(ok) => ok,
                         Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
@@ -117,13 +137,22 @@ impl Sockets {
                             continue;
                         }
                         Err(e) => {
+                            // ICMP errors are reported as a socket error, the details are on the socket's error queue.
+                            match recv_icmp_error(socket) {
+                                Ok(Some((dest, error))) => {
+                                    return Poll::Ready(Err(Error::Icmp { port, dest, error }));
+                                }
+                                Ok(None) => {}
+                                Err(e) => {
+                                    tracing::debug!(target: "relay", %port, "Failed to read error queue: {e}");
+                                }
+                            }
+
                             self.current_ready_socket = None;
                             return Poll::Ready(Err(Error::Io(e)));
                         }
                     };
 
-                    let (port, _) = token_to_port_and_address_family(current);
-
                     return Poll::Ready(Ok(Received {
                         port,
                         from,
@@ -163,11 +192,21 @@ pub struct Received<'a> {
 #[derive(Debug)]
 pub enum Error {
     Io(io::Error),
+    /// We received an ICMP error in response to a packet we sent from `port` to `dest`.
+    Icmp {
+        port: u16,
+        dest: SocketAddr,
+        error: IcmpError,
+    },
     MioTaskCrashed(anyhow::Error),
 }
 
 enum Command {
-    NewSocket((u16, AddressFamily)),
+    NewSocket {
+        port: u16,
+        address_family: AddressFamily,
+        reuse_port: bool,
+    },
     DisposeSocket(mio::net::UdpSocket),
 }
 
@@ -206,8 +245,13 @@ fn mio_worker_task(
             match cmd_rx.try_recv() {
                 Err(mpsc::error::TryRecvError::Empty) => break, // Drain all events from the channel until it is empty.
 
-                Ok(Command::NewSocket((port, af))) => {
-                    let mut socket = mio::net::UdpSocket::from_std(make_wildcard_socket(af, port)?);
+                Ok(Command::NewSocket {
+                    port,
+                    address_family: af,
+                    reuse_port,
+                }) => {
+                    let mut socket =
+                        mio::net::UdpSocket::from_std(make_wildcard_socket(af, port, reuse_port)?);
                     let token = token_from_port_and_address_family(port, af);
 
                     poll.registry()
@@ -255,7 +299,13 @@ fn token_to_port_and_address_family(token: mio::Token) -> (u16, AddressFamily) {
 /// Creates an [std::net::UdpSocket] via the [socket2] library that is configured for our needs.
 ///
 /// Most importantly, this sets the `IPV6_V6ONLY` flag to ensure we disallow IP4-mapped IPv6 addresses and can bind to IP4 and IP6 addresses on the same port.
-fn make_wildcard_socket(family: AddressFamily, port: u16) -> io::Result<std::net::UdpSocket> {
+/// It also enables `IP_RECVERR` so we learn about ICMP errors, see [`recv_icmp_error`].
+/// If `reuse_port` is set, the socket is created with `SO_REUSEPORT`, see [`Sockets::bind_shared`].
+fn make_wildcard_socket(
+    family: AddressFamily,
+    port: u16,
+    reuse_port: bool,
+) -> io::Result<std::net::UdpSocket> {
     use socket2::*;
 
     let domain = match family {
@@ -271,9 +321,127 @@ fn make_wildcard_socket(family: AddressFamily, port: u16) -> io::Result<std::net
     if family == AddressFamily::V6 {
         socket.set_only_v6(true)?;
     }
+    if reuse_port {
+        socket.set_reuse_port(true)?;
+    }
+    set_recv_err(&socket, family)?;
 
     socket.set_nonblocking(true)?;
     socket.bind(&SockAddr::from(SocketAddr::new(address, port)))?;
 
     Ok(socket.into())
 }
+
+/// Enables queueing of ICMP errors on the socket's error queue.
+#[cfg(target_os = "linux")]
+fn set_recv_err(socket: &socket2::Socket, family: AddressFamily) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let (level, name) = match family {
+        AddressFamily::V4 => (libc::SOL_IP, libc::IP_RECVERR),
+        AddressFamily::V6 => (libc::SOL_IPV6, libc::IPV6_RECVERR),
+    };
+    let enable: libc::c_int = 1;
+
+    // SAFETY: We pass a valid pointer to a `c_int` together with its size.
+    let ret = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            level,
+            name,
+            &enable as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of_val(&enable) as libc::socklen_t,
+        )
+    };
+
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_recv_err(_: &socket2::Socket, _: AddressFamily) -> io::Result<()> {
+    Ok(())
+}
+
+/// Reads the next ICMP error from the socket's error queue.
+///
+/// Returns the destination of the packet that triggered the error together with the error itself.
+/// Returns [`None`] if the queue is empty or the error didn't originate from an ICMP message.
+#[cfg(target_os = "linux")]
+fn recv_icmp_error(socket: &mio::net::UdpSocket) -> io::Result<Option<(SocketAddr, IcmpError)>> {
+    use std::os::fd::AsRawFd;
+
+    // SAFETY: All-zero is a valid bit-pattern for these C structs.
+    let mut name: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    let mut control = [0u64; 64]; // `u64` to satisfy the alignment of `cmsghdr`.
+
+    msg.msg_name = &mut name as *mut libc::sockaddr_storage as *mut libc::c_void;
+    msg.msg_namelen = std::mem::size_of_val(&name) as libc::socklen_t;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = std::mem::size_of_val(&control) as _;
+
+    // SAFETY: `msg` points to buffers that outlive this call. We don't need the original packet so we don't pass any `iovec`s.
+    let ret = unsafe {
+        libc::recvmsg(
+            socket.as_raw_fd(),
+            &mut msg,
+            libc::MSG_ERRQUEUE | libc::MSG_DONTWAIT,
+        )
+    };
+
+    if ret < 0 {
+        let e = io::Error::last_os_error();
+
+        if e.kind() == io::ErrorKind::WouldBlock {
+            return Ok(None);
+        }
+
+        return Err(e);
+    }
+
+    // SAFETY: The kernel initialised `msg_namelen` bytes of `name`.
+    let Some(dest) = unsafe { socket2::SockAddr::new(name, msg.msg_namelen) }.as_socket() else {
+        return Ok(None);
+    };
+
+    // SAFETY: `msg` has been initialised by `recvmsg` and we only read control messages within `msg_controllen`.
+    let mut cmsg = unsafe { libc::CMSG_FIRSTHDR(&msg) };
+    while !cmsg.is_null() {
+        let header = unsafe { &*cmsg };
+
+        let is_recv_err = (header.cmsg_level == libc::SOL_IP
+            && header.cmsg_type == libc::IP_RECVERR)
+            || (header.cmsg_level == libc::SOL_IPV6 && header.cmsg_type == libc::IPV6_RECVERR);
+
+        if is_recv_err {
+            let err = unsafe {
+                std::ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const libc::sock_extended_err)
+            };
+
+            if err.ee_origin == libc::SO_EE_ORIGIN_ICMP || err.ee_origin == libc::SO_EE_ORIGIN_ICMP6
+            {
+                return Ok(Some((
+                    dest,
+                    IcmpError {
+                        icmp_type: err.ee_type,
+                        icmp_code: err.ee_code,
+                        error_data: err.ee_info,
+                    },
+                )));
+            }
+        }
+
+        cmsg = unsafe { libc::CMSG_NXTHDR(&msg, cmsg) };
+    }
+
+    Ok(None)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn recv_icmp_error(_: &mio::net::UdpSocket) -> io::Result<Option<(SocketAddr, IcmpError)>> {
+    Ok(None)
+}
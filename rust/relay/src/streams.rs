@@ -0,0 +1,322 @@
+use crate::framing::{pad_to_multiple_of_four, Framer};
+use anyhow::{Context as _, Result};
+use bytes::Bytes;
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufReader},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    path::Path,
+    sync::Arc,
+    task::{Context, Poll},
+};
+use stun_codec::rfc8656::attributes::AddressFamily;
+use tokio::io::{AsyncRead, AsyncReadExt as _, AsyncWrite, AsyncWriteExt as _};
+use tokio::sync::mpsc;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::TlsAcceptor;
+
+/// How many messages we buffer for an individual connection before we start dropping them.
+const CONNECTION_SEND_BUFFER: usize = 256;
+
+const READ_BUFFER_SIZE: usize = 65536;
+
+/// A collection of stream-based listeners (TCP and TLS) for TURN clients.
+///
+/// Each accepted connection is served by its own task which de-frames the byte-stream into individual STUN and `ChannelData` messages.
+/// These are forwarded to the foreground task which can then hand them to the [`Server`](crate::Server).
+///
+/// Connections are identified by the remote's [`SocketAddr`], the same way UDP clients are.
+pub struct Streams {
+    /// The send-half of all currently active connections.
+    connections: HashMap<SocketAddr, mpsc::Sender<Vec<u8>>>,
+
+    event_tx: mpsc::Sender<Event>,
+    event_rx: mpsc::Receiver<Event>,
+}
+
+impl Default for Streams {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Streams {
+    pub fn new() -> Self {
+        let (event_tx, event_rx) = mpsc::channel(1_024);
+
+        Self {
+            connections: Default::default(),
+            event_tx,
+            event_rx,
+        }
+    }
+
+    /// Listens for TURN over TCP connections on the given port and address family.
+    ///
+    /// Must be called from within a tokio runtime.
+    pub fn listen_tcp(&mut self, port: u16, address_family: AddressFamily) -> Result<()> {
+        let listener = make_wildcard_listener(address_family, port)?;
+
+        tokio::spawn(accept_loop(listener, None, self.event_tx.clone()));
+
+        Ok(())
+    }
+
+    /// Listens for TURN over TLS connections on the given port and address family.
+    ///
+    /// Must be called from within a tokio runtime.
+    pub fn listen_tls(
+        &mut self,
+        port: u16,
+        address_family: AddressFamily,
+        acceptor: TlsAcceptor,
+    ) -> Result<()> {
+        let listener = make_wildcard_listener(address_family, port)?;
+
+        tokio::spawn(accept_loop(listener, Some(acceptor), self.event_tx.clone()));
+
+        Ok(())
+    }
+
+    /// Whether the given client is connected via one of our stream-based listeners.
+    pub fn contains(&self, client: SocketAddr) -> bool {
+        self.connections.contains_key(&client)
+    }
+
+    /// Queues a message to be sent to the given client.
+    ///
+    /// The message is padded to a multiple of 4 bytes as required for `ChannelData` messages on stream-based transports.
+    pub fn try_send(&self, client: SocketAddr, mut msg: Vec<u8>) -> io::Result<()> {
+        let connection = self
+            .connections
+            .get(&client)
+            .ok_or_else(|| not_connected(client))?;
+
+        pad_to_multiple_of_four(&mut msg);
+
+        connection.try_send(msg).map_err(|e| match e {
+            mpsc::error::TrySendError::Full(_) => {
+                io::Error::new(io::ErrorKind::WouldBlock, "send buffer is full")
+            }
+            mpsc::error::TrySendError::Closed(_) => not_connected(client),
+        })?;
+
+        Ok(())
+    }
+
+    pub fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Received> {
+        loop {
+            let event = match self.event_rx.poll_recv(cx) {
+                Poll::Ready(Some(event)) => event,
+                Poll::Ready(None) => unreachable!("we hold a sender ourselves"),
+                Poll::Pending => return Poll::Pending,
+            };
+
+            match event {
+                Event::Connected { from, sender } => {
+                    tracing::debug!(target: "relay", %from, "New stream connection");
+
+                    self.connections.insert(from, sender);
+                    continue;
+                }
+                Event::Message { from, packet } => {
+                    return Poll::Ready(Received::Message { from, packet });
+                }
+                Event::Closed { from } => {
+                    tracing::debug!(target: "relay", %from, "Stream connection closed");
+
+                    self.connections.remove(&from);
+                    return Poll::Ready(Received::Closed { from });
+                }
+            }
+        }
+    }
+}
+
+/// An event from one of our stream-based connections.
+#[derive(Debug)]
+pub enum Received {
+    /// A single, de-framed STUN or `ChannelData` message.
+    Message { from: SocketAddr, packet: Bytes },
+    /// The connection to the given client closed.
+    Closed { from: SocketAddr },
+}
+
+/// Loads a TLS certificate chain and private key from PEM files and constructs a [`TlsAcceptor`] from it.
+pub fn tls_acceptor_from_pem_files(cert_file: &Path, key_file: &Path) -> Result<TlsAcceptor> {
+    let certs = rustls_pemfile::certs(&mut BufReader::new(
+        File::open(cert_file).with_context(|| format!("Failed to open {}", cert_file.display()))?,
+    ))
+    .context("Failed to parse certificates")?
+    .into_iter()
+    .map(CertificateDer::from)
+    .collect::<Vec<_>>();
+
+    let mut key_reader = BufReader::new(
+        File::open(key_file).with_context(|| format!("Failed to open {}", key_file.display()))?,
+    );
+    let key = loop {
+        match rustls_pemfile::read_one(&mut key_reader).context("Failed to parse private key")? {
+            Some(rustls_pemfile::Item::PKCS8Key(key)) => break PrivateKeyDer::Pkcs8(key.into()),
+            Some(rustls_pemfile::Item::RSAKey(key)) => break PrivateKeyDer::Pkcs1(key.into()),
+            Some(rustls_pemfile::Item::ECKey(key)) => break PrivateKeyDer::Sec1(key.into()),
+            Some(_) => continue,
+            None => anyhow::bail!("No private key found in {}", key_file.display()),
+        }
+    };
+
+    let config = tokio_rustls::rustls::ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .context("Invalid certificate or private key")?;
+
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+enum Event {
+    Connected {
+        from: SocketAddr,
+        sender: mpsc::Sender<Vec<u8>>,
+    },
+    Message {
+        from: SocketAddr,
+        packet: Bytes,
+    },
+    Closed {
+        from: SocketAddr,
+    },
+}
+
+async fn accept_loop(
+    listener: tokio::net::TcpListener,
+    acceptor: Option<TlsAcceptor>,
+    event_tx: mpsc::Sender<Event>,
+) {
+    loop {
+        let (stream, from) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                tracing::warn!(target: "relay", "Failed to accept connection: {e}");
+                continue;
+            }
+        };
+
+        let acceptor = acceptor.clone();
+        let event_tx = event_tx.clone();
+
+        tokio::spawn(async move {
+            match acceptor {
+                None => serve_connection(stream, from, event_tx).await,
+                Some(acceptor) => match acceptor.accept(stream).await {
+                    Ok(stream) => serve_connection(stream, from, event_tx).await,
+                    Err(e) => {
+                        tracing::debug!(target: "relay", %from, "TLS handshake failed: {e}");
+                    }
+                },
+            };
+        });
+    }
+}
+
+/// Registers the connection with the foreground task and serves it until it closes.
+async fn serve_connection(
+    stream: impl AsyncRead + AsyncWrite,
+    from: SocketAddr,
+    event_tx: mpsc::Sender<Event>,
+) {
+    let (sender, receiver) = mpsc::channel::<Vec<u8>>(CONNECTION_SEND_BUFFER);
+
+    if event_tx
+        .send(Event::Connected { from, sender })
+        .await
+        .is_err()
+    {
+        return;
+    }
+
+    if let Err(e) = read_write_loop(stream, receiver, from, &event_tx).await {
+        tracing::debug!(target: "relay", %from, "Stream connection failed: {e}");
+    }
+
+    let _ = event_tx.send(Event::Closed { from }).await;
+}
+
+/// Reads messages from the stream and writes all messages we receive via the channel.
+///
+/// Returns once the remote closes the connection or an error occurs.
+async fn read_write_loop(
+    stream: impl AsyncRead + AsyncWrite,
+    mut receiver: mpsc::Receiver<Vec<u8>>,
+    from: SocketAddr,
+    event_tx: &mpsc::Sender<Event>,
+) -> io::Result<()> {
+    let (mut reader, mut writer) = tokio::io::split(stream);
+
+    let mut framer = Framer::default();
+    let mut buffer = vec![0u8; READ_BUFFER_SIZE];
+
+    loop {
+        tokio::select! {
+            num_read = reader.read(&mut buffer) => {
+                let num_read = num_read?;
+
+                if num_read == 0 {
+                    return Ok(());
+                }
+
+                framer.handle_input(&buffer[..num_read]);
+
+                while let Some(packet) = framer.next_message().map_err(|e| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("unknown message type {}", e.0),
+                    )
+                })? {
+                    event_tx
+                        .send(Event::Message { from, packet })
+                        .await
+                        .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+                }
+            }
+            Some(msg) = receiver.recv() => {
+                writer.write_all(&msg).await?;
+            }
+        }
+    }
+}
+
+fn not_connected(client: SocketAddr) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotConnected,
+        format!("No stream connection for {client}"),
+    )
+}
+
+/// Creates a [`tokio::net::TcpListener`] via the [socket2] library that is configured for our needs.
+///
+/// Like for our UDP sockets, this sets the `IPV6_V6ONLY` flag to ensure we can listen on IP4 and IP6 addresses on the same port.
+fn make_wildcard_listener(family: AddressFamily, port: u16) -> io::Result<tokio::net::TcpListener> {
+    use socket2::*;
+
+    let domain = match family {
+        AddressFamily::V4 => Domain::IPV4,
+        AddressFamily::V6 => Domain::IPV6,
+    };
+    let address = match family {
+        AddressFamily::V4 => IpAddr::from(Ipv4Addr::UNSPECIFIED),
+        AddressFamily::V6 => IpAddr::from(Ipv6Addr::UNSPECIFIED),
+    };
+
+    let socket = Socket::new(domain, Type::STREAM, Some(Protocol::TCP))?;
+    if family == AddressFamily::V6 {
+        socket.set_only_v6(true)?;
+    }
+
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&SockAddr::from(SocketAddr::new(address, port)))?;
+    socket.listen(1024)?;
+
+    tokio::net::TcpListener::from_std(socket.into())
+}
@@ -1,7 +1,9 @@
 use bytecodec::{DecodeExt, EncodeExt};
+use firezone_relay::peering::{Peering, RelayPeer};
 use firezone_relay::{
-    AddressFamily, Allocate, AllocationPort, Attribute, Binding, ChannelBind, ChannelData,
-    ClientMessage, ClientSocket, Command, IpStack, PeerSocket, Refresh, Server,
+    AddressFamily, Allocate, AllocationInfo, AllocationPort, Attribute, Binding, ChannelBind,
+    ChannelData, ClientMessage, ClientSocket, Command, CreatePermission, IcmpError, IpStack, Limit,
+    Limits, PeerSocket, PortRange, Refresh, SendIndication, Server, ServerConfig, UsernameUsages,
 };
 use rand::rngs::mock::StepRng;
 use secrecy::SecretString;
@@ -11,12 +13,16 @@ use std::time::{Duration, Instant, SystemTime};
 use stun_codec::rfc5389::attributes::{ErrorCode, Nonce, Realm, Username, XorMappedAddress};
 use stun_codec::rfc5389::errors::Unauthorized;
 use stun_codec::rfc5389::methods::BINDING;
-use stun_codec::rfc5766::attributes::{ChannelNumber, Lifetime, XorPeerAddress, XorRelayAddress};
-use stun_codec::rfc5766::methods::{ALLOCATE, CHANNEL_BIND, REFRESH};
+use stun_codec::rfc5766::attributes::{
+    ChannelNumber, Data, Lifetime, XorPeerAddress, XorRelayAddress,
+};
+use stun_codec::rfc5766::errors::{AllocationQuotaReached, InsufficientCapacity};
+use stun_codec::rfc5766::methods::{ALLOCATE, CHANNEL_BIND, CREATE_PERMISSION, DATA, REFRESH};
+use stun_codec::rfc8656::attributes::Icmp;
 use stun_codec::{Message, MessageClass, MessageDecoder, MessageEncoder, TransactionId};
 use test_strategy::proptest;
 use uuid::Uuid;
-use Output::{CreateAllocation, FreeAllocation};
+use Output::{CreateAllocation, FreeAllocation, RelayToPeer};
 
 #[proptest]
 fn can_answer_stun_request_from_ip4_address(
@@ -84,6 +90,109 @@ fn deallocate_once_time_expired(
     );
 }
 
+#[proptest]
+fn lists_and_force_deletes_allocations(
+    #[strategy(firezone_relay::proptest::transaction_id())] transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::allocation_lifetime())] lifetime: Lifetime,
+    #[strategy(firezone_relay::proptest::username_salt())] username_salt: String,
+    source: SocketAddrV4,
+    public_relay_addr: Ipv4Addr,
+    #[strategy(firezone_relay::proptest::nonce())] nonce: Uuid,
+) {
+    let now = Instant::now();
+
+    let mut server = TestServer::new(public_relay_addr).with_nonce(nonce);
+    let secret = server.auth_secret();
+    let username = valid_username(&username_salt);
+
+    server.assert_commands(
+        from_client(
+            source,
+            Allocate::new_authenticated_udp_implicit_ip4(
+                transaction_id,
+                Some(lifetime.clone()),
+                username.clone(),
+                secret,
+                nonce,
+            ),
+            now,
+        ),
+        [
+            create_allocation(49152, AddressFamily::V4),
+            send_message(
+                source,
+                allocate_response(transaction_id, public_relay_addr, 49152, source, &lifetime),
+            ),
+        ],
+    );
+
+    assert_eq!(
+        server.server.allocations(),
+        vec![AllocationInfo {
+            client: ClientSocket::new(source.into()),
+            username: username.name().to_owned(),
+            port: AllocationPort::new(49152),
+            relay_addresses: vec![IpAddr::V4(public_relay_addr)],
+            channels: vec![],
+            bytes_relayed: 0,
+            expires_at: now + lifetime.lifetime(),
+        }]
+    );
+
+    server.assert_commands(
+        force_delete_allocation(49152),
+        [free_allocation(49152, AddressFamily::V4)],
+    );
+
+    assert_eq!(server.server.allocations(), vec![]);
+    assert!(!server
+        .server
+        .force_delete_allocation(AllocationPort::new(49152)));
+}
+
+#[proptest]
+fn deallocate_once_stream_connection_closed(
+    #[strategy(firezone_relay::proptest::transaction_id())] transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::allocation_lifetime())] lifetime: Lifetime,
+    #[strategy(firezone_relay::proptest::username_salt())] username_salt: String,
+    source: SocketAddrV4,
+    public_relay_addr: Ipv4Addr,
+    #[strategy(firezone_relay::proptest::nonce())] nonce: Uuid,
+) {
+    let now = Instant::now();
+
+    let mut server = TestServer::new(public_relay_addr).with_nonce(nonce);
+    let secret = server.auth_secret().to_owned();
+
+    server.assert_commands(
+        from_client(
+            source,
+            Allocate::new_authenticated_udp_implicit_ip4(
+                transaction_id,
+                Some(lifetime.clone()),
+                valid_username(&username_salt),
+                &secret,
+                nonce,
+            ),
+            now,
+        ),
+        [
+            create_allocation(49152, AddressFamily::V4),
+            send_message(
+                source,
+                allocate_response(transaction_id, public_relay_addr, 49152, source, &lifetime),
+            ),
+        ],
+    );
+
+    server.assert_commands(
+        connection_closed(source),
+        [free_allocation(49152, AddressFamily::V4)],
+    );
+
+    assert_eq!(server.server.num_allocations(), 0);
+}
+
 #[proptest]
 fn unauthenticated_allocate_triggers_authentication(
     #[strategy(firezone_relay::proptest::transaction_id())] transaction_id: TransactionId,
@@ -277,20 +386,812 @@ fn when_receiving_lifetime_0_for_existing_allocation_then_delete(
             ),
             now,
         ),
-        [
-            free_allocation(49152, AddressFamily::V4),
-            send_message(
-                source,
-                refresh_response(
-                    refresh_transaction_id,
-                    Lifetime::new(Duration::ZERO).unwrap(),
-                ),
-            ),
-        ],
+        [
+            free_allocation(49152, AddressFamily::V4),
+            send_message(
+                source,
+                refresh_response(
+                    refresh_transaction_id,
+                    Lifetime::new(Duration::ZERO).unwrap(),
+                ),
+            ),
+        ],
+    );
+
+    // Assert that forwarding time does not produce an obsolete event.
+    server.assert_commands(forward_time_to(first_wake + Duration::from_secs(1)), []);
+}
+
+#[proptest]
+fn send_and_data_indications_are_relayed_with_permission(
+    #[strategy(firezone_relay::proptest::transaction_id())] allocate_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::transaction_id())]
+    create_permission_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::transaction_id())] send_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::username_salt())] username_salt: String,
+    source: SocketAddrV4,
+    peer: SocketAddrV4,
+    public_relay_addr: Ipv4Addr,
+    client_to_peer_ping: [u8; 32],
+    peer_to_client_pong: [u8; 32],
+    #[strategy(firezone_relay::proptest::nonce())] nonce: Uuid,
+) {
+    let now = Instant::now();
+
+    let mut server = TestServer::new(public_relay_addr).with_nonce(nonce);
+    let secret = server.auth_secret().to_owned();
+    let lifetime = Lifetime::new(Duration::from_secs(60 * 60)).unwrap();
+
+    server.assert_commands(
+        from_client(
+            source,
+            Allocate::new_authenticated_udp_implicit_ip4(
+                allocate_transaction_id,
+                Some(lifetime.clone()),
+                valid_username(&username_salt),
+                &secret,
+                nonce,
+            ),
+            now,
+        ),
+        [
+            create_allocation(49152, AddressFamily::V4),
+            send_message(
+                source,
+                allocate_response(
+                    allocate_transaction_id,
+                    public_relay_addr,
+                    49152,
+                    source,
+                    &lifetime,
+                ),
+            ),
+        ],
+    );
+
+    // Without a permission, data is discarded in both directions.
+    server.assert_commands(
+        from_client(
+            source,
+            send_indication(send_transaction_id, peer, &client_to_peer_ping),
+            now,
+        ),
+        [],
+    );
+    server.assert_commands(from_peer(peer, &peer_to_client_pong, 49152, now), []);
+
+    server.assert_commands(
+        from_client(
+            source,
+            CreatePermission::new(
+                create_permission_transaction_id,
+                vec![XorPeerAddress::new(peer.into())],
+                valid_username(&username_salt),
+                &secret,
+                nonce,
+            ),
+            now,
+        ),
+        [send_message(
+            source,
+            create_permission_response(create_permission_transaction_id),
+        )],
+    );
+
+    server.assert_commands(
+        from_client(
+            source,
+            send_indication(send_transaction_id, peer, &client_to_peer_ping),
+            now,
+        ),
+        [relay_to_peer(49152, peer, &client_to_peer_ping)],
+    );
+    server.assert_commands(
+        from_peer(peer, &peer_to_client_pong, 49152, now),
+        [send_message(
+            source,
+            data_indication(peer, &peer_to_client_pong),
+        )],
+    );
+
+    // Permissions expire after 5 minutes.
+    let now = now + Duration::from_secs(5 * 60);
+
+    server.assert_commands(
+        from_client(
+            source,
+            send_indication(send_transaction_id, peer, &client_to_peer_ping),
+            now,
+        ),
+        [],
+    );
+    server.assert_commands(from_peer(peer, &peer_to_client_pong, 49152, now), []);
+}
+
+#[proptest]
+fn icmp_errors_are_forwarded_with_permission(
+    #[strategy(firezone_relay::proptest::transaction_id())] allocate_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::transaction_id())]
+    create_permission_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::username_salt())] username_salt: String,
+    source: SocketAddrV4,
+    peer: SocketAddrV4,
+    public_relay_addr: Ipv4Addr,
+    #[strategy(firezone_relay::proptest::nonce())] nonce: Uuid,
+) {
+    let now = Instant::now();
+
+    let mut server = TestServer::new(public_relay_addr).with_nonce(nonce);
+    let secret = server.auth_secret().to_owned();
+    let lifetime = Lifetime::new(Duration::from_secs(60 * 60)).unwrap();
+    let port_unreachable = IcmpError {
+        icmp_type: 3,
+        icmp_code: 3,
+        error_data: 0,
+    };
+
+    server.assert_commands(
+        from_client(
+            source,
+            Allocate::new_authenticated_udp_implicit_ip4(
+                allocate_transaction_id,
+                Some(lifetime.clone()),
+                valid_username(&username_salt),
+                &secret,
+                nonce,
+            ),
+            now,
+        ),
+        [
+            create_allocation(49152, AddressFamily::V4),
+            send_message(
+                source,
+                allocate_response(
+                    allocate_transaction_id,
+                    public_relay_addr,
+                    49152,
+                    source,
+                    &lifetime,
+                ),
+            ),
+        ],
+    );
+
+    // Without a permission, ICMP errors are discarded.
+    server.assert_commands(icmp_error_from_peer(peer, port_unreachable, 49152, now), []);
+
+    server.assert_commands(
+        from_client(
+            source,
+            CreatePermission::new(
+                create_permission_transaction_id,
+                vec![XorPeerAddress::new(peer.into())],
+                valid_username(&username_salt),
+                &secret,
+                nonce,
+            ),
+            now,
+        ),
+        [send_message(
+            source,
+            create_permission_response(create_permission_transaction_id),
+        )],
+    );
+
+    server.assert_commands(
+        icmp_error_from_peer(peer, port_unreachable, 49152, now),
+        [send_message(
+            source,
+            icmp_data_indication(peer, port_unreachable),
+        )],
+    );
+
+    // Errors unrelated to the peer, like redirects, are discarded.
+    server.assert_commands(
+        icmp_error_from_peer(
+            peer,
+            IcmpError {
+                icmp_type: 5,
+                icmp_code: 1,
+                error_data: 0,
+            },
+            49152,
+            now,
+        ),
+        [],
+    );
+}
+
+#[proptest]
+fn draining_server_rejects_new_allocations_but_keeps_existing_ones(
+    #[strategy(firezone_relay::proptest::transaction_id())] first_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::transaction_id())] second_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::transaction_id())] refresh_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::username_salt())] username_salt: String,
+    first_source: SocketAddrV4,
+    second_source: SocketAddrV4,
+    public_relay_addr: Ipv4Addr,
+    #[strategy(firezone_relay::proptest::nonce())] nonce: Uuid,
+) {
+    proptest::prop_assume!(first_source != second_source);
+
+    let now = Instant::now();
+
+    let mut server = TestServer::new(public_relay_addr).with_nonce(nonce);
+    let secret = server.auth_secret().to_owned();
+    let lifetime = Lifetime::new(Duration::from_secs(60 * 60)).unwrap();
+
+    server.assert_commands(
+        from_client(
+            first_source,
+            Allocate::new_authenticated_udp_implicit_ip4(
+                first_transaction_id,
+                Some(lifetime.clone()),
+                valid_username(&username_salt),
+                &secret,
+                nonce,
+            ),
+            now,
+        ),
+        [
+            create_allocation(49152, AddressFamily::V4),
+            send_message(
+                first_source,
+                allocate_response(
+                    first_transaction_id,
+                    public_relay_addr,
+                    49152,
+                    first_source,
+                    &lifetime,
+                ),
+            ),
+        ],
+    );
+
+    server.start_draining();
+
+    server.assert_commands(
+        from_client(
+            second_source,
+            Allocate::new_authenticated_udp_implicit_ip4(
+                second_transaction_id,
+                Some(lifetime.clone()),
+                valid_username(&username_salt),
+                &secret,
+                nonce,
+            ),
+            now,
+        ),
+        [send_message(
+            second_source,
+            insufficient_capacity_allocate_response(second_transaction_id),
+        )],
+    );
+    server.assert_commands(
+        from_client(
+            first_source,
+            Refresh::new(
+                refresh_transaction_id,
+                Some(lifetime.clone()),
+                valid_username(&username_salt),
+                &secret,
+                nonce,
+            ),
+            now,
+        ),
+        [send_message(
+            first_source,
+            refresh_response(refresh_transaction_id, lifetime.clone()),
+        )],
+    );
+}
+
+#[proptest]
+fn data_exceeding_allocation_quota_is_dropped(
+    #[strategy(firezone_relay::proptest::transaction_id())] allocate_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::transaction_id())]
+    create_permission_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::transaction_id())] send_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::username_salt())] username_salt: String,
+    source: SocketAddrV4,
+    peer: SocketAddrV4,
+    public_relay_addr: Ipv4Addr,
+    client_to_peer_ping: [u8; 32],
+    peer_to_client_pong: [u8; 32],
+    #[strategy(firezone_relay::proptest::nonce())] nonce: Uuid,
+) {
+    let now = Instant::now();
+
+    let mut server = TestServer::new(public_relay_addr)
+        .with_nonce(nonce)
+        .with_limits(Limits {
+            per_allocation: Limit {
+                bytes_per_second: None,
+                quota_bytes: Some(48),
+            },
+            per_username: Limit::default(),
+        });
+    let secret = server.auth_secret().to_owned();
+    let lifetime = Lifetime::new(Duration::from_secs(60 * 60)).unwrap();
+
+    server.assert_commands(
+        from_client(
+            source,
+            Allocate::new_authenticated_udp_implicit_ip4(
+                allocate_transaction_id,
+                Some(lifetime.clone()),
+                valid_username(&username_salt),
+                &secret,
+                nonce,
+            ),
+            now,
+        ),
+        [
+            create_allocation(49152, AddressFamily::V4),
+            send_message(
+                source,
+                allocate_response(
+                    allocate_transaction_id,
+                    public_relay_addr,
+                    49152,
+                    source,
+                    &lifetime,
+                ),
+            ),
+        ],
+    );
+    server.assert_commands(
+        from_client(
+            source,
+            CreatePermission::new(
+                create_permission_transaction_id,
+                vec![XorPeerAddress::new(peer.into())],
+                valid_username(&username_salt),
+                &secret,
+                nonce,
+            ),
+            now,
+        ),
+        [send_message(
+            source,
+            create_permission_response(create_permission_transaction_id),
+        )],
+    );
+
+    server.assert_commands(
+        from_client(
+            source,
+            send_indication(send_transaction_id, peer, &client_to_peer_ping),
+            now,
+        ),
+        [relay_to_peer(49152, peer, &client_to_peer_ping)],
+    );
+
+    // The quota applies to both directions: 32 + 32 bytes exceeds the quota of 48 bytes.
+    server.assert_commands(from_peer(peer, &peer_to_client_pong, 49152, now), []);
+    server.assert_commands(
+        from_client(
+            source,
+            send_indication(send_transaction_id, peer, &client_to_peer_ping),
+            now,
+        ),
+        [],
+    );
+}
+
+#[proptest]
+fn exhausted_username_quota_survives_deleting_the_allocation(
+    #[strategy(firezone_relay::proptest::transaction_id())] allocate_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::transaction_id())]
+    create_permission_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::transaction_id())] send_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::transaction_id())] refresh_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::transaction_id())] delete_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::transaction_id())]
+    second_allocate_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::username_salt())] username_salt: String,
+    source: SocketAddrV4,
+    peer: SocketAddrV4,
+    public_relay_addr: Ipv4Addr,
+    client_to_peer_ping: [u8; 32],
+    #[strategy(firezone_relay::proptest::nonce())] nonce: Uuid,
+) {
+    let now = Instant::now();
+
+    let mut server = TestServer::new(public_relay_addr)
+        .with_nonce(nonce)
+        .with_limits(Limits {
+            per_allocation: Limit::default(),
+            per_username: Limit {
+                bytes_per_second: None,
+                quota_bytes: Some(32),
+            },
+        });
+    let secret = server.auth_secret().to_owned();
+    let lifetime = Lifetime::new(Duration::from_secs(60 * 60)).unwrap();
+
+    server.assert_commands(
+        from_client(
+            source,
+            Allocate::new_authenticated_udp_implicit_ip4(
+                allocate_transaction_id,
+                Some(lifetime.clone()),
+                valid_username(&username_salt),
+                &secret,
+                nonce,
+            ),
+            now,
+        ),
+        [
+            create_allocation(49152, AddressFamily::V4),
+            send_message(
+                source,
+                allocate_response(
+                    allocate_transaction_id,
+                    public_relay_addr,
+                    49152,
+                    source,
+                    &lifetime,
+                ),
+            ),
+        ],
+    );
+    server.assert_commands(
+        from_client(
+            source,
+            CreatePermission::new(
+                create_permission_transaction_id,
+                vec![XorPeerAddress::new(peer.into())],
+                valid_username(&username_salt),
+                &secret,
+                nonce,
+            ),
+            now,
+        ),
+        [send_message(
+            source,
+            create_permission_response(create_permission_transaction_id),
+        )],
+    );
+    server.assert_commands(
+        from_client(
+            source,
+            send_indication(send_transaction_id, peer, &client_to_peer_ping),
+            now,
+        ),
+        [relay_to_peer(49152, peer, &client_to_peer_ping)],
+    );
+
+    server.assert_commands(
+        from_client(
+            source,
+            Refresh::new(
+                refresh_transaction_id,
+                Some(lifetime.clone()),
+                valid_username(&username_salt),
+                &secret,
+                nonce,
+            ),
+            now,
+        ),
+        [send_message(
+            source,
+            allocation_quota_reached_refresh_response(refresh_transaction_id),
+        )],
+    );
+    server.assert_commands(
+        from_client(
+            source,
+            Refresh::new(
+                delete_transaction_id,
+                Some(Lifetime::new(Duration::ZERO).unwrap()),
+                valid_username(&username_salt),
+                &secret,
+                nonce,
+            ),
+            now,
+        ),
+        [
+            free_allocation(49152, AddressFamily::V4),
+            send_message(
+                source,
+                refresh_response(
+                    delete_transaction_id,
+                    Lifetime::new(Duration::ZERO).unwrap(),
+                ),
+            ),
+        ],
+    );
+    server.assert_commands(
+        from_client(
+            source,
+            Allocate::new_authenticated_udp_implicit_ip4(
+                second_allocate_transaction_id,
+                Some(lifetime.clone()),
+                valid_username(&username_salt),
+                &secret,
+                nonce,
+            ),
+            now,
+        ),
+        [send_message(
+            source,
+            allocation_quota_reached_allocate_response(second_allocate_transaction_id),
+        )],
+    );
+}
+
+#[proptest]
+fn username_quota_is_shared_between_workers(
+    #[strategy(firezone_relay::proptest::transaction_id())] allocate_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::transaction_id())]
+    create_permission_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::transaction_id())] send_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::transaction_id())]
+    second_allocate_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::username_salt())] username_salt: String,
+    source: SocketAddrV4,
+    second_source: SocketAddrV4,
+    peer: SocketAddrV4,
+    public_relay_addr: Ipv4Addr,
+    client_to_peer_ping: [u8; 32],
+    #[strategy(firezone_relay::proptest::nonce())] nonce: Uuid,
+) {
+    let now = Instant::now();
+    let username_usages = UsernameUsages::default();
+    let limits = Limits {
+        per_allocation: Limit::default(),
+        per_username: Limit {
+            bytes_per_second: None,
+            quota_bytes: Some(32),
+        },
+    };
+
+    let mut first_worker = TestServer::new(public_relay_addr)
+        .with_nonce(nonce)
+        .with_limits(limits)
+        .with_username_usages(username_usages.clone());
+    let secret = first_worker.auth_secret().to_owned();
+    let mut second_worker = TestServer::new(public_relay_addr)
+        .with_nonce(nonce)
+        .with_limits(limits)
+        .with_username_usages(username_usages)
+        .with_auth_secret(secret.clone());
+    let lifetime = Lifetime::new(Duration::from_secs(60 * 60)).unwrap();
+
+    first_worker.assert_commands(
+        from_client(
+            source,
+            Allocate::new_authenticated_udp_implicit_ip4(
+                allocate_transaction_id,
+                Some(lifetime.clone()),
+                valid_username(&username_salt),
+                &secret,
+                nonce,
+            ),
+            now,
+        ),
+        [
+            create_allocation(49152, AddressFamily::V4),
+            send_message(
+                source,
+                allocate_response(
+                    allocate_transaction_id,
+                    public_relay_addr,
+                    49152,
+                    source,
+                    &lifetime,
+                ),
+            ),
+        ],
+    );
+    first_worker.assert_commands(
+        from_client(
+            source,
+            CreatePermission::new(
+                create_permission_transaction_id,
+                vec![XorPeerAddress::new(peer.into())],
+                valid_username(&username_salt),
+                &secret,
+                nonce,
+            ),
+            now,
+        ),
+        [send_message(
+            source,
+            create_permission_response(create_permission_transaction_id),
+        )],
+    );
+    first_worker.assert_commands(
+        from_client(
+            source,
+            send_indication(send_transaction_id, peer, &client_to_peer_ping),
+            now,
+        ),
+        [relay_to_peer(49152, peer, &client_to_peer_ping)],
+    );
+
+    second_worker.assert_commands(
+        from_client(
+            second_source,
+            Allocate::new_authenticated_udp_implicit_ip4(
+                second_allocate_transaction_id,
+                Some(lifetime.clone()),
+                valid_username(&username_salt),
+                &secret,
+                nonce,
+            ),
+            now,
+        ),
+        [send_message(
+            second_source,
+            allocation_quota_reached_allocate_response(second_allocate_transaction_id),
+        )],
+    );
+}
+
+#[proptest]
+fn allocations_follow_server_config(
+    #[strategy(firezone_relay::proptest::transaction_id())] first_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::transaction_id())] second_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::username_salt())] username_salt: String,
+    first_source: SocketAddrV4,
+    second_source: SocketAddrV4,
+    public_relay_addr: Ipv4Addr,
+    #[strategy(firezone_relay::proptest::nonce())] nonce: Uuid,
+) {
+    proptest::prop_assume!(first_source != second_source);
+
+    let now = Instant::now();
+
+    let mut server = TestServer::new(public_relay_addr)
+        .with_config(ServerConfig {
+            default_allocation_lifetime: Duration::from_secs(120),
+            max_allocations_per_username: Some(1),
+            excluded_ports: vec![PortRange {
+                lowest: 49152,
+                highest: 49160,
+            }],
+            ..ServerConfig::default()
+        })
+        .with_nonce(nonce);
+    let secret = server.auth_secret().to_owned();
+    let default_lifetime = Lifetime::new(Duration::from_secs(120)).unwrap();
+
+    server.assert_commands(
+        from_client(
+            first_source,
+            Allocate::new_authenticated_udp_implicit_ip4(
+                first_transaction_id,
+                None,
+                valid_username(&username_salt),
+                &secret,
+                nonce,
+            ),
+            now,
+        ),
+        [
+            create_allocation(49161, AddressFamily::V4),
+            send_message(
+                first_source,
+                allocate_response(
+                    first_transaction_id,
+                    public_relay_addr,
+                    49161,
+                    first_source,
+                    &default_lifetime,
+                ),
+            ),
+        ],
+    );
+    server.assert_commands(
+        from_client(
+            second_source,
+            Allocate::new_authenticated_udp_implicit_ip4(
+                second_transaction_id,
+                None,
+                valid_username(&username_salt),
+                &secret,
+                nonce,
+            ),
+            now,
+        ),
+        [send_message(
+            second_source,
+            allocation_quota_reached_allocate_response(second_transaction_id),
+        )],
+    );
+}
+
+#[proptest]
+fn channel_bind_beyond_max_channels_per_allocation_is_rejected(
+    #[strategy(firezone_relay::proptest::transaction_id())] allocate_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::transaction_id())]
+    channel_bind_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::transaction_id())]
+    channel_bind_2_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::username_salt())] username_salt: String,
+    source: SocketAddrV4,
+    peer: SocketAddrV4,
+    peer2: SocketAddrV4,
+    public_relay_addr: Ipv4Addr,
+    #[strategy(firezone_relay::proptest::nonce())] nonce: Uuid,
+) {
+    proptest::prop_assume!(peer != peer2);
+
+    let now = Instant::now();
+
+    let mut server = TestServer::new(public_relay_addr)
+        .with_config(ServerConfig {
+            max_channels_per_allocation: Some(1),
+            ..ServerConfig::default()
+        })
+        .with_nonce(nonce);
+    let secret = server.auth_secret().to_owned();
+    let lifetime = Lifetime::new(Duration::from_secs(60 * 60)).unwrap();
+
+    server.assert_commands(
+        from_client(
+            source,
+            Allocate::new_authenticated_udp_implicit_ip4(
+                allocate_transaction_id,
+                Some(lifetime.clone()),
+                valid_username(&username_salt),
+                &secret,
+                nonce,
+            ),
+            now,
+        ),
+        [
+            create_allocation(49152, AddressFamily::V4),
+            send_message(
+                source,
+                allocate_response(
+                    allocate_transaction_id,
+                    public_relay_addr,
+                    49152,
+                    source,
+                    &lifetime,
+                ),
+            ),
+        ],
+    );
+    server.assert_commands(
+        from_client(
+            source,
+            ChannelBind::new(
+                channel_bind_transaction_id,
+                ChannelNumber::new(0x4000).unwrap(),
+                XorPeerAddress::new(peer.into()),
+                valid_username(&username_salt),
+                &secret,
+                nonce,
+            ),
+            now,
+        ),
+        [send_message(
+            source,
+            channel_bind_response(channel_bind_transaction_id),
+        )],
+    );
+    server.assert_commands(
+        from_client(
+            source,
+            ChannelBind::new(
+                channel_bind_2_transaction_id,
+                ChannelNumber::new(0x4001).unwrap(),
+                XorPeerAddress::new(peer2.into()),
+                valid_username(&username_salt),
+                &secret,
+                nonce,
+            ),
+            now,
+        ),
+        [send_message(
+            source,
+            insufficient_capacity_channel_bind_response(channel_bind_2_transaction_id),
+        )],
     );
-
-    // Assert that forwarding time does not produce an obsolete event.
-    server.assert_commands(forward_time_to(first_wake + Duration::from_secs(1)), []);
 }
 
 // #[test]
@@ -394,6 +1295,7 @@ fn ping_pong_relay(
         peer_to_client_ping.as_slice(),
         PeerSocket::new(peer.into()),
         AllocationPort::new(49152),
+        now,
     );
 
     assert_eq!(
@@ -405,6 +1307,322 @@ fn ping_pong_relay(
     );
 }
 
+#[proptest]
+fn captures_client_and_peer_traffic_of_allocation(
+    #[strategy(firezone_relay::proptest::transaction_id())] allocate_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::transaction_id())]
+    channel_bind_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::username_salt())] username_salt: String,
+    source: SocketAddrV4,
+    peer: SocketAddrV4,
+    public_relay_addr: Ipv4Addr,
+    peer_to_client_ping: [u8; 32],
+    #[strategy(firezone_relay::proptest::channel_data())] client_to_peer_ping: ChannelData<'static>,
+    #[strategy(firezone_relay::proptest::nonce())] nonce: Uuid,
+) {
+    let now = Instant::now();
+
+    let mut server = TestServer::new(public_relay_addr).with_nonce(nonce);
+    let secret = server.auth_secret().to_owned();
+    let lifetime = Lifetime::new(Duration::from_secs(60 * 60)).unwrap();
+
+    server.assert_commands(
+        from_client(
+            source,
+            Allocate::new_authenticated_udp_implicit_ip4(
+                allocate_transaction_id,
+                Some(lifetime.clone()),
+                valid_username(&username_salt),
+                &secret,
+                nonce,
+            ),
+            now,
+        ),
+        [
+            create_allocation(49152, AddressFamily::V4),
+            send_message(
+                source,
+                allocate_response(
+                    allocate_transaction_id,
+                    public_relay_addr,
+                    49152,
+                    source,
+                    &lifetime,
+                ),
+            ),
+        ],
+    );
+
+    assert!(server.server.start_capture(
+        AllocationPort::new(49152),
+        Duration::from_secs(60),
+        1024 * 1024,
+        now,
+        SystemTime::now()
+    ));
+    assert!(!server.server.start_capture(
+        AllocationPort::new(49153),
+        Duration::from_secs(60),
+        1024 * 1024,
+        now,
+        SystemTime::now()
+    ));
+
+    server.assert_commands(
+        from_client(
+            source,
+            ChannelBind::new(
+                channel_bind_transaction_id,
+                client_to_peer_ping.channel(),
+                XorPeerAddress::new(peer.into()),
+                valid_username(&username_salt),
+                &secret,
+                nonce,
+            ),
+            now,
+        ),
+        [send_message(
+            source,
+            channel_bind_response(channel_bind_transaction_id),
+        )],
+    );
+    server.server.handle_client_input(
+        client_to_peer_ping.as_msg(),
+        ClientSocket::new(source.into()),
+        now,
+    );
+    server.server.handle_peer_traffic(
+        peer_to_client_ping.as_slice(),
+        PeerSocket::new(peer.into()),
+        AllocationPort::new(49152),
+        now,
+    );
+
+    let pcapng = server
+        .server
+        .stop_capture(AllocationPort::new(49152))
+        .unwrap();
+
+    // Channel bind response, channel data from the client, its payload to the peer, data from the peer and its channel data to the client.
+    assert_eq!(count_pcapng_packets(&pcapng), 5);
+    assert_eq!(server.server.stop_capture(AllocationPort::new(49152)), None);
+}
+
+#[proptest]
+fn capture_can_be_fetched_for_a_while_after_allocation_is_deleted(
+    #[strategy(firezone_relay::proptest::transaction_id())] transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::username_salt())] username_salt: String,
+    source: SocketAddrV4,
+    public_relay_addr: Ipv4Addr,
+    #[strategy(firezone_relay::proptest::nonce())] nonce: Uuid,
+) {
+    let now = Instant::now();
+
+    let mut server = TestServer::new(public_relay_addr).with_nonce(nonce);
+    let secret = server.auth_secret().to_owned();
+    let lifetime = Lifetime::new(Duration::from_secs(60 * 60)).unwrap();
+
+    // Fetching a capture removes it, so we capture, delete and look for the capture twice.
+    for (start, fetch_at, is_available) in [
+        (now, now + Duration::from_secs(9 * 60), true),
+        (
+            now + Duration::from_secs(9 * 60),
+            now + Duration::from_secs(20 * 60),
+            false,
+        ),
+    ] {
+        server.assert_commands(
+            from_client(
+                source,
+                Allocate::new_authenticated_udp_implicit_ip4(
+                    transaction_id,
+                    Some(lifetime.clone()),
+                    valid_username(&username_salt),
+                    &secret,
+                    nonce,
+                ),
+                start,
+            ),
+            [
+                create_allocation(49152, AddressFamily::V4),
+                send_message(
+                    source,
+                    allocate_response(transaction_id, public_relay_addr, 49152, source, &lifetime),
+                ),
+            ],
+        );
+        assert!(server.server.start_capture(
+            AllocationPort::new(49152),
+            Duration::from_secs(60),
+            1024 * 1024,
+            start,
+            SystemTime::now()
+        ));
+        server.assert_commands(
+            force_delete_allocation(49152),
+            [free_allocation(49152, AddressFamily::V4)],
+        );
+        server.assert_commands(forward_time_to(fetch_at), []);
+
+        assert_eq!(
+            server
+                .server
+                .stop_capture(AllocationPort::new(49152))
+                .is_some(),
+            is_available
+        );
+    }
+}
+
+#[proptest]
+fn forwards_channel_data_to_peered_relay(
+    #[strategy(firezone_relay::proptest::transaction_id())] allocate_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::transaction_id())]
+    channel_bind_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::username_salt())] username_salt: String,
+    client: SocketAddrV4,
+    gateway: SocketAddrV4,
+    #[strategy(firezone_relay::proptest::channel_data())] client_to_gateway: ChannelData<'static>,
+    #[strategy(firezone_relay::proptest::nonce())] nonce: Uuid,
+) {
+    let now = Instant::now();
+    let relay_a = Ipv4Addr::new(203, 0, 113, 1);
+    let relay_b = Ipv4Addr::new(198, 51, 100, 1);
+    let link_a = SocketAddr::from(([10, 0, 0, 1], 3479));
+    let link_b = SocketAddr::from(([10, 0, 0, 2], 3479));
+
+    let mut server_a = TestServer::new(relay_a)
+        .with_nonce(nonce)
+        .with_peering(Peering::new(
+            SecretString::from("peering".to_owned()),
+            vec![RelayPeer {
+                public_ip: relay_b.into(),
+                link: link_b,
+            }],
+            SystemTime::now(),
+        ));
+    let mut server_b = TestServer::new(relay_b)
+        .with_nonce(nonce)
+        .with_peering(Peering::new(
+            SecretString::from("peering".to_owned()),
+            vec![RelayPeer {
+                public_ip: relay_a.into(),
+                link: link_a,
+            }],
+            SystemTime::now(),
+        ));
+    let lifetime = Lifetime::new(Duration::from_secs(60 * 60)).unwrap();
+    let channel = client_to_gateway.channel();
+
+    // Client and gateway each allocate on their own relay and bind a channel to the other's allocation.
+    for (server, source, public_relay_addr, peer) in [
+        (
+            &mut server_a,
+            client,
+            relay_a,
+            SocketAddrV4::new(relay_b, 49152),
+        ),
+        (
+            &mut server_b,
+            gateway,
+            relay_b,
+            SocketAddrV4::new(relay_a, 49152),
+        ),
+    ] {
+        let secret = server.auth_secret().to_owned();
+
+        server.assert_commands(
+            from_client(
+                source,
+                Allocate::new_authenticated_udp_implicit_ip4(
+                    allocate_transaction_id,
+                    Some(lifetime.clone()),
+                    valid_username(&username_salt),
+                    &secret,
+                    nonce,
+                ),
+                now,
+            ),
+            [
+                create_allocation(49152, AddressFamily::V4),
+                send_message(
+                    source,
+                    allocate_response(
+                        allocate_transaction_id,
+                        public_relay_addr,
+                        49152,
+                        source,
+                        &lifetime,
+                    ),
+                ),
+            ],
+        );
+        server.assert_commands(
+            from_client(
+                source,
+                ChannelBind::new(
+                    channel_bind_transaction_id,
+                    channel,
+                    XorPeerAddress::new(peer.into()),
+                    valid_username(&username_salt),
+                    &secret,
+                    nonce,
+                ),
+                now,
+            ),
+            [send_message(
+                source,
+                channel_bind_response(channel_bind_transaction_id),
+            )],
+        );
+    }
+
+    let maybe_relay = server_a.server.handle_client_input(
+        client_to_gateway.as_msg(),
+        ClientSocket::new(client.into()),
+        now,
+    );
+    assert_eq!(maybe_relay, None);
+
+    let Some(Command::ForwardToRelay {
+        payload: frame,
+        link,
+    }) = server_a.server.next_command()
+    else {
+        panic!("Expected data to be forwarded to relay B");
+    };
+    assert_eq!(link, link_b);
+
+    // Frames are only accepted from relays we peer with.
+    assert_eq!(
+        server_b
+            .server
+            .handle_relay_forwarded_traffic(&frame, link_b, now),
+        None
+    );
+
+    let maybe_forward = server_b
+        .server
+        .handle_relay_forwarded_traffic(&frame, link_a, now);
+    assert_eq!(
+        maybe_forward,
+        Some((
+            ClientSocket::new(gateway.into()),
+            channel,
+            client_to_gateway.data().len()
+        ))
+    );
+    assert!(frame.starts_with(client_to_gateway.data()));
+
+    // Replayed frames are dropped.
+    assert_eq!(
+        server_b
+            .server
+            .handle_relay_forwarded_traffic(&frame, link_a, now),
+        None
+    );
+}
+
 #[proptest]
 fn allows_rebind_channel_after_expiry(
     #[strategy(firezone_relay::proptest::transaction_id())] allocate_transaction_id: TransactionId,
@@ -622,6 +1840,7 @@ fn ping_pong_ip6_relay(
         peer_to_client_ping.as_slice(),
         PeerSocket::new(peer.into()),
         AllocationPort::new(49152),
+        now,
     );
 
     assert_eq!(
@@ -642,11 +1861,45 @@ impl TestServer {
     }
 
     fn with_nonce(mut self, nonce: Uuid) -> Self {
-        self.server.add_nonce(nonce);
+        self.server.add_nonce(nonce, Instant::now());
+
+        self
+    }
+
+    fn with_peering(mut self, peering: Peering) -> Self {
+        self.server = self.server.with_peering(peering);
 
         self
     }
 
+    fn with_config(mut self, config: ServerConfig) -> Self {
+        self.server = self.server.with_config(config);
+
+        self
+    }
+
+    fn with_limits(mut self, limits: Limits) -> Self {
+        self.server = self.server.with_limits(limits);
+
+        self
+    }
+
+    fn with_username_usages(mut self, username_usages: UsernameUsages) -> Self {
+        self.server = self.server.with_username_usages(username_usages);
+
+        self
+    }
+
+    fn with_auth_secret(mut self, auth_secret: SecretString) -> Self {
+        self.server = self.server.with_auth_secret(auth_secret);
+
+        self
+    }
+
+    fn start_draining(&mut self) {
+        self.server.start_draining();
+    }
+
     fn auth_secret(&self) -> &SecretString {
         self.server.auth_secret()
     }
@@ -659,6 +1912,20 @@ impl TestServer {
             Input::Time(now) => {
                 self.server.handle_timeout(now);
             }
+            Input::ConnectionClosed(client) => {
+                self.server.handle_client_connection_closed(client);
+            }
+            Input::Peer(sender, payload, allocation, now) => {
+                self.server
+                    .handle_peer_traffic(&payload, sender, allocation, now);
+            }
+            Input::PeerIcmpError(peer, error, allocation, now) => {
+                self.server
+                    .handle_peer_icmp_error(error, peer, allocation, now);
+            }
+            Input::ForceDeleteAllocation(port) => {
+                self.server.force_delete_allocation(port);
+            }
         }
 
         for expected_output in output {
@@ -673,6 +1940,9 @@ impl TestServer {
                     FreeAllocation(port, family) => {
                         format!("to free allocation on port {port} for address family {family}")
                     }
+                    RelayToPeer(port, peer, _) => {
+                        format!("to relay data to {peer} from allocation on port {port}")
+                    }
                 };
 
                 panic!("No commands produced but expected {msg}");
@@ -716,6 +1986,18 @@ impl TestServer {
                     assert_eq!(port, actual_port);
                     assert_eq!(family, actual_family);
                 }
+                (
+                    RelayToPeer(port, peer, payload),
+                    Command::RelayToPeer {
+                        payload: actual_payload,
+                        allocation: actual_port,
+                        peer: actual_peer,
+                    },
+                ) => {
+                    assert_eq!(port, actual_port);
+                    assert_eq!(peer, actual_peer);
+                    assert_eq!(payload, actual_payload);
+                }
                 (expected, actual) => panic!("Unhandled combination: {expected:?} {actual:?}"),
             }
         }
@@ -780,6 +2062,30 @@ fn unauthorized_allocate_response(
     message
 }
 
+fn insufficient_capacity_allocate_response(transaction_id: TransactionId) -> Message<Attribute> {
+    let mut message =
+        Message::<Attribute>::new(MessageClass::ErrorResponse, ALLOCATE, transaction_id);
+    message.add_attribute(ErrorCode::from(InsufficientCapacity));
+
+    message
+}
+
+fn allocation_quota_reached_allocate_response(transaction_id: TransactionId) -> Message<Attribute> {
+    let mut message =
+        Message::<Attribute>::new(MessageClass::ErrorResponse, ALLOCATE, transaction_id);
+    message.add_attribute(ErrorCode::from(AllocationQuotaReached));
+
+    message
+}
+
+fn allocation_quota_reached_refresh_response(transaction_id: TransactionId) -> Message<Attribute> {
+    let mut message =
+        Message::<Attribute>::new(MessageClass::ErrorResponse, REFRESH, transaction_id);
+    message.add_attribute(ErrorCode::from(AllocationQuotaReached));
+
+    message
+}
+
 fn refresh_response(transaction_id: TransactionId, lifetime: Lifetime) -> Message<Attribute> {
     let mut message =
         Message::<Attribute>::new(MessageClass::SuccessResponse, REFRESH, transaction_id);
@@ -792,6 +2098,83 @@ fn channel_bind_response(transaction_id: TransactionId) -> Message<Attribute> {
     Message::<Attribute>::new(MessageClass::SuccessResponse, CHANNEL_BIND, transaction_id)
 }
 
+fn insufficient_capacity_channel_bind_response(
+    transaction_id: TransactionId,
+) -> Message<Attribute> {
+    let mut message =
+        Message::<Attribute>::new(MessageClass::ErrorResponse, CHANNEL_BIND, transaction_id);
+    message.add_attribute(ErrorCode::from(InsufficientCapacity));
+
+    message
+}
+
+fn create_permission_response(transaction_id: TransactionId) -> Message<Attribute> {
+    Message::<Attribute>::new(
+        MessageClass::SuccessResponse,
+        CREATE_PERMISSION,
+        transaction_id,
+    )
+}
+
+fn send_indication(
+    transaction_id: TransactionId,
+    peer: impl Into<SocketAddr>,
+    payload: &[u8],
+) -> SendIndication {
+    SendIndication::new(
+        transaction_id,
+        XorPeerAddress::new(peer.into()),
+        Data::new(payload.to_vec()).unwrap(),
+    )
+}
+
+fn data_indication(peer: impl Into<SocketAddr>, payload: &[u8]) -> Message<Attribute> {
+    // Transaction IDs are generated randomly and we control the randomness in the test, thus this is deterministic.
+    let mut message = Message::<Attribute>::new(
+        MessageClass::Indication,
+        DATA,
+        TransactionId::new([0u8; 12]),
+    );
+    message.add_attribute(XorPeerAddress::new(peer.into()));
+    message.add_attribute(Data::new(payload.to_vec()).unwrap());
+
+    message
+}
+
+fn icmp_data_indication(peer: impl Into<SocketAddr>, error: IcmpError) -> Message<Attribute> {
+    let mut message = Message::<Attribute>::new(
+        MessageClass::Indication,
+        DATA,
+        TransactionId::new([0u8; 12]),
+    );
+    message.add_attribute(XorPeerAddress::new(peer.into()));
+    message.add_attribute(Icmp::new(
+        error.icmp_type,
+        error.icmp_code,
+        error.error_data,
+    ));
+
+    message
+}
+
+/// Counts the Enhanced Packet Blocks in a pcapng file.
+fn count_pcapng_packets(mut pcapng: &[u8]) -> usize {
+    let mut num_packets = 0;
+
+    while !pcapng.is_empty() {
+        let block_type = u32::from_le_bytes(pcapng[..4].try_into().unwrap());
+        let block_len = u32::from_le_bytes(pcapng[4..8].try_into().unwrap()) as usize;
+
+        if block_type == 6 {
+            num_packets += 1;
+        }
+
+        pcapng = &pcapng[block_len..];
+    }
+
+    num_packets
+}
+
 fn parse_message(message: &[u8]) -> Message<Attribute> {
     MessageDecoder::new()
         .decode_from_bytes(message)
@@ -802,6 +2185,10 @@ fn parse_message(message: &[u8]) -> Message<Attribute> {
 enum Input<'a> {
     Client(ClientSocket, ClientMessage<'a>, Instant),
     Time(Instant),
+    ConnectionClosed(ClientSocket),
+    Peer(PeerSocket, Vec<u8>, AllocationPort, Instant),
+    PeerIcmpError(PeerSocket, IcmpError, AllocationPort, Instant),
+    ForceDeleteAllocation(AllocationPort),
 }
 
 fn from_client<'a>(
@@ -816,11 +2203,48 @@ fn forward_time_to<'a>(when: Instant) -> Input<'a> {
     Input::Time(when)
 }
 
+fn connection_closed<'a>(client: impl Into<SocketAddr>) -> Input<'a> {
+    Input::ConnectionClosed(ClientSocket::new(client.into()))
+}
+
+fn force_delete_allocation<'a>(port: u16) -> Input<'a> {
+    Input::ForceDeleteAllocation(AllocationPort::new(port))
+}
+
+fn from_peer<'a>(
+    from: impl Into<SocketAddr>,
+    payload: &[u8],
+    port: u16,
+    now: Instant,
+) -> Input<'a> {
+    Input::Peer(
+        PeerSocket::new(from.into()),
+        payload.to_vec(),
+        AllocationPort::new(port),
+        now,
+    )
+}
+
 #[derive(Debug)]
 enum Output {
     SendMessage((ClientSocket, Message<Attribute>)),
     CreateAllocation(AllocationPort, AddressFamily),
     FreeAllocation(AllocationPort, AddressFamily),
+    RelayToPeer(AllocationPort, PeerSocket, Vec<u8>),
+}
+
+fn icmp_error_from_peer<'a>(
+    peer: impl Into<SocketAddr>,
+    error: IcmpError,
+    port: u16,
+    now: Instant,
+) -> Input<'a> {
+    Input::PeerIcmpError(
+        PeerSocket::new(peer.into()),
+        error,
+        AllocationPort::new(port),
+        now,
+    )
 }
 
 fn create_allocation(port: u16, fam: AddressFamily) -> Output {
@@ -831,6 +2255,14 @@ fn free_allocation(port: u16, fam: AddressFamily) -> Output {
     Output::FreeAllocation(AllocationPort::new(port), fam)
 }
 
+fn relay_to_peer(port: u16, peer: impl Into<SocketAddr>, payload: &[u8]) -> Output {
+    Output::RelayToPeer(
+        AllocationPort::new(port),
+        PeerSocket::new(peer.into()),
+        payload.to_vec(),
+    )
+}
+
 fn send_message(source: impl Into<SocketAddr>, message: Message<Attribute>) -> Output {
     Output::SendMessage((ClientSocket::new(source.into()), message))
 }
@@ -0,0 +1,18 @@
+//! An in-memory network simulator for snownet.
+//!
+//! [`Network`] acts as the UDP transport of [`snownet::Endpoint`]s.
+//! It models NATs of different [`NatType`]s, packet loss, latency and reordering (see [`Link`]) and hosts relays backed by the real [`firezone_relay::Server`].
+//! This allows NAT traversal scenarios to run as part of `cargo test`, without containers or real sockets.
+//!
+//! Runs are not reproducible: snownet, str0m and boringtun draw their own randomness (e.g. ICE credentials and STUN transaction IDs) and boringtun reads the system clock for its handshake timers.
+//! Tests should therefore assert on outcomes and bounds, not on exact timings.
+
+mod nat;
+mod network;
+mod node;
+mod relay;
+
+pub use nat::NatType;
+pub use network::{Handle, Link, NatId, Network};
+pub use node::SimNode;
+pub use relay::SimRelay;
@@ -0,0 +1,204 @@
+use std::{
+    collections::{HashMap, HashSet},
+    net::{IpAddr, SocketAddr},
+};
+
+/// The first port a [`Nat`] hands out for its mappings.
+const FIRST_MAPPED_PORT: u16 = 10_000;
+
+/// The behaviour of a simulated NAT, named after the classic terminology of RFC 3489.
+///
+/// See <https://www.rfc-editor.org/rfc/rfc4787#section-4> for the mapping and filtering behaviours these correspond to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NatType {
+    /// Endpoint-independent mapping and endpoint-independent filtering.
+    FullCone,
+    /// Endpoint-independent mapping and address-dependent filtering.
+    RestrictedCone,
+    /// Endpoint-independent mapping and address and port-dependent filtering.
+    PortRestrictedCone,
+    /// Address and port-dependent mapping and filtering.
+    ///
+    /// Every remote sees a different public port, which defeats server-reflexive candidates.
+    Symmetric,
+}
+
+/// A NAT translating between a private network and a single public IP.
+#[derive(Debug)]
+pub(crate) struct Nat {
+    nat_type: NatType,
+    public_ip: IpAddr,
+    next_port: u16,
+
+    /// Maps an internal socket (and the remote, for symmetric NATs) to the public port we allocated for it.
+    mappings: HashMap<(SocketAddr, Option<SocketAddr>), u16>,
+    /// The reverse of `mappings`, together with all remotes the internal socket has sent traffic to.
+    bindings: HashMap<u16, Binding>,
+}
+
+#[derive(Debug)]
+struct Binding {
+    internal: SocketAddr,
+    contacted: HashSet<SocketAddr>,
+}
+
+impl Nat {
+    pub(crate) fn new(nat_type: NatType, public_ip: IpAddr) -> Self {
+        Self {
+            nat_type,
+            public_ip,
+            next_port: FIRST_MAPPED_PORT,
+            mappings: Default::default(),
+            bindings: Default::default(),
+        }
+    }
+
+    pub(crate) fn public_ip(&self) -> IpAddr {
+        self.public_ip
+    }
+
+    /// Translates the source of a packet leaving the private network, creating a mapping if necessary.
+    pub(crate) fn outbound(&mut self, internal: SocketAddr, remote: SocketAddr) -> SocketAddr {
+        let key = match self.nat_type {
+            NatType::FullCone | NatType::RestrictedCone | NatType::PortRestrictedCone => {
+                (internal, None)
+            }
+            NatType::Symmetric => (internal, Some(remote)),
+        };
+
+        let port = match self.mappings.get(&key) {
+            Some(port) => *port,
+            None => {
+                let port = self.next_port;
+                self.next_port = self
+                    .next_port
+                    .checked_add(1)
+                    .expect("simulations should not exhaust the port space");
+
+                tracing::trace!(%internal, public = %SocketAddr::new(self.public_ip, port), "Created NAT mapping");
+
+                self.mappings.insert(key, port);
+                self.bindings.insert(
+                    port,
+                    Binding {
+                        internal,
+                        contacted: HashSet::default(),
+                    },
+                );
+
+                port
+            }
+        };
+
+        self.bindings
+            .get_mut(&port)
+            .expect("every mapping has a binding")
+            .contacted
+            .insert(remote);
+
+        SocketAddr::new(self.public_ip, port)
+    }
+
+    /// Translates the destination of a packet entering the private network.
+    ///
+    /// Returns `None` if there is no mapping for the port or the NAT's filtering rejects the packet.
+    pub(crate) fn inbound(&self, public_port: u16, remote: SocketAddr) -> Option<SocketAddr> {
+        let binding = self.bindings.get(&public_port)?;
+
+        let allowed = match self.nat_type {
+            NatType::FullCone => true,
+            NatType::RestrictedCone => binding
+                .contacted
+                .iter()
+                .any(|contacted| contacted.ip() == remote.ip()),
+            NatType::PortRestrictedCone | NatType::Symmetric => binding.contacted.contains(&remote),
+        };
+
+        allowed.then_some(binding.internal)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INTERNAL: &str = "192.168.0.2:1000";
+    const PUBLIC_IP: &str = "203.0.113.1";
+    const REMOTE: &str = "198.51.100.1:3478";
+    const REMOTE_OTHER_PORT: &str = "198.51.100.1:4000";
+    const OTHER_REMOTE: &str = "198.51.100.2:3478";
+
+    #[test]
+    fn cone_nats_reuse_mapping_across_remotes() {
+        for nat_type in [
+            NatType::FullCone,
+            NatType::RestrictedCone,
+            NatType::PortRestrictedCone,
+        ] {
+            let mut nat = Nat::new(nat_type, ip(PUBLIC_IP));
+
+            let first = nat.outbound(s(INTERNAL), s(REMOTE));
+            let second = nat.outbound(s(INTERNAL), s(OTHER_REMOTE));
+
+            assert_eq!(first, second, "{nat_type:?}");
+        }
+    }
+
+    #[test]
+    fn symmetric_nat_allocates_mapping_per_remote() {
+        let mut nat = Nat::new(NatType::Symmetric, ip(PUBLIC_IP));
+
+        let first = nat.outbound(s(INTERNAL), s(REMOTE));
+        let second = nat.outbound(s(INTERNAL), s(OTHER_REMOTE));
+
+        assert_ne!(first, second);
+        assert_eq!(first, nat.outbound(s(INTERNAL), s(REMOTE)));
+    }
+
+    #[test]
+    fn full_cone_accepts_anyone() {
+        let mut nat = Nat::new(NatType::FullCone, ip(PUBLIC_IP));
+        let public = nat.outbound(s(INTERNAL), s(REMOTE));
+
+        assert_eq!(
+            nat.inbound(public.port(), s(OTHER_REMOTE)),
+            Some(s(INTERNAL))
+        );
+    }
+
+    #[test]
+    fn restricted_cone_filters_by_address() {
+        let mut nat = Nat::new(NatType::RestrictedCone, ip(PUBLIC_IP));
+        let public = nat.outbound(s(INTERNAL), s(REMOTE));
+
+        assert_eq!(
+            nat.inbound(public.port(), s(REMOTE_OTHER_PORT)),
+            Some(s(INTERNAL))
+        );
+        assert_eq!(nat.inbound(public.port(), s(OTHER_REMOTE)), None);
+    }
+
+    #[test]
+    fn port_restricted_cone_filters_by_address_and_port() {
+        let mut nat = Nat::new(NatType::PortRestrictedCone, ip(PUBLIC_IP));
+        let public = nat.outbound(s(INTERNAL), s(REMOTE));
+
+        assert_eq!(nat.inbound(public.port(), s(REMOTE)), Some(s(INTERNAL)));
+        assert_eq!(nat.inbound(public.port(), s(REMOTE_OTHER_PORT)), None);
+    }
+
+    #[test]
+    fn unmapped_port_is_dropped() {
+        let nat = Nat::new(NatType::FullCone, ip(PUBLIC_IP));
+
+        assert_eq!(nat.inbound(FIRST_MAPPED_PORT, s(REMOTE)), None);
+    }
+
+    fn s(socket: &str) -> SocketAddr {
+        socket.parse().unwrap()
+    }
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+}
@@ -0,0 +1,461 @@
+use crate::{
+    nat::{Nat, NatType},
+    SimNode, SimRelay,
+};
+use boringtun::x25519::StaticSecret;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use snownet::{Client, Endpoint, Server, Transmit};
+use std::{
+    cell::RefCell,
+    cmp::{Ordering, Reverse},
+    collections::{BinaryHeap, HashSet},
+    net::{IpAddr, SocketAddr},
+    rc::Rc,
+    time::{Duration, Instant},
+};
+use tracing::Span;
+
+/// The smallest amount of time the network advances its clock by.
+///
+/// Guarantees progress even if an endpoint keeps asking to be woken up in the past.
+const RESOLUTION: Duration = Duration::from_millis(1);
+
+/// A shared handle to something attached to a [`Network`].
+pub type Handle<E> = Rc<RefCell<E>>;
+
+/// Identifies a NAT within a [`Network`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NatId(usize);
+
+/// The conditions of the link between a host and the rest of the network.
+///
+/// They apply to every packet the host sends.
+/// Packets overtake each other if the jitter exceeds the interval at which they are sent.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Link {
+    /// The one-way delay of every packet.
+    pub latency: Duration,
+    /// An additional delay, picked uniformly between zero and this value for every packet.
+    pub jitter: Duration,
+    /// The probability of a packet getting lost, between `0.0` and `1.0`.
+    pub loss: f64,
+}
+
+/// An in-memory UDP network.
+///
+/// The network owns the clock: time only advances when the network runs, directly to the next point at which something happens.
+/// The randomness of the network itself, i.e. packet loss, jitter and key generation, is derived from the seed.
+/// The nodes attached to it draw their own randomness, hence runs are not reproducible, see the crate docs.
+pub struct Network {
+    now: Instant,
+    rng: StdRng,
+    default_link: Link,
+
+    hosts: Vec<Host>,
+    nats: Vec<Nat>,
+    signalling: Vec<Signalling>,
+
+    in_flight: BinaryHeap<Reverse<InFlight>>,
+    next_sequence: u64,
+    next_connection_id: u64,
+}
+
+struct Host {
+    endpoint: Handle<dyn Endpoint>,
+    address: SocketAddr,
+    nat: Option<NatId>,
+    link: Link,
+}
+
+/// A signalling channel between two nodes for a single connection.
+struct Signalling {
+    id: u64,
+    client: Handle<SimNode<Client>>,
+    server: Handle<SimNode<Server>>,
+}
+
+struct InFlight {
+    deliver_at: Instant,
+    /// Tie-breaker to deliver packets with the same `deliver_at` in the order they were sent.
+    sequence: u64,
+
+    src: SocketAddr,
+    dst: SocketAddr,
+    payload: Vec<u8>,
+    /// The NAT the sender sits behind, if any.
+    sender_nat: Option<NatId>,
+}
+
+impl Network {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            now: Instant::now(),
+            rng: StdRng::seed_from_u64(seed),
+            default_link: Link::default(),
+            hosts: Default::default(),
+            nats: Default::default(),
+            signalling: Default::default(),
+            in_flight: Default::default(),
+            next_sequence: 0,
+            next_connection_id: 1,
+        }
+    }
+
+    /// Sets the [`Link`] of all hosts added from now on.
+    pub fn with_default_link(mut self, link: Link) -> Self {
+        self.default_link = link;
+
+        self
+    }
+
+    pub fn now(&self) -> Instant {
+        self.now
+    }
+
+    pub fn add_nat(&mut self, nat_type: NatType, public_ip: IpAddr) -> NatId {
+        self.nats.push(Nat::new(nat_type, public_ip));
+
+        NatId(self.nats.len() - 1)
+    }
+
+    /// Attaches an arbitrary [`Endpoint`] to the network.
+    ///
+    /// Transmits without a `src` are sent from `address`.
+    /// If `nat` is set, the endpoint is only reachable through that NAT or from other hosts behind it.
+    pub fn add_endpoint(
+        &mut self,
+        endpoint: Handle<dyn Endpoint>,
+        address: SocketAddr,
+        nat: Option<NatId>,
+    ) {
+        assert!(
+            self.hosts.iter().all(|h| h.address.ip() != address.ip()),
+            "IP {} is already in use",
+            address.ip()
+        );
+
+        self.hosts.push(Host {
+            endpoint,
+            address,
+            nat,
+            link: self.default_link,
+        });
+    }
+
+    /// Overrides the [`Link`] of the host with the given IP.
+    pub fn set_link(&mut self, ip: IpAddr, link: Link) {
+        let host = self
+            .hosts
+            .iter_mut()
+            .find(|h| h.address.ip() == ip)
+            .expect("unknown host");
+
+        host.link = link;
+    }
+
+    /// Adds a [`ClientNode`](snownet::ClientNode) which uses `address` as its host candidate.
+    pub fn add_client(
+        &mut self,
+        span: Span,
+        address: SocketAddr,
+        nat: Option<NatId>,
+    ) -> Handle<SimNode<Client>> {
+        self.add_node(span, address, nat)
+    }
+
+    /// Adds a [`ServerNode`](snownet::ServerNode) which uses `address` as its host candidate.
+    pub fn add_server(
+        &mut self,
+        span: Span,
+        address: SocketAddr,
+        nat: Option<NatId>,
+    ) -> Handle<SimNode<Server>> {
+        self.add_node(span, address, nat)
+    }
+
+    /// Adds a relay, reachable on the given public IP.
+    pub fn add_relay(&mut self, span: Span, ip: IpAddr) -> Handle<SimRelay> {
+        let relay = SimRelay::new(ip, StdRng::seed_from_u64(self.rng.gen()), span);
+        let address = relay.listen_addr();
+        let relay = Rc::new(RefCell::new(relay));
+
+        self.add_endpoint(relay.clone(), address, None);
+
+        relay
+    }
+
+    /// Creates a new connection from `client` to `server` using the given STUN servers and relays.
+    ///
+    /// Any relay can also act as a STUN server.
+    /// Offer, answer and ICE candidates are signalled without delay.
+    pub fn connect(
+        &mut self,
+        client: &Handle<SimNode<Client>>,
+        server: &Handle<SimNode<Server>>,
+        stun_servers: &[&Handle<SimRelay>],
+        relays: &[&Handle<SimRelay>],
+    ) -> u64 {
+        let id = self.next_connection_id;
+        self.next_connection_id += 1;
+
+        let now = self.now;
+        let turn_servers = |username: &str| {
+            relays
+                .iter()
+                .map(|relay| relay.borrow().turn_server(username))
+                .collect::<HashSet<_>>()
+        };
+        let stun_servers = stun_servers
+            .iter()
+            .map(|server| server.borrow().listen_addr())
+            .collect::<HashSet<_>>();
+
+        {
+            let mut client = client.borrow_mut();
+            let mut server = server.borrow_mut();
+            let client_span = client.span().clone();
+            let server_span = server.span().clone();
+
+            let offer = client_span.in_scope(|| {
+                client.node_mut().new_connection(
+                    id,
+                    stun_servers.clone(),
+                    turn_servers("client"),
+                    now,
+                    now,
+                )
+            });
+            let client_key = client.node().public_key();
+            let answer = server_span.in_scope(|| {
+                server.node_mut().accept_connection(
+                    id,
+                    offer,
+                    client_key,
+                    stun_servers,
+                    turn_servers("server"),
+                    now,
+                )
+            });
+            let server_key = server.node().public_key();
+            client_span.in_scope(|| client.node_mut().accept_answer(id, server_key, answer, now));
+
+            client.drain_events(now);
+            server.drain_events(now);
+        }
+
+        self.signalling.push(Signalling {
+            id,
+            client: client.clone(),
+            server: server.clone(),
+        });
+
+        id
+    }
+
+    /// Runs the network until `condition` is true or `timeout` has passed.
+    ///
+    /// Returns whether the condition was met.
+    pub fn run_until(&mut self, timeout: Duration, mut condition: impl FnMut() -> bool) -> bool {
+        let deadline = self.now + timeout;
+
+        loop {
+            if condition() {
+                return true;
+            }
+
+            if self.now >= deadline {
+                return false;
+            }
+
+            self.step(deadline);
+        }
+    }
+
+    /// Runs the network for the given amount of time.
+    pub fn advance(&mut self, duration: Duration) {
+        let deadline = self.now + duration;
+
+        while self.now < deadline {
+            self.step(deadline);
+        }
+    }
+
+    fn add_node<T>(
+        &mut self,
+        span: Span,
+        address: SocketAddr,
+        nat: Option<NatId>,
+    ) -> Handle<SimNode<T>>
+    where
+        SimNode<T>: Endpoint + 'static,
+    {
+        let mut node = SimNode::new(StaticSecret::random_from_rng(&mut self.rng), span);
+        node.node_mut()
+            .add_local_host_candidate(address)
+            .expect("host candidate to be valid");
+
+        let node = Rc::new(RefCell::new(node));
+
+        self.add_endpoint(node.clone(), address, nat);
+
+        node
+    }
+
+    fn step(&mut self, deadline: Instant) {
+        self.flush();
+
+        let next_timeout = self
+            .hosts
+            .iter()
+            .filter_map(|h| h.endpoint.borrow_mut().poll_timeout())
+            .min();
+        let next_delivery = self.in_flight.peek().map(|Reverse(p)| p.deliver_at);
+
+        self.now = [next_timeout, next_delivery]
+            .into_iter()
+            .flatten()
+            .min()
+            .unwrap_or(deadline)
+            .min(deadline)
+            .max(self.now + RESOLUTION);
+
+        while self
+            .in_flight
+            .peek()
+            .is_some_and(|Reverse(p)| p.deliver_at <= self.now)
+        {
+            let Reverse(packet) = self.in_flight.pop().expect("just peeked");
+
+            self.deliver(packet);
+        }
+
+        for host in &self.hosts {
+            let mut endpoint = host.endpoint.borrow_mut();
+
+            if endpoint.poll_timeout().is_some_and(|t| t <= self.now) {
+                endpoint.handle_timeout(self.now);
+            }
+        }
+    }
+
+    /// Sends all pending transmits and signals all pending candidates.
+    fn flush(&mut self) {
+        for index in 0..self.hosts.len() {
+            loop {
+                let Some(transmit) = self.hosts[index].endpoint.borrow_mut().poll_transmit() else {
+                    break;
+                };
+
+                self.send(index, transmit);
+            }
+        }
+
+        for Signalling { id, client, server } in &self.signalling {
+            while let Some(candidate) = client.borrow_mut().poll_candidate(*id) {
+                server
+                    .borrow_mut()
+                    .add_remote_candidate(*id, candidate, self.now);
+            }
+
+            while let Some(candidate) = server.borrow_mut().poll_candidate(*id) {
+                client
+                    .borrow_mut()
+                    .add_remote_candidate(*id, candidate, self.now);
+            }
+        }
+    }
+
+    fn send(&mut self, index: usize, transmit: Transmit<'static>) {
+        let host = &self.hosts[index];
+        let link = host.link;
+        let nat = host.nat;
+
+        let mut src = transmit.src.unwrap_or(host.address);
+        let dst = transmit.dst;
+
+        if self.rng.gen_bool(link.loss) {
+            tracing::trace!(%src, %dst, "Packet lost");
+            return;
+        }
+
+        // Traffic between hosts behind the same NAT stays within the private network.
+        let is_local = self
+            .hosts
+            .iter()
+            .any(|h| h.address.ip() == dst.ip() && h.nat.is_some() && h.nat == nat);
+
+        if let Some(NatId(nat)) = nat.filter(|_| !is_local) {
+            src = self.nats[nat].outbound(src, dst);
+        }
+
+        let jitter = link.jitter.mul_f64(self.rng.gen());
+
+        self.in_flight.push(Reverse(InFlight {
+            deliver_at: self.now + link.latency + jitter,
+            sequence: self.next_sequence,
+            src,
+            dst,
+            payload: transmit.payload.into_owned(),
+            sender_nat: nat,
+        }));
+        self.next_sequence += 1;
+    }
+
+    fn deliver(&mut self, packet: InFlight) {
+        let InFlight {
+            src,
+            mut dst,
+            payload,
+            sender_nat,
+            ..
+        } = packet;
+        let mut via_nat = None;
+
+        if let Some(index) = self.nats.iter().position(|n| n.public_ip() == dst.ip()) {
+            let Some(internal) = self.nats[index].inbound(dst.port(), src) else {
+                tracing::trace!(%src, %dst, "Packet filtered by NAT");
+                return;
+            };
+
+            dst = internal;
+            via_nat = Some(NatId(index));
+        }
+
+        let Some(host) = self.hosts.iter().find(|h| h.address.ip() == dst.ip()) else {
+            tracing::trace!(%src, %dst, "No route to host");
+            return;
+        };
+
+        if host
+            .nat
+            .is_some_and(|nat| via_nat != Some(nat) && sender_nat != Some(nat))
+        {
+            tracing::trace!(%src, %dst, "Host is not reachable from outside its NAT");
+            return;
+        }
+
+        host.endpoint
+            .borrow_mut()
+            .handle_input(dst, src, &payload, self.now);
+    }
+}
+
+impl PartialEq for InFlight {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for InFlight {}
+
+impl PartialOrd for InFlight {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for InFlight {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.deliver_at, self.sequence).cmp(&(other.deliver_at, other.sequence))
+    }
+}
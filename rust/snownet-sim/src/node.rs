@@ -0,0 +1,169 @@
+use boringtun::x25519::StaticSecret;
+use snownet::{ConnectionStats, Endpoint, Event, IpPacket, Node, NodeStats, Transmit};
+use std::{
+    collections::VecDeque,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+use tracing::Span;
+
+/// A [`snownet::Node`] attached to a simulated network.
+///
+/// Connections are identified by the `u64` assigned by [`Network::connect`](crate::Network::connect).
+pub struct SimNode<T> {
+    node: Node<T, u64>,
+    span: Span,
+
+    events: Vec<(Event<u64>, Instant)>,
+    /// ICE candidates we still need to signal to the remote.
+    pending_candidates: VecDeque<(u64, String)>,
+    received_packets: Vec<IpPacket<'static>>,
+
+    buffered_transmits: VecDeque<Transmit<'static>>,
+    buffer: Vec<u8>,
+}
+
+impl<T> SimNode<T> {
+    pub(crate) fn new(private_key: StaticSecret, span: Span) -> Self {
+        Self {
+            node: Node::new(private_key),
+            span,
+            events: Default::default(),
+            pending_candidates: Default::default(),
+            received_packets: Default::default(),
+            buffered_transmits: Default::default(),
+            buffer: vec![0u8; (1 << 16) - 1],
+        }
+    }
+
+    pub fn node(&self) -> &Node<T, u64> {
+        &self.node
+    }
+
+    pub fn node_mut(&mut self) -> &mut Node<T, u64> {
+        &mut self.node
+    }
+
+    pub(crate) fn span(&self) -> &Span {
+        &self.span
+    }
+
+    pub fn is_connected_to<U>(&self, other: &SimNode<U>) -> bool {
+        self.node.is_connected_to(other.node.public_key())
+    }
+
+    pub fn node_stats(&self) -> NodeStats {
+        self.node.stats().0
+    }
+
+    pub fn connection_stats(&self, id: u64) -> Option<ConnectionStats> {
+        self.node
+            .stats()
+            .1
+            .find_map(|(conn, stats)| (conn == id).then_some(stats))
+    }
+
+    /// All events emitted by the node, together with the (simulated) time at which they happened.
+    pub fn events(&self) -> &[(Event<u64>, Instant)] {
+        &self.events
+    }
+
+    /// How long it took from `start` until the given connection was established.
+    pub fn established_after(&self, id: u64, start: Instant) -> Option<Duration> {
+        self.events.iter().find_map(|(event, at)| match event {
+            Event::ConnectionEstablished(conn) if *conn == id => Some(at.duration_since(start)),
+            Event::ConnectionEstablished(_)
+            | Event::SignalIceCandidate { .. }
+            | Event::ConnectionUpgraded { .. }
+            | Event::ConnectionFailed(_) => None,
+        })
+    }
+
+    pub fn received_packets(&self) -> &[IpPacket<'static>] {
+        &self.received_packets
+    }
+
+    /// Sends an IP packet through the tunnel of the given connection.
+    pub fn send(
+        &mut self,
+        id: u64,
+        packet: IpPacket<'_>,
+        now: Instant,
+    ) -> Result<(), snownet::Error> {
+        let _guard = self.span.enter();
+
+        if let Some(transmit) = self.node.encapsulate(id, packet, now)? {
+            self.buffered_transmits.push_back(transmit.into_owned());
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn add_remote_candidate(&mut self, id: u64, candidate: String, now: Instant) {
+        let _guard = self.span.enter();
+
+        self.node.add_remote_candidate(id, candidate, now);
+    }
+
+    pub(crate) fn poll_candidate(&mut self, id: u64) -> Option<String> {
+        let index = self
+            .pending_candidates
+            .iter()
+            .position(|(conn, _)| *conn == id)?;
+
+        self.pending_candidates
+            .remove(index)
+            .map(|(_, candidate)| candidate)
+    }
+
+    pub(crate) fn drain_events(&mut self, now: Instant) {
+        while let Some(event) = self.node.poll_event() {
+            if let Event::SignalIceCandidate {
+                connection,
+                candidate,
+            } = &event
+            {
+                self.pending_candidates
+                    .push_back((*connection, candidate.clone()));
+            }
+
+            self.events.push((event, now));
+        }
+    }
+}
+
+impl<T> Endpoint for SimNode<T> {
+    fn handle_input(&mut self, local: SocketAddr, from: SocketAddr, packet: &[u8], now: Instant) {
+        let _guard = self.span.enter();
+
+        match self
+            .node
+            .decapsulate(local, from, packet, now, self.buffer.as_mut())
+        {
+            Ok(Some((_, packet))) => self.received_packets.push(packet.to_immutable().to_owned()),
+            Ok(None) => {}
+            Err(e) => tracing::debug!(%from, "Failed to decapsulate packet: {e}"),
+        }
+
+        self.drain_events(now);
+    }
+
+    fn poll_transmit(&mut self) -> Option<Transmit<'static>> {
+        if let Some(transmit) = self.buffered_transmits.pop_front() {
+            return Some(transmit);
+        }
+
+        self.node.poll_transmit()
+    }
+
+    fn poll_timeout(&mut self) -> Option<Instant> {
+        self.node.poll_timeout()
+    }
+
+    fn handle_timeout(&mut self, now: Instant) {
+        let _guard = self.span.enter();
+
+        self.node.handle_timeout(now);
+        self.drain_events(now);
+    }
+}
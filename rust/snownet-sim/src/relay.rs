@@ -0,0 +1,145 @@
+use firezone_relay::{AllocationPort, ClientSocket, IpStack, PeerSocket};
+use rand::rngs::StdRng;
+use snownet::{Endpoint, Transmit};
+use std::{
+    collections::VecDeque,
+    net::{IpAddr, SocketAddr},
+    time::{Duration, Instant, SystemTime},
+};
+use tracing::Span;
+
+/// The port relays listen on for TURN traffic.
+const TURN_PORT: u16 = 3478;
+/// How long the credentials handed out by a [`SimRelay`] are valid for.
+const CREDENTIALS_VALIDITY: Duration = Duration::from_secs(60 * 60);
+
+/// A relay backed by the real [`firezone_relay::Server`].
+pub struct SimRelay {
+    inner: firezone_relay::Server<StdRng>,
+    listen_addr: SocketAddr,
+    span: Span,
+
+    buffered_transmits: VecDeque<Transmit<'static>>,
+    buffer: Vec<u8>,
+}
+
+impl SimRelay {
+    pub(crate) fn new(ip: IpAddr, rng: StdRng, span: Span) -> Self {
+        Self {
+            inner: firezone_relay::Server::new(IpStack::from(ip), rng, 49152, 65535),
+            listen_addr: SocketAddr::new(ip, TURN_PORT),
+            span,
+            buffered_transmits: Default::default(),
+            buffer: vec![0u8; (1 << 16) - 1],
+        }
+    }
+
+    pub fn listen_addr(&self) -> SocketAddr {
+        self.listen_addr
+    }
+
+    /// Creates a TURN server entry for a node, as expected by [`snownet::Node::new_connection`].
+    pub fn turn_server(&self, username: &str) -> (SocketAddr, String, String, String) {
+        let expiry = SystemTime::now() + CREDENTIALS_VALIDITY;
+
+        let secs = expiry
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .expect("expiry must be later than UNIX_EPOCH")
+            .as_secs();
+
+        let password =
+            firezone_relay::auth::generate_password(self.inner.auth_secret(), expiry, username);
+
+        (
+            self.listen_addr,
+            format!("{secs}:{username}"),
+            password,
+            "firezone".to_owned(),
+        )
+    }
+
+    fn drain_commands(&mut self) {
+        while let Some(command) = self.inner.next_command() {
+            match command {
+                firezone_relay::Command::SendMessage { payload, recipient } => {
+                    self.buffered_transmits.push_back(Transmit {
+                        src: Some(self.listen_addr),
+                        dst: recipient.into_socket(),
+                        payload: payload.into(),
+                        segment_size: None,
+                    });
+                }
+                // The network delivers traffic for any port of the relay's IP, allocated or not.
+                firezone_relay::Command::CreateAllocation { .. }
+                | firezone_relay::Command::FreeAllocation { .. } => {}
+                firezone_relay::Command::RelayToPeer { .. } => {
+                    tracing::debug!("snownet only relays data via channels")
+                }
+                firezone_relay::Command::ForwardToRelay { .. } => {
+                    tracing::debug!("Relay peering is not simulated")
+                }
+            }
+        }
+    }
+}
+
+impl Endpoint for SimRelay {
+    fn handle_input(&mut self, local: SocketAddr, from: SocketAddr, packet: &[u8], now: Instant) {
+        let _guard = self.span.enter();
+
+        if local == self.listen_addr {
+            let Some((port, peer)) =
+                self.inner
+                    .handle_client_input(packet, ClientSocket::new(from), now)
+            else {
+                return;
+            };
+
+            // Relaying to ourselves (from one allocation to another) is handled by the network like any other packet.
+            self.buffered_transmits.push_back(Transmit {
+                src: Some(SocketAddr::new(self.listen_addr.ip(), port.value())),
+                dst: peer.into_socket(),
+                payload: packet[4..].to_vec().into(),
+                segment_size: None,
+            });
+            return;
+        }
+
+        let Some((client, channel)) = self.inner.handle_peer_traffic(
+            packet,
+            PeerSocket::new(from),
+            AllocationPort::new(local.port()),
+            now,
+        ) else {
+            return;
+        };
+
+        let full_length = firezone_relay::ChannelData::encode_header_to_slice(
+            channel,
+            packet.len() as u16,
+            &mut self.buffer[..4],
+        );
+        self.buffer[4..full_length].copy_from_slice(packet);
+
+        self.buffered_transmits.push_back(Transmit {
+            src: Some(self.listen_addr),
+            dst: client.into_socket(),
+            payload: self.buffer[..full_length].to_vec().into(),
+            segment_size: None,
+        });
+    }
+
+    fn poll_transmit(&mut self) -> Option<Transmit<'static>> {
+        self.drain_commands();
+
+        self.buffered_transmits.pop_front()
+    }
+
+    fn poll_timeout(&mut self) -> Option<Instant> {
+        self.inner.poll_timeout()
+    }
+
+    fn handle_timeout(&mut self, now: Instant) {
+        self.span.in_scope(|| self.inner.handle_timeout(now));
+    }
+}
@@ -0,0 +1,205 @@
+use snownet::{NatBehaviour, PathType};
+use snownet_sim::{Handle, Link, NatType, Network, SimNode};
+use std::{
+    net::{IpAddr, SocketAddr},
+    time::Duration,
+};
+use tracing::info_span;
+use tracing_subscriber::util::SubscriberInitExt;
+
+const ALICE_PRIVATE: &str = "192.168.0.2:1000";
+const ALICE_NAT: &str = "203.0.113.1";
+const BOB_PRIVATE: &str = "10.0.0.2:2000";
+const BOB_NAT: &str = "198.51.100.1";
+const BOB_PUBLIC: &str = "198.51.100.2:2000";
+const RELAY: &str = "192.0.2.1";
+const STUN1: &str = "192.0.2.11";
+const STUN2: &str = "192.0.2.12";
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+#[test]
+fn full_cone_nats_connect_directly() {
+    let _guard = setup_tracing();
+
+    let mut network = Network::new(1);
+    let relay = network.add_relay(info_span!("Roger"), ip(RELAY));
+    let (alice, bob) = alice_and_bob_behind(&mut network, NatType::FullCone, NatType::FullCone);
+
+    let id = network.connect(&alice, &bob, &[], &[&relay]);
+
+    assert!(run_until_connected(&mut network, &alice, &bob));
+    assert!(wait_for_direct_path(&mut network, &alice, id));
+}
+
+#[test]
+fn restricted_cone_nats_connect_directly() {
+    let _guard = setup_tracing();
+
+    let mut network = Network::new(2);
+    let relay = network.add_relay(info_span!("Roger"), ip(RELAY));
+    let (alice, bob) = alice_and_bob_behind(
+        &mut network,
+        NatType::RestrictedCone,
+        NatType::PortRestrictedCone,
+    );
+
+    let id = network.connect(&alice, &bob, &[], &[&relay]);
+
+    assert!(run_until_connected(&mut network, &alice, &bob));
+    assert!(wait_for_direct_path(&mut network, &alice, id));
+}
+
+#[test]
+fn symmetric_nat_connects_directly_to_public_server() {
+    let _guard = setup_tracing();
+
+    let mut network = Network::new(3);
+    let relay = network.add_relay(info_span!("Roger"), ip(RELAY));
+    let nat = network.add_nat(NatType::Symmetric, ip(ALICE_NAT));
+    let alice = network.add_client(info_span!("Alice"), s(ALICE_PRIVATE), Some(nat));
+    let bob = network.add_server(info_span!("Bob"), s(BOB_PUBLIC), None);
+
+    let id = network.connect(&alice, &bob, &[], &[&relay]);
+
+    assert!(run_until_connected(&mut network, &alice, &bob));
+    assert!(wait_for_direct_path(&mut network, &alice, id));
+}
+
+#[test]
+fn symmetric_nats_need_relay() {
+    let _guard = setup_tracing();
+
+    let mut network = Network::new(4);
+    let relay = network.add_relay(info_span!("Roger"), ip(RELAY));
+    let (alice, bob) = alice_and_bob_behind(&mut network, NatType::Symmetric, NatType::Symmetric);
+
+    let id = network.connect(&alice, &bob, &[], &[&relay]);
+
+    assert!(run_until_connected(&mut network, &alice, &bob));
+
+    network.advance(Duration::from_secs(10));
+
+    let stats = alice.borrow().connection_stats(id).unwrap();
+    assert_eq!(stats.path, Some(PathType::Relayed));
+}
+
+#[test]
+fn symmetric_nat_with_predictable_ports_connects_directly() {
+    let _guard = setup_tracing();
+
+    let mut network = Network::new(8);
+    let stun1 = network.add_relay(info_span!("Stella"), ip(STUN1));
+    let stun2 = network.add_relay(info_span!("Steve"), ip(STUN2));
+    let relay = network.add_relay(info_span!("Roger"), ip(RELAY));
+    let (alice, bob) = alice_and_bob_behind(
+        &mut network,
+        NatType::Symmetric,
+        NatType::PortRestrictedCone,
+    );
+
+    let id = network.connect(&alice, &bob, &[&stun1, &stun2], &[&relay]);
+
+    assert!(run_until_connected(&mut network, &alice, &bob));
+    assert!(wait_for_direct_path(&mut network, &alice, id));
+
+    let alice_nat = alice.borrow().node_stats().nat_type;
+    let bob_nat = bob.borrow().node_stats().nat_type;
+    assert_eq!(
+        alice_nat.mapping,
+        Some(NatBehaviour::AddressAndPortDependent)
+    );
+    assert_eq!(bob_nat.mapping, Some(NatBehaviour::EndpointIndependent));
+}
+
+#[test]
+fn symmetric_nats_without_relay_cannot_connect() {
+    let _guard = setup_tracing();
+
+    let mut network = Network::new(5);
+    let (alice, bob) = alice_and_bob_behind(&mut network, NatType::Symmetric, NatType::Symmetric);
+
+    network.connect(&alice, &bob, &[], &[]);
+
+    assert!(!run_until_connected(&mut network, &alice, &bob));
+}
+
+#[test]
+fn connects_over_lossy_link_with_reordering() {
+    let _guard = setup_tracing();
+
+    let mut network = Network::new(6).with_default_link(lossy_link());
+    let relay = network.add_relay(info_span!("Roger"), ip(RELAY));
+    let (alice, bob) = alice_and_bob_behind(&mut network, NatType::Symmetric, NatType::FullCone);
+
+    network.connect(&alice, &bob, &[], &[&relay]);
+
+    assert!(run_until_connected(&mut network, &alice, &bob));
+}
+
+fn alice_and_bob_behind(
+    network: &mut Network,
+    alice_nat: NatType,
+    bob_nat: NatType,
+) -> (
+    Handle<SimNode<snownet::Client>>,
+    Handle<SimNode<snownet::Server>>,
+) {
+    let alice_nat = network.add_nat(alice_nat, ip(ALICE_NAT));
+    let bob_nat = network.add_nat(bob_nat, ip(BOB_NAT));
+
+    let alice = network.add_client(info_span!("Alice"), s(ALICE_PRIVATE), Some(alice_nat));
+    let bob = network.add_server(info_span!("Bob"), s(BOB_PRIVATE), Some(bob_nat));
+
+    (alice, bob)
+}
+
+fn run_until_connected(
+    network: &mut Network,
+    alice: &Handle<SimNode<snownet::Client>>,
+    bob: &Handle<SimNode<snownet::Server>>,
+) -> bool {
+    network.run_until(TIMEOUT, || {
+        alice.borrow().is_connected_to(&bob.borrow())
+            && bob.borrow().is_connected_to(&alice.borrow())
+    })
+}
+
+/// Direct paths may only be nominated after the relayed one, so give ICE some time.
+fn wait_for_direct_path(
+    network: &mut Network,
+    alice: &Handle<SimNode<snownet::Client>>,
+    id: u64,
+) -> bool {
+    network.run_until(TIMEOUT, || {
+        alice
+            .borrow()
+            .connection_stats(id)
+            .and_then(|stats| stats.path)
+            .is_some_and(|path| path != PathType::Relayed)
+    })
+}
+
+fn lossy_link() -> Link {
+    Link {
+        latency: Duration::from_millis(30),
+        jitter: Duration::from_millis(20),
+        loss: 0.1,
+    }
+}
+
+fn setup_tracing() -> tracing::subscriber::DefaultGuard {
+    tracing_subscriber::fmt()
+        .with_test_writer()
+        .with_env_filter("debug")
+        .finish()
+        .set_default()
+}
+
+fn s(socket: &str) -> SocketAddr {
+    socket.parse().unwrap()
+}
+
+fn ip(ip: &str) -> IpAddr {
+    ip.parse().unwrap()
+}
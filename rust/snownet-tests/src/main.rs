@@ -393,6 +393,13 @@ impl<T> Eventloop<T> {
             Some(snownet::Event::ConnectionEstablished(conn)) => {
                 return Poll::Ready(Ok(Event::ConnectionEstablished { conn }))
             }
+            Some(snownet::Event::ConnectionUpgraded {
+                connection,
+                relay,
+                direct,
+            }) => {
+                tracing::info!(%connection, %relay, %direct, "Upgraded to direct path");
+            }
             Some(snownet::Event::ConnectionFailed(conn)) => {
                 return Poll::Ready(Ok(Event::ConnectionFailed { conn }))
             }